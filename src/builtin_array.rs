@@ -0,0 +1,618 @@
+// Array and collection builtins, like `sort`, `flatten`, `contains`.
+// Ordering and equality between documents follow the collation defined
+// in `collate` module.
+
+use std::cmp::Ordering;
+
+use db::{Document, Doctype};
+use collate;
+
+pub fn sort<D>(doc: D) -> Result<D,String> where D: Document {
+    let mut items = array("sorted", doc)?;
+    items.sort_by(collate::cmp);
+    Ok(From::from(items))
+}
+
+pub fn unique<D>(doc: D) -> Result<D,String> where D: Document {
+    let mut items = array("sorted", doc)?;
+    items.sort_by(collate::cmp);
+    items.dedup_by(|a, b| collate::equal(a, b));
+    Ok(From::from(items))
+}
+
+pub fn min<D>(doc: D) -> Result<D,String> where D: Document {
+    let items = array("min", doc)?;
+    Ok(items.into_iter().min_by(collate::cmp).unwrap_or(D::null()))
+}
+
+pub fn max<D>(doc: D) -> Result<D,String> where D: Document {
+    let items = array("max", doc)?;
+    Ok(items.into_iter().max_by(collate::cmp).unwrap_or(D::null()))
+}
+
+pub fn reverse<D>(doc: D) -> Result<D,String> where D: Document {
+    match doc.doctype() {
+        Doctype::Null => Ok(From::from(Vec::<D>::new())),
+        Doctype::String => {
+            let text: String = doc.string().unwrap().chars().rev().collect();
+            Ok(From::from(text))
+        },
+        Doctype::Array => {
+            let mut items = doc.array().unwrap();
+            items.reverse();
+            Ok(From::from(items))
+        },
+        dt => Err(format!("cannot reverse {:?}", dt)),
+    }
+}
+
+pub fn flatten<D>(doc: D) -> Result<D,String> where D: Document {
+    let items = array("flattened", doc)?;
+    Ok(From::from(do_flatten(items, None)))
+}
+
+pub fn flatten_depth<D>(doc: D, depth: D) -> Result<D,String>
+    where D: Document
+{
+    let items = array("flattened", doc)?;
+    match depth.integer() {
+        Some(depth) if depth >= 0 => {
+            Ok(From::from(do_flatten(items, Some(depth))))
+        },
+        Some(_) => Err(format!("flatten depth must not be negative")),
+        None => Err(format!("flatten depth must be an integer")),
+    }
+}
+
+/// Add all the items, nulls are ignored, empty array adds up to null.
+pub fn add<D>(doc: D) -> Result<D,String> where D: Document {
+    let mut acc: Option<D> = None;
+    for item in array("added", doc)?.into_iter() {
+        acc = match (acc, item.doctype()) {
+            (acc, Doctype::Null) => acc,
+            (None, _) => Some(item),
+            (Some(acc), dt) => {
+                let adt = acc.doctype();
+                match (adt, dt) {
+                    (Doctype::Integer, Doctype::Float) |
+                    (Doctype::Float, Doctype::Integer) => (),
+                    (adt, dt) if adt == dt => (),
+                    (adt, dt) => {
+                        let err = format!("cannot add {:?} and {:?}", adt, dt);
+                        return Err(err)
+                    },
+                }
+                Some(acc + item)
+            },
+        }
+    }
+    Ok(acc.unwrap_or(D::null()))
+}
+
+pub fn transpose<D>(doc: D) -> Result<D,String> where D: Document {
+    let mut rows = Vec::new();
+    for row in array("transposed", doc)?.into_iter() {
+        rows.push(array("transposed", row)?);
+    }
+    let n = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+    let cols: Vec<D> = (0..n).map(|i| {
+        let col: Vec<D> = rows.iter()
+            .map(|row| row.get(i).cloned().unwrap_or(D::null()))
+            .collect();
+        From::from(col)
+    }).collect();
+    Ok(From::from(cols))
+}
+
+pub fn first<D>(doc: D) -> Result<D,String> where D: Document {
+    nth(doc, From::from(0_i128))
+}
+
+pub fn last<D>(doc: D) -> Result<D,String> where D: Document {
+    nth(doc, From::from(-1_i128))
+}
+
+/// Item at offset `n`, negative offsets count from the end.
+pub fn nth<D>(doc: D, n: D) -> Result<D,String> where D: Document {
+    let n = n.integer().ok_or(format!("nth expects an integer offset"))?;
+    match doc.doctype() {
+        Doctype::Null => Ok(D::null()),
+        Doctype::Array => {
+            let items = doc.array().unwrap();
+            let n = if n < 0 { n + (items.len() as i128) } else { n };
+            if n < 0 { return Ok(D::null()) }
+            Ok(items.into_iter().nth(n as usize).unwrap_or(D::null()))
+        },
+        dt => Err(format!("cannot index {:?} with number", dt)),
+    }
+}
+
+/// Offsets of `item` in the input, for string input the offsets of the
+/// sub-string, for array input the offsets of the sub-array, if `item` is
+/// an array, or the offsets of `item`.
+pub fn indices<D>(doc: D, item: D) -> Result<D,String> where D: Document {
+    let offsets: Vec<usize> = match (doc.doctype(), item.doctype()) {
+        (Doctype::Null, _) => return Ok(D::null()),
+        (Doctype::String, Doctype::String) => {
+            let chars: Vec<char> = doc.string().unwrap().chars().collect();
+            let needle: Vec<char> = item.string().unwrap().chars().collect();
+            subslice_offsets(&chars, &needle, |a, b| a == b)
+        },
+        (Doctype::Array, Doctype::Array) => {
+            let items = doc.array().unwrap();
+            let needle = item.array().unwrap();
+            subslice_offsets(&items, &needle, collate::equal)
+        },
+        (Doctype::Array, _) => {
+            let items = doc.array().unwrap();
+            items.iter().enumerate()
+                .filter(|(_, x)| collate::equal(*x, &item))
+                .map(|(i, _)| i)
+                .collect()
+        },
+        (dt, it) => {
+            return Err(format!("cannot find {:?} in {:?}", it, dt))
+        },
+    };
+    let offsets: Vec<D> = offsets.into_iter()
+        .map(|off| From::from(off as i128))
+        .collect();
+    Ok(From::from(offsets))
+}
+
+pub fn index<D>(doc: D, item: D) -> Result<D,String> where D: Document {
+    let offsets = indices(doc, item)?.array();
+    Ok(offsets.and_then(|offs| offs.into_iter().next()).unwrap_or(D::null()))
+}
+
+pub fn rindex<D>(doc: D, item: D) -> Result<D,String> where D: Document {
+    let offsets = indices(doc, item)?.array();
+    Ok(offsets.and_then(|offs| offs.into_iter().last()).unwrap_or(D::null()))
+}
+
+pub fn contains<D>(doc: D, item: D) -> Result<D,String> where D: Document {
+    Ok(From::from(check_contains(&doc, &item)?))
+}
+
+pub fn inside<D>(doc: D, container: D) -> Result<D,String> where D: Document {
+    Ok(From::from(check_contains(&container, &doc)?))
+}
+
+/// Numbers from `from` upto `upto`, excluding it, in steps of `by`,
+/// counted as they are consumed. Integers if `from`, `upto` and `by` are
+/// integers, else floats. Range is empty if `by` is zero, as in jq.
+pub enum Range {
+    Integer(i128, i128, i128),
+    Float(f64, f64, f64),
+}
+
+impl Range {
+    pub fn new<D>(from: D, upto: D, by: D) -> Result<Range,String>
+        where D: Document
+    {
+        let ints = (
+            from.clone().integer(), upto.clone().integer(), by.clone().integer()
+        );
+        if let (Some(from), Some(upto), Some(by)) = ints {
+            return Ok(Range::Integer(from, upto, by))
+        }
+        let number = |doc: D| {
+            let dt = doc.doctype();
+            doc.clone().integer().map(|x| x as f64).or(doc.float())
+                .ok_or(format!("range expects numbers, not {:?}", dt))
+        };
+        Ok(Range::Float(number(from)?, number(upto)?, number(by)?))
+    }
+
+    /// Next number in the range, if any.
+    pub fn pull<D>(&mut self) -> Option<D> where D: Document {
+        match self {
+            Range::Integer(from, upto, by)
+                if *by > 0 && *from < *upto || *by < 0 && *from > *upto =>
+            {
+                let x = *from;
+                // range ends on overflow.
+                *from = from.checked_add(*by).unwrap_or(*upto);
+                Some(From::from(x))
+            },
+            Range::Float(from, upto, by)
+                if *by > 0.0 && *from < *upto || *by < 0.0 && *from > *upto =>
+            {
+                let x = *from;
+                *from += *by;
+                Some(From::from(x))
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Every combination of picking one item from each array of the input,
+/// with `n`, every combination of picking `n` items from the input.
+pub fn combinations<D>(doc: D, args: Vec<D>) -> Result<Vec<D>,String>
+    where D: Document
+{
+    let rows = match args.into_iter().next() {
+        Some(n) => {
+            let n = n.integer()
+                .ok_or(format!("combinations expects an integer"))?;
+            let items = array("combined", doc)?;
+            (0..n).map(|_| items.clone()).collect()
+        },
+        None => {
+            let mut rows = Vec::new();
+            for row in array("combined", doc)?.into_iter() {
+                rows.push(array("combined", row)?);
+            }
+            rows
+        },
+    };
+    let mut outs: Vec<Vec<D>> = vec![vec![]];
+    for row in rows.into_iter() {
+        let mut next = Vec::new();
+        for out in outs.into_iter() {
+            for item in row.iter() {
+                let mut out = out.clone();
+                out.push(item.clone());
+                next.push(out);
+            }
+        }
+        outs = next;
+    }
+    Ok(outs.into_iter().map(From::from).collect())
+}
+
+/// Sort items by their keys, stable for items with equal keys.
+pub fn sort_by<D>(items: Vec<D>, keys: Vec<D>) -> D where D: Document {
+    let items: Vec<D> = sorted_by(items, keys).into_iter()
+        .map(|x| x.1)
+        .collect();
+    From::from(items)
+}
+
+/// Group items with equal keys, groups are sorted by their keys.
+pub fn group_by<D>(items: Vec<D>, keys: Vec<D>) -> D where D: Document {
+    let groups: Vec<D> = groups(items, keys).into_iter()
+        .map(From::from)
+        .collect();
+    From::from(groups)
+}
+
+/// First item of every group of items with equal keys.
+pub fn unique_by<D>(items: Vec<D>, keys: Vec<D>) -> D where D: Document {
+    let items: Vec<D> = groups(items, keys).into_iter()
+        .map(|group| group.into_iter().next().unwrap())
+        .collect();
+    From::from(items)
+}
+
+/// Item with the smallest key, first such item if there are many.
+pub fn min_by<D>(items: Vec<D>, keys: Vec<D>) -> D where D: Document {
+    let item = keys.into_iter().zip(items.into_iter()).min_by(cmp_keys);
+    item.map(|x| x.1).unwrap_or(D::null())
+}
+
+/// Item with the largest key, last such item if there are many.
+pub fn max_by<D>(items: Vec<D>, keys: Vec<D>) -> D where D: Document {
+    let item = keys.into_iter().zip(items.into_iter()).max_by(cmp_keys);
+    item.map(|x| x.1).unwrap_or(D::null())
+}
+
+fn cmp_keys<D>(x: &(D, D), y: &(D, D)) -> Ordering where D: Document {
+    collate::cmp(&x.0, &y.0)
+}
+
+fn sorted_by<D>(items: Vec<D>, keys: Vec<D>) -> Vec<(D, D)>
+    where D: Document
+{
+    let mut pairs: Vec<(D, D)> = keys.into_iter().zip(items.into_iter())
+        .collect();
+    pairs.sort_by(cmp_keys);
+    pairs
+}
+
+fn groups<D>(items: Vec<D>, keys: Vec<D>) -> Vec<Vec<D>> where D: Document {
+    let mut groups: Vec<(D, Vec<D>)> = Vec::new();
+    for (key, item) in sorted_by(items, keys).into_iter() {
+        match groups.last_mut() {
+            Some(group) if collate::equal(&group.0, &key) => {
+                group.1.push(item);
+                continue
+            },
+            _ => (),
+        }
+        groups.push((key, vec![item]));
+    }
+    groups.into_iter().map(|group| group.1).collect()
+}
+
+/// Items of an array, error mentions `what` could not be done otherwise.
+pub fn array<D>(what: &str, doc: D) -> Result<Vec<D>,String>
+    where D: Document
+{
+    let dt = doc.doctype();
+    match doc.array() {
+        Some(items) => Ok(items),
+        None => Err(format!("{:?} cannot be {}, not an array", dt, what)),
+    }
+}
+
+fn do_flatten<D>(items: Vec<D>, depth: Option<i128>) -> Vec<D>
+    where D: Document
+{
+    let mut out = Vec::new();
+    for item in items.into_iter() {
+        match (item.doctype(), depth) {
+            (Doctype::Array, Some(0)) => out.push(item),
+            (Doctype::Array, depth) => {
+                let depth = depth.map(|d| d - 1);
+                out.extend(do_flatten(item.array().unwrap(), depth));
+            },
+            _ => out.push(item),
+        }
+    }
+    out
+}
+
+// nested items of different kinds are not contained, only the top level
+// kinds must match.
+fn do_contains<D>(a: &D, b: &D) -> bool where D: Document {
+    match (a.doctype(), b.doctype()) {
+        (Doctype::Object, Doctype::Object) => {
+            b.object_ref().unwrap().iter().all(|prop| {
+                match a.get_ref(prop.key_ref()) {
+                    Some(value) => do_contains(value, prop.value_ref()),
+                    None => false,
+                }
+            })
+        },
+        (Doctype::Array, Doctype::Array) => {
+            let xs = a.array_ref().unwrap();
+            b.array_ref().unwrap().iter()
+                .all(|y| xs.iter().any(|x| do_contains(x, y)))
+        },
+        (Doctype::String, Doctype::String) => {
+            let (x, y) = (a.string_ref().unwrap(), b.string_ref().unwrap());
+            x.contains(y.as_str())
+        },
+        _ => collate::equal(a, b),
+    }
+}
+
+fn check_contains<D>(a: &D, b: &D) -> Result<bool,String> where D: Document {
+    let (adt, bdt) = (a.doctype(), b.doctype());
+    match (adt, bdt) {
+        (Doctype::Integer, Doctype::Float) |
+        (Doctype::Float, Doctype::Integer) => Ok(do_contains(a, b)),
+        (adt, bdt) if adt == bdt => Ok(do_contains(a, b)),
+        (adt, bdt) => {
+            Err(format!("cannot check if {:?} contains {:?}", adt, bdt))
+        },
+    }
+}
+
+fn subslice_offsets<T,F>(items: &[T], needle: &[T], eq: F) -> Vec<usize>
+    where F: Fn(&T, &T) -> bool
+{
+    if needle.len() == 0 || needle.len() > items.len() { return vec![] }
+    (0..=(items.len() - needle.len()))
+        .filter(|i| {
+            items[*i..].iter().zip(needle.iter()).all(|(x, y)| eq(x, y))
+        })
+        .collect()
+}
+
+/// First `n` outputs of a filter, outputs after that are not evaluated.
+pub fn limit<T,D,I>(outs: &mut I, n: Option<D>) -> Result<Vec<T>,String>
+    where D: Document, I: Iterator<Item=T>
+{
+    let n = n.and_then(|n| n.integer())
+        .ok_or(format!("limit expects an integer"))?;
+    Ok(outs.take(if n < 0 { 0 } else { n as usize }).collect())
+}
+
+/// First output of a filter, if any.
+pub fn first_of<T,D,I>(outs: &mut I, _: Option<D>) -> Result<Vec<T>,String>
+    where D: Document, I: Iterator<Item=T>
+{
+    Ok(outs.next().into_iter().collect())
+}
+
+/// Last output of a filter, if any.
+pub fn last_of<T,D,I>(outs: &mut I, _: Option<D>) -> Result<Vec<T>,String>
+    where D: Document, I: Iterator<Item=T>
+{
+    Ok(outs.last().into_iter().collect())
+}
+
+/// Output at offset `n` of a filter, if any.
+pub fn nth_of<T,D,I>(outs: &mut I, n: Option<D>) -> Result<Vec<T>,String>
+    where D: Document, I: Iterator<Item=T>
+{
+    match n.and_then(|n| n.integer()) {
+        Some(n) if n >= 0 => Ok(outs.nth(n as usize).into_iter().collect()),
+        Some(_) => Err(format!("nth doesn't support negative offsets")),
+        None => Err(format!("nth expects an integer offset")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::Value;
+    use json::Json;
+
+    fn doc(text: &str) -> Json {
+        text.parse().unwrap()
+    }
+
+    fn text(res: Result<Json,String>) -> String {
+        match res {
+            Ok(doc) => format!("{}", doc),
+            Err(err) => format!("error: {}", err),
+        }
+    }
+
+    #[test]
+    fn test_sort() {
+        assert_eq!("[null,1,\"a\",[]]", text(sort(doc(r#"["a",[],1,null]"#))));
+        assert_eq!("[1,2]", text(unique(doc("[2,1,2,1.0]"))));
+        assert_eq!("[]", text(sort(doc("[]"))));
+        assert_eq!("[]", text(unique(doc("[]"))));
+        assert_eq!("null", text(min(doc("[]"))));
+        assert_eq!("null", text(max(doc("[]"))));
+        let err = "error: Object cannot be sorted, not an array";
+        assert_eq!(err, text(sort(doc("{}"))));
+        let err = "error: Null cannot be max, not an array";
+        assert_eq!(err, text(max(doc("null"))));
+
+        assert_eq!("[]", text(reverse(doc("null"))));
+        assert_eq!(r#""cba""#, text(reverse(doc(r#""abc""#))));
+        assert_eq!("error: cannot reverse Integer", text(reverse(doc("1"))));
+
+        // items with equal keys keep their order, min is the first of them
+        // and max the last.
+        let (items, keys) = (doc("[1,2,3,4]"), doc("[1,0,1,0]"));
+        let items = || items.clone().array().unwrap();
+        let keys = || keys.clone().array().unwrap();
+        assert_eq!("[2,4,1,3]", format!("{}", sort_by(items(), keys())));
+        assert_eq!("[[2,4],[1,3]]", format!("{}", group_by(items(), keys())));
+        assert_eq!("[2,1]", format!("{}", unique_by(items(), keys())));
+        assert_eq!("2", format!("{}", min_by(items(), keys())));
+        assert_eq!("3", format!("{}", max_by(items(), keys())));
+        let none = || Vec::<Json>::new();
+        assert_eq!("[]", format!("{}", group_by(none(), none())));
+        assert_eq!("null", format!("{}", min_by(none(), none())));
+        assert_eq!("null", format!("{}", max_by(none(), none())));
+    }
+
+    #[test]
+    fn test_flatten_add() {
+        let items = doc("[1,[2,[3,[]]]]");
+        assert_eq!("[1,2,3]", text(flatten(items.clone())));
+        let depth = |n| text(flatten_depth(items.clone(), doc(n)));
+        assert_eq!("[1,2,[3,[]]]", depth("1"));
+        assert_eq!("[1,[2,[3,[]]]]", depth("0"));
+        let err = "error: flatten depth must not be negative";
+        assert_eq!(err, text(flatten_depth(items.clone(), doc("-1"))));
+        let err = "error: flatten depth must be an integer";
+        assert_eq!(err, text(flatten_depth(items, doc("1.5"))));
+        assert_eq!("[]", text(flatten(doc("[[],[[]]]"))));
+
+        assert_eq!("null", text(add(doc("[]"))));
+        assert_eq!("null", text(add(doc("[null,null]"))));
+        assert_eq!("4.5e0", text(add(doc("[1,null,3.5]"))));
+        assert_eq!(r#""ab""#, text(add(doc(r#"["a",null,"b"]"#))));
+        let err = "error: cannot add String and Integer";
+        assert_eq!(err, text(add(doc(r#"["a",1]"#))));
+        // integer overflow is a float.
+        let max = format!("[{},1]", i128::max_value());
+        assert_eq!("1.7014118346046923e38", text(add(doc(&max))));
+    }
+
+    #[test]
+    fn test_transpose_nth() {
+        assert_eq!("[[1,2],[null,3]]", text(transpose(doc("[[1],[2,3]]"))));
+        assert_eq!("[]", text(transpose(doc("[]"))));
+        assert_eq!("[]", text(transpose(doc("[[],[]]"))));
+        let err = "error: Integer cannot be transposed, not an array";
+        assert_eq!(err, text(transpose(doc("[[1],2]"))));
+
+        assert_eq!("null", text(first(doc("[]"))));
+        assert_eq!("null", text(last(doc("[]"))));
+        assert_eq!("3", text(last(doc("[1,2,3]"))));
+        assert_eq!("null", text(nth(doc("[1,2]"), doc("2"))));
+        assert_eq!("1", text(nth(doc("[1,2]"), doc("-2"))));
+        assert_eq!("null", text(nth(doc("[1,2]"), doc("-3"))));
+        assert_eq!("null", text(nth(doc("null"), doc("0"))));
+        let err = "error: nth expects an integer offset";
+        assert_eq!(err, text(nth(doc("[1]"), doc(r#""a""#))));
+        let err = "error: cannot index Object with number";
+        assert_eq!(err, text(nth(doc("{}"), doc("0"))));
+    }
+
+    #[test]
+    fn test_indices_contains() {
+        let items = doc("[1,2,1,2]");
+        assert_eq!("[0,2]", text(indices(items.clone(), doc("[1,2]"))));
+        assert_eq!("[1,3]", text(indices(items.clone(), doc("2"))));
+        assert_eq!("[]", text(indices(items.clone(), doc("[]"))));
+        assert_eq!("[]", text(indices(doc("[1]"), doc("[1,1]"))));
+        assert_eq!("[1,3]", text(indices(doc(r#""abab""#), doc(r#""b""#))));
+        assert_eq!("null", text(indices(doc("null"), doc("1"))));
+        assert_eq!("null", text(index(items.clone(), doc("3"))));
+        assert_eq!("3", text(rindex(items, doc("2"))));
+        let err = "error: cannot find Integer in String";
+        assert_eq!(err, text(indices(doc(r#""a""#), doc("1"))));
+
+        let obj = doc(r#"{"a":[1,"xyz"],"b":2}"#);
+        assert_eq!("true", text(contains(obj.clone(), doc(r#"{"a":["y"]}"#))));
+        assert_eq!("false", text(contains(obj.clone(), doc(r#"{"c":1}"#))));
+        assert_eq!("true", text(contains(obj.clone(), doc("{}"))));
+        assert_eq!("true", text(inside(doc("[]"), doc("[1]"))));
+        assert_eq!("true", text(contains(doc("1"), doc("1.0"))));
+        let err = "error: cannot check if Object contains Array";
+        assert_eq!(err, text(contains(obj, doc("[]"))));
+    }
+
+    #[test]
+    fn test_combinations() {
+        let text = |res: Result<Vec<Json>,String>| {
+            res.map(|docs| format!("{}", Json::from(docs)))
+                .unwrap_or_else(|err| format!("error: {}", err))
+        };
+        assert_eq!("[[]]", text(combinations(doc("[]"), vec![])));
+        assert_eq!("[]", text(combinations(doc("[[1],[]]"), vec![])));
+        assert_eq!("[[]]", text(combinations(doc("[1,2]"), vec![doc("0")])));
+        assert_eq!("[[]]", text(combinations(doc("[1,2]"), vec![doc("-1")])));
+        let err = "error: combinations expects an integer";
+        assert_eq!(err, text(combinations(doc("[1]"), vec![doc("null")])));
+        let err = "error: Integer cannot be combined, not an array";
+        assert_eq!(err, text(combinations(doc("[[1],2]"), vec![])));
+    }
+
+    #[test]
+    fn test_range() {
+        let pull = |mut range: Range| {
+            let mut items: Vec<Json> = Vec::new();
+            while let Some(x) = range.pull() { items.push(x) }
+            format!("{}", Json::from(items))
+        };
+        let range = |from, upto, by| Range::new(doc(from), doc(upto), doc(by));
+        assert_eq!("[0,2,4]", pull(range("0", "5", "2").unwrap()));
+        assert_eq!("[2,1]", pull(range("2", "0", "-1").unwrap()));
+        assert_eq!("[]", pull(range("0", "5", "0").unwrap()));
+        assert_eq!("[]", pull(range("5", "5", "1").unwrap()));
+        assert_eq!("[]", pull(range("5", "0", "1").unwrap()));
+        assert_eq!("[5e-1,1.5e0]", pull(range("0.5", "2", "1").unwrap()));
+        assert_eq!("[]", pull(range("0", "1", "0.0").unwrap()));
+        let err = "range expects numbers, not Null";
+        assert_eq!(err, range("0", "1", "null").err().unwrap());
+        // range ends when the next number overflows.
+        let (max, by) = (i128::max_value(), i128::max_value() / 2 + 1);
+        let (from, upto, by) =
+            ((max - by).to_string(), max.to_string(), by.to_string());
+        let out = pull(range(&from, &upto, &by).unwrap());
+        assert_eq!(format!("[{}]", from), out);
+        let min = i128::min_value().to_string();
+        let out = pull(range(&min, "0", &max.to_string()).unwrap());
+        assert_eq!(format!("[{},-1]", min), out);
+    }
+
+    #[test]
+    fn test_limit() {
+        let outs = || vec![1, 2, 3].into_iter();
+        let n = |n: &str| Some(doc(n));
+        assert_eq!(Ok(vec![1, 2]), limit(&mut outs(), n("2")));
+        assert_eq!(Ok(vec![]), limit(&mut outs(), n("-1")));
+        assert_eq!(Ok(vec![1, 2, 3]), limit(&mut outs(), n("5")));
+        assert!(limit(&mut outs(), n("null")).is_err());
+        assert_eq!(Ok(vec![1]), first_of(&mut outs(), None::<Json>));
+        assert_eq!(Ok(vec![3]), last_of(&mut outs(), None::<Json>));
+        assert_eq!(Ok(vec![]), first_of(&mut vec![0; 0].into_iter(), n("0")));
+        assert_eq!(Ok(vec![3]), nth_of(&mut outs(), n("2")));
+        assert_eq!(Ok(vec![]), nth_of(&mut outs(), n("3")));
+        let err = Err("nth doesn't support negative offsets".to_string());
+        assert_eq!(err, nth_of(&mut outs(), n("-1")));
+    }
+}
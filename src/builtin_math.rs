@@ -0,0 +1,256 @@
+// Math builtins, like `floor`, `sqrt`, `pow`. Rounding functions keep
+// integers as is and return integers for finite floats that fit in i128,
+// `div` and `mod` compute in i128 without going through floats.
+
+use db::{Document, Doctype};
+
+pub fn floor<D>(doc: D) -> Result<D,String> where D: Document {
+    round_with("floor", doc, f64::floor)
+}
+
+pub fn ceil<D>(doc: D) -> Result<D,String> where D: Document {
+    round_with("ceil", doc, f64::ceil)
+}
+
+/// Round half away from zero.
+pub fn round<D>(doc: D) -> Result<D,String> where D: Document {
+    round_with("round", doc, f64::round)
+}
+
+pub fn fabs<D>(doc: D) -> Result<D,String> where D: Document {
+    match doc.doctype() {
+        Doctype::Integer => {
+            let n = doc.integer().unwrap();
+            match n.checked_abs() {
+                Some(n) => Ok(From::from(n)),
+                None => Ok(From::from((n as f64).abs())),
+            }
+        },
+        _ => Ok(From::from(float("fabs", doc)?.abs())),
+    }
+}
+
+pub fn sqrt<D>(doc: D) -> Result<D,String> where D: Document {
+    Ok(From::from(float("sqrt", doc)?.sqrt()))
+}
+
+/// Natural logarithm.
+pub fn log<D>(doc: D) -> Result<D,String> where D: Document {
+    Ok(From::from(float("log", doc)?.ln()))
+}
+
+pub fn log2<D>(doc: D) -> Result<D,String> where D: Document {
+    Ok(From::from(float("log2", doc)?.log2()))
+}
+
+pub fn log10<D>(doc: D) -> Result<D,String> where D: Document {
+    Ok(From::from(float("log10", doc)?.log10()))
+}
+
+pub fn exp<D>(doc: D) -> Result<D,String> where D: Document {
+    Ok(From::from(float("exp", doc)?.exp()))
+}
+
+pub fn exp2<D>(doc: D) -> Result<D,String> where D: Document {
+    Ok(From::from(float("exp2", doc)?.exp2()))
+}
+
+pub fn exp10<D>(doc: D) -> Result<D,String> where D: Document {
+    Ok(From::from(10_f64.powf(float("exp10", doc)?)))
+}
+
+pub fn sin<D>(doc: D) -> Result<D,String> where D: Document {
+    Ok(From::from(float("sin", doc)?.sin()))
+}
+
+pub fn cos<D>(doc: D) -> Result<D,String> where D: Document {
+    Ok(From::from(float("cos", doc)?.cos()))
+}
+
+pub fn tan<D>(doc: D) -> Result<D,String> where D: Document {
+    Ok(From::from(float("tan", doc)?.tan()))
+}
+
+pub fn asin<D>(doc: D) -> Result<D,String> where D: Document {
+    Ok(From::from(float("asin", doc)?.asin()))
+}
+
+pub fn acos<D>(doc: D) -> Result<D,String> where D: Document {
+    Ok(From::from(float("acos", doc)?.acos()))
+}
+
+pub fn atan<D>(doc: D) -> Result<D,String> where D: Document {
+    Ok(From::from(float("atan", doc)?.atan()))
+}
+
+pub fn sinh<D>(doc: D) -> Result<D,String> where D: Document {
+    Ok(From::from(float("sinh", doc)?.sinh()))
+}
+
+pub fn cosh<D>(doc: D) -> Result<D,String> where D: Document {
+    Ok(From::from(float("cosh", doc)?.cosh()))
+}
+
+pub fn tanh<D>(doc: D) -> Result<D,String> where D: Document {
+    Ok(From::from(float("tanh", doc)?.tanh()))
+}
+
+/// Mantissa of the number, scaled to the range [1, 2).
+pub fn significand<D>(doc: D) -> Result<D,String> where D: Document {
+    let x = float("significand", doc)?;
+    if x == 0.0 || !x.is_finite() { return Ok(From::from(x)) }
+    Ok(From::from(x / x.abs().log2().floor().exp2()))
+}
+
+pub fn pow<D>(base: D, exp: D) -> Result<D,String> where D: Document {
+    let (base, exp) = (float("pow", base)?, float("pow", exp)?);
+    Ok(From::from(base.powf(exp)))
+}
+
+pub fn atan2<D>(y: D, x: D) -> Result<D,String> where D: Document {
+    let (y, x) = (float("atan2", y)?, float("atan2", x)?);
+    Ok(From::from(y.atan2(x)))
+}
+
+/// Integer division, truncated towards zero.
+pub fn div<D>(a: D, b: D) -> Result<D,String> where D: Document {
+    let (a, b) = integers("div", a, b)?;
+    if b == 0 { return Err(format!("{} cannot be divided by zero", a)) }
+    match a.checked_div(b) {
+        Some(n) => Ok(From::from(n)),
+        None => Err(format!("{} div {} overflows", a, b)),
+    }
+}
+
+/// Remainder of integer division, with the sign of the dividend.
+pub fn modulo<D>(a: D, b: D) -> Result<D,String> where D: Document {
+    let (a, b) = integers("mod", a, b)?;
+    if b == 0 { return Err(format!("{} cannot be divided by zero", a)) }
+    Ok(From::from(a.wrapping_rem(b)))
+}
+
+fn round_with<D>(name: &str, doc: D, f: fn(f64) -> f64) -> Result<D,String>
+    where D: Document
+{
+    if doc.doctype() == Doctype::Integer { return Ok(doc) }
+
+    let x = f(float(name, doc)?);
+    // i128 range is roughly +/- 1.7e38.
+    if x.is_finite() && x.abs() < 1.7e38 {
+        Ok(From::from(x as i128))
+    } else {
+        Ok(From::from(x))
+    }
+}
+
+fn float<D>(name: &str, doc: D) -> Result<f64,String> where D: Document {
+    let dt = doc.doctype();
+    match dt {
+        Doctype::Integer => Ok(doc.integer().unwrap() as f64),
+        Doctype::Float => Ok(doc.float().unwrap()),
+        _ => Err(format!("{}() cannot be applied to {:?}", name, dt)),
+    }
+}
+
+fn integers<D>(name: &str, a: D, b: D) -> Result<(i128, i128),String>
+    where D: Document
+{
+    match (a.integer(), b.integer()) {
+        (Some(a), Some(b)) => Ok((a, b)),
+        _ => Err(format!("{}() requires integer inputs", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use json::Json;
+
+    fn doc(text: &str) -> Json {
+        text.parse().unwrap()
+    }
+
+    fn text(res: Result<Json,String>) -> String {
+        match res {
+            Ok(doc) => format!("{}", doc),
+            Err(err) => format!("error: {}", err),
+        }
+    }
+
+    #[test]
+    fn test_round() {
+        assert_eq!("1", text(floor(doc("1.5"))));
+        assert_eq!("-2", text(floor(doc("-1.5"))));
+        assert_eq!("2", text(ceil(doc("1.5"))));
+        assert_eq!("-2", text(round(doc("-1.5"))));
+        assert_eq!("3", text(round(doc("2.5"))));
+        let max = i128::max_value().to_string();
+        assert_eq!(max, text(floor(doc(&max))));
+        // floats beyond i128 stay floats.
+        assert_eq!("1e40", text(floor(doc("1e40"))));
+        assert_eq!("-1e40", text(ceil(doc("-1e40"))));
+        let err = "error: floor() cannot be applied to String";
+        assert_eq!(err, text(floor(doc(r#""1""#))));
+        let err = "error: round() cannot be applied to Null";
+        assert_eq!(err, text(round(doc("null"))));
+
+        assert_eq!("5", text(fabs(doc("-5"))));
+        assert_eq!("1.5e0", text(fabs(doc("-1.5"))));
+        // abs of i128::MIN overflows into a float.
+        let min = i128::min_value().to_string();
+        assert_eq!("1.7014118346046923e38", text(fabs(doc(&min))));
+        let err = "error: fabs() cannot be applied to Array";
+        assert_eq!(err, text(fabs(doc("[]"))));
+    }
+
+    #[test]
+    fn test_float() {
+        assert_eq!("3e0", text(sqrt(doc("9"))));
+        assert_eq!("0e0", text(log(doc("1"))));
+        assert_eq!("1e1", text(log2(doc("1024"))));
+        assert_eq!("2e0", text(log10(doc("100"))));
+        assert_eq!("1e0", text(exp(doc("0"))));
+        assert_eq!("1.024e3", text(exp2(doc("10"))));
+        assert_eq!("1e3", text(exp10(doc("3"))));
+        assert_eq!("0e0", text(sin(doc("0"))));
+        assert_eq!("1e0", text(cos(doc("0"))));
+        assert_eq!("0e0", text(atan(doc("0"))));
+        assert_eq!("8e0", text(pow(doc("2"), doc("3"))));
+        assert_eq!("5e-1", text(pow(doc("4"), doc("-0.5"))));
+        assert_eq!("0e0", text(atan2(doc("0"), doc("1"))));
+        let err = "error: sqrt() cannot be applied to Object";
+        assert_eq!(err, text(sqrt(doc("{}"))));
+        let err = "error: pow() cannot be applied to Bool";
+        assert_eq!(err, text(pow(doc("2"), doc("true"))));
+        let err = "error: atan2() cannot be applied to Null";
+        assert_eq!(err, text(atan2(doc("null"), doc("1"))));
+
+        assert_eq!("1.5e0", text(significand(doc("3"))));
+        assert_eq!("-1.25e0", text(significand(doc("-10"))));
+        assert_eq!("0e0", text(significand(doc("0"))));
+        let err = "error: significand() cannot be applied to String";
+        assert_eq!(err, text(significand(doc(r#""a""#))));
+    }
+
+    #[test]
+    fn test_div_mod() {
+        assert_eq!("3", text(div(doc("7"), doc("2"))));
+        assert_eq!("-3", text(div(doc("-7"), doc("2"))));
+        assert_eq!("1", text(modulo(doc("7"), doc("3"))));
+        assert_eq!("-1", text(modulo(doc("-7"), doc("3"))));
+        assert_eq!("1", text(modulo(doc("7"), doc("-3"))));
+        let err = "error: 7 cannot be divided by zero";
+        assert_eq!(err, text(div(doc("7"), doc("0"))));
+        assert_eq!(err, text(modulo(doc("7"), doc("0"))));
+
+        let min = i128::min_value().to_string();
+        let err = format!("error: {} div -1 overflows", min);
+        assert_eq!(err, text(div(doc(&min), doc("-1"))));
+        assert_eq!("0", text(modulo(doc(&min), doc("-1"))));
+
+        let err = "error: div() requires integer inputs";
+        assert_eq!(err, text(div(doc("7.0"), doc("2"))));
+        let err = "error: mod() requires integer inputs";
+        assert_eq!(err, text(modulo(doc("7"), doc("null"))));
+    }
+}
@@ -0,0 +1,202 @@
+// Path builtins, like `getpath`, `paths`, `topointer`, and `env`. Paths
+// are arrays of keys and offsets, a JSON Pointer string is accepted where
+// ever a path is expected.
+
+use std::env;
+
+use db::{Document, Doctype};
+use path::{self, Path};
+use prop::Property;
+
+pub fn getpath<D>(doc: D, path: D) -> Result<D,String> where D: Document {
+    let path = to_path(&doc, path)?;
+    path::getpath(&doc, &path)
+}
+
+/// `setpath(path; value)`.
+pub fn setpath<D>(mut doc: D, args: Vec<D>) -> Result<Vec<D>,String>
+    where D: Document
+{
+    let mut args = args.into_iter();
+    let (path, value) = (args.next().unwrap(), args.next().unwrap());
+    let path = to_path(&doc, path)?;
+    path::setpath(&mut doc, &path, value)?;
+    Ok(vec![doc])
+}
+
+pub fn delpaths<D>(mut doc: D, paths: D) -> Result<D,String>
+    where D: Document
+{
+    let dt = paths.doctype();
+    let items = paths.array()
+        .ok_or(format!("{:?} cannot be deleted, not an array of paths", dt))?;
+    let mut paths = Vec::new();
+    for item in items.into_iter() { paths.push(to_path(&doc, item)?) }
+    path::delpaths(&mut doc, paths)?;
+    Ok(doc)
+}
+
+/// Every path within the input, except the empty path to itself.
+pub fn paths<D>(doc: D, _args: Vec<D>) -> Result<Vec<D>,String>
+    where D: Document
+{
+    let items = path::all_paths_at(vec![], doc).into_iter()
+        .skip(1)
+        .map(|(path, _)| From::from(path))
+        .collect();
+    Ok(items)
+}
+
+/// Paths to the scalar values within the input.
+pub fn leaf_paths<D>(doc: D, _args: Vec<D>) -> Result<Vec<D>,String>
+    where D: Document
+{
+    let items = path::all_paths_at(vec![], doc).into_iter()
+        .skip(1)
+        .filter(|(_, value)| match value.doctype() {
+            Doctype::Array | Doctype::Object => false,
+            _ => true,
+        })
+        .map(|(path, _)| From::from(path))
+        .collect();
+    Ok(items)
+}
+
+/// Path as JSON Pointer string.
+pub fn topointer<D>(doc: D) -> Result<D,String> where D: Document {
+    let dt = doc.doctype();
+    let path = doc.array().ok_or(format!("{:?} is not a valid path", dt))?;
+    Ok(From::from(path::to_pointer(&path)?))
+}
+
+/// Environment variables as object, as in `env` and `$ENV`.
+pub fn env<D>(_doc: D) -> Result<D,String> where D: Document {
+    Ok(environ())
+}
+
+pub fn environ<D>() -> D where D: Document {
+    let props: Vec<Property<D>> = env::vars_os()
+        .map(|(key, value)| {
+            let key = key.to_string_lossy().to_string();
+            let value = value.to_string_lossy().to_string();
+            Property::new(key, From::from(value))
+        })
+        .collect();
+    From::from(props)
+}
+
+// path as array, or as JSON Pointer into `doc`.
+fn to_path<D>(doc: &D, path: D) -> Result<Path<D>,String> where D: Document {
+    match path.doctype() {
+        Doctype::Array => Ok(path.array().unwrap()),
+        Doctype::String => path::from_pointer(doc, path.string_ref().unwrap()),
+        dt => Err(format!("{:?} is not a valid path", dt)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use json::Json;
+
+    fn doc(text: &str) -> Json {
+        text.parse().unwrap()
+    }
+
+    fn text(res: Result<Json,String>) -> String {
+        match res {
+            Ok(doc) => format!("{}", doc),
+            Err(err) => format!("error: {}", err),
+        }
+    }
+
+    fn texts(res: Result<Vec<Json>,String>) -> String {
+        match res {
+            Ok(docs) => format!("{}", Json::from(docs)),
+            Err(err) => format!("error: {}", err),
+        }
+    }
+
+    #[test]
+    fn test_getpath() {
+        let value = doc(r#"{"a":[1,{"b/c":2}],"~":3}"#);
+        let get = |path| text(getpath(value.clone(), doc(path)));
+        assert_eq!("2", get(r#"["a",1,"b/c"]"#));
+        assert_eq!("2", get(r#""/a/1/b~1c""#));
+        assert_eq!("3", get(r#""/~0""#));
+        assert_eq!(format!("{}", value), get("[]"));
+        assert_eq!(format!("{}", value), get(r#""""#));
+        // missing paths, and paths under null, are null.
+        assert_eq!("null", get(r#"["x","y",0]"#));
+        assert_eq!("null", get(r#"["a",5]"#));
+        assert_eq!("null", get(r#""/a/-""#));
+        assert_eq!("null", get(r#""/x/y""#));
+        let err = r#"error: cannot index "x" into Array"#;
+        assert_eq!(err, get(r#"["a","x"]"#));
+        assert_eq!("error: Integer is not a valid path", get("1"));
+        let err = r#"error: "a" is not a valid JSON Pointer"#;
+        assert_eq!(err, get(r#""a""#));
+        let err = r#"error: "01" is not a valid array index"#;
+        assert_eq!(err, get(r#""/a/01""#));
+    }
+
+    #[test]
+    fn test_setpath() {
+        let set = |value: &str, path: &str| {
+            texts(setpath(doc(value), vec![doc(path), doc("1")]))
+        };
+        assert_eq!("[1]", set("null", "[]"));
+        assert_eq!("[1]", set(r#"{"a":2}"#, "[]"));
+        assert_eq!("[1]", set(r#"{"a":2}"#, r#""""#));
+        assert_eq!(r#"[{"a":{"b":1}}]"#, set("null", r#"["a","b"]"#));
+        assert_eq!("[[null,null,1]]", set("null", "[2]"));
+        assert_eq!("[[1,2,1]]", set("[1,2]", r#""/-""#));
+        assert_eq!("[[1]]", set("[0]", "[-1]"));
+        let err = "error: out of bounds negative array index";
+        assert_eq!(err, set("[0]", "[-2]"));
+        let err = r#"error: cannot index "a" into Integer"#;
+        assert_eq!(err, set("1", r#"["a"]"#));
+        assert_eq!("error: Null is not a valid path", set("{}", "null"));
+    }
+
+    #[test]
+    fn test_delpaths() {
+        let del = |value: &str, paths: &str| {
+            text(delpaths(doc(value), doc(paths)))
+        };
+        assert_eq!("null", del(r#"{"a":1}"#, "[[]]"));
+        assert_eq!(r#"{"a":1}"#, del(r#"{"a":1}"#, "[]"));
+        assert_eq!("[2]", del("[1,2,3]", "[[0],[2]]"));
+        assert_eq!("[1]", del("[1,2]", r#"["/1"]"#));
+        // the empty path is deleted last.
+        assert_eq!("null", del(r#"{"a":[1]}"#, r#"[[],["a",0]]"#));
+        // missing paths are ignored.
+        let value = r#"{"a":[1]}"#;
+        assert_eq!(value, del(value, r#"[["x","y"],["a",5],["a",-5]]"#));
+        let err = r#"error: cannot index "x" into Array"#;
+        assert_eq!(err, del(value, r#"[["a","x"]]"#));
+        let err = "error: Object cannot be deleted, not an array of paths";
+        assert_eq!(err, del(value, "{}"));
+        assert_eq!("error: Integer is not a valid path", del(value, "[1]"));
+    }
+
+    #[test]
+    fn test_paths() {
+        let value = doc(r#"{"a":[1,{"b":null}],"c":[]}"#);
+        let refval = r#"[["a"],["a",0],["a",1],["a",1,"b"],["c"]]"#;
+        assert_eq!(refval, texts(paths(value.clone(), vec![])));
+        let refval = r#"[["a",0],["a",1,"b"]]"#;
+        assert_eq!(refval, texts(leaf_paths(value, vec![])));
+        assert_eq!("[]", texts(paths(doc("1"), vec![])));
+        assert_eq!("[]", texts(leaf_paths(doc("[]"), vec![])));
+
+        assert_eq!(r#""/a~1b/0/~0""#, text(topointer(doc(r#"["a/b",0,"~"]"#))));
+        assert_eq!(r#""""#, text(topointer(doc("[]"))));
+        let err = "error: Integer cannot be in a JSON Pointer";
+        assert_eq!(err, text(topointer(doc("[-1]"))));
+        let err = "error: Null cannot be in a JSON Pointer";
+        assert_eq!(err, text(topointer(doc("[null]"))));
+        let err = "error: String is not a valid path";
+        assert_eq!(err, text(topointer(doc(r#""/a""#))));
+    }
+}
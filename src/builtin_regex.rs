@@ -0,0 +1,234 @@
+// Regular expression builtins, like `test`, `match`, `sub`. Offsets and
+// lengths in match objects count unicode codepoints, not bytes.
+
+use regex::{Captures, Regex, RegexBuilder};
+
+use db::Document;
+use prop::Property;
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum RegexOp {
+    Test,
+    Match,
+    Capture,
+    Scan,
+    Split,
+    Sub,
+    Gsub,
+}
+
+impl RegexOp {
+    pub fn from_name(name: &str) -> Option<RegexOp> {
+        match name {
+            "test" => Some(RegexOp::Test),
+            "match" => Some(RegexOp::Match),
+            "capture" => Some(RegexOp::Capture),
+            "scan" => Some(RegexOp::Scan),
+            "split" => Some(RegexOp::Split),
+            "sub" => Some(RegexOp::Sub),
+            "gsub" => Some(RegexOp::Gsub),
+            _ => None,
+        }
+    }
+
+    // scan, split and gsub apply on all the matches.
+    fn is_global(&self) -> bool {
+        match self {
+            RegexOp::Scan | RegexOp::Split | RegexOp::Gsub => true,
+            _ => false,
+        }
+    }
+}
+
+/// Compiled regular expression along with its flags.
+pub struct Pattern {
+    regex: Regex,
+    global: bool,
+    noempty: bool,
+}
+
+impl Pattern {
+    /// Compile `re` with `flags`, `g` for all matches, `i` to ignore
+    /// case, `x` for extended syntax and `n` to ignore empty matches.
+    pub fn compile(re: &str, flags: &str, op: RegexOp)
+        -> Result<Pattern,String>
+    {
+        let mut builder = RegexBuilder::new(re);
+        let (mut global, mut noempty) = (op.is_global(), false);
+        for flag in flags.chars() {
+            match flag {
+                'g' => global = true,
+                'n' => noempty = true,
+                'i' => { builder.case_insensitive(true); },
+                'x' => { builder.ignore_whitespace(true); },
+                _ => {
+                    return Err(format!("{} is not a valid modifier", flags))
+                },
+            }
+        }
+        match builder.build() {
+            Ok(regex) => Ok(Pattern{regex, global, noempty}),
+            Err(err) => Err(format!("invalid regex {:?}: {}", re, err)),
+        }
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.captures(text).len() > 0
+    }
+
+    /// Match objects, with offset, length, string and captures.
+    pub fn matches<D>(&self, text: &str) -> Vec<D> where D: Document {
+        self.captures(text).iter().map(|caps| {
+            let m = caps.get(0).unwrap();
+            let captures: Vec<D> = self.regex.capture_names().enumerate()
+                .skip(1)
+                .map(|(i, name)| {
+                    let name = match name {
+                        Some(name) => From::from(name.to_string()),
+                        None => D::null(),
+                    };
+                    let (offset, length, string) = match caps.get(i) {
+                        Some(c) => (
+                            codepoints(&text[..c.start()]),
+                            codepoints(c.as_str()),
+                            From::from(c.as_str().to_string()),
+                        ),
+                        None => (-1, 0, D::null()),
+                    };
+                    object(vec![
+                        ("offset", From::from(offset)),
+                        ("length", From::from(length)),
+                        ("string", string),
+                        ("name", name),
+                    ])
+                })
+                .collect();
+            object(vec![
+                ("offset", From::from(codepoints(&text[..m.start()]))),
+                ("length", From::from(codepoints(m.as_str()))),
+                ("string", From::from(m.as_str().to_string())),
+                ("captures", From::from(captures)),
+            ])
+        }).collect()
+    }
+
+    /// Objects of named captures, one for each match.
+    pub fn named_captures<D>(&self, text: &str) -> Vec<D> where D: Document {
+        self.captures(text).iter().map(|caps| self.capture_doc(caps)).collect()
+    }
+
+    /// Matched string for every match, or the list of captured strings if
+    /// the pattern has groups.
+    pub fn scan<D>(&self, text: &str) -> Vec<D> where D: Document {
+        self.captures(text).iter().map(|caps| {
+            if caps.len() == 1 {
+                return From::from(caps[0].to_string())
+            }
+            let groups: Vec<D> = caps.iter().skip(1).map(|c| match c {
+                Some(c) => From::from(c.as_str().to_string()),
+                None => D::null(),
+            }).collect();
+            From::from(groups)
+        }).collect()
+    }
+
+    /// Split text on the matches.
+    pub fn split<D>(&self, text: &str) -> D where D: Document {
+        let mut items: Vec<D> = Vec::new();
+        let mut start = 0;
+        for caps in self.captures(text).iter() {
+            let m = caps.get(0).unwrap();
+            items.push(From::from(text[start..m.start()].to_string()));
+            start = m.end();
+        }
+        items.push(From::from(text[start..].to_string()));
+        From::from(items)
+    }
+
+    /// Replace matches with the output of `repl`, applied on the object of
+    /// named captures.
+    pub fn replace<D,F,E>(&self, text: &str, mut repl: F) -> Result<String,E>
+        where D: Document, F: FnMut(D) -> Result<String,E>
+    {
+        let (mut out, mut start) = (String::new(), 0);
+        for caps in self.captures(text).iter() {
+            let m = caps.get(0).unwrap();
+            out.push_str(&text[start..m.start()]);
+            out.push_str(&repl(self.capture_doc(caps))?);
+            start = m.end();
+        }
+        out.push_str(&text[start..]);
+        Ok(out)
+    }
+
+    fn captures<'t>(&self, text: &'t str) -> Vec<Captures<'t>> {
+        let noempty = self.noempty;
+        let mut iter = self.regex.captures_iter(text)
+            .filter(|caps| !noempty || caps[0].len() > 0);
+        if self.global {
+            iter.collect()
+        } else {
+            iter.next().into_iter().collect()
+        }
+    }
+
+    fn capture_doc<D>(&self, caps: &Captures) -> D where D: Document {
+        let props: Vec<Property<D>> = self.regex.capture_names()
+            .filter_map(|name| name)
+            .map(|name| {
+                let value = match caps.name(name) {
+                    Some(c) => From::from(c.as_str().to_string()),
+                    None => D::null(),
+                };
+                Property::new(name.to_string(), value)
+            })
+            .collect();
+        From::from(props)
+    }
+}
+
+fn codepoints(text: &str) -> i128 {
+    text.chars().count() as i128
+}
+
+fn object<D>(items: Vec<(&str, D)>) -> D where D: Document {
+    let props: Vec<Property<D>> = items.into_iter()
+        .map(|(key, value)| Property::new(key.to_string(), value))
+        .collect();
+    From::from(props)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use json::Json;
+
+    #[test]
+    fn test_pattern() {
+        let p = Pattern::compile(r"(?P<x>a)(b)?", "g", RegexOp::Match);
+        let p = p.unwrap();
+        let outs: Vec<Json> = p.matches("éab a");
+        let refs = [
+            r#"{"captures":[{"length":1,"name":"x","offset":1,"string":"a"},"#,
+            r#"{"length":1,"name":null,"offset":2,"string":"b"}],"#,
+            r#""length":2,"offset":1,"string":"ab"}"#,
+        ];
+        assert_eq!(refs.concat(), format!("{}", outs[0]));
+        let refs = [
+            r#"{"captures":[{"length":1,"name":"x","offset":4,"string":"a"},"#,
+            r#"{"length":0,"name":null,"offset":-1,"string":null}],"#,
+            r#""length":1,"offset":4,"string":"a"}"#,
+        ];
+        assert_eq!(refs.concat(), format!("{}", outs[1]));
+
+        let p = Pattern::compile("x*", "n", RegexOp::Scan).unwrap();
+        let outs: Vec<Json> = p.scan("axxbx");
+        assert_eq!("[\"xx\", \"x\"]", format!("{:?}", outs));
+
+        let p = Pattern::compile("A", "ix", RegexOp::Test).unwrap();
+        assert!(p.is_match("bad"));
+        let err = Pattern::compile("a", "q", RegexOp::Test).err().unwrap();
+        assert_eq!("q is not a valid modifier", err);
+        assert!(Pattern::compile("(", "", RegexOp::Test).is_err());
+    }
+}
@@ -0,0 +1,275 @@
+// String builtins and format strings, like `split`, `@csv`. Each function
+// takes the input document, and its argument if any, and either returns
+// the output document or an error message.
+
+use db::{Document, Doctype};
+use builtin_type;
+use collate;
+use util;
+
+pub fn split<D>(doc: D, sep: D) -> Result<D,String> where D: Document {
+    let (text, sep) = strings("split", doc, sep)?;
+    let items: Vec<D> = if sep.len() == 0 {
+        text.chars().map(|ch| From::from(ch.to_string())).collect()
+    } else {
+        text.split(sep.as_str()).map(|s| From::from(s.to_string())).collect()
+    };
+    Ok(From::from(items))
+}
+
+pub fn join<D>(doc: D, sep: D) -> Result<D,String> where D: Document {
+    let dt = doc.doctype();
+    let (items, sep) = match (doc.array(), sep.string()) {
+        (Some(items), Some(sep)) => (items, sep),
+        _ => return Err(format!("cannot join {:?} with a non-string", dt)),
+    };
+    let mut parts = Vec::new();
+    for item in items.into_iter() {
+        let part = match item.doctype() {
+            Doctype::Null => "".to_string(),
+            Doctype::String => item.string().unwrap(),
+            Doctype::Integer | Doctype::Float => collate::number(&item),
+            Doctype::Bool => format!("{:?}", item),
+            dt => return Err(format!("cannot join {:?}", dt)),
+        };
+        parts.push(part);
+    }
+    Ok(From::from(parts.join(&sep)))
+}
+
+pub fn ltrimstr<D>(doc: D, prefix: D) -> Result<D,String> where D: Document {
+    let out = match (doc.string_ref(), prefix.string_ref()) {
+        (Some(text), Some(prefix)) if text.starts_with(prefix.as_str()) => {
+            From::from(text[prefix.len()..].to_string())
+        },
+        _ => doc,
+    };
+    Ok(out)
+}
+
+pub fn rtrimstr<D>(doc: D, suffix: D) -> Result<D,String> where D: Document {
+    let out = match (doc.string_ref(), suffix.string_ref()) {
+        (Some(text), Some(suffix)) if text.ends_with(suffix.as_str()) => {
+            From::from(text[..text.len()-suffix.len()].to_string())
+        },
+        _ => doc,
+    };
+    Ok(out)
+}
+
+pub fn startswith<D>(doc: D, prefix: D) -> Result<D,String>
+    where D: Document
+{
+    let (text, prefix) = strings("startswith", doc, prefix)?;
+    Ok(From::from(text.starts_with(prefix.as_str())))
+}
+
+pub fn endswith<D>(doc: D, suffix: D) -> Result<D,String> where D: Document {
+    let (text, suffix) = strings("endswith", doc, suffix)?;
+    Ok(From::from(text.ends_with(suffix.as_str())))
+}
+
+pub fn ascii_downcase<D>(doc: D) -> Result<D,String> where D: Document {
+    let text = string("ascii_downcase", doc)?;
+    Ok(From::from(text.to_ascii_lowercase()))
+}
+
+pub fn ascii_upcase<D>(doc: D) -> Result<D,String> where D: Document {
+    let text = string("ascii_upcase", doc)?;
+    Ok(From::from(text.to_ascii_uppercase()))
+}
+
+pub fn explode<D>(doc: D) -> Result<D,String> where D: Document {
+    let text = string("explode", doc)?;
+    let codes: Vec<D> = text.chars().map(|ch| From::from(ch as i128)).collect();
+    Ok(From::from(codes))
+}
+
+pub fn implode<D>(doc: D) -> Result<D,String> where D: Document {
+    let dt = doc.doctype();
+    let items = doc.array().ok_or(format!("cannot implode {:?}", dt))?;
+    let mut text = String::new();
+    for item in items.into_iter() {
+        let ch = item.integer()
+            .and_then(|code| if code < 0 { None } else { Some(code) })
+            .and_then(|code| ::std::char::from_u32(code as u32))
+            .ok_or(format!("cannot implode, invalid codepoint"))?;
+        text.push(ch);
+    }
+    Ok(From::from(text))
+}
+
+pub fn tostring<D>(doc: D) -> Result<D,String> where D: Document {
+    Ok(From::from(text(doc)))
+}
+
+pub fn tonumber<D>(doc: D) -> Result<D,String> where D: Document {
+    match doc.doctype() {
+        Doctype::Integer | Doctype::Float => Ok(doc),
+        Doctype::String => {
+            let text = doc.string().unwrap();
+            let text = text.trim();
+            if let Ok(n) = text.parse::<i128>() { return Ok(From::from(n)) }
+            match text.parse::<f64>() {
+                Ok(f) => Ok(From::from(f)),
+                Err(_) => Err(format!("cannot parse {:?} as number", text)),
+            }
+        },
+        dt => Err(format!("cannot parse {:?} as number", dt)),
+    }
+}
+
+/// Apply format `name`, as in `@name`, on `doc`.
+/// Names of the format strings, `@text`, `@csv` ...
+pub const FORMATS: [&str; 9] = [
+    "text", "json", "html", "uri", "csv", "tsv", "sh", "base64", "base64d",
+];
+
+pub fn format<D>(name: &str, doc: D) -> Result<String,String>
+    where D: Document
+{
+    match name {
+        "text" => Ok(text(doc)),
+        "json" => Ok(builtin_type::json_text(doc)),
+        "html" => Ok(escape_html(&text(doc))),
+        "uri" => Ok(escape_uri(&text(doc))),
+        "csv" => row("csv", doc, ",", quote_csv),
+        "tsv" => row("tsv", doc, "\t", escape_tsv),
+        "sh" => match doc.doctype() {
+            Doctype::Array => row("sh", doc, " ", quote_sh),
+            _ => row::<D,_>("sh", From::from(vec![doc]), " ", quote_sh),
+        },
+        "base64" => Ok(util::base64_encode(text(doc).as_bytes())),
+        "base64d" => {
+            let data = util::base64_decode(&text(doc))
+                .ok_or(format!("cannot decode invalid base64 text"))?;
+            Ok(String::from_utf8_lossy(&data).to_string())
+        },
+        _ => Err(format!("{} is not a valid format", name)),
+    }
+}
+
+// string as is, numbers as in object keys, other values as JSON text.
+fn text<D>(doc: D) -> String where D: Document {
+    match doc.doctype() {
+        Doctype::String => doc.string().unwrap(),
+        Doctype::Integer | Doctype::Float => collate::number(&doc),
+        _ => builtin_type::json_text(doc),
+    }
+}
+
+fn string<D>(name: &str, doc: D) -> Result<String,String>
+    where D: Document
+{
+    let dt = doc.doctype();
+    doc.string().ok_or(format!("{}() cannot be applied to {:?}", name, dt))
+}
+
+fn strings<D>(name: &str, doc: D, arg: D) -> Result<(String, String),String>
+    where D: Document
+{
+    match (doc.string(), arg.string()) {
+        (Some(text), Some(arg)) => Ok((text, arg)),
+        _ => Err(format!("{}() requires string inputs", name)),
+    }
+}
+
+// join the scalar items of an array as one row, strings are escaped.
+fn row<D,F>(name: &str, doc: D, sep: &str, escape: F) -> Result<String,String>
+    where D: Document, F: Fn(&str) -> String
+{
+    let dt = doc.doctype();
+    let items = doc.array().ok_or(format!("cannot @{} {:?}", name, dt))?;
+    let mut parts = Vec::new();
+    for item in items.into_iter() {
+        let part = match item.doctype() {
+            Doctype::Null => "".to_string(),
+            Doctype::String => escape(item.string_ref().unwrap()),
+            Doctype::Integer | Doctype::Float => collate::number(&item),
+            Doctype::Bool => format!("{:?}", item),
+            dt => return Err(format!("cannot @{} {:?}", name, dt)),
+        };
+        parts.push(part);
+    }
+    Ok(parts.join(sep))
+}
+
+fn quote_csv(text: &str) -> String {
+    format!("\"{}\"", text.replace("\"", "\"\""))
+}
+
+fn escape_tsv(text: &str) -> String {
+    text.replace("\\", "\\\\")
+        .replace("\t", "\\t")
+        .replace("\n", "\\n")
+        .replace("\r", "\\r")
+}
+
+fn quote_sh(text: &str) -> String {
+    format!("'{}'", text.replace("'", "'\\''"))
+}
+
+fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '\'' => out.push_str("&#39;"),
+            '"' => out.push_str("&quot;"),
+            ch => out.push(ch),
+        }
+    }
+    out
+}
+
+// percent encode all but the unreserved characters.
+fn escape_uri(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for b in text.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' => out.push(b as char),
+            b'-' | b'_' | b'.' | b'~' => out.push(b as char),
+            b => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use json::Json;
+
+    fn doc(text: &str) -> Json {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn test_format() {
+        let row = doc(r#"[1,"a,\"b\"",null,true,"it's"]"#);
+        let out = format("csv", row.clone()).unwrap();
+        assert_eq!(r#"1,"a,""b""",,true,"it's""#, out);
+        let out = format("tsv", doc(r#"["a\tb","c\\d"]"#)).unwrap();
+        assert_eq!("a\\tb\tc\\\\d", out);
+        let out = format("sh", doc(r#"[1,{"a":1}]"#)).unwrap_err();
+        assert_eq!("cannot @sh Object", out);
+        let out = format("sh", doc(r#"["a b","it's"]"#)).unwrap();
+        assert_eq!(r#"'a b' 'it'\''s'"#, out);
+        let out = format("html", doc(r#""<a href='x'>&</a>""#)).unwrap();
+        assert_eq!("&lt;a href=&#39;x&#39;&gt;&amp;&lt;/a&gt;", out);
+        let out = format("uri", doc(r#""a b/ü~""#)).unwrap();
+        assert_eq!("a%20b%2F%C3%BC~", out);
+
+        for text in ["", "a", "ab", "abc", "hello world"].iter() {
+            let encoded = format("base64", Json::from(text.to_string()));
+            let encoded = encoded.unwrap();
+            let out = format("base64d", Json::from(encoded)).unwrap();
+            assert_eq!(*text, out);
+        }
+        assert_eq!("aGk=", format("base64", doc(r#""hi""#)).unwrap());
+        let err = format("xyz", doc("1")).unwrap_err();
+        assert_eq!("xyz is not a valid format", err);
+    }
+}
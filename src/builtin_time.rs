@@ -0,0 +1,613 @@
+// Date and time builtins, like `todate`, `strftime`, `dateadd`. Instants
+// are epoch seconds, integer or float, RFC 3339 / ISO 8601 strings or
+// timestamps. Broken down time, as in `gmtime`, is the array
+//
+//   [year, month (0-11), day, hours, minutes, seconds, weekday, yearday]
+//
+// where seconds can have a fraction. All computations are in UTC, there is
+// no dependency on the timezone database.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use db::{Document, Doctype};
+use util;
+
+const NANOS: i128 = 1_000_000_000;
+
+const MONTHS: [&'static str; 12] = [
+    "January", "February", "March", "April", "May", "June", "July",
+    "August", "September", "October", "November", "December",
+];
+
+const WEEKDAYS: [&'static str; 7] = [
+    "Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday",
+    "Saturday",
+];
+
+/// Current time as epoch seconds.
+pub fn now<D>(_doc: D) -> Result<D,String> where D: Document {
+    let elapsed = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map_err(|err| format!("invalid system time: {}", err))?;
+    Ok(seconds(elapsed.as_nanos() as i128))
+}
+
+/// Instant or broken down time as RFC 3339 string in UTC.
+pub fn todate<D>(doc: D) -> Result<D,String> where D: Document {
+    Ok(From::from(util::rfc3339(time("todate", doc)?.to_nanos())))
+}
+
+/// Instant as epoch seconds.
+pub fn fromdate<D>(doc: D) -> Result<D,String> where D: Document {
+    Ok(seconds(instant("fromdate", doc)?))
+}
+
+pub fn gmtime<D>(doc: D) -> Result<D,String> where D: Document {
+    Ok(Tm::from_nanos(instant("gmtime", doc)?).to_doc())
+}
+
+/// Broken down time as epoch seconds, fraction of seconds is dropped.
+pub fn mktime<D>(doc: D) -> Result<D,String> where D: Document {
+    let tm = Tm::from_doc("mktime", doc)?;
+    Ok(From::from(tm.to_nanos().div_euclid(NANOS)))
+}
+
+/// Format an instant or broken down time, as in strftime(3).
+pub fn strftime<D>(doc: D, format: D) -> Result<D,String> where D: Document {
+    let tm = time("strftime", doc)?;
+    let dt = format.doctype();
+    let format = format.string()
+        .ok_or(format!("strftime() requires a string format, not {:?}", dt))?;
+    Ok(From::from(tm.format(&format)?))
+}
+
+/// Parse text as per format, as in strptime(3), into broken down time.
+pub fn strptime<D>(doc: D, format: D) -> Result<D,String> where D: Document {
+    match (doc.string(), format.string()) {
+        (Some(text), Some(format)) => Ok(Tm::parse(&text, &format)?.to_doc()),
+        _ => Err(format!("strptime() requires string inputs")),
+    }
+}
+
+/// `dateadd(unit; n)`, add `n` units, like "days", to the instant. Output
+/// is of the same kind as input, epoch seconds or RFC 3339 string.
+pub fn dateadd<D>(doc: D, args: Vec<D>) -> Result<Vec<D>,String>
+    where D: Document
+{
+    shift_date("dateadd", doc, args, 1)
+}
+
+/// `datesub(unit; n)`, subtract `n` units from the instant.
+pub fn datesub<D>(doc: D, args: Vec<D>) -> Result<Vec<D>,String>
+    where D: Document
+{
+    shift_date("datesub", doc, args, -1)
+}
+
+/// Parse ISO 8601 date and time, like `2015-03-05T23:51:47Z`, into
+/// nanoseconds since UNIX epoch. Time defaults to midnight and the
+/// offset to UTC.
+pub fn iso8601(text: &str) -> Result<i128,String> {
+    let err = || format!("{:?} is not a valid ISO 8601 date", text);
+
+    let mut s = Scan::new(text);
+    let year = s.signed(4, 6).ok_or_else(err)?;
+    let month = s.expect(b'-').and_then(|_| s.digits(2, 2)).ok_or_else(err)?;
+    let day = s.expect(b'-').and_then(|_| s.digits(2, 2)).ok_or_else(err)?;
+    let mut tm = Tm::new(year, month as u32, day as u32);
+    if s.eat(b'T') || s.eat(b't') || s.eat(b' ') {
+        tm.hour = s.digits(2, 2).ok_or_else(err)? as u32;
+        tm.min = s.expect(b':').and_then(|_| s.digits(2, 2))
+            .ok_or_else(err)? as u32;
+        if s.eat(b':') {
+            tm.sec = s.digits(2, 2).ok_or_else(err)? as u32;
+            if s.eat(b'.') || s.eat(b',') {
+                tm.nanos = s.fraction().ok_or_else(err)?;
+            }
+        }
+        if !s.is_end() {
+            tm.offset = s.offset().ok_or_else(err)?;
+        }
+    }
+    if !s.is_end() { return Err(err()) }
+    tm.validate()?;
+    Ok(tm.to_nanos())
+}
+
+fn shift_date<D>(name: &str, doc: D, args: Vec<D>, sign: i128)
+    -> Result<Vec<D>,String>
+    where D: Document
+{
+    let mut args = args.into_iter();
+    let (unit, n) = (args.next().unwrap(), args.next().unwrap());
+    let unit = match unit.string_ref().map(|unit| unit.as_str()) {
+        Some("seconds") | Some("second") => NANOS,
+        Some("minutes") | Some("minute") => 60 * NANOS,
+        Some("hours") | Some("hour") => 3600 * NANOS,
+        Some("days") | Some("day") => 86400 * NANOS,
+        Some("weeks") | Some("week") => 7 * 86400 * NANOS,
+        _ => return Err(format!("{:?} is not a valid unit", unit)),
+    };
+    let n = match (n.doctype(), n.clone().integer(), n.float()) {
+        (_, Some(n), _) => n * unit,
+        (_, _, Some(f)) if f.is_finite() => (f * (unit as f64)) as i128,
+        (dt, _, _) => {
+            return Err(format!("{}() requires a number, not {:?}", name, dt))
+        },
+    };
+    let is_number = match doc.doctype() {
+        Doctype::Integer | Doctype::Float => true,
+        _ => false,
+    };
+    let nanos = instant(name, doc)? + sign * n;
+    if is_number {
+        Ok(vec![seconds(nanos)])
+    } else {
+        Ok(vec![From::from(util::rfc3339(nanos))])
+    }
+}
+
+// nanoseconds since UNIX epoch from epoch seconds, ISO 8601 string or
+// timestamp.
+fn instant<D>(name: &str, doc: D) -> Result<i128,String> where D: Document {
+    match doc.doctype() {
+        Doctype::Integer => {
+            let secs = doc.integer().unwrap();
+            secs.checked_mul(NANOS)
+                .ok_or(format!("{} seconds is out of range", secs))
+        },
+        Doctype::Float => {
+            let secs = doc.float().unwrap();
+            if secs.is_finite() && secs.abs() < 1e20 {
+                Ok((secs * (NANOS as f64)).round() as i128)
+            } else {
+                Err(format!("{:?} seconds is out of range", secs))
+            }
+        },
+        Doctype::String => iso8601(doc.string_ref().unwrap()),
+        Doctype::Timestamp => Ok(doc.timestamp().unwrap()),
+        dt => Err(format!("{}() cannot be applied to {:?}", name, dt)),
+    }
+}
+
+// calendar fields of an instant or broken down time.
+fn time<D>(name: &str, doc: D) -> Result<Tm,String> where D: Document {
+    match doc.doctype() {
+        Doctype::Array => Tm::from_doc(name, doc),
+        _ => Ok(Tm::from_nanos(instant(name, doc)?)),
+    }
+}
+
+// epoch seconds, integer unless there is a fraction.
+fn seconds<D>(nanos: i128) -> D where D: Document {
+    if nanos % NANOS == 0 {
+        From::from(nanos / NANOS)
+    } else {
+        From::from((nanos as f64) / (NANOS as f64))
+    }
+}
+
+fn days_in_month(year: i128, month: u32) -> u32 {
+    let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// calendar fields, month and day start from 1.
+struct Tm {
+    year: i128,
+    month: u32,
+    day: u32,
+    hour: u32,
+    min: u32,
+    sec: u32,
+    nanos: u32,
+    // seconds east of UTC, used while parsing.
+    offset: i128,
+}
+
+impl Tm {
+    fn new(year: i128, month: u32, day: u32) -> Tm {
+        Tm{year, month, day, hour: 0, min: 0, sec: 0, nanos: 0, offset: 0}
+    }
+
+    fn from_nanos(nanos: i128) -> Tm {
+        let (secs, nsec) = (nanos.div_euclid(NANOS), nanos.rem_euclid(NANOS));
+        let (days, secs) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+        let (year, month, day) = util::civil_from_days(days);
+        let mut tm = Tm::new(year, month, day);
+        tm.hour = (secs / 3600) as u32;
+        tm.min = ((secs % 3600) / 60) as u32;
+        tm.sec = (secs % 60) as u32;
+        tm.nanos = nsec as u32;
+        tm
+    }
+
+    fn to_nanos(&self) -> i128 {
+        let days = util::days_from_civil(self.year, self.month, self.day);
+        let secs = days * 86400 + (self.hour as i128) * 3600 +
+            (self.min as i128) * 60 + (self.sec as i128) - self.offset;
+        secs * NANOS + (self.nanos as i128)
+    }
+
+    fn from_doc<D>(name: &str, doc: D) -> Result<Tm,String>
+        where D: Document
+    {
+        let err = || format!("{}() requires broken down time", name);
+
+        let items = doc.array().ok_or_else(err)?;
+        if items.len() < 6 { return Err(err()) }
+        let mut fields = Vec::new();
+        for item in items.into_iter().take(6) {
+            match (item.clone().integer(), item.float()) {
+                (Some(n), _) => fields.push((n, 0)),
+                (_, Some(f)) if f.is_finite() => {
+                    let nanos = (f.fract() * (NANOS as f64)).round() as u32;
+                    fields.push((f.trunc() as i128, nanos))
+                },
+                _ => return Err(err()),
+            }
+        }
+        // fields can be out of range, like 25 hours, and are normalized.
+        let (month, day) = (fields[1].0, fields[2].0);
+        let year = fields[0].0 + month.div_euclid(12);
+        let month = (month.rem_euclid(12) + 1) as u32;
+        let days = util::days_from_civil(year, month, 1) + day - 1;
+        let secs = days * 86400 + fields[3].0 * 3600 + fields[4].0 * 60 +
+            fields[5].0;
+        Ok(Tm::from_nanos(secs * NANOS + (fields[5].1 as i128)))
+    }
+
+    fn to_doc<D>(&self) -> D where D: Document {
+        let sec: D = if self.nanos == 0 {
+            From::from(self.sec as i128)
+        } else {
+            From::from((self.sec as f64) + (self.nanos as f64) / 1e9)
+        };
+        let items: Vec<D> = vec![
+            From::from(self.year),
+            From::from((self.month - 1) as i128),
+            From::from(self.day as i128),
+            From::from(self.hour as i128),
+            From::from(self.min as i128),
+            sec,
+            From::from(self.weekday() as i128),
+            From::from(self.yearday() as i128),
+        ];
+        From::from(items)
+    }
+
+    fn days(&self) -> i128 {
+        util::days_from_civil(self.year, self.month, self.day)
+    }
+
+    // days since sunday, 1970-01-01 is a thursday.
+    fn weekday(&self) -> u32 {
+        (self.days() + 4).rem_euclid(7) as u32
+    }
+
+    // days since january 1st.
+    fn yearday(&self) -> u32 {
+        (self.days() - util::days_from_civil(self.year, 1, 1)) as u32
+    }
+
+    fn validate(&self) -> Result<(),String> {
+        let ok = self.month >= 1 && self.month <= 12 && self.day >= 1 &&
+            self.day <= days_in_month(self.year, self.month) &&
+            self.hour < 24 && self.min < 60 && self.sec <= 60;
+        if ok { Ok(()) } else { Err(format!("date is out of range")) }
+    }
+
+    fn format(&self, format: &str) -> Result<String,String> {
+        let mut out = String::new();
+        let mut chars = format.chars();
+        while let Some(ch) = chars.next() {
+            if ch != '%' { out.push(ch); continue }
+            let hour12 = if self.hour % 12 == 0 { 12 } else { self.hour % 12 };
+            let month = MONTHS[(self.month - 1) as usize];
+            let weekday = WEEKDAYS[self.weekday() as usize];
+            match chars.next() {
+                Some('Y') => out.push_str(&format!("{}", self.year)),
+                Some('C') => out.push_str(&format!("{:02}", self.year / 100)),
+                Some('y') => {
+                    out.push_str(&format!("{:02}", self.year.rem_euclid(100)))
+                },
+                Some('m') => out.push_str(&format!("{:02}", self.month)),
+                Some('d') => out.push_str(&format!("{:02}", self.day)),
+                Some('e') => out.push_str(&format!("{:2}", self.day)),
+                Some('H') => out.push_str(&format!("{:02}", self.hour)),
+                Some('I') => out.push_str(&format!("{:02}", hour12)),
+                Some('M') => out.push_str(&format!("{:02}", self.min)),
+                Some('S') => out.push_str(&format!("{:02}", self.sec)),
+                Some('j') => {
+                    out.push_str(&format!("{:03}", self.yearday() + 1))
+                },
+                Some('p') => {
+                    out.push_str(if self.hour < 12 { "AM" } else { "PM" })
+                },
+                Some('a') => out.push_str(&weekday[..3]),
+                Some('A') => out.push_str(weekday),
+                Some('b') | Some('h') => out.push_str(&month[..3]),
+                Some('B') => out.push_str(month),
+                Some('u') => match self.weekday() {
+                    0 => out.push('7'),
+                    wday => out.push_str(&format!("{}", wday)),
+                },
+                Some('w') => out.push_str(&format!("{}", self.weekday())),
+                Some('s') => {
+                    let secs = self.to_nanos().div_euclid(NANOS);
+                    out.push_str(&format!("{}", secs))
+                },
+                Some('Z') => out.push_str("UTC"),
+                Some('z') => out.push_str("+0000"),
+                Some('T') => out.push_str(&self.format("%H:%M:%S")?),
+                Some('D') => out.push_str(&self.format("%m/%d/%y")?),
+                Some('F') => out.push_str(&self.format("%Y-%m-%d")?),
+                Some('R') => out.push_str(&self.format("%H:%M")?),
+                Some('c') => out.push_str(&self.format("%a %b %e %T %Y")?),
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('%') => out.push('%'),
+                Some(ch) => {
+                    return Err(format!("%{} is not a valid specifier", ch))
+                },
+                None => return Err(format!("format ends with %")),
+            }
+        }
+        Ok(out)
+    }
+
+    fn parse(text: &str, format: &str) -> Result<Tm,String> {
+        let err = || format!("{:?} does not match format {:?}", text, format);
+
+        let mut tm = Tm::new(1900, 1, 1);
+        let (mut pm, mut epoch, mut yday) = (None, None, None);
+        // `%j` sets the date, unless month or day is also parsed.
+        let mut dated = false;
+        let mut s = Scan::new(text);
+        let format = expand(format);
+        let mut chars = format.chars();
+        while let Some(ch) = chars.next() {
+            if ch.is_whitespace() { s.whitespace(); continue }
+            if ch != '%' {
+                let mut buf = [0; 4];
+                let ok = ch.encode_utf8(&mut buf).bytes().all(|b| s.eat(b));
+                if ok { continue } else { return Err(err()) }
+            }
+            let spec = chars.next().ok_or(format!("format ends with %"))?;
+            let ok = match spec {
+                'Y' => s.signed(1, 4).map(|n| tm.year = n),
+                'y' => s.digits(2, 2).map(|n| {
+                    tm.year = if n < 69 { 2000 + n } else { 1900 + n }
+                }),
+                'm' => s.digits(1, 2).map(|n| {
+                    tm.month = n as u32;
+                    dated = true
+                }),
+                'd' | 'e' => {
+                    s.whitespace();
+                    s.digits(1, 2).map(|n| { tm.day = n as u32; dated = true })
+                },
+                'H' => s.digits(1, 2).map(|n| tm.hour = n as u32),
+                'I' => s.digits(1, 2).map(|n| tm.hour = (n % 12) as u32),
+                'M' => s.digits(1, 2).map(|n| tm.min = n as u32),
+                'S' => s.digits(1, 2).map(|n| tm.sec = n as u32),
+                'p' => s.name(&["AM", "PM"]).map(|i| pm = Some(i == 1)),
+                'j' => s.digits(1, 3).map(|n| yday = Some(n)),
+                // weekday is computed from the date.
+                'a' | 'A' => s.name(&WEEKDAYS).map(|_| ()),
+                'b' | 'B' | 'h' => s.name(&MONTHS).map(|i| {
+                    tm.month = (i + 1) as u32;
+                    dated = true
+                }),
+                's' => s.signed(1, 20).map(|n| epoch = Some(n)),
+                'z' => s.offset().map(|off| tm.offset = off),
+                'Z' => s.name(&["UTC", "GMT", "Z"]).map(|_| ()),
+                'n' | 't' => Some(s.whitespace()),
+                '%' => if s.eat(b'%') { Some(()) } else { None },
+                ch => return Err(format!("%{} is not a valid specifier", ch)),
+            };
+            if ok.is_none() { return Err(err()) }
+        }
+        if !s.is_end() { return Err(err()) }
+
+        if let Some(secs) = epoch { return Ok(Tm::from_nanos(secs * NANOS)) }
+        match pm {
+            Some(true) if tm.hour < 12 => tm.hour += 12,
+            _ => (),
+        }
+        match yday {
+            Some(n) if !dated => {
+                let days = util::days_from_civil(tm.year, 1, 1) + n - 1;
+                match util::civil_from_days(days) {
+                    (year, month, day) if n > 0 && year == tm.year => {
+                        tm.month = month;
+                        tm.day = day;
+                    },
+                    _ => return Err("date is out of range".to_string()),
+                }
+            },
+            _ => (),
+        }
+        tm.validate()?;
+        // broken down time is always in UTC.
+        Ok(Tm::from_nanos(tm.to_nanos()))
+    }
+}
+
+// replace composite specifiers, like `%T`, with their expansion.
+fn expand(format: &str) -> String {
+    let mut out = String::new();
+    let mut chars = format.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '%' { out.push(ch); continue }
+        match chars.next() {
+            Some('T') => out.push_str("%H:%M:%S"),
+            Some('D') => out.push_str("%m/%d/%y"),
+            Some('F') => out.push_str("%Y-%m-%d"),
+            Some('R') => out.push_str("%H:%M"),
+            Some(ch) => { out.push('%'); out.push(ch) },
+            None => out.push('%'),
+        }
+    }
+    out
+}
+
+// cursor over text being parsed.
+struct Scan<'a> {
+    text: &'a str,
+    off: usize,
+}
+
+impl<'a> Scan<'a> {
+    fn new(text: &'a str) -> Scan<'a> {
+        Scan{text, off: 0}
+    }
+
+    fn is_end(&self) -> bool {
+        self.off >= self.text.len()
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.off).cloned()
+    }
+
+    fn eat(&mut self, b: u8) -> bool {
+        if self.peek() == Some(b) { self.off += 1; true } else { false }
+    }
+
+    fn expect(&mut self, b: u8) -> Option<()> {
+        if self.eat(b) { Some(()) } else { None }
+    }
+
+    fn whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') = self.peek() {
+            self.off += 1
+        }
+    }
+
+    // atleast `min` and atmost `max` decimal digits.
+    fn digits(&mut self, min: usize, max: usize) -> Option<i128> {
+        let start = self.off;
+        while self.off - start < max {
+            match self.peek() {
+                Some(b'0'..=b'9') => self.off += 1,
+                _ => break,
+            }
+        }
+        if self.off - start < min {
+            self.off = start;
+            return None
+        }
+        self.text[start..self.off].parse().ok()
+    }
+
+    fn signed(&mut self, min: usize, max: usize) -> Option<i128> {
+        let neg = if self.eat(b'-') { true } else { self.eat(b'+'); false };
+        self.digits(min, max).map(|n| if neg { -n } else { n })
+    }
+
+    // fraction of seconds as nanoseconds, digits beyond nanoseconds are
+    // dropped.
+    fn fraction(&mut self) -> Option<u32> {
+        let start = self.off;
+        self.digits(1, usize::max_value())?;
+        let digits = &self.text[start..self.off];
+        let digits = format!("{:0<9}", &digits[..digits.len().min(9)]);
+        digits.parse().ok()
+    }
+
+    // `Z` or `+hh`, `+hhmm`, `+hh:mm` as seconds east of UTC.
+    fn offset(&mut self) -> Option<i128> {
+        if self.eat(b'Z') || self.eat(b'z') { return Some(0) }
+        let sign = match self.peek() {
+            Some(b'+') => 1,
+            Some(b'-') => -1,
+            _ => return None,
+        };
+        self.off += 1;
+        let hours = self.digits(2, 2)?;
+        self.eat(b':');
+        let mins = self.digits(2, 2).unwrap_or(0);
+        Some(sign * (hours * 3600 + mins * 60))
+    }
+
+    // case insensitive match of one of the names, or its first three
+    // letters, returning its index.
+    fn name(&mut self, names: &[&str]) -> Option<usize> {
+        let rest = self.text[self.off..].to_ascii_lowercase();
+        for (i, name) in names.iter().enumerate() {
+            let name = name.to_ascii_lowercase();
+            if rest.starts_with(&name) {
+                self.off += name.len();
+                return Some(i)
+            }
+        }
+        for (i, name) in names.iter().enumerate() {
+            let abbrev = name[..name.len().min(3)].to_ascii_lowercase();
+            if rest.starts_with(&abbrev) {
+                self.off += abbrev.len();
+                return Some(i)
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use json::Json;
+
+    #[test]
+    fn test_iso8601() {
+        assert_eq!(Ok(0), iso8601("1970-01-01"));
+        let secs = 1425599507 * NANOS;
+        assert_eq!(Ok(secs), iso8601("2015-03-05T23:51:47Z"));
+        assert_eq!(Ok(secs), iso8601("2015-03-06T05:21:47+05:30"));
+        assert_eq!(Ok(secs), iso8601("2015-03-05 18:51:47-0500"));
+        assert_eq!(Ok(secs + 120_000_000), iso8601("2015-03-05T23:51:47.12Z"));
+        assert_eq!(Ok(-86400 * NANOS), iso8601("1969-12-31T00:00"));
+        assert!(iso8601("2015-02-29").is_err());
+        assert!(iso8601("2015-03-05T23:51:47X").is_err());
+        assert!(iso8601("20150305").is_err());
+    }
+
+    #[test]
+    fn test_tm() {
+        let doc: Json = gmtime(Json::Integer(1425599507)).unwrap();
+        assert_eq!("[2015,2,5,23,51,47,4,63]", format!("{}", doc));
+        let secs: Json = mktime(doc.clone()).unwrap();
+        assert_eq!(Json::Integer(1425599507), secs);
+        let fmt = Json::from("%A, %B %e %Y %I:%M %p (%j)".to_string());
+        let text: Json = strftime(doc, fmt.clone()).unwrap();
+        let refs = r#""Thursday, March  5 2015 11:51 PM (064)""#;
+        assert_eq!(refs, format!("{}", text));
+        let out: Json = strptime(text, fmt).unwrap();
+        assert_eq!("[2015,2,5,23,51,0,4,63]", format!("{}", out));
+
+        // day of year sets the date, unless month or day is given.
+        let strp = |text: &str, fmt: &str| {
+            let (text, fmt) = (text.to_string(), fmt.to_string());
+            strptime(Json::from(text), Json::from(fmt))
+                .map(|doc: Json| format!("{}", doc))
+        };
+        let out = strp("2015 063", "%Y %j");
+        assert_eq!(Ok("[2015,2,4,0,0,0,3,62]".to_string()), out);
+        let out = strp("2016 366", "%Y %j");
+        assert_eq!(Ok("[2016,11,31,0,0,0,6,365]".to_string()), out);
+        let out = strp("2015-03-05 001", "%Y-%m-%d %j");
+        assert_eq!(Ok("[2015,2,5,0,0,0,4,63]".to_string()), out);
+        assert!(strp("2015 366", "%Y %j").is_err());
+        assert!(strp("2015 000", "%Y %j").is_err());
+
+        let days: Vec<i128> = vec![-719468, -1, 0, 59, 10957, 2932896];
+        for days in days.into_iter() {
+            let (y, m, d) = util::civil_from_days(days);
+            assert_eq!(days, util::days_from_civil(y, m, d));
+        }
+    }
+}
@@ -0,0 +1,185 @@
+// Type builtins, like `type`, `isnan`, `tojson`, and the selectors like
+// `arrays`, `scalars` that pass through only the inputs of some kind.
+
+use std::f64;
+
+use db::{Document, Doctype};
+use json::{self, Json};
+use prop::Property;
+
+/// Name of the kind of document, as in `type`.
+pub fn type_of<D>(doc: D) -> Result<D,String> where D: Document {
+    Ok(From::from(doc.doctype().name().to_string()))
+}
+
+pub fn not<D>(doc: D) -> Result<D,String> where D: Document {
+    let out = match doc.doctype() {
+        Doctype::Null => true,
+        Doctype::Bool => !doc.boolean().unwrap(),
+        _ => false,
+    };
+    Ok(From::from(out))
+}
+
+pub fn isinfinite<D>(doc: D) -> Result<D,String> where D: Document {
+    Ok(From::from(number("isinfinite", doc)?.is_infinite()))
+}
+
+pub fn isnan<D>(doc: D) -> Result<D,String> where D: Document {
+    Ok(From::from(number("isnan", doc)?.is_nan()))
+}
+
+pub fn isnormal<D>(doc: D) -> Result<D,String> where D: Document {
+    Ok(From::from(number("isnormal", doc)?.is_normal()))
+}
+
+pub fn infinite<D>(_doc: D) -> Result<D,String> where D: Document {
+    Ok(From::from(f64::INFINITY))
+}
+
+pub fn nan<D>(_doc: D) -> Result<D,String> where D: Document {
+    Ok(From::from(f64::NAN))
+}
+
+pub fn tojson<D>(doc: D) -> Result<D,String> where D: Document {
+    Ok(From::from(json_text(doc)))
+}
+
+/// JSON text of the document, NaN and infinities are written as null.
+pub fn json_text<D>(doc: D) -> String where D: Document {
+    to_json(doc).to_string()
+}
+
+pub fn fromjson<D>(doc: D) -> Result<D,String> where D: Document {
+    let dt = doc.doctype();
+    let text = doc.string()
+        .ok_or(format!("{:?} cannot be parsed, not a string", dt))?;
+    match json::parse_complete(&text) {
+        Ok(value) => Ok(from_json(value)),
+        Err(err) => Err(format!("cannot parse {:?} as JSON: {}", text, err)),
+    }
+}
+
+/// Predicate for the selector `name`, like `arrays`, `empty` selects
+/// nothing.
+pub fn selector<D>(name: &str) -> Option<fn(&D) -> bool> where D: Document {
+    let pred: fn(&D) -> bool = match name {
+        "arrays" => |doc| doc.doctype() == Doctype::Array,
+        "objects" => |doc| doc.doctype() == Doctype::Object,
+        "iterables" => |doc| match doc.doctype() {
+            Doctype::Array | Doctype::Object => true,
+            _ => false,
+        },
+        "booleans" => |doc| doc.doctype() == Doctype::Bool,
+        "numbers" => |doc| match doc.doctype() {
+            Doctype::Integer | Doctype::Float => true,
+            _ => false,
+        },
+        "strings" => |doc| doc.doctype() == Doctype::String,
+        "nulls" => |doc| doc.doctype() == Doctype::Null,
+        "values" => |doc| doc.doctype() != Doctype::Null,
+        "scalars" => |doc| match doc.doctype() {
+            Doctype::Array | Doctype::Object => false,
+            _ => true,
+        },
+        "empty" => |_| false,
+        _ => return None,
+    };
+    Some(pred)
+}
+
+fn number<D>(name: &str, doc: D) -> Result<f64,String> where D: Document {
+    let dt = doc.doctype();
+    match dt {
+        Doctype::Integer => Ok(doc.integer().unwrap() as f64),
+        Doctype::Float => Ok(doc.float().unwrap()),
+        _ => Err(format!("{}() cannot be applied to {:?}", name, dt)),
+    }
+}
+
+fn from_json<D>(value: Json) -> D where D: Document {
+    match value {
+        Json::Null => D::null(),
+        Json::Bool(val) => From::from(val),
+        Json::Integer(val) => From::from(val),
+        Json::Float(val) => From::from(val),
+        Json::String(val) => From::from(val),
+        Json::Array(vals) => {
+            let vals: Vec<D> = vals.into_iter().map(from_json).collect();
+            From::from(vals)
+        },
+        Json::Object(props) => {
+            let props: Vec<Property<D>> = props.into_iter()
+                .map(|prop| {
+                    let key = prop.key_ref().clone();
+                    Property::new(key, from_json(prop.value()))
+                })
+                .collect();
+            From::from(props)
+        },
+        value => From::from(format!("{}", value)),
+    }
+}
+
+fn to_json<D>(doc: D) -> Json where D: Document {
+    match doc.doctype() {
+        Doctype::Null => Json::Null,
+        Doctype::Bool => Json::Bool(doc.boolean().unwrap()),
+        Doctype::Integer => Json::Integer(doc.integer().unwrap()),
+        Doctype::Float => match doc.float().unwrap() {
+            val if val.is_finite() => Json::Float(val),
+            _ => Json::Null,
+        },
+        Doctype::String => Json::String(doc.string().unwrap()),
+        Doctype::Array => {
+            Json::Array(doc.array().unwrap().into_iter().map(to_json).collect())
+        },
+        Doctype::Object => {
+            let props: Vec<Property<Json>> = doc.object().unwrap().into_iter()
+                .map(|prop| {
+                    let key = prop.key_ref().clone();
+                    Property::new(key, to_json(prop.value()))
+                })
+                .collect();
+            Json::Object(props)
+        },
+        Doctype::Bytes => Json::Bytes(doc.bytes().unwrap()),
+        Doctype::Timestamp => Json::Timestamp(doc.timestamp().unwrap()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fromjson() {
+        let text = r#"{"b":[1,2.5,null],"a":"x"}"#;
+        let doc: Json = fromjson(Json::from(text.to_string())).unwrap();
+        assert_eq!(r#"{"a":"x","b":[1,2.5e0,null]}"#, format!("{}", doc));
+        let out: Json = tojson(doc).unwrap();
+        let refs = r#""{\"a\":\"x\",\"b\":[1,2.5e0,null]}""#;
+        assert_eq!(refs, format!("{}", out));
+        assert!(fromjson(Json::Integer(1)).is_err());
+        assert!(fromjson(Json::from("nope".to_string())).is_err());
+        // truncated input and text after the value are errors.
+        let err = fromjson(Json::from("[1,".to_string())).unwrap_err();
+        assert!(err.starts_with(r#"cannot parse "[1," as JSON"#), "{}", err);
+        let err = fromjson(Json::from("1 2".to_string())).unwrap_err();
+        assert!(err.contains("unexpected text after value"), "{}", err);
+        let out: Json = fromjson(Json::from(" [1] ".to_string())).unwrap();
+        assert_eq!("[1]", format!("{}", out));
+    }
+
+    #[test]
+    fn test_tojson() {
+        let doc = Json::Array(vec![
+            Json::Float(f64::NAN), Json::Float(f64::INFINITY),
+            Json::Float(-f64::INFINITY), Json::Float(1.5),
+        ]);
+        let out: Json = tojson(doc).unwrap();
+        assert_eq!(r#""[null,null,null,1.5e0]""#, format!("{}", out));
+        let out: Json = tojson(Json::from("a\"b".to_string())).unwrap();
+        assert_eq!(r#""\"a\\\"b\"""#, format!("{}", out));
+    }
+}
@@ -0,0 +1,206 @@
+// Collation for documents, total order used for sorting and grouping:
+//
+//   null < false < true < numbers < strings < arrays < objects
+//
+// numbers compare by value irrespective of integer or float, strings
+// compare by unicode codepoints, arrays compare item by item. Objects
+// compare their sorted keys first, as arrays, and then the values in the
+// order of the keys. Binary kinds, bytes and timestamp, sort after all
+// JSON kinds.
+
+use std::cmp::Ordering;
+
+use db::{Document, Doctype};
+
+/// Compare `a` and `b` as per the collation order.
+pub fn cmp<D>(a: &D, b: &D) -> Ordering where D: Document {
+    let (ra, rb) = (rank(a), rank(b));
+    if ra != rb { return ra.cmp(&rb) }
+
+    match a.doctype() {
+        Doctype::Null => Ordering::Equal,
+        Doctype::Bool => Ordering::Equal, // rank differ for false/true
+        Doctype::Integer | Doctype::Float => cmp_numbers(a, b),
+        Doctype::String => a.string_ref().cmp(&b.string_ref()),
+        Doctype::Array => {
+            let (x, y) = (a.array_ref().unwrap(), b.array_ref().unwrap());
+            cmp_items(x.iter(), y.iter())
+        },
+        Doctype::Object => {
+            let (mut x, mut y) = (props(a), props(b));
+            x.sort_by(|p, q| p.0.cmp(q.0));
+            y.sort_by(|p, q| p.0.cmp(q.0));
+            match x.iter().map(|p| p.0).cmp(y.iter().map(|p| p.0)) {
+                Ordering::Equal => (),
+                ord => return ord,
+            }
+            cmp_items(x.iter().map(|p| p.1), y.iter().map(|p| p.1))
+        },
+        Doctype::Bytes => a.bytes_ref().cmp(&b.bytes_ref()),
+        Doctype::Timestamp => {
+            a.clone().timestamp().cmp(&b.clone().timestamp())
+        },
+    }
+}
+
+/// Whether `a` and `b` collate equal.
+pub fn equal<D>(a: &D, b: &D) -> bool where D: Document {
+    cmp(a, b) == Ordering::Equal
+}
+
+/// Encoding of `doc` for hashing, documents that collate equal have the
+/// same encoding, like `1` and `1.0`, or objects with the same properties
+/// in different order.
+pub fn encode<D>(doc: &D) -> String where D: Document {
+    let mut out = String::new();
+    do_encode(doc, &mut out);
+    out
+}
+
+/// Object key for `doc`, as in `INDEX`. Strings are the key as is, other
+/// kinds are keyed by their encoding.
+pub fn key<D>(doc: &D) -> String where D: Document {
+    match doc.string_ref() {
+        Some(s) => s.clone(),
+        None => encode(doc),
+    }
+}
+
+/// Text of a number, as in `tostring` and the object keys of `INDEX`.
+/// Floats with integral values are written as integers, like `1` for
+/// `1.0`.
+pub fn number<D>(doc: &D) -> String where D: Document {
+    if let Some(x) = doc.clone().integer() { return x.to_string() }
+    let x = doc.clone().float().unwrap();
+    // i128 range is roughly +/- 1.7e38.
+    if x.fract() == 0.0 && x.abs() < 1.7e38 {
+        (x as i128).to_string()
+    } else if x.is_nan() {
+        "nan".to_string()
+    } else {
+        x.to_string()
+    }
+}
+
+fn do_encode<D>(doc: &D, out: &mut String) where D: Document {
+    match doc.doctype() {
+        Doctype::Integer | Doctype::Float => out.push_str(&number(doc)),
+        Doctype::Array => {
+            out.push('[');
+            for (i, item) in doc.array_ref().unwrap().iter().enumerate() {
+                if i > 0 { out.push(',') }
+                do_encode(item, out);
+            }
+            out.push(']');
+        },
+        Doctype::Object => {
+            let mut props = props(doc);
+            props.sort_by(|p, q| p.0.cmp(q.0));
+            out.push('{');
+            for (i, (key, value)) in props.into_iter().enumerate() {
+                if i > 0 { out.push(',') }
+                out.push_str(&format!("{:?}:", key));
+                do_encode(value, out);
+            }
+            out.push('}');
+        },
+        _ => out.push_str(&format!("{:?}", doc)),
+    }
+}
+
+fn rank<D>(doc: &D) -> u8 where D: Document {
+    match doc.doctype() {
+        Doctype::Null => 0,
+        Doctype::Bool if doc.clone().boolean().unwrap() => 2,
+        Doctype::Bool => 1,
+        Doctype::Integer | Doctype::Float => 3,
+        Doctype::String => 4,
+        Doctype::Array => 5,
+        Doctype::Object => 6,
+        Doctype::Bytes => 7,
+        Doctype::Timestamp => 8,
+    }
+}
+
+// NaN sorts before all other numbers.
+fn cmp_numbers<D>(a: &D, b: &D) -> Ordering where D: Document {
+    match (a.clone().integer(), b.clone().integer()) {
+        (Some(x), Some(y)) => return x.cmp(&y),
+        _ => (),
+    }
+    let x = a.clone().integer().map(|x| x as f64)
+        .or(a.clone().float()).unwrap();
+    let y = b.clone().integer().map(|y| y as f64)
+        .or(b.clone().float()).unwrap();
+    match (x.is_nan(), y.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Less,
+        (false, true) => Ordering::Greater,
+        (false, false) => x.partial_cmp(&y).unwrap(),
+    }
+}
+
+fn cmp_items<'a,D,I,J>(mut x: I, mut y: J) -> Ordering
+    where D: 'a + Document, I: Iterator<Item=&'a D>, J: Iterator<Item=&'a D>
+{
+    loop {
+        match (x.next(), y.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(p), Some(q)) => match cmp(p, q) {
+                Ordering::Equal => (),
+                ord => return ord,
+            },
+        }
+    }
+}
+
+fn props<D>(doc: &D) -> Vec<(&String, &D)> where D: Document {
+    doc.object_ref().unwrap().iter()
+        .map(|prop| (prop.key_ref(), prop.value_ref()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use json::Json;
+
+    #[test]
+    fn test_collate() {
+        let texts = [
+            "null", "false", "true", "-1", "1.5e0", "2", "10", r#""""#,
+            r#""a""#, r#""ab""#, r#""b""#, "[]", "[1]", "[1,2]", "[2]",
+            "{}", r#"{"a":2}"#, r#"{"a":1,"b":1}"#, r#"{"b":0}"#,
+        ];
+        let docs: Vec<Json> = texts.iter()
+            .map(|t| t.parse().unwrap())
+            .collect();
+        for (i, a) in docs.iter().enumerate() {
+            for (j, b) in docs.iter().enumerate() {
+                assert_eq!(i.cmp(&j), cmp(a, b), "{} {}", a, b);
+            }
+        }
+        assert!(equal(&Json::Integer(1), &Json::Float(1.0)));
+        let nan = Json::Float(::std::f64::NAN);
+        assert_eq!(Ordering::Less, cmp(&nan, &docs[3]));
+    }
+
+    #[test]
+    fn test_encode() {
+        let texts = [
+            ("1", "1.0e0"),
+            (r#"{"a":[1],"b":null}"#, r#"{"b":null,"a":[1e0]}"#),
+        ];
+        for (a, b) in texts.iter() {
+            let (a, b): (Json, Json) = (a.parse().unwrap(), b.parse().unwrap());
+            assert_eq!(encode(&a), encode(&b));
+        }
+        let (a, b) = (Json::Integer(1), Json::from("1".to_string()));
+        assert_ne!(encode(&a), encode(&b));
+        assert_eq!("1", key(&a));
+        assert_eq!("1", key(&b));
+        assert_eq!("1.5", encode(&Json::Float(1.5)));
+    }
+}
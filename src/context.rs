@@ -1,27 +1,162 @@
-use std::collections::HashMap;
+use std::rc::Rc;
+use std::cell::RefCell;
 
 use db::Document;
+use query::Thunk;
 
+/// Current value of a bound variable. Shared between the stage that binds
+/// the variable and all the stages that refer to it.
+pub type Slot<D> = Rc<RefCell<D>>;
+
+/// Lexical environment, threaded through the thunks while a query is
+/// prepared. Binding a name returns a new context and leaves the parent
+/// untouched, so a binding is only visible inside its own body.
+#[derive(Clone)]
+pub struct Context<'a,D> where D: Document {
+    scope: Option<Rc<Scope<'a,D>>>,
+}
+
+struct Scope<'a,D> where D: Document {
+    name: String,
+    binding: Binding<'a,D>,
+    parent: Option<Rc<Scope<'a,D>>>,
+}
+
+enum Binding<'a,D> where D: Document {
+    Var(Slot<D>),
+    Func(Func<'a,D>),
+}
+
+/// Function visible in a context, either defined with `def` or passed as
+/// a filter argument to another function.
 #[derive(Clone)]
-pub struct Context {
-    namespace: HashMap<String, Box<Document>>,
+pub struct Func<'a,D> where D: Document {
+    pub name: &'a str,
+    pub params: &'a [String],
+    pub body: &'a Thunk,
+    // context at the point of definition, for a filter argument this is
+    // the context of the caller.
+    env: Context<'a,D>,
+    // filter arguments are not visible inside their own body.
+    recursive: bool,
 }
 
-impl Context {
-    pub fn new() -> Context {
-        let namespace = HashMap::with_capacity(64);
-        Context{namespace}
+impl<'a,D> Context<'a,D> where D: Document {
+    pub fn new() -> Context<'a,D> {
+        Context{scope: None}
+    }
+
+    /// Bind `name` to a new slot, shadowing outer bindings of same name.
+    pub fn bind(&self, name: &str) -> (Context<'a,D>, Slot<D>) {
+        let slot = Rc::new(RefCell::new(D::null()));
+        let ctxt = self.push(name, Binding::Var(Rc::clone(&slot)));
+        (ctxt, slot)
+    }
+
+    /// Define function `name`, visible to the returned context and,
+    /// for recursion, to its own body.
+    pub fn define(&self, name: &'a str, params: &'a [String], body: &'a Thunk)
+        -> Context<'a,D>
+    {
+        let env = self.clone();
+        let func = Func{name, params, body, env, recursive: true};
+        self.push(name, Binding::Func(func))
     }
 
-    pub fn get(&self, name: &str) -> Option<Box<Document>> {
-        self.namespace.get(name).cloned()
+    /// Lookup the innermost variable binding for `name`.
+    pub fn get(&self, name: &str) -> Option<Slot<D>> {
+        let mut scope = self.scope.as_ref();
+        while let Some(s) = scope {
+            match &s.binding {
+                Binding::Var(slot) if s.name == name => {
+                    return Some(Rc::clone(slot))
+                },
+                _ => (),
+            }
+            scope = s.parent.as_ref();
+        }
+        None
     }
 
-    pub fn get_mut(&mut self, name: &str) -> Option<&mut D> {
-        self.namespace.get_mut(name)
+    /// Lookup the innermost function `name` taking `arity` arguments.
+    pub fn get_func(&self, name: &str, arity: usize) -> Option<Func<'a,D>> {
+        let mut scope = self.scope.as_ref();
+        while let Some(s) = scope {
+            match &s.binding {
+                Binding::Func(f) if s.name == name => {
+                    if f.params.len() == arity { return Some(f.clone()) }
+                },
+                _ => (),
+            }
+            scope = s.parent.as_ref();
+        }
+        None
     }
 
-    pub fn set(&mut self, name: &str, value: D) {
-        self.namespace.insert(name.to_string(), value);
+    fn push(&self, name: &str, binding: Binding<'a,D>) -> Context<'a,D> {
+        let name = name.to_string();
+        let scope = Scope{name, binding, parent: self.scope.clone()};
+        Context{scope: Some(Rc::new(scope))}
+    }
+}
+
+impl<'a,D> Func<'a,D> where D: Document {
+    /// Context for evaluating the function's body, with `args`, evaluated
+    /// in the `caller` context, bound to the function's parameters.
+    pub fn call_env(&self, args: &'a [Thunk], caller: &Context<'a,D>)
+        -> Context<'a,D>
+    {
+        let mut env = if self.recursive {
+            self.env.define(self.name, self.params, self.body)
+        } else {
+            self.env.clone()
+        };
+        for (param, arg) in self.params.iter().zip(args.iter()) {
+            let func = Func{
+                name: param.as_str(),
+                params: &[],
+                body: arg,
+                env: caller.clone(),
+                recursive: false,
+            };
+            env = env.push(param, Binding::Func(func));
+        }
+        env
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use json::Json;
+
+    #[test]
+    fn test_context_shadow() {
+        let env: Context<Json> = Context::new();
+        assert!(env.get("x").is_none());
+
+        let (outer, x1) = env.bind("x");
+        *x1.borrow_mut() = Json::Integer(1);
+        let (inner, x2) = outer.bind("x");
+        *x2.borrow_mut() = Json::Integer(2);
+
+        assert_eq!(Json::Integer(2), *inner.get("x").unwrap().borrow());
+        assert_eq!(Json::Integer(1), *outer.get("x").unwrap().borrow());
+        assert!(env.get("x").is_none());
+    }
+
+    #[test]
+    fn test_context_func() {
+        let (params, body) = (vec!["f".to_string()], Thunk::Identity);
+        let env: Context<Json> = Context::new();
+        let env = env.define("g", &params, &body);
+        assert!(env.get_func("g", 0).is_none());
+        assert!(env.get("g").is_none());
+
+        let func = env.get_func("g", 1).unwrap();
+        let args = vec![Thunk::Null(false)];
+        let call_env = func.call_env(&args, &env);
+        assert_eq!(0, call_env.get_func("f", 0).unwrap().params.len());
+        assert!(call_env.get_func("g", 1).is_some());
     }
 }
@@ -0,0 +1,441 @@
+// CSV/TSV reader and writer for Json records, refer RFC-4180.
+//
+// Each row is read as a Json::Object keyed by column names, taken
+// either from the header row or supplied explicitly. Unquoted fields
+// are typed as null, bool, integer or float when they look like one,
+// quoted fields are always strings.
+//
+// Writer flattens nested objects into dotted column names, `a.b`,
+// and writes arrays as JSON text.
+
+use std::{result, error, fmt, io, mem};
+use std::io::BufRead;
+
+use db::Value;
+use json::{Json, Property};
+
+
+pub type Result<T> = result::Result<T,Error>;
+
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Parse(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Parse(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+
+pub struct Csvs<R> where R: io::Read {
+    reader: io::BufReader<R>,
+    delimiter: char,
+    columns: Option<Vec<String>>,
+    infer: bool,
+    lineno: usize,
+}
+
+impl<R> Csvs<R> where R: io::Read {
+    /// Comma separated records, with column names from header row.
+    pub fn new(reader: R) -> Csvs<R> {
+        Csvs{
+            reader: io::BufReader::new(reader),
+            delimiter: ',',
+            columns: None,
+            infer: true,
+            lineno: 0,
+        }
+    }
+
+    /// Tab separated records, with column names from header row.
+    pub fn tsv(reader: R) -> Csvs<R> {
+        let mut csvs = Csvs::new(reader);
+        csvs.set_delimiter('\t');
+        csvs
+    }
+
+    pub fn set_delimiter(&mut self, delimiter: char) {
+        self.delimiter = delimiter;
+    }
+
+    /// Use explicit column names, first row shall be treated as record.
+    pub fn set_columns(&mut self, columns: Vec<String>) {
+        self.columns = Some(columns);
+    }
+
+    /// Disable type inference, all fields shall be read as strings.
+    pub fn set_infer(&mut self, infer: bool) {
+        self.infer = infer;
+    }
+
+    fn read_record(&mut self) -> Result<Option<Vec<(String, bool)>>> {
+        let mut text = String::new();
+        loop {
+            let n = self.reader.read_line(&mut text)?;
+            if n == 0 && text.len() == 0 { return Ok(None) }
+            self.lineno += 1;
+            // blank lines, like the ones trailing the records, are skipped.
+            if text.trim_end_matches(&['\r', '\n'][..]).is_empty() {
+                text.clear();
+                continue
+            }
+            // a record is complete when quotes are balanced.
+            if n == 0 || text.matches('"').count() % 2 == 0 { break }
+        }
+        if text.ends_with('\n') { text.pop(); }
+        if text.ends_with('\r') { text.pop(); }
+        Ok(Some(parse_record(&text, self.delimiter, self.lineno)?))
+    }
+
+    fn to_value(&self, field: String, quoted: bool) -> Json {
+        if quoted || !self.infer { return Json::String(field) }
+        infer_value(field)
+    }
+}
+
+impl<R> Iterator for Csvs<R> where R: io::Read {
+    type Item=Result<Json>;
+
+    fn next(&mut self) -> Option<Result<Json>> {
+        if self.columns.is_none() {
+            match self.read_record() {
+                Ok(Some(fields)) => {
+                    let cols = fields.into_iter().map(|(f, _)| f).collect();
+                    self.columns = Some(cols);
+                },
+                Ok(None) => return None,
+                Err(err) => return Some(Err(err)),
+            }
+        }
+
+        let fields = match self.read_record() {
+            Ok(Some(fields)) => fields,
+            Ok(None) => return None,
+            Err(err) => return Some(Err(err)),
+        };
+        let n = self.columns.as_ref().unwrap().len();
+        if fields.len() > n {
+            let err = format!(
+                "line {} has {} fields, expected {}", self.lineno, fields.len(), n
+            );
+            return Some(Err(Error::Parse(err)))
+        }
+
+        let mut props = Vec::with_capacity(n);
+        let mut fields = fields.into_iter();
+        for i in 0..n {
+            let value = match fields.next() {
+                Some((field, quoted)) => self.to_value(field, quoted),
+                None => Json::Null,
+            };
+            let key = self.columns.as_ref().unwrap()[i].clone();
+            props.push(Property::new(key, value));
+        }
+        Some(Ok(From::from(props)))
+    }
+}
+
+
+pub struct CsvWriter<W> where W: io::Write {
+    writer: W,
+    delimiter: char,
+    columns: Option<Vec<String>>,
+    header: bool,
+}
+
+impl<W> CsvWriter<W> where W: io::Write {
+    /// Comma separated records, with header row.
+    pub fn new(writer: W) -> CsvWriter<W> {
+        CsvWriter{ writer, delimiter: ',', columns: None, header: true }
+    }
+
+    /// Tab separated records, with header row.
+    pub fn tsv(writer: W) -> CsvWriter<W> {
+        let mut w = CsvWriter::new(writer);
+        w.set_delimiter('\t');
+        w
+    }
+
+    pub fn set_delimiter(&mut self, delimiter: char) {
+        self.delimiter = delimiter;
+    }
+
+    /// Select columns, using dotted names for nested fields. By default
+    /// columns are picked from the first record written.
+    pub fn set_columns(&mut self, columns: Vec<String>) {
+        self.columns = Some(columns);
+    }
+
+    pub fn set_header(&mut self, header: bool) {
+        self.header = header;
+    }
+
+    pub fn write(&mut self, doc: &Json) -> Result<()> {
+        let mut fields = Vec::new();
+        flatten("", doc, &mut fields);
+
+        if self.columns.is_none() {
+            let cols = fields.iter().map(|(k, _)| k.clone()).collect();
+            self.columns = Some(cols);
+        }
+        if self.header {
+            self.header = false;
+            let cols: Vec<String> = self.columns.as_ref().unwrap().clone();
+            self.write_row(cols.into_iter())?;
+        }
+
+        let values: Vec<String> = {
+            let cols = self.columns.as_ref().unwrap();
+            cols.iter().map(|col| {
+                fields.iter()
+                    .find(|(k, _)| k == col)
+                    .map(|(_, v)| field_text(v))
+                    .unwrap_or(String::new())
+            }).collect()
+        };
+        self.write_row(values.into_iter())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write_row<I>(&mut self, fields: I) -> Result<()>
+        where I: Iterator<Item=String>
+    {
+        let mut line = String::new();
+        for (i, field) in fields.enumerate() {
+            if i > 0 { line.push(self.delimiter) }
+            line.push_str(&quote_field(field, self.delimiter));
+        }
+        line.push_str("\r\n");
+        self.writer.write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+
+fn parse_record(text: &str, delimiter: char, lineno: usize)
+    -> Result<Vec<(String, bool)>>
+{
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let (mut quoted, mut inquote) = (false, false);
+    let mut chars = text.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        if inquote {
+            match ch {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                },
+                '"' => inquote = false,
+                ch => field.push(ch),
+            }
+        } else if ch == delimiter {
+            fields.push((mem::replace(&mut field, String::new()), quoted));
+            quoted = false;
+        } else if ch == '"' && field.len() == 0 && !quoted {
+            quoted = true;
+            inquote = true;
+        } else if quoted {
+            let err = format!("line {} unexpected {:?} after quote", lineno, ch);
+            return Err(Error::Parse(err))
+        } else {
+            field.push(ch);
+        }
+    }
+    if inquote {
+        return Err(Error::Parse(format!("line {} unterminated quote", lineno)))
+    }
+    fields.push((field, quoted));
+    Ok(fields)
+}
+
+fn infer_value(field: String) -> Json {
+    match field.as_str() {
+        "" | "null" => return Json::Null,
+        "true" => return Json::Bool(true),
+        "false" => return Json::Bool(false),
+        _ => (),
+    }
+    let numeric = field.bytes().all(|b| match b {
+        b'0'..=b'9' | b'+' | b'-' | b'.' | b'e' | b'E' => true,
+        _ => false,
+    });
+    if numeric {
+        if let Ok(n) = field.parse::<i128>() {
+            return Json::Integer(n)
+        } else if let Ok(f) = field.parse::<f64>() {
+            return Json::Float(f)
+        }
+    }
+    Json::String(field)
+}
+
+fn flatten(prefix: &str, doc: &Json, fields: &mut Vec<(String, Json)>) {
+    match doc {
+        Json::Object(props) => {
+            for prop in props.iter() {
+                let key = if prefix.len() == 0 {
+                    prop.key_ref().clone()
+                } else {
+                    format!("{}.{}", prefix, prop.key_ref())
+                };
+                flatten(&key, prop.value_ref(), fields);
+            }
+        },
+        doc => fields.push((prefix.to_string(), doc.clone())),
+    }
+}
+
+fn field_text(doc: &Json) -> String {
+    match doc {
+        Json::Null => String::new(),
+        Json::String(s) => s.clone(),
+        Json::Float(f) => format!("{}", f),
+        Json::Bytes(_) | Json::Timestamp(_) => {
+            doc.clone().project().string().unwrap()
+        },
+        doc => format!("{}", doc),
+    }
+}
+
+fn quote_field(field: String, delimiter: char) -> String {
+    let quote = field.chars().any(|ch| {
+        ch == delimiter || ch == '"' || ch == '\n' || ch == '\r'
+    });
+    if quote {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str;
+    use InputMem;
+
+    #[test]
+    fn test_csv_reader() {
+        let text = "name,age,score,active,note\r\n".to_string() +
+            "alice,30,1.5,true,\"hello, \"\"world\"\"\"\r\n" +
+            "bob,,null,false,\"multi\nline\"\r\n" +
+            "\"10\",x1,-2e1,False\n";
+        let docs: Vec<Json> = Csvs::new(text.as_bytes())
+            .map(|res| res.unwrap())
+            .collect();
+        assert_eq!(docs.len(), 3);
+
+        let mut refval = r#"{"active":true,"age":30,"name":"alice","#.to_string();
+        refval += r#""note":"hello, \"world\"","score":1.5e0}"#;
+        assert_eq!(refval, format!("{}", docs[0]));
+        let mut refval = r#"{"active":false,"age":null,"name":"bob","#.to_string();
+        refval += r#""note":"multi\nline","score":null}"#;
+        assert_eq!(refval, format!("{}", docs[1]));
+        let mut refval = r#"{"active":"False","age":"x1","name":"10","#.to_string();
+        refval += r#""note":null,"score":-2e1}"#;
+        assert_eq!(refval, format!("{}", docs[2]));
+
+        let input: InputMem<Json> = docs.clone().into_iter().collect();
+        let outs: Vec<Json> = input.repeat().map(|entry| entry.doc).collect();
+        assert_eq!(outs, docs);
+    }
+
+    #[test]
+    fn test_tsv_reader_columns() {
+        let text = "1\ta b\n2\t\"c\"\n";
+        let mut tsvs = Csvs::tsv(text.as_bytes());
+        tsvs.set_columns(vec!["id".to_string(), "tag".to_string()]);
+        tsvs.set_infer(false);
+        let docs: Vec<String> = tsvs
+            .map(|res| format!("{}", res.unwrap()))
+            .collect();
+        assert_eq!(
+            docs,
+            vec![r#"{"id":"1","tag":"a b"}"#, r#"{"id":"2","tag":"c"}"#]
+        );
+
+        let text = "a,b\n1,2,3\n";
+        let mut csvs = Csvs::new(text.as_bytes());
+        assert!(csvs.next().unwrap().is_err());
+
+        let text = "a,b\n\"1,2\n";
+        let mut csvs = Csvs::new(text.as_bytes());
+        assert!(csvs.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_csv_reader_blank_lines() {
+        let text = "\r\na,b\n1,2\n\n3,\"x\n\ny\"\r\n\r\n\n";
+        let docs: Vec<String> = Csvs::new(text.as_bytes())
+            .map(|res| format!("{}", res.unwrap()))
+            .collect();
+        let refs = vec![r#"{"a":1,"b":2}"#, r#"{"a":3,"b":"x\n\ny"}"#];
+        assert_eq!(refs, docs);
+        // line numbers count the blank lines.
+        let text = "a\n\n1,2\n";
+        match Csvs::new(text.as_bytes()).next() {
+            Some(Err(err)) => {
+                assert_eq!("line 3 has 2 fields, expected 1", err.to_string())
+            },
+            res => panic!("{:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_csv_writer() {
+        let docs: Vec<Json> = vec![
+            r#"{"name":"alice","addr":{"city":"x, y"},"tags":[1,2]}"#,
+            r#"{"name":"bob \"b\"","addr":{"city":null},"age":10}"#,
+        ].into_iter().map(|s| s.parse().unwrap()).collect();
+
+        let mut w = CsvWriter::new(Vec::new());
+        for doc in docs.iter() { w.write(doc).unwrap() }
+        let out = w.into_inner();
+        let mut refval = "addr.city,name,tags\r\n".to_string();
+        refval += "\"x, y\",alice,\"[1,2]\"\r\n";
+        refval += ",\"bob \"\"b\"\"\",\r\n";
+        assert_eq!(refval, str::from_utf8(&out).unwrap());
+
+        let mut w = CsvWriter::tsv(Vec::new());
+        w.set_columns(vec!["age".to_string(), "name".to_string()]);
+        w.set_header(false);
+        for doc in docs.iter() { w.write(doc).unwrap() }
+        let out = w.into_inner();
+        let refval = "\talice\r\n10\t\"bob \"\"b\"\"\"\r\n";
+        assert_eq!(refval, str::from_utf8(&out).unwrap());
+
+        let mut tsvs = Csvs::tsv(&out[..]);
+        tsvs.set_columns(vec!["age".to_string(), "name".to_string()]);
+        let outs: Vec<Json> = tsvs.map(|res| res.unwrap()).collect();
+        assert_eq!(r#"{"age":null,"name":"alice"}"#, format!("{}", outs[0]));
+        assert_eq!(r#"{"age":10,"name":"bob \"b\""}"#, format!("{}", outs[1]));
+    }
+}
@@ -27,6 +27,43 @@ pub enum Doctype {
     String,
     Array,
     Object,
+    /// Optional kind, opaque byte-string. Projected to JSON as base64
+    /// encoded string.
+    Bytes,
+    /// Optional kind, nanoseconds since UNIX epoch. Projected to JSON as
+    /// RFC 3339 string in UTC.
+    Timestamp,
+}
+
+impl Doctype {
+    /// Name of the kind, as used by queries, integers and floats are both
+    /// number.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Doctype::Null => "null",
+            Doctype::Bool => "boolean",
+            Doctype::Integer | Doctype::Float => "number",
+            Doctype::String => "string",
+            Doctype::Array => "array",
+            Doctype::Object => "object",
+            Doctype::Bytes => "bytes",
+            Doctype::Timestamp => "timestamp",
+        }
+    }
+
+    /// Name of the kind as JSON Schema type, bytes and timestamps are
+    /// encoded as strings.
+    pub fn schema_name(&self) -> &'static str {
+        match self {
+            Doctype::Null => "null",
+            Doctype::Bool => "boolean",
+            Doctype::Integer => "integer",
+            Doctype::Float => "number",
+            Doctype::String | Doctype::Bytes | Doctype::Timestamp => "string",
+            Doctype::Array => "array",
+            Doctype::Object => "object",
+        }
+    }
 }
 
 
@@ -97,6 +134,18 @@ pub trait Value {
     fn object_ref(&self) -> Option<&Vec<Property<Self::item>>>;
 
     fn object(self) -> Option<Vec<Property<Self::item>>>;
+
+    fn bytes_ref(&self) -> Option<&Vec<u8>> {
+        None
+    }
+
+    fn bytes(self) -> Option<Vec<u8>> where Self: Sized {
+        None
+    }
+
+    fn timestamp(self) -> Option<i128> where Self: Sized {
+        None
+    }
 }
 
 pub trait Slice {
@@ -28,11 +28,21 @@ impl<D> Entry<D> where D: Document {
     }
 
     pub fn set_error(&mut self, s: String) {
-        self.op.set("errors", <D as From<Vec<D>>>::from(vec![From::from(s)]));
+        self.set_error_value(From::from(s))
+    }
+
+    pub fn set_error_value(&mut self, value: D) {
+        self.op.set("errors", <D as From<Vec<D>>>::from(vec![value]));
     }
 
     pub fn has_error(&self) -> bool {
-        self.op.get_ref("error").unwrap().array_ref().unwrap().len() > 0
+        self.op.get_ref("errors").unwrap().array_ref().unwrap().len() > 0
+    }
+
+    /// Return the first error, if any, carried by this entry.
+    pub fn error_value(&self) -> Option<D> {
+        let errors = self.op.get_ref("errors").unwrap().array_ref().unwrap();
+        errors.first().cloned()
     }
 
     pub fn iter_position(&self) -> IterPosition {
@@ -40,6 +50,10 @@ impl<D> Entry<D> where D: Document {
         From::from(val)
     }
 
+    pub fn set_iter_position(&mut self, pos: IterPosition) {
+        self.op.set("iterpos", <D as From<i128>>::from(From::from(pos)));
+    }
+
     pub fn into<T>(self) -> Entry<T> where T: Document + From<D> {
         Entry{meta:self.meta.into(), op:self.op.into(), doc:From::from(self.doc)}
     }
@@ -0,0 +1,444 @@
+// Schema inference over a stream of documents.
+//
+// Every value in a document is located by its path, `.` for the document
+// itself, `.key` for object properties and `[]` for array items, like
+// `.steps[].run`. Keys that are not identifiers are quoted as in queries,
+// like `."odd key"`. For each path we gather:
+//
+// * count of values seen at that path, per JSON Schema type.
+// * nullability, when null value is seen at that path.
+// * optionality, when property is missing in some of the parent objects.
+// * cardinality of scalar values, exact for few distinct values and
+//   estimated using k-minimum-values sketch beyond that.
+// * first few distinct scalar values as examples.
+
+use std::collections::BTreeMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use db::{Document, Doctype, Input};
+use json::Json;
+use prop::Property;
+
+// number of minimum hash values tracked for cardinality estimate.
+const KMV_SIZE: usize = 256;
+
+const JSON_SCHEMA_DRAFT: &'static str =
+    "https://json-schema.org/draft/2020-12/schema";
+
+
+pub struct Infer<D> where D: Document {
+    documents: usize,
+    examples: usize,
+    paths: BTreeMap<String, PathStat<D>>,
+}
+
+struct PathStat<D> where D: Document {
+    parent: Option<String>,
+    key: Option<String>,     // None for array items.
+    count: usize,
+    types: Vec<(Doctype, usize)>,
+    hashes: Vec<u64>,        // sorted minimum hash values of scalars.
+    examples: Vec<D>,
+}
+
+impl<D> Infer<D> where D: Document {
+    pub fn new() -> Infer<D> {
+        Infer{ documents: 0, examples: 3, paths: BTreeMap::new() }
+    }
+
+    /// Number of example values to collect for each path, default is 3.
+    pub fn set_examples(&mut self, n: usize) -> &mut Self {
+        self.examples = n;
+        self
+    }
+
+    pub fn add(&mut self, doc: &D) {
+        self.documents += 1;
+        self.visit(".".to_string(), None, None, doc);
+    }
+
+    /// Consume documents from query pipeline, entries with errors
+    /// are skipped.
+    pub fn add_input<'a>(&mut self, input: Input<'a,D>) where D: 'a {
+        for entry in input.filter(|entry| !entry.has_error()) {
+            self.add(&entry.doc)
+        }
+    }
+
+    fn visit(
+        &mut self, path: String, parent: Option<&str>, key: Option<&str>,
+        doc: &D)
+    {
+        let doctype = doc.doctype();
+        {
+            let examples = self.examples;
+            let stat = self.paths.entry(path.clone()).or_insert_with(|| {
+                PathStat::new(parent, key)
+            });
+            stat.count += 1;
+            match stat.types.iter().position(|(t, _)| *t == doctype) {
+                Some(i) => stat.types[i].1 += 1,
+                None => stat.types.push((doctype, 1)),
+            }
+            match doctype {
+                Doctype::Array | Doctype::Object => (),
+                _ => stat.observe(doc, examples),
+            }
+        }
+
+        match doctype {
+            Doctype::Array => {
+                let ipath = item_path(&path);
+                for item in doc.array_ref().unwrap().iter() {
+                    self.visit(ipath.clone(), Some(&path), None, item);
+                }
+            },
+            Doctype::Object => {
+                for prop in doc.object_ref().unwrap().iter() {
+                    let (key, value) = (prop.key_ref(), prop.value_ref());
+                    let kpath = key_path(&path, key);
+                    self.visit(kpath, Some(&path), Some(key), value);
+                }
+            },
+            _ => (),
+        }
+    }
+
+    /// Summary of all the paths observed so far, as:
+    ///
+    /// {"documents": <n>, "paths": {<path>: {"count": <n>,
+    ///  "types": {<type>: <n>, ..}, "nullable": <bool>, "optional": <bool>,
+    ///  "distinct": <n>, "estimated": <bool>, "examples": [..]}, ..}}
+    ///
+    /// Types are named as in JSON Schema. "optional" is reported only for
+    /// object properties, "distinct", "estimated" and "examples" only for
+    /// paths with scalar values.
+    pub fn to_report(&self) -> D {
+        let mut paths = Vec::with_capacity(self.paths.len());
+        for (path, stat) in self.paths.iter() {
+            let mut props = vec![new_prop("count", stat.count)];
+
+            let mut types: Vec<(&str, usize)> = vec![];
+            for (t, n) in stat.types.iter() {
+                let name = t.schema_name();
+                match types.iter().position(|(tn, _)| *tn == name) {
+                    Some(i) => types[i].1 += *n,
+                    None => types.push((name, *n)),
+                }
+            }
+            let types: Vec<Property<D>> = types.into_iter()
+                .map(|(t, n)| new_prop(t, n))
+                .collect();
+            props.push(Property::new("types".to_string(), From::from(types)));
+
+            let nullable = stat.has_type(Doctype::Null);
+            props.push(Property::new("nullable".to_string(), From::from(nullable)));
+
+            if stat.key.is_some() {
+                let optional = stat.count < self.objects_at(stat.parent.as_ref());
+                props.push(Property::new("optional".to_string(), From::from(optional)));
+            }
+
+            if stat.hashes.len() > 0 {
+                let (distinct, estimated) = stat.cardinality();
+                props.push(new_prop("distinct", distinct));
+                let estimated = From::from(estimated);
+                props.push(Property::new("estimated".to_string(), estimated));
+                let examples = stat.examples.clone();
+                props.push(Property::new("examples".to_string(), From::from(examples)));
+            }
+            paths.push(Property::new(path.clone(), From::from(props)));
+        }
+
+        From::from(vec![
+            new_prop("documents", self.documents),
+            Property::new("paths".to_string(), From::from(paths)),
+        ])
+    }
+
+    /// Describe the observed documents as JSON Schema draft 2020-12.
+    pub fn to_json_schema(&self) -> D {
+        let mut schema = match self.paths.get(".") {
+            Some(stat) => self.schema_for(".", stat),
+            None => <D as From<Vec<Property<D>>>>::from(vec![]),
+        };
+        schema.set("$schema", From::from(JSON_SCHEMA_DRAFT.to_string()));
+        schema
+    }
+
+    fn schema_for(&self, path: &str, stat: &PathStat<D>) -> D {
+        let mut props = vec![];
+
+        let mut types: Vec<&'static str> = vec![];
+        for (t, _) in stat.types.iter() {
+            let name = match t {
+                Doctype::Integer if stat.has_type(Doctype::Float) => "number",
+                t => t.schema_name(),
+            };
+            if !types.contains(&name) { types.push(name) }
+        }
+        types.sort();
+        let types: D = match types.len() {
+            1 => From::from(types[0].to_string()),
+            _ => {
+                let types = types.into_iter().map(|t| From::from(t.to_string()));
+                From::from(types.collect::<Vec<D>>())
+            },
+        };
+        props.push(Property::new("type".to_string(), types));
+
+        if stat.has_type(Doctype::Bytes) {
+            let value = From::from("base64".to_string());
+            props.push(Property::new("contentEncoding".to_string(), value));
+        }
+        if stat.has_type(Doctype::Timestamp) {
+            let value = From::from("date-time".to_string());
+            props.push(Property::new("format".to_string(), value));
+        }
+
+        if stat.has_type(Doctype::Object) {
+            let objects = self.objects_at(Some(&path.to_string()));
+            let mut properties: Vec<Property<D>> = vec![];
+            let mut required: Vec<D> = vec![];
+            for (cpath, child) in self.children(path) {
+                let key = child.key.clone().unwrap();
+                if child.count >= objects {
+                    required.push(From::from(key.clone()));
+                }
+                properties.push(Property::new(key, self.schema_for(cpath, child)));
+            }
+            props.push(Property::new("properties".to_string(), From::from(properties)));
+            if required.len() > 0 {
+                props.push(Property::new("required".to_string(), From::from(required)));
+            }
+        }
+
+        if stat.has_type(Doctype::Array) {
+            let ipath = item_path(path);
+            if let Some(child) = self.paths.get(&ipath) {
+                let items = self.schema_for(&ipath, child);
+                props.push(Property::new("items".to_string(), items));
+            }
+        }
+
+        From::from(props)
+    }
+
+    fn children<'a>(&'a self, path: &'a str)
+        -> impl Iterator<Item=(&'a str, &'a PathStat<D>)> + 'a
+    {
+        self.paths.iter()
+            .filter(move |(_, stat)| {
+                stat.key.is_some() &&
+                stat.parent.as_ref().map(|p| p.as_str()) == Some(path)
+            })
+            .map(|(cpath, stat)| (cpath.as_str(), stat))
+    }
+
+    // number of objects seen at path.
+    fn objects_at(&self, path: Option<&String>) -> usize {
+        match path.and_then(|path| self.paths.get(path)) {
+            Some(stat) => stat.type_count(Doctype::Object),
+            None => 0,
+        }
+    }
+}
+
+impl<D> Extend<D> for Infer<D> where D: Document {
+    fn extend<I>(&mut self, iter: I) where I: IntoIterator<Item=D> {
+        for doc in iter { self.add(&doc) }
+    }
+}
+
+impl<D> PathStat<D> where D: Document {
+    fn new(parent: Option<&str>, key: Option<&str>) -> PathStat<D> {
+        PathStat{
+            parent: parent.map(|p| p.to_string()),
+            key: key.map(|k| k.to_string()),
+            count: 0,
+            types: vec![],
+            hashes: vec![],
+            examples: vec![],
+        }
+    }
+
+    fn type_count(&self, doctype: Doctype) -> usize {
+        match self.types.iter().find(|(t, _)| *t == doctype) {
+            Some((_, n)) => *n,
+            None => 0,
+        }
+    }
+
+    fn has_type(&self, doctype: Doctype) -> bool {
+        self.type_count(doctype) > 0
+    }
+
+    fn observe(&mut self, doc: &D, examples: usize) {
+        let mut hasher = DefaultHasher::new();
+        format!("{:?}", doc).hash(&mut hasher);
+        let hash = hasher.finish();
+
+        match self.hashes.binary_search(&hash) {
+            Ok(_) => (), // seen before.
+            Err(off) if off < KMV_SIZE => {
+                self.hashes.insert(off, hash);
+                self.hashes.truncate(KMV_SIZE);
+                if self.examples.len() < examples {
+                    self.examples.push(doc.clone());
+                }
+            },
+            Err(_) => (),
+        }
+    }
+
+    // (distinct values, whether the count is an estimate)
+    fn cardinality(&self) -> (usize, bool) {
+        if self.hashes.len() < KMV_SIZE {
+            return (self.hashes.len(), false)
+        }
+        let kth = self.hashes[KMV_SIZE - 1] as f64 / (u64::max_value() as f64);
+        (((KMV_SIZE - 1) as f64 / kth) as usize, true)
+    }
+}
+
+
+fn new_prop<D>(key: &str, n: usize) -> Property<D> where D: Document {
+    Property::new(key.to_string(), From::from(n as i128))
+}
+
+fn item_path(path: &str) -> String {
+    if path == "." { ".[]".to_string() } else { format!("{}[]", path) }
+}
+
+fn key_path(path: &str, key: &str) -> String {
+    let ident = key.len() > 0 &&
+        !key.starts_with(|ch: char| ch.is_ascii_digit()) &&
+        key.chars().all(|ch| ch.is_ascii_alphanumeric() || ch == '_');
+    let key = if ident {
+        key.to_string()
+    } else {
+        Json::String(key.to_string()).to_string()
+    };
+    if path == "." { format!(".{}", key) } else { format!("{}.{}", path, key) }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::{Docindex, Value};
+    use json::{Json, JsonBuf};
+    use input_mem::InputMem;
+
+    fn docs() -> Vec<Json> {
+        let text = r#"
+            {"id": 1, "name": "alpha", "tags": ["x", "y"], "meta": {"ok": true}}
+            {"id": 2, "name": null, "tags": [], "meta": {"ok": false, "score": 1.5}}
+            {"id": 3, "tags": ["x"], "meta": {"ok": true, "score": 2}, "odd key": 0}
+        "#;
+        JsonBuf::iter(text.as_bytes()).collect()
+    }
+
+    #[test]
+    fn test_infer_report() {
+        let mut infer = Infer::new();
+        infer.set_examples(2);
+        infer.extend(docs());
+        let report = infer.to_report();
+
+        assert_eq!(report.get_ref("documents"), Some(&Json::Integer(3)));
+        let paths = report.get_ref("paths").unwrap();
+        let keys: Vec<String> = paths.object_ref().unwrap().iter()
+            .map(|p| p.key_ref().clone())
+            .collect();
+        let refkeys = vec![
+            ".", ".\"odd key\"", ".id", ".meta", ".meta.ok", ".meta.score",
+            ".name", ".tags", ".tags[]",
+        ];
+        assert_eq!(keys, refkeys);
+
+        let refval = r#"{"count":3,"distinct":3,"estimated":false,"#.to_string() +
+            r#""examples":[1,2],"nullable":false,"optional":false,"# +
+            r#""types":{"integer":3}}"#;
+        assert_eq!(format!("{}", paths.get_ref(".id").unwrap()), refval);
+
+        let name = paths.get_ref(".name").unwrap();
+        assert_eq!(name.get_ref("nullable"), Some(&Json::Bool(true)));
+        assert_eq!(name.get_ref("optional"), Some(&Json::Bool(true)));
+        let refval = r#"{"null":1,"string":1}"#;
+        assert_eq!(format!("{}", name.get_ref("types").unwrap()), refval);
+
+        let score = paths.get_ref(".meta.score").unwrap();
+        assert_eq!(score.get_ref("optional"), Some(&Json::Bool(true)));
+        let tags = paths.get_ref(".tags[]").unwrap();
+        assert_eq!(tags.get_ref("count"), Some(&Json::Integer(3)));
+        assert_eq!(tags.get_ref("distinct"), Some(&Json::Integer(2)));
+        assert_eq!(tags.get_ref("optional"), None);
+
+        let mut infer = Infer::new();
+        let doc = r#"{"a\"b": [1.5, 2, "x"], "c\u0001": 0}"#;
+        infer.add(&doc.parse().unwrap());
+        infer.add(&Json::Array(vec![Json::Bytes(vec![1]), Json::Timestamp(0)]));
+        let report = infer.to_report();
+        let paths = report.get_ref("paths").unwrap();
+        let keys: Vec<String> = paths.object_ref().unwrap().iter()
+            .map(|p| p.key_ref().clone())
+            .collect();
+        let refkeys = vec![
+            ".", r#"."a\"b""#, r#"."a\"b"[]"#, r#"."c\u0001""#, ".[]",
+        ];
+        assert_eq!(keys, refkeys);
+        let items = paths.get_ref(r#"."a\"b"[]"#).unwrap();
+        let refval = r#"{"integer":1,"number":1,"string":1}"#;
+        assert_eq!(format!("{}", items.get_ref("types").unwrap()), refval);
+        let items = paths.get_ref(".[]").unwrap();
+        let refval = r#"{"string":2}"#;
+        assert_eq!(format!("{}", items.get_ref("types").unwrap()), refval);
+    }
+
+    #[test]
+    fn test_infer_input() {
+        let mem: InputMem<Json> = docs().into_iter().collect();
+        let mut infer = Infer::new();
+        infer.add_input(mem.repeat());
+        let report = infer.to_report();
+        assert_eq!(report.get_ref("documents"), Some(&Json::Integer(3)));
+    }
+
+    #[test]
+    fn test_infer_json_schema() {
+        let mut infer = Infer::new();
+        infer.extend(docs());
+        let mut refval = r#"{"$schema":"https://json-schema.org/draft/2020-12/schema","#
+            .to_string();
+        refval += r#""properties":{"id":{"type":"integer"},"#;
+        refval += r#""meta":{"properties":{"ok":{"type":"boolean"},"#;
+        refval += r#""score":{"type":"number"}},"required":["ok"],"type":"object"},"#;
+        refval += r#""name":{"type":["null","string"]},"#;
+        refval += r#""odd key":{"type":"integer"},"#;
+        refval += r#""tags":{"items":{"type":"string"},"type":"array"}},"#;
+        refval += r#""required":["id","meta","tags"],"type":"object"}"#;
+        assert_eq!(format!("{}", infer.to_json_schema()), refval);
+
+        let mut infer = Infer::new();
+        infer.add(&Json::Array(vec![Json::Bytes(vec![1]), Json::Timestamp(0)]));
+        let mut refval = r#"{"$schema":"https://json-schema.org/draft/2020-12/schema","#
+            .to_string();
+        refval += r#""items":{"contentEncoding":"base64","format":"date-time","#;
+        refval += r#""type":"string"},"type":"array"}"#;
+        assert_eq!(format!("{}", infer.to_json_schema()), refval);
+    }
+
+    #[test]
+    fn test_infer_cardinality() {
+        let mut infer = Infer::new();
+        infer.extend((0..10000).map(|i| Json::Integer(i % 5000)));
+        let report = infer.to_report();
+        let root = report.get_ref("paths").unwrap().get_ref(".").unwrap();
+        assert_eq!(root.get_ref("estimated"), Some(&Json::Bool(true)));
+        let n = root.get_ref("distinct").unwrap().clone().integer().unwrap();
+        assert!(n > 4000 && n < 6000, "distinct estimate {}", n);
+        assert_eq!(root.get_ref("examples").unwrap().len(), Some(3));
+    }
+}
@@ -1,3 +1,5 @@
+use std::iter::FromIterator;
+
 use db::{Document, Input, Pipeline, Repeater};
 use entry::Entry;
 
@@ -16,6 +18,12 @@ impl<D> InputMem<D> where D: Document {
     }
 }
 
+impl<D> FromIterator<D> for InputMem<D> where D: Document {
+    fn from_iter<I>(iter: I) -> InputMem<D> where I: IntoIterator<Item=D> {
+        InputMem::new(iter.into_iter().collect())
+    }
+}
+
 
 
 struct Iter<'a,D> where D: 'a + Document {
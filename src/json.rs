@@ -333,7 +333,7 @@ fn decode_json_hex_code(chars: &mut CharIndices, lex: &mut Lex)
     let mut n = 0;
     let mut code = 0_u32;
     while let Some((_, ch)) = chars.next() {
-        if (ch as u8) > 128 || HEXNUM[ch as usize] == 20 {
+        if (ch as u32) > 127 || HEXNUM[ch as usize] == 20 {
             let err = format!("invalid string escape code {:?}", ch);
             return Err(Error::Parse(lex.format(&err)))
         }
@@ -370,10 +370,12 @@ pub(super) fn parse_array(text: &str, lex: &mut Lex) -> Result<Json> {
 
     let mut array: Vec<Json> = Vec::new();
     parse_whitespace(text, lex);
+    check_eof(text, lex)?;
     if (&text[lex.off..]).as_bytes()[0] == b',' {
         return Err(Error::Parse(lex.format("expected ','")))
     }
     loop {
+        check_eof(text, lex)?;
         if (&text[lex.off..]).as_bytes()[0] == b']' { // end of array.
             lex.incr_col(1);
             break Ok(Json::Array(array))
@@ -382,6 +384,7 @@ pub(super) fn parse_array(text: &str, lex: &mut Lex) -> Result<Json> {
         array.push(parse_value(text, lex)?);
 
         parse_whitespace(text, lex);
+        check_eof(text, lex)?;
         if (&text[lex.off..]).as_bytes()[0] == b',' { // skip comma
             lex.incr_col(1);
             parse_whitespace(text, lex);
@@ -394,6 +397,7 @@ pub(super) fn parse_object(text: &str, lex: &mut Lex) -> Result<Json> {
 
     let mut m: Vec<Property> = Vec::new();
     parse_whitespace(text, lex);
+    check_eof(text, lex)?;
     if (&text[lex.off..]).as_bytes()[0] == b'}' {
         lex.incr_col(1);
         return Ok(Json::Object(m))
@@ -401,6 +405,7 @@ pub(super) fn parse_object(text: &str, lex: &mut Lex) -> Result<Json> {
     loop {
         // key
         parse_whitespace(text, lex);
+        check_eof(text, lex)?;
         let key: String = parse_string(text, lex)?.string().unwrap();
         // colon
         parse_whitespace(text, lex);
@@ -475,6 +480,10 @@ pub enum Json {
     String(String),
     Array(Vec<Json>),
     Object(Vec<Property>),
+    /// Byte-string from binary formats, displayed as base64 string.
+    Bytes(Vec<u8>),
+    /// Nanoseconds since UNIX epoch, displayed as RFC 3339 string in UTC.
+    Timestamp(i128),
 }
 
 impl Json {
@@ -498,6 +507,28 @@ impl Json {
         write!(w, "\"")
     }
 
+    /// Project Bytes and Timestamp values, nested or otherwise, into
+    /// their JSON strings.
+    pub fn project(self) -> Json {
+        match self {
+            Json::Bytes(data) => Json::String(util::base64_encode(&data)),
+            Json::Timestamp(n) => Json::String(util::rfc3339(n)),
+            Json::Array(arr) => {
+                Json::Array(arr.into_iter().map(Json::project).collect())
+            },
+            Json::Object(props) => {
+                let props = props.into_iter()
+                    .map(|prop| {
+                        let (key, value) = (prop.key_ref().clone(), prop.value());
+                        Property::new(key, value.project())
+                    })
+                    .collect();
+                Json::Object(props)
+            },
+            val => val,
+        }
+    }
+
     fn insert(props: &mut Vec<Property>, prop: Property) {
         match search_by_key(props, prop.key_ref()) {
             Ok(off) => props[off] = prop,
@@ -556,6 +587,18 @@ impl From<Json> for bool {
     }
 }
 
+/// Parse `text` as a single JSON value, text other than white space after
+/// the value is an error.
+pub fn parse_complete(text: &str) -> Result<Json> {
+    let mut lex = Lex::new(0, 1, 1);
+    let val = parse_value(text, &mut lex)?;
+    parse_whitespace(text, &mut lex);
+    match check_eof(text, &mut lex) {
+        Ok(_) => Err(Error::Parse(lex.format("unexpected text after value"))),
+        Err(_) => Ok(val),
+    }
+}
+
 impl FromStr for Json {
     type Err=Error;
 
@@ -576,6 +619,12 @@ impl fmt::Display for Json {
             Integer(val) => write!(f, "{}", val),
             Float(val) => write!(f, "{:e}", val),
             S(val) => { Self::encode_string(f, &val)?; Ok(()) },
+            Json::Bytes(val) => {
+                Self::encode_string(f, &util::base64_encode(val))
+            },
+            Json::Timestamp(val) => {
+                Self::encode_string(f, &util::rfc3339(*val))
+            },
             Array(val) => {
                 if val.len() == 0 {
                     write!(f, "[]")
@@ -624,6 +673,8 @@ impl Document for Json {
             Json::String(_) => Doctype::String,
             Json::Array(_) => Doctype::Array,
             Json::Object(_) => Doctype::Object,
+            Json::Bytes(_) => Doctype::Bytes,
+            Json::Timestamp(_) => Doctype::Timestamp,
         }
     }
 
@@ -632,6 +683,7 @@ impl Document for Json {
             Json::String(s) => Some(s.len()),
             Json::Array(a) => Some(a.len()),
             Json::Object(o) => Some(o.len()),
+            Json::Bytes(b) => Some(b.len()),
             Json::Null => Some(0),
             _ => None,
         }
@@ -689,6 +741,18 @@ impl Value for Json {
     fn object(self) -> Option<Vec<Property>> {
         match self { Json::Object(obj) => Some(obj), _ => None }
     }
+
+    fn bytes_ref(&self) -> Option<&Vec<u8>> {
+        match self { Json::Bytes(data) => Some(data), _ => None }
+    }
+
+    fn bytes(self) -> Option<Vec<u8>> {
+        match self { Json::Bytes(data) => Some(data), _ => None }
+    }
+
+    fn timestamp(self) -> Option<i128> {
+        match self { Json::Timestamp(n) => Some(n), _ => None }
+    }
 }
 
 impl Recurse for Json {
@@ -830,6 +894,10 @@ impl Slice for Json {
                 let (a, z) = util::slice_range_check(start, end, s.len())?;
                 Some(Json::String(s[a..z].to_string()))
             },
+            Json::Bytes(data) => {
+                let (a, z) = util::slice_range_check(start, end, data.len())?;
+                Some(Json::Bytes(data[a..z].to_vec()))
+            },
             _ => None,
         }
     }
@@ -874,7 +942,10 @@ impl Neg for Json {
 
     fn neg(self) -> Json {
         match self {
-            Json::Integer(n) => Json::Integer(-n),
+            Json::Integer(n) => match n.checked_neg() {
+                Some(n) => Json::Integer(n),
+                None => Json::Float(-(n as f64)),
+            },
             Json::Float(n) => Json::Float(-n),
             _ => Json::Null,
         }
@@ -897,7 +968,10 @@ impl Mul for Json {
         use json::Json::{Null,Integer,Float,Object, String as S};
 
         match (self, rhs) {
-            (Integer(l), Integer(r)) => Integer(l*r),
+            (Integer(l), Integer(r)) => match l.checked_mul(r) {
+                Some(n) => Integer(n),
+                None => Float((l as f64) * (r as f64)),
+            },
             (Integer(l), Float(r)) => Float((l as f64) * r),
             (lhs@Integer(_), rhs) => rhs.mul(lhs),
             (Float(l), Float(r)) => Float(l*r),
@@ -948,7 +1022,8 @@ impl Rem for Json {
 
         match (self, rhs) {
             (Integer(_), Integer(0)) => Null,
-            (Integer(l), Integer(r)) => Integer(l%r),
+            // i128::MIN % -1 overflows, though the remainder is zero.
+            (Integer(l), Integer(r)) => Integer(l.wrapping_rem(r)),
             (Integer(_), Float(f)) if f == 0_f64 => Null,
             (Integer(l), Float(r)) => Float((l as f64)%r),
             (Float(_), Integer(0)) => Null,
@@ -967,7 +1042,10 @@ impl Add for Json {
         use json::Json::{Null,Integer,Float,Array,Object, String as S};
 
         match (self, rhs) {
-            (Integer(l), Integer(r)) => Integer(l+r),
+            (Integer(l), Integer(r)) => match l.checked_add(r) {
+                Some(n) => Integer(n),
+                None => Float((l as f64) + (r as f64)),
+            },
             (Integer(l), Float(r)) => Float((l as f64)+r),
             (lhs@Integer(_), rhs) => rhs.add(lhs),
             (Float(l), Float(r)) => Float(l+r),
@@ -1001,14 +1079,21 @@ impl Sub for Json {
         use json::Json::{Null,Integer,Float,Array};
 
         match (self, rhs) {
-            (Integer(l), Integer(r)) => Integer(l-r),
+            (Integer(l), Integer(r)) => match l.checked_sub(r) {
+                Some(n) => Integer(n),
+                None => Float((l as f64) - (r as f64)),
+            },
             (Integer(l), Float(r)) => Float((l as f64)-r),
             (lhs@Integer(_), rhs) => rhs.sub(lhs),
             (Float(l), Float(r)) => Float(l-r),
             (Float(l), Integer(r)) => Float(l-(r as f64)),
             (lhs@Float(_), rhs) => rhs.sub(lhs),
             (Array(mut lhs), Array(rhs)) => {
-                rhs.iter().for_each(|x| {lhs.remove_item(x);});
+                rhs.iter().for_each(|x| {
+                    if let Some(i) = lhs.iter().position(|y| y == x) {
+                        lhs.remove(i);
+                    }
+                });
                 Array(lhs)
             },
             (_, _) => Null,
@@ -1021,7 +1106,13 @@ impl Shr for Json {
 
     fn shr(self, rhs: Json) -> Json {
         match (self, rhs) {
-            (Json::Integer(l), Json::Integer(r)) => Json::Integer(l>>r),
+            (Json::Integer(l), Json::Integer(r)) => match shift(r) {
+                Some(r) => {
+                    let fill = if l < 0 { -1 } else { 0 };
+                    Json::Integer(l.checked_shr(r).unwrap_or(fill))
+                },
+                None => Json::Null,
+            },
             (_, _) => Json::Null,
         }
     }
@@ -1032,12 +1123,20 @@ impl Shl for Json {
 
     fn shl(self, rhs: Json) -> Json {
         match (self, rhs) {
-            (Json::Integer(l), Json::Integer(r)) => Json::Integer(l<<r),
+            (Json::Integer(l), Json::Integer(r)) => match shift(r) {
+                Some(r) => Json::Integer(l.checked_shl(r).unwrap_or(0)),
+                None => Json::Null,
+            },
             (_, _) => Json::Null,
         }
     }
 }
 
+// shift amounts are non-negative, shifting out all the bits yields zero.
+fn shift(r: i128) -> Option<u32> {
+    if r < 0 { None } else if r > 128 { Some(128) } else { Some(r as u32) }
+}
+
 impl BitAnd for Json {
     type Output=Json;
 
@@ -1186,6 +1285,61 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_bytes_timestamp() {
+        let val = Json::Bytes(vec![0xff, 0x00, 0x10]);
+        assert_eq!(Doctype::Bytes, val.doctype());
+        assert_eq!(Some(3), val.len());
+        assert_eq!(Some(vec![0xff, 0x00]), val.clone().slice(0, 2).unwrap().bytes());
+        assert_eq!(r#""/wAQ""#, &format!("{}", val));
+
+        let val = Json::Timestamp(951_782_400_123_000_000);
+        assert_eq!(Doctype::Timestamp, val.doctype());
+        assert_eq!(Some(951_782_400_123_000_000), val.clone().timestamp());
+        assert_eq!(r#""2000-02-29T00:00:00.123Z""#, &format!("{}", val));
+
+        let props = vec![
+            Property::new("data".to_string(), Json::Bytes(vec![1, 2, 3])),
+            Property::new("at".to_string(), Json::Array(vec![Json::Timestamp(0)])),
+        ];
+        let val = Json::from(props).project();
+        let refval: Json = r#"{"at":["1970-01-01T00:00:00Z"],"data":"AQID"}"#
+            .parse().unwrap();
+        assert_eq!(refval, val);
+        assert_eq!(None, Json::Null.bytes());
+    }
+
+    #[test]
+    fn test_integer_overflow() {
+        use self::Json::{Integer, Float, Null};
+
+        let (max, min) = (i128::max_value(), i128::min_value());
+        assert_eq!(Integer(max), Integer(max - 1) + Integer(1));
+        assert_eq!(Float((max as f64) + 1.0), Integer(max) + Integer(1));
+        assert_eq!(Float((min as f64) - 1.0), Integer(min) - Integer(1));
+        assert_eq!(Float((max as f64) * 2.0), Integer(max) * Integer(2));
+        assert_eq!(Float(-(min as f64)), -Integer(min));
+        assert_eq!(Integer(0), Integer(min) % Integer(-1));
+        assert_eq!(Integer(0), Integer(1) << Integer(200));
+        assert_eq!(Integer(-1), Integer(-8) >> Integer(200));
+        assert_eq!(Null, Integer(1) << Integer(-1));
+    }
+
+    #[test]
+    fn test_parse_incomplete() {
+        let text = r#"{"a":[1,"x\u00e9",{"b":null}],"c":true}"#;
+        for (i, _) in text.char_indices().skip(1) {
+            assert!(text[..i].parse::<Json>().is_err(), "{}", &text[..i]);
+        }
+        assert!(text.parse::<Json>().is_ok());
+        assert!(r#""\u00é1""#.parse::<Json>().is_err());
+
+        assert_eq!(Ok(Json::Integer(1)), parse_complete(" 1 \n"));
+        let err = "unexpected text after value at offset:2 line:1 col:3";
+        assert_eq!(Err(Error::Parse(err.to_string())), parse_complete("1 2"));
+        assert!(parse_complete("[1,").is_err());
+    }
+
     #[test]
     fn test_json_iter() {
         use self::Json::{Integer, Float, Bool, Array, Object, String as S};
@@ -6,6 +6,15 @@ impl Lex {
         Lex{off, row, col}
     }
 
+    /// Position of byte offset `off` within `text`, lines and columns
+    /// counting from 1.
+    pub fn locate(text: &str, off: usize) -> Lex {
+        let before = &text[..off];
+        let row = before.matches('\n').count() + 1;
+        let col = before.rsplit('\n').next().unwrap().chars().count() + 1;
+        Lex{off, row, col}
+    }
+
     pub fn incr_col(&mut self, i: usize) {
         self.off += i;
         self.col += i;
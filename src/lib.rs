@@ -6,7 +6,6 @@
 #![feature(box_patterns)]
 #![feature(slice_index_methods)]
 #![feature(associated_type_defaults)]
-#![feature(vec_remove_item)]
 
 
 extern crate test;
@@ -14,19 +13,35 @@ extern crate regex;
 #[macro_use] extern crate nom;
 
 
-//pub mod context;
+mod builtin_array;
+mod builtin_math;
+mod builtin_path;
+mod builtin_regex;
+mod builtin_str;
+mod builtin_time;
+mod builtin_type;
+mod collate;
+mod context;
 pub mod db;
+pub mod csv;
 pub mod entry;
+pub mod infer;
 mod input_mem;
 pub mod json;
 mod lex;
 pub mod meta;
+pub mod msgpack;
 pub mod op;
 mod ops;
+mod path;
 mod prop;
 pub mod query;
+mod query_check;
 mod query_nom;
+mod query_opt;
+pub mod schema;
 mod util;
+pub mod yaml;
 
 pub use input_mem::InputMem;
 
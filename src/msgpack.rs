@@ -0,0 +1,554 @@
+// MessagePack codec for Json, refer https://github.com/msgpack/msgpack.
+//
+// Integers are decoded from all msgpack integer formats into
+// Json::Integer, and encoded back using the smallest format that can
+// hold the value. Json values that have no msgpack representation
+// are mapped as follows:
+//
+// * bin-family is decoded as Json::Bytes and encoded back as bin.
+// * timestamp extension (type -1) is decoded as Json::Timestamp and
+//   encoded back using the smallest timestamp format.
+// * other ext-family is decoded as {"type": <ext-type>, "data": <base64>}.
+// * map keys that are not strings are converted to their JSON text.
+//
+// Lengths in the input are not trusted for allocation, buffers grow as
+// data is actually read, and arrays and maps nest at most MAX_DEPTH deep.
+
+use std::{cmp, result, error, fmt, io};
+use std::io::Read;
+
+use json::{Json, Property};
+use util;
+
+
+pub type Result<T> = result::Result<T,Error>;
+
+/// Maximum nesting of arrays and maps while decoding.
+pub const MAX_DEPTH: usize = 128;
+
+// maximum items or bytes allocated upfront for a length read from input.
+const MAX_PREALLOC: usize = 4096;
+
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Decode(String),
+    Encode(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use msgpack::Error::*;
+
+        match self {
+            Io(err) => write!(f, "{}", err),
+            Decode(s) => write!(f, "{}", s),
+            Encode(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+
+pub struct MsgPacks<R> where R: io::Read {
+    reader: R,
+}
+
+impl<R> MsgPacks<R> where R: io::Read {
+    pub fn new(reader: R) -> MsgPacks<R> {
+        MsgPacks{ reader }
+    }
+}
+
+impl<R> Iterator for MsgPacks<R> where R: io::Read {
+    type Item=Result<Json>;
+
+    fn next(&mut self) -> Option<Result<Json>> {
+        let mut byte = [0_u8; 1];
+        loop {
+            match self.reader.read(&mut byte) {
+                Ok(0) => return None,
+                Ok(_) => break,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {
+                    continue
+                },
+                Err(err) => return Some(Err(Error::Io(err))),
+            }
+        }
+        Some(decode_value(byte[0], &mut self.reader, 0))
+    }
+}
+
+
+pub fn encode<W>(val: &Json, w: &mut W) -> Result<()> where W: io::Write {
+    use json::Json::{Null, Bool, Integer, Float, Array, Object, String as S};
+
+    match val {
+        Null => w.write_all(&[0xc0])?,
+        Bool(false) => w.write_all(&[0xc2])?,
+        Bool(true) => w.write_all(&[0xc3])?,
+        Integer(n) => encode_integer(*n, w)?,
+        Float(f) => {
+            w.write_all(&[0xcb])?;
+            w.write_all(&f.to_bits().to_be_bytes())?;
+        },
+        S(s) => {
+            let n = s.len();
+            if n < 32 {
+                w.write_all(&[0xa0 | (n as u8)])?;
+            } else {
+                encode_length(n, [0xd9, 0xda, 0xdb], w)?;
+            }
+            w.write_all(s.as_bytes())?;
+        },
+        Array(arr) => {
+            let n = arr.len();
+            if n < 16 {
+                w.write_all(&[0x90 | (n as u8)])?;
+            } else {
+                encode_length(n, [0, 0xdc, 0xdd], w)?;
+            }
+            for item in arr.iter() { encode(item, w)? }
+        },
+        Object(obj) => {
+            let n = obj.len();
+            if n < 16 {
+                w.write_all(&[0x80 | (n as u8)])?;
+            } else {
+                encode_length(n, [0, 0xde, 0xdf], w)?;
+            }
+            for prop in obj.iter() {
+                encode(&S(prop.key_ref().clone()), w)?;
+                encode(prop.value_ref(), w)?;
+            }
+        },
+        Json::Bytes(data) => {
+            encode_length(data.len(), [0xc4, 0xc5, 0xc6], w)?;
+            w.write_all(data)?;
+        },
+        Json::Timestamp(n) => encode_timestamp(*n, w)?,
+    }
+    Ok(())
+}
+
+pub fn decode<R>(r: &mut R) -> Result<Json> where R: io::Read {
+    decode_at(r, 0)
+}
+
+impl Json {
+    pub fn to_msgpack(&self) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        encode(self, &mut out)?;
+        Ok(out)
+    }
+
+    pub fn from_msgpack(mut data: &[u8]) -> Result<Json> {
+        decode(&mut data)
+    }
+}
+
+
+fn encode_integer<W>(n: i128, w: &mut W) -> Result<()> where W: io::Write {
+    if n >= 0 {
+        if n < 128 {
+            w.write_all(&[n as u8])?;
+        } else if n <= (u8::max_value() as i128) {
+            w.write_all(&[0xcc, n as u8])?;
+        } else if n <= (u16::max_value() as i128) {
+            w.write_all(&[0xcd])?;
+            w.write_all(&(n as u16).to_be_bytes())?;
+        } else if n <= (u32::max_value() as i128) {
+            w.write_all(&[0xce])?;
+            w.write_all(&(n as u32).to_be_bytes())?;
+        } else if n <= (u64::max_value() as i128) {
+            w.write_all(&[0xcf])?;
+            w.write_all(&(n as u64).to_be_bytes())?;
+        } else {
+            let err = format!("integer {} out of msgpack range", n);
+            return Err(Error::Encode(err))
+        }
+    } else {
+        if n >= -32 {
+            w.write_all(&[n as i8 as u8])?;
+        } else if n >= (i8::min_value() as i128) {
+            w.write_all(&[0xd0, n as i8 as u8])?;
+        } else if n >= (i16::min_value() as i128) {
+            w.write_all(&[0xd1])?;
+            w.write_all(&(n as i16).to_be_bytes())?;
+        } else if n >= (i32::min_value() as i128) {
+            w.write_all(&[0xd2])?;
+            w.write_all(&(n as i32).to_be_bytes())?;
+        } else if n >= (i64::min_value() as i128) {
+            w.write_all(&[0xd3])?;
+            w.write_all(&(n as i64).to_be_bytes())?;
+        } else {
+            let err = format!("integer {} out of msgpack range", n);
+            return Err(Error::Encode(err))
+        }
+    }
+    Ok(())
+}
+
+fn encode_timestamp<W>(n: i128, w: &mut W) -> Result<()> where W: io::Write {
+    let (secs, nsec) = (n.div_euclid(1_000_000_000), n.rem_euclid(1_000_000_000));
+    if nsec == 0 && secs >= 0 && secs <= (u32::max_value() as i128) {
+        w.write_all(&[0xd6, 0xff])?;
+        w.write_all(&(secs as u32).to_be_bytes())?;
+    } else if secs >= 0 && secs < (1 << 34) {
+        w.write_all(&[0xd7, 0xff])?;
+        w.write_all(&(((nsec << 34) | secs) as u64).to_be_bytes())?;
+    } else if secs >= (i64::min_value() as i128) && secs <= (i64::max_value() as i128) {
+        w.write_all(&[0xc7, 12, 0xff])?;
+        w.write_all(&(nsec as u32).to_be_bytes())?;
+        w.write_all(&(secs as i64).to_be_bytes())?;
+    } else {
+        let err = format!("timestamp {} out of msgpack range", n);
+        return Err(Error::Encode(err))
+    }
+    Ok(())
+}
+
+// codes for 8-bit, 16-bit and 32-bit length prefix, 0 if not applicable.
+fn encode_length<W>(n: usize, codes: [u8; 3], w: &mut W) -> Result<()>
+    where W: io::Write
+{
+    if codes[0] != 0 && n <= (u8::max_value() as usize) {
+        w.write_all(&[codes[0], n as u8])?;
+    } else if n <= (u16::max_value() as usize) {
+        w.write_all(&[codes[1]])?;
+        w.write_all(&(n as u16).to_be_bytes())?;
+    } else if n <= (u32::max_value() as usize) {
+        w.write_all(&[codes[2]])?;
+        w.write_all(&(n as u32).to_be_bytes())?;
+    } else {
+        return Err(Error::Encode(format!("length {} out of msgpack range", n)))
+    }
+    Ok(())
+}
+
+// decode the value following `code`, nested `depth` arrays and maps deep.
+fn decode_at<R>(r: &mut R, depth: usize) -> Result<Json> where R: io::Read {
+    let code = read_u8(r)?;
+    decode_value(code, r, depth)
+}
+
+fn decode_value<R>(code: u8, r: &mut R, depth: usize) -> Result<Json>
+    where R: io::Read
+{
+    let val = match code {
+        0x00..=0x7f => Json::Integer(code as i128),
+        0x80..=0x8f => decode_map((code & 0x0f) as usize, r, depth)?,
+        0x90..=0x9f => decode_array((code & 0x0f) as usize, r, depth)?,
+        0xa0..=0xbf => decode_string((code & 0x1f) as usize, r)?,
+        0xc0 => Json::Null,
+        0xc2 => Json::Bool(false),
+        0xc3 => Json::Bool(true),
+        0xc4 => { let n = read_u8(r)? as usize; decode_bin(n, r)? },
+        0xc5 => { let n = read_u16(r)? as usize; decode_bin(n, r)? },
+        0xc6 => { let n = read_u32(r)? as usize; decode_bin(n, r)? },
+        0xc7 => { let n = read_u8(r)? as usize; decode_ext(n, r)? },
+        0xc8 => { let n = read_u16(r)? as usize; decode_ext(n, r)? },
+        0xc9 => { let n = read_u32(r)? as usize; decode_ext(n, r)? },
+        0xca => {
+            let mut buf = [0_u8; 4];
+            r.read_exact(&mut buf)?;
+            Json::Float(f32::from_bits(u32::from_be_bytes(buf)) as f64)
+        },
+        0xcb => {
+            let mut buf = [0_u8; 8];
+            r.read_exact(&mut buf)?;
+            Json::Float(f64::from_bits(u64::from_be_bytes(buf)))
+        },
+        0xcc => Json::Integer(read_u8(r)? as i128),
+        0xcd => Json::Integer(read_u16(r)? as i128),
+        0xce => Json::Integer(read_u32(r)? as i128),
+        0xcf => Json::Integer(read_u64(r)? as i128),
+        0xd0 => Json::Integer(read_u8(r)? as i8 as i128),
+        0xd1 => Json::Integer(read_u16(r)? as i16 as i128),
+        0xd2 => Json::Integer(read_u32(r)? as i32 as i128),
+        0xd3 => Json::Integer(read_u64(r)? as i64 as i128),
+        0xd4 => decode_ext(1, r)?,
+        0xd5 => decode_ext(2, r)?,
+        0xd6 => decode_ext(4, r)?,
+        0xd7 => decode_ext(8, r)?,
+        0xd8 => decode_ext(16, r)?,
+        0xd9 => { let n = read_u8(r)? as usize; decode_string(n, r)? },
+        0xda => { let n = read_u16(r)? as usize; decode_string(n, r)? },
+        0xdb => { let n = read_u32(r)? as usize; decode_string(n, r)? },
+        0xdc => {
+            let n = read_u16(r)? as usize;
+            decode_array(n, r, depth)?
+        },
+        0xdd => {
+            let n = read_u32(r)? as usize;
+            decode_array(n, r, depth)?
+        },
+        0xde => { let n = read_u16(r)? as usize; decode_map(n, r, depth)? },
+        0xdf => { let n = read_u32(r)? as usize; decode_map(n, r, depth)? },
+        0xe0..=0xff => Json::Integer(code as i8 as i128),
+        code => {
+            return Err(Error::Decode(format!("invalid msgpack code {:x}", code)))
+        },
+    };
+    Ok(val)
+}
+
+fn decode_string<R>(n: usize, r: &mut R) -> Result<Json> where R: io::Read {
+    match String::from_utf8(read_bytes(n, r)?) {
+        Ok(s) => Ok(Json::String(s)),
+        Err(err) => Err(Error::Decode(format!("invalid msgpack str {}", err))),
+    }
+}
+
+fn decode_bin<R>(n: usize, r: &mut R) -> Result<Json> where R: io::Read {
+    Ok(Json::Bytes(read_bytes(n, r)?))
+}
+
+fn decode_ext<R>(n: usize, r: &mut R) -> Result<Json> where R: io::Read {
+    let typ = read_u8(r)? as i8;
+    let data = read_bytes(n, r)?;
+    if typ == -1 {
+        return decode_timestamp(&data)
+    }
+    let data = util::base64_encode(&data);
+    let props = vec![
+        Property::new("type".to_string(), Json::Integer(typ as i128)),
+        Property::new("data".to_string(), Json::String(data)),
+    ];
+    Ok(From::from(props))
+}
+
+fn decode_timestamp(data: &[u8]) -> Result<Json> {
+    let mut buf = [0_u8; 8];
+    let (secs, nsec) = match data.len() {
+        4 => {
+            buf[4..].copy_from_slice(data);
+            (u64::from_be_bytes(buf) as i128, 0)
+        },
+        8 => {
+            buf.copy_from_slice(data);
+            let n = u64::from_be_bytes(buf);
+            ((n & 0x3_ffff_ffff) as i128, (n >> 34) as i128)
+        },
+        12 => {
+            buf[4..].copy_from_slice(&data[..4]);
+            let nsec = u64::from_be_bytes(buf) as i128;
+            buf.copy_from_slice(&data[4..]);
+            (i64::from_be_bytes(buf) as i128, nsec)
+        },
+        n => {
+            let err = format!("invalid msgpack timestamp length {}", n);
+            return Err(Error::Decode(err))
+        },
+    };
+    if nsec >= 1_000_000_000 {
+        let err = format!("invalid msgpack timestamp nanoseconds {}", nsec);
+        return Err(Error::Decode(err))
+    }
+    Ok(Json::Timestamp(secs * 1_000_000_000 + nsec))
+}
+
+fn decode_array<R>(n: usize, r: &mut R, depth: usize) -> Result<Json>
+    where R: io::Read
+{
+    check_depth(depth)?;
+    let mut arr = Vec::with_capacity(cmp::min(n, MAX_PREALLOC));
+    for _ in 0..n { arr.push(decode_at(r, depth + 1)?) }
+    Ok(Json::Array(arr))
+}
+
+fn decode_map<R>(n: usize, r: &mut R, depth: usize) -> Result<Json>
+    where R: io::Read
+{
+    check_depth(depth)?;
+    let mut props = Vec::with_capacity(cmp::min(n, MAX_PREALLOC));
+    for _ in 0..n {
+        let key = match decode_at(r, depth + 1)? {
+            Json::String(s) => s,
+            key => format!("{}", key),
+        };
+        props.push(Property::new(key, decode_at(r, depth + 1)?));
+    }
+    Ok(From::from(props))
+}
+
+fn check_depth(depth: usize) -> Result<()> {
+    if depth >= MAX_DEPTH {
+        let err = format!("msgpack nested deeper than {}", MAX_DEPTH);
+        return Err(Error::Decode(err))
+    }
+    Ok(())
+}
+
+// read `n` bytes, the buffer grows with the bytes actually read.
+fn read_bytes<R>(n: usize, r: &mut R) -> Result<Vec<u8>> where R: io::Read {
+    let mut buf = Vec::with_capacity(cmp::min(n, MAX_PREALLOC));
+    r.take(n as u64).read_to_end(&mut buf)?;
+    if buf.len() < n {
+        return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()))
+    }
+    Ok(buf)
+}
+
+fn read_u8<R>(r: &mut R) -> Result<u8> where R: io::Read {
+    let mut buf = [0_u8; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u16<R>(r: &mut R) -> Result<u16> where R: io::Read {
+    let mut buf = [0_u8; 2];
+    r.read_exact(&mut buf)?;
+    Ok(u16::from_be_bytes(buf))
+}
+
+fn read_u32<R>(r: &mut R) -> Result<u32> where R: io::Read {
+    let mut buf = [0_u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_u64<R>(r: &mut R) -> Result<u64> where R: io::Read {
+    let mut buf = [0_u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use json::JsonBuf;
+
+    #[test]
+    fn test_simple_msgpack() {
+        let jsons = include!("../testdata/test_simple.jsons");
+        let mut jsonbuf = JsonBuf::new();
+        for (i, text) in jsons.iter().enumerate() {
+            jsonbuf.set(text);
+            let value = jsonbuf.parse().unwrap();
+            let data = value.to_msgpack().unwrap();
+            let out = Json::from_msgpack(&data).unwrap();
+            assert_eq!(value, out, "testcase: {}", i);
+        }
+    }
+
+    #[test]
+    fn test_msgpack_integers() {
+        let nums: Vec<i128> = vec![
+            0, 1, 127, 128, 255, 256, 65535, 65536, 4294967295, 4294967296,
+            u64::max_value() as i128,
+            -1, -32, -33, -128, -129, -32768, -32769,
+            -2147483648, -2147483649, i64::min_value() as i128,
+        ];
+        let lens = vec![1, 1, 1, 2, 2, 3, 3, 5, 5, 9, 9, 1, 1, 2, 2, 3, 3, 5, 5, 9, 9];
+        for (n, ln) in nums.into_iter().zip(lens.into_iter()) {
+            let data = Json::Integer(n).to_msgpack().unwrap();
+            assert_eq!(data.len(), ln, "integer {}", n);
+            assert_eq!(Json::from_msgpack(&data).unwrap(), Json::Integer(n));
+        }
+
+        let n = (u64::max_value() as i128) + 1;
+        assert!(Json::Integer(n).to_msgpack().is_err());
+        let n = (i64::min_value() as i128) - 1;
+        assert!(Json::Integer(n).to_msgpack().is_err());
+    }
+
+    #[test]
+    fn test_msgpack_bin_ext() {
+        let data = [0xc4, 0x03, 0x01, 0x02, 0x03];
+        let val = Json::from_msgpack(&data).unwrap();
+        assert_eq!(Json::Bytes(vec![1, 2, 3]), val);
+        assert_eq!(r#""AQID""#, &format!("{}", val));
+        assert_eq!(val.to_msgpack().unwrap(), data.to_vec());
+
+        let data = [0xd5, 0x05, 0xff, 0x00];
+        let val = Json::from_msgpack(&data).unwrap();
+        assert_eq!(r#"{"data":"/wA=","type":5}"#, &format!("{}", val));
+
+        let data = [0x81, 0x01, 0xa1, b'a'];
+        let val = Json::from_msgpack(&data).unwrap();
+        assert_eq!(r#"{"1":"a"}"#, &format!("{}", val));
+    }
+
+    #[test]
+    fn test_msgpack_timestamp() {
+        let data = [0xd6, 0xff, 0x5b, 0xd9, 0xc6, 0x80];
+        let val = Json::from_msgpack(&data).unwrap();
+        assert_eq!(Json::Timestamp(1_540_998_784_000_000_000), val);
+        assert_eq!(r#""2018-10-31T15:13:04Z""#, &format!("{}", val));
+        assert_eq!(val.to_msgpack().unwrap(), data.to_vec());
+
+        let n = 1_541_000_832_250_000_000;
+        let data = Json::Timestamp(n).to_msgpack().unwrap();
+        assert_eq!(data[..2], [0xd7, 0xff]);
+        assert_eq!(Json::from_msgpack(&data).unwrap(), Json::Timestamp(n));
+
+        let n = -1_500_000_000; // before epoch
+        let data = Json::Timestamp(n).to_msgpack().unwrap();
+        assert_eq!(data[..3], [0xc7, 12, 0xff]);
+        let val = Json::from_msgpack(&data).unwrap();
+        assert_eq!(Json::Timestamp(n), val);
+        assert_eq!(r#""1969-12-31T23:59:58.5Z""#, &format!("{}", val));
+    }
+
+    #[test]
+    fn test_msgpack_malformed() {
+        // lengths claiming 4GB of data, with no data following.
+        let inputs: [&[u8]; 5] = [
+            &[0xc6, 0xff, 0xff, 0xff, 0xff], &[0xdb, 0xff, 0xff, 0xff, 0xff],
+            &[0xc9, 0xff, 0xff, 0xff, 0xff], &[0xdd, 0xff, 0xff, 0xff, 0xff],
+            &[0xdf, 0xff, 0xff, 0xff, 0xff],
+        ];
+        for data in inputs.iter() {
+            match Json::from_msgpack(data) {
+                Err(Error::Io(ref err))
+                    if err.kind() == io::ErrorKind::UnexpectedEof => (),
+                res => panic!("{:?} {:?}", data, res),
+            }
+        }
+
+        let mut data = vec![0x91_u8; MAX_DEPTH];
+        data.push(0xc0);
+        assert!(Json::from_msgpack(&data).is_ok());
+        let mut data = vec![0x91_u8; 100_000];
+        data.push(0xc0);
+        match Json::from_msgpack(&data) {
+            Err(Error::Decode(s)) => assert!(s.contains("deeper than")),
+            res => panic!("{:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_msgpack_iter() {
+        let docs = r#"null 10 10.2 "hello world" true [1,2] {"a":10}"#;
+        let docs: Vec<Json> = JsonBuf::iter(docs.as_bytes()).collect();
+        let mut data = Vec::new();
+        for doc in docs.iter() { encode(doc, &mut data).unwrap() }
+
+        let outs: Vec<Json> = MsgPacks::new(data.as_slice())
+            .map(|res| res.unwrap())
+            .collect();
+        assert_eq!(docs, outs);
+
+        // truncated stream
+        let data = [0x92, 0x01];
+        let mut iter = MsgPacks::new(&data[..]);
+        assert!(iter.next().unwrap().is_err());
+    }
+}
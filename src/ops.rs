@@ -1,8 +1,19 @@
-use std::{vec, iter};
+use std::{vec, mem};
+use std::rc::Rc;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::cell::RefCell;
 
 use entry::{self,Entry,IterPosition};
 use db::{Document, Doctype, ItemIterator, Input, Pipeline, Repeater};
+use context::{Context, Func, Slot};
+use query::{Thunk, UpdateOp, StrPart};
 use prop::Property;
+use path;
+use builtin_str;
+use builtin_array;
+use builtin_path;
+use builtin_regex::{RegexOp, Pattern};
+use collate;
 
 
 pub struct Identity<'a, D> where D: Document {
@@ -317,6 +328,61 @@ impl<'a,D> Pipeline<'a,D> for Index<'a,D> where D: 'a + Document {
 }
 
 
+/// Index lookups fused into a single stage, like `.a.b.[0]`. Lookups are
+/// applied in order, failing at the first key or offset not found.
+pub struct IndexPath<'a,D> where D: Document {
+    input: Input<'a,D>,
+    keys: &'a [(Option<String>, Option<isize>)],
+}
+
+impl<'a,D> IndexPath<'a,D> where D: Document {
+    pub fn new(input: Input<'a,D>, keys: &'a [(Option<String>, Option<isize>)])
+        -> IndexPath<'a,D>
+    {
+        IndexPath{input, keys}
+    }
+
+    fn do_index(&self, mut doc: D) -> Result<D,String> {
+        for key in self.keys.iter() {
+            let dt = doc.doctype();
+            doc = match key {
+                (Some(key), _) => doc.get(key)
+                    .ok_or(format!("cannot index {} into {:?}", key, dt))?,
+                (None, Some(off)) => doc.index(*off)
+                    .ok_or(format!("cannot index {} into {:?}", off, dt))?,
+                (None, None) => unreachable!(),
+            };
+        }
+        Ok(doc)
+    }
+}
+
+impl<'a,D> Repeater<'a,D> for IndexPath<'a,D> where D: 'a + Document {
+    fn repeat(&self) -> Input<'a,D> {
+        Box::new(IndexPath{input: self.input.repeat(), keys: self.keys})
+    }
+}
+
+impl<'a,D> Iterator for IndexPath<'a,D> where D: Document {
+    type Item=Entry<D>;
+
+    fn next(&mut self) -> Option<Entry<D>> {
+        let mut d_entry = self.input.next()?;
+        if d_entry.has_error() { return Some(d_entry) }
+
+        let doc = mem::replace(&mut d_entry.doc, D::null());
+        match self.do_index(doc) {
+            Ok(doc) => d_entry.doc = doc,
+            Err(s) => d_entry.set_error(s),
+        }
+        Some(d_entry)
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for IndexPath<'a,D> where D: 'a + Document {
+}
+
+
 pub struct Identifier<'a,D> where D: Document {
     input: Input<'a,D>,
     symbol: String,
@@ -540,55 +606,46 @@ impl<'a,D> Pipeline<'a,D> for Iter<'a,D> where D: 'a + Document {
 }
 
 
-pub struct List<'a,D> where D: Document {
-    inputs: Vec<Input<'a,D>>,
+/// `[f, g, ...]`, collect all the outputs of the items, evaluated on the
+/// input, into a single array. `[]` is the empty array.
+pub struct List<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    thunks: &'a [Thunk],
+    env: Context<'a,D>,
+    funcs: Vec<Subquery<'a,D>>,
 }
 
-impl<'a,D> List<'a,D> where D: Document {
-    pub fn new(inputs: Vec<Input<D>>) -> List<D> {
-        List{inputs}
-    }
-
-    fn next_entries(&mut self) -> Option<Vec<Entry<D>>> {
-        let mut entries: Vec<Entry<D>> = Vec::new();
-        let mut n = self.inputs.len();
-        for input in self.inputs.iter_mut() {
-            loop {
-                match input.next() {
-                    Some(entry) => match entry.iter_position() {
-                        IterPosition::Next => entries.push(entry),
-                        IterPosition::Item | IterPosition::End => {
-                            entries.push(entry);
-                            break
-                        },
-                    },
-                    None => {
-                        n -= 1;
-                        entries.push(Entry::new(D::null()));
-                        break
-                    },
-                }
-            }
-        }
-        if n > 0 { Some(entries) } else { None }
+impl<'a,D> List<'a,D> where D: 'a + Document {
+    pub fn new(input: Input<'a,D>, thunks: &'a [Thunk], env: &Context<'a,D>)
+        -> List<'a,D>
+    {
+        let funcs = thunks.iter().map(|t| Subquery::new(t, env)).collect();
+        List{input, thunks, env: env.clone(), funcs}
     }
 }
 
 impl<'a,D> Repeater<'a,D> for List<'a,D> where D: 'a + Document {
     fn repeat(&self) -> Input<'a,D> {
-        let inputs = self.inputs.iter().map(|input| input.repeat()).collect();
-        Box::new(List{ inputs })
+        let input = self.input.repeat();
+        Box::new(List::new(input, self.thunks, &self.env))
     }
 }
 
-
-impl<'a,D> Iterator for List<'a,D> where D: Document {
+impl<'a,D> Iterator for List<'a,D> where D: 'a + Document {
     type Item=Entry<D>;
 
     fn next(&mut self) -> Option<Entry<D>> {
-        let entries = self.next_entries()?;
-        let values: Vec<D> = entries.iter().map(|e| e.doc.clone()).collect();
-        let d_entry = Entry::new_merged(entries, From::from(values));
+        let mut d_entry = self.input.next()?;
+        if d_entry.has_error() { return Some(d_entry) }
+
+        let mut values: Vec<D> = Vec::new();
+        for func in self.funcs.iter_mut() {
+            for entry in func.run(d_entry.clone()).into_iter() {
+                if entry.has_error() { return Some(entry) }
+                values.push(entry.doc)
+            }
+        }
+        d_entry.doc = From::from(values);
         Some(d_entry)
     }
 }
@@ -597,110 +654,87 @@ impl<'a,D> Pipeline<'a,D> for List<'a,D> where D: 'a + Document {
 }
 
 
-pub struct Dict<'a,D> where D: Document {
-    inputs: Vec<(Input<'a,D>, Input<'a,D>)>,
+/// `{k1: v1, k2: v2, ...}`, an object for every combination of the
+/// outputs of keys and values, the earlier properties varying slowest,
+/// as in `{a: (1,2), b: (3,4)}` yielding {a:1,b:3}, {a:1,b:4}, {a:2,b:3}
+/// and {a:2,b:4}.
+pub struct Dict<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    thunks: &'a [(Thunk, Thunk)],
+    env: Context<'a,D>,
+    funcs: Vec<(Subquery<'a,D>, Subquery<'a,D>)>,
     iter: Option<vec::IntoIter<Entry<D>>>,
 }
 
-impl<'a,D> Dict<'a,D> where D: Document {
-    pub fn new(inputs: Vec<(Input<'a,D>,Input<'a,D>)>) -> Dict<'a,D> {
-        Dict{inputs, iter: None}
+impl<'a,D> Dict<'a,D> where D: 'a + Document {
+    pub fn new(
+        input: Input<'a,D>,
+        thunks: &'a [(Thunk, Thunk)],
+        env: &Context<'a,D>) -> Dict<'a,D>
+    {
+        let funcs = thunks.iter()
+            .map(|(kt, vt)| (Subquery::new(kt, env), Subquery::new(vt, env)))
+            .collect();
+        Dict{input, thunks, env: env.clone(), funcs, iter: None}
     }
 
-    fn collect_prop(kinput: &mut Input<D>, vinput: &mut Input<D>)
-        -> Option<Vec<(Entry<D>, Vec<Entry<D>>)>>
-    {
-        // gather keys
-        let mut keys = Vec::new();
-        loop {
-            match kinput.next() {
-                Some(entry) => match entry.iter_position() {
-                    IterPosition::Next => keys.push(entry),
-                    IterPosition::Item | IterPosition::End => {
-                        keys.push(entry);
-                        break
-                    },
-                },
-                None => break,
+    fn dicts(&mut self, d_entry: &Entry<D>) -> Result<Vec<D>,Entry<D>> {
+        let mut dicts: Vec<Vec<Property<D>>> = vec![vec![]];
+        for (kfunc, vfunc) in self.funcs.iter_mut() {
+            let keys = kfunc.run(d_entry.clone());
+            if let Some(entry) = keys.iter().find(|e| e.has_error()) {
+                return Err(entry.clone())
             }
-        }
-        if keys.len() == 0 { return None }
-
-        // gather values
-        let mut values = Vec::new();
-        loop {
-            match vinput.next() {
-                Some(entry) => match entry.iter_position() {
-                    IterPosition::Next => values.push(entry),
-                    IterPosition::Item | IterPosition::End => {
-                        values.push(entry);
-                        break
+            let values = vfunc.run(d_entry.clone());
+            if let Some(entry) = values.iter().find(|e| e.has_error()) {
+                return Err(entry.clone())
+            }
+            let mut props = Vec::new();
+            for key in keys.into_iter() {
+                let key = match key.doc.string_ref() {
+                    Some(key) => key.clone(),
+                    None => {
+                        let dt = key.doc.doctype();
+                        let mut entry = key;
+                        entry.doc = D::null();
+                        entry.set_error(
+                            format!("{:?} cannot be an object key", dt)
+                        );
+                        return Err(entry)
                     },
-                },
-                None => break,
+                };
+                for value in values.iter() {
+                    props.push(Property::new(key.clone(), value.doc.clone()))
+                }
             }
-        }
-        if values.len() == 0 { return None }
-
-        // final
-        let mut props = Vec::new();
-        for key in keys.into_iter() {
-            props.push((key, values.clone()))
-        }
-        Some(props)
-    }
-
-    fn collect_props(&mut self) -> Option<Vec<(Entry<D>, Vec<Entry<D>>)>> {
-        let mut props = Vec::new();
-        let mut n = self.inputs.len();
-        for (kinput, vinput) in self.inputs.iter_mut() {
-            match Self::collect_prop(kinput, vinput) {
-                Some(mut sub_props) => props.append(&mut sub_props),
-                None => { n -= 1; },
+            let mut next = Vec::with_capacity(dicts.len() * props.len());
+            for dict in dicts.into_iter() {
+                for prop in props.iter() {
+                    let mut dict = dict.clone();
+                    dict.push(prop.clone());
+                    next.push(dict)
+                }
             }
+            dicts = next;
         }
-        if n > 0 { Some(props) } else { None }
+        Ok(dicts.into_iter().map(From::from).collect())
     }
 
-    fn collect_dict(mut bp: Vec<(Entry<D>, Vec<Entry<D>>)>)
-        -> Vec<Vec<(Entry<D>, Entry<D>)>>
-    {
-        let (key, values) = bp.remove(0);
-        if bp.len() == 0 {
-            return values.into_iter().map(|v| vec![(key.clone(), v)]).collect();
-        }
-        let tail = Self::collect_dict(bp);
-        let mut dict_lists: Vec<Vec<Vec<(Entry<D>, Entry<D>)>>> =
-            iter::repeat(tail).take(values.len()).collect();
-        for (i, value) in values.into_iter().enumerate() {
-            for dict in dict_lists[i].iter_mut() {
-                dict.push((key.clone(), value.clone()))
-            }
-        }
-        dict_lists.into_iter().flatten().collect()
-    }
-
-    fn next_props(&mut self) -> Option<Vec<Entry<D>>> {
-        let bp = self.collect_props()?;
-        let mut full = Vec::new();
-        for dict in Self::collect_dict(bp).into_iter() {
-            let mut entries: Vec<Entry<D>> = Vec::new();
-            let mut keys: Vec<String> = Vec::new();
-            let mut values: Vec<D> = Vec::new();
-            for (kentry, ventry) in dict {
-                entries.push(kentry.clone());
-                entries.push(ventry.clone());
-                if kentry.has_error() || ventry.has_error() { continue }
-                match kentry.doc.string() {
-                    Some(s) => { keys.push(s); values.push(ventry.doc) },
-                    None => (),
-                }
-            }
-            let props: Vec<Property<D>> = keys.into_iter().zip(values)
-                .map(|(k,v)| Property::new(k,v)).collect();
-            full.push(Entry::new_merged(entries, From::from(props)));
+    fn values(&mut self) -> Option<Vec<Entry<D>>> {
+        let d_entry = self.input.next()?;
+        if d_entry.has_error() { return Some(vec![d_entry]) }
+
+        match self.dicts(&d_entry) {
+            Ok(docs) => {
+                let entries = docs.into_iter().map(|doc| {
+                    let mut entry = d_entry.clone();
+                    entry.doc = doc;
+                    entry
+                }).collect();
+                Some(entry::fixpositions(entries))
+            },
+            Err(entry) => Some(vec![entry]),
         }
-        Some(full)
     }
 }
 
@@ -709,30 +743,23 @@ impl<'a,D> Repeater<'a,D> for Dict<'a,D> where D: 'a + Document {
         if let Some(_) = self.iter {
             panic!("cannot repeat Dict after the statement is prepared");
         }
-        let inputs = self.inputs.iter().map(|(ki,vi)| {
-            (ki.repeat(), vi.repeat())
-        }).collect();
-        Box::new(Dict{ inputs, iter: None })
+        let input = self.input.repeat();
+        Box::new(Dict::new(input, self.thunks, &self.env))
     }
 }
 
-impl<'a,D> Iterator for Dict<'a,D> where D: Document {
+impl<'a,D> Iterator for Dict<'a,D> where D: 'a + Document {
     type Item=Entry<D>;
 
     fn next(&mut self) -> Option<Entry<D>> {
-        if let None = self.iter {
-            self.iter = Some(self.next_props()?.into_iter());
-        }
-
         let entry = loop {
-            match self.iter.as_mut().unwrap().next() {
+            match self.iter.as_mut().and_then(|iter| iter.next()) {
                 Some(entry) => break entry,
-                None => { self.iter = Some(self.next_props()?.into_iter()); },
+                None => { self.iter = Some(self.values()?.into_iter()); },
             }
         };
         Some(entry)
     }
-
 }
 
 impl<'a,D> Pipeline<'a,D> for Dict<'a,D> where D: 'a + Document {
@@ -803,615 +830,1379 @@ impl<'a,D> Pipeline<'a,D> for Not<'a,D> where D: 'a + Document {
 }
 
 
-pub struct Mul<'a,D> where D: Document {
-    lhs: Input<'a,D>,
-    rhs: Input<'a,D>,
+/// Input shared by the stages repeating it, like the branches in
+/// `.a | (.b, .c)`. Outputs of the input are evaluated once and buffered
+/// until every repeat has read them. Repeats made after reading has
+/// started get an input of their own.
+pub struct Shared<'a,D> where D: 'a + Document {
+    state: Rc<RefCell<SharedState<'a,D>>>,
+    id: usize,
+}
+
+struct SharedState<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    started: bool,
+    buffer: VecDeque<Entry<D>>,
+    start: usize, // position of the first buffered entry
+    cursors: Vec<Option<usize>>, // None for dropped repeats
+}
+
+impl<'a,D> Shared<'a,D> where D: 'a + Document {
+    pub fn new(input: Input<'a,D>) -> Shared<'a,D> {
+        let state = SharedState{
+            input, started: false, buffer: VecDeque::new(), start: 0,
+            cursors: vec![Some(0)],
+        };
+        Shared{state: Rc::new(RefCell::new(state)), id: 0}
+    }
 }
 
-impl<'a,D> Mul<'a,D> where D: Document {
-    pub fn new(lhs: Input<'a,D>, rhs: Input<'a,D>) -> Mul<'a,D> {
-        Mul{lhs, rhs}
+impl<'a,D> SharedState<'a,D> where D: 'a + Document {
+    // drop the entries read by every repeat.
+    fn trim(&mut self) {
+        let end = self.start + self.buffer.len();
+        let min = self.cursors.iter().filter_map(|c| *c).min().unwrap_or(end);
+        while self.start < min {
+            self.buffer.pop_front();
+            self.start += 1;
+        }
     }
 }
 
-impl<'a,D> Repeater<'a,D> for Mul<'a,D> where D: 'a + Document {
+impl<'a,D> Repeater<'a,D> for Shared<'a,D> where D: 'a + Document {
     fn repeat(&self) -> Input<'a,D> {
-        let x = Mul{ lhs: self.lhs.repeat(), rhs: self.rhs.repeat() };
-        Box::new(x)
+        let mut state = self.state.borrow_mut();
+        if state.started {
+            return Box::new(Shared::new(state.input.repeat()))
+        }
+        state.cursors.push(Some(0));
+        let id = state.cursors.len() - 1;
+        Box::new(Shared{state: Rc::clone(&self.state), id})
     }
 }
 
-impl<'a,D> Iterator for Mul<'a,D> where D: Document {
+impl<'a,D> Iterator for Shared<'a,D> where D: 'a + Document {
     type Item=Entry<D>;
 
     fn next(&mut self) -> Option<Entry<D>> {
-        let d_lhs = self.lhs.next()?;
-        let d_rhs = self.rhs.next()?;
-        let doc = d_lhs.doc.clone() * d_rhs.doc.clone();
-        let entries = vec![d_lhs, d_rhs];
-        Some(Entry::new_merged(entries, doc))
+        let mut state = self.state.borrow_mut();
+        state.started = true;
+        let pos = state.cursors[self.id].unwrap();
+        let entry = match state.buffer.get(pos - state.start) {
+            Some(entry) => entry.clone(),
+            None => {
+                let entry = state.input.next()?;
+                state.buffer.push_back(entry.clone());
+                entry
+            },
+        };
+        state.cursors[self.id] = Some(pos + 1);
+        state.trim();
+        Some(entry)
     }
 }
 
-impl<'a,D> Pipeline<'a,D> for Mul<'a,D> where D: 'a + Document {
+impl<'a,D> Drop for Shared<'a,D> where D: 'a + Document {
+    fn drop(&mut self) {
+        let mut state = self.state.borrow_mut();
+        state.cursors[self.id] = None;
+        state.trim();
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for Shared<'a,D> where D: 'a + Document {
 }
 
 
-pub struct Div<'a,D> where D: Document {
-    lhs: Input<'a,D>,
-    rhs: Input<'a,D>,
+/// Binary operators, like `lhs + rhs`. Both the operands are evaluated on
+/// the input and `op` is applied on every combination of their outputs,
+/// lhs varying fastest, as in `(1,2) + (10,20)` yielding 11, 12, 21, 22.
+/// Operands are evaluated as the outputs are consumed.
+pub struct Binary<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    thunks: (&'a Thunk, &'a Thunk),
+    env: Context<'a,D>,
+    op: fn(D, D) -> D,
+    lhs: Replay<'a,D>,
+    rhs: Subquery<'a,D>,
+    ahead: Ahead<D>,
+    d_entry: Option<Entry<D>>,
+    error: Option<Entry<D>>,
+    r_entry: Option<Entry<D>>,
+}
+
+impl<'a,D> Binary<'a,D> where D: 'a + Document {
+    pub fn new(
+        input: Input<'a,D>,
+        thunks: (&'a Thunk, &'a Thunk),
+        env: &Context<'a,D>,
+        op: fn(D, D) -> D) -> Binary<'a,D>
+    {
+        let lhs = Replay::new(Subquery::new(thunks.0, env));
+        let rhs = Subquery::new(thunks.1, env);
+        Binary{
+            input, thunks, env: env.clone(), op, lhs, rhs,
+            ahead: Ahead::new(), d_entry: None, error: None, r_entry: None,
+        }
+    }
 }
 
-impl<'a,D> Div<'a,D> where D: Document {
-    pub fn new(lhs: Input<'a,D>, rhs: Input<'a,D>) -> Div<'a,D> {
-        Div{lhs, rhs}
+impl<'a,D> Generator<D> for Binary<'a,D> where D: 'a + Document {
+    fn ahead(&mut self) -> &mut Ahead<D> {
+        &mut self.ahead
+    }
+
+    fn start(&mut self) -> Option<()> {
+        let d_entry = self.input.next()?;
+        self.lhs.reset();
+        self.r_entry = None;
+        self.error = None;
+        if d_entry.has_error() {
+            self.error = Some(d_entry.clone());
+        } else {
+            self.rhs.push(d_entry.clone());
+        }
+        self.d_entry = Some(d_entry);
+        Some(())
+    }
+
+    fn pull(&mut self) -> Option<Entry<D>> {
+        if let Some(entry) = self.error.take() { return Some(entry) }
+        loop {
+            if let Some(d_rhs) = self.r_entry.as_ref() {
+                match self.lhs.next() {
+                    Some(d_lhs) if d_lhs.has_error() => return Some(d_lhs),
+                    Some(d_lhs) => {
+                        let (lhs, rhs) = (d_lhs.doc.clone(), d_rhs.doc.clone());
+                        let doc = (self.op)(lhs, rhs);
+                        let merged = vec![d_lhs, d_rhs.clone()];
+                        return Some(Entry::new_merged(merged, doc))
+                    },
+                    None => (),
+                }
+            }
+            let d_rhs = self.rhs.pull()?;
+            if d_rhs.has_error() {
+                self.r_entry = None;
+                return Some(d_rhs)
+            }
+            self.lhs.rewind(self.d_entry.as_ref()?);
+            self.r_entry = Some(d_rhs);
+        }
     }
 }
 
-impl<'a,D> Repeater<'a,D> for Div<'a,D> where D: 'a + Document {
+impl<'a,D> Repeater<'a,D> for Binary<'a,D> where D: 'a + Document {
     fn repeat(&self) -> Input<'a,D> {
-        let x = Div{ lhs: self.lhs.repeat(), rhs: self.rhs.repeat() };
-        Box::new(x)
+        if self.d_entry.is_some() {
+            panic!("cannot repeat Binary after the statement is prepared");
+        }
+        let input = self.input.repeat();
+        Box::new(Binary::new(input, self.thunks, &self.env, self.op))
     }
 }
 
-impl<'a,D> Iterator for Div<'a,D> where D: Document {
+impl<'a,D> Iterator for Binary<'a,D> where D: 'a + Document {
     type Item=Entry<D>;
 
     fn next(&mut self) -> Option<Entry<D>> {
-        let d_lhs = self.lhs.next()?;
-        let d_rhs = self.rhs.next()?;
-        let doc = d_lhs.doc.clone() / d_rhs.doc.clone();
-        let entries = vec![d_lhs, d_rhs];
-        Some(Entry::new_merged(entries, doc))
+        generate(self)
     }
 }
 
-impl<'a,D> Pipeline<'a,D> for Div<'a,D> where D: 'a + Document {
+impl<'a,D> Pipeline<'a,D> for Binary<'a,D> where D: 'a + Document {
 }
 
-
-pub struct Rem<'a,D> where D: Document {
-    lhs: Input<'a,D>,
-    rhs: Input<'a,D>,
+pub fn mul<D>(lhs: D, rhs: D) -> D where D: Document {
+    lhs * rhs
 }
 
-impl<'a,D> Rem<'a,D> where D: Document {
-    pub fn new(lhs: Input<'a,D>, rhs: Input<'a,D>) -> Rem<'a,D> {
-        Rem{lhs, rhs}
-    }
+pub fn div<D>(lhs: D, rhs: D) -> D where D: Document {
+    lhs / rhs
 }
 
-impl<'a,D> Repeater<'a,D> for Rem<'a,D> where D: 'a + Document {
-    fn repeat(&self) -> Input<'a,D> {
-        let x = Rem{ lhs: self.lhs.repeat(), rhs: self.rhs.repeat() };
-        Box::new(x)
-    }
+pub fn rem<D>(lhs: D, rhs: D) -> D where D: Document {
+    lhs % rhs
 }
 
-impl<'a,D> Iterator for Rem<'a,D> where D: Document {
-    type Item=Entry<D>;
-
-    fn next(&mut self) -> Option<Entry<D>> {
-        let d_lhs = self.lhs.next()?;
-        let d_rhs = self.rhs.next()?;
-        let doc = d_lhs.doc.clone() % d_rhs.doc.clone();
-        let entries = vec![d_lhs, d_rhs];
-        Some(Entry::new_merged(entries, doc))
-    }
+pub fn add<D>(lhs: D, rhs: D) -> D where D: Document {
+    lhs + rhs
 }
 
-impl<'a,D> Pipeline<'a,D> for Rem<'a,D> where D: 'a + Document {
+pub fn sub<D>(lhs: D, rhs: D) -> D where D: Document {
+    lhs - rhs
 }
 
+pub fn shr<D>(lhs: D, rhs: D) -> D where D: Document {
+    lhs >> rhs
+}
 
-pub struct Add<'a,D> where D: Document {
-    lhs: Input<'a,D>,
-    rhs: Input<'a,D>,
+pub fn shl<D>(lhs: D, rhs: D) -> D where D: Document {
+    lhs << rhs
 }
 
-impl<'a,D> Add<'a,D> where D: Document {
-    pub fn new(lhs: Input<'a,D>, rhs: Input<'a,D>) -> Add<'a,D> {
-        Add{lhs, rhs}
-    }
+pub fn bitand<D>(lhs: D, rhs: D) -> D where D: Document {
+    lhs & rhs
 }
 
-impl<'a,D> Repeater<'a,D> for Add<'a,D> where D: 'a + Document {
-    fn repeat(&self) -> Input<'a,D> {
-        let x = Add{ lhs: self.lhs.repeat(), rhs: self.rhs.repeat() };
-        Box::new(x)
-    }
+pub fn bitor<D>(lhs: D, rhs: D) -> D where D: Document {
+    lhs | rhs
 }
 
-impl<'a,D> Iterator for Add<'a,D> where D: Document {
-    type Item=Entry<D>;
+pub fn bitxor<D>(lhs: D, rhs: D) -> D where D: Document {
+    lhs ^ rhs
+}
 
-    fn next(&mut self) -> Option<Entry<D>> {
-        let d_lhs = self.lhs.next()?;
-        let d_rhs = self.rhs.next()?;
-        let doc = d_lhs.doc.clone() + d_rhs.doc.clone();
-        let entries = vec![d_lhs, d_rhs];
-        Some(Entry::new_merged(entries, doc))
-    }
+pub fn eq<D>(lhs: D, rhs: D) -> D where D: Document {
+    From::from(lhs == rhs)
 }
 
-impl<'a,D> Pipeline<'a,D> for Add<'a,D> where D: 'a + Document {
+pub fn ne<D>(lhs: D, rhs: D) -> D where D: Document {
+    From::from(lhs != rhs)
 }
 
+pub fn lt<D>(lhs: D, rhs: D) -> D where D: Document {
+    From::from(lhs < rhs)
+}
 
-pub struct Sub<'a,D> where D: Document {
-    lhs: Input<'a,D>,
-    rhs: Input<'a,D>,
+pub fn le<D>(lhs: D, rhs: D) -> D where D: Document {
+    From::from(lhs <= rhs)
 }
 
-impl<'a,D> Sub<'a,D> where D: Document {
-    pub fn new(lhs: Input<'a,D>, rhs: Input<'a,D>) -> Sub<'a,D> {
-        Sub{lhs, rhs}
-    }
+pub fn gt<D>(lhs: D, rhs: D) -> D where D: Document {
+    From::from(lhs > rhs)
 }
 
-impl<'a,D> Repeater<'a,D> for Sub<'a,D> where D: 'a + Document {
-    fn repeat(&self) -> Input<'a,D> {
-        let x = Sub{ lhs: self.lhs.repeat(), rhs: self.rhs.repeat() };
-        Box::new(x)
-    }
+pub fn ge<D>(lhs: D, rhs: D) -> D where D: Document {
+    From::from(lhs >= rhs)
 }
 
-impl<'a,D> Iterator for Sub<'a,D> where D: Document {
-    type Item=Entry<D>;
 
-    fn next(&mut self) -> Option<Entry<D>> {
-        let d_lhs = self.lhs.next()?;
-        let d_rhs = self.rhs.next()?;
-        let doc = d_lhs.doc.clone() - d_rhs.doc.clone();
-        let entries = vec![d_lhs, d_rhs];
-        Some(Entry::new_merged(entries, doc))
+/// `lhs && rhs` and `lhs || rhs`. For every output of lhs, rhs is
+/// evaluated only when lhs does not decide the result, so errors from rhs
+/// surface only when it is evaluated. Only null and false are false.
+/// Operands are evaluated as the outputs are consumed.
+pub struct Logical<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    thunks: (&'a Thunk, &'a Thunk),
+    env: Context<'a,D>,
+    or: bool,
+    lhs: Subquery<'a,D>,
+    rhs: Replay<'a,D>,
+    ahead: Ahead<D>,
+    d_entry: Option<Entry<D>>,
+    error: Option<Entry<D>>,
+    in_rhs: bool,
+}
+
+impl<'a,D> Logical<'a,D> where D: 'a + Document {
+    pub fn new(
+        input: Input<'a,D>,
+        thunks: (&'a Thunk, &'a Thunk),
+        env: &Context<'a,D>,
+        or: bool) -> Logical<'a,D>
+    {
+        let lhs = Subquery::new(thunks.0, env);
+        let rhs = Replay::new(Subquery::new(thunks.1, env));
+        Logical{
+            input, thunks, env: env.clone(), or, lhs, rhs,
+            ahead: Ahead::new(), d_entry: None, error: None, in_rhs: false,
+        }
     }
 }
 
-impl<'a,D> Pipeline<'a,D> for Sub<'a,D> where D: 'a + Document {
-}
-
+impl<'a,D> Generator<D> for Logical<'a,D> where D: 'a + Document {
+    fn ahead(&mut self) -> &mut Ahead<D> {
+        &mut self.ahead
+    }
 
-pub struct Shr<'a,D> where D: Document {
-    lhs: Input<'a,D>,
-    rhs: Input<'a,D>,
-}
+    fn start(&mut self) -> Option<()> {
+        let d_entry = self.input.next()?;
+        // rhs is evaluated at most once for the input, and only if needed.
+        self.rhs.reset();
+        self.in_rhs = false;
+        self.error = None;
+        if d_entry.has_error() {
+            self.error = Some(d_entry.clone());
+        } else {
+            self.lhs.push(d_entry.clone());
+        }
+        self.d_entry = Some(d_entry);
+        Some(())
+    }
 
-impl<'a,D> Shr<'a,D> where D: Document {
-    pub fn new(lhs: Input<'a,D>, rhs: Input<'a,D>) -> Shr<'a,D> {
-        Shr{lhs, rhs}
+    fn pull(&mut self) -> Option<Entry<D>> {
+        if let Some(entry) = self.error.take() { return Some(entry) }
+        loop {
+            if self.in_rhs {
+                match self.rhs.next() {
+                    Some(mut entry) => {
+                        if !entry.has_error() {
+                            entry.doc = From::from(is_truthy(&entry.doc))
+                        }
+                        return Some(entry)
+                    },
+                    None => self.in_rhs = false,
+                }
+            }
+            let mut l_entry = self.lhs.pull()?;
+            if l_entry.has_error() { return Some(l_entry) }
+            // true for `||`, false for `&&`, decides the result.
+            if is_truthy(&l_entry.doc) == self.or {
+                l_entry.doc = From::from(self.or);
+                return Some(l_entry)
+            }
+            self.rhs.rewind(self.d_entry.as_ref()?);
+            self.in_rhs = true;
+        }
     }
 }
 
-impl<'a,D> Repeater<'a,D> for Shr<'a,D> where D: 'a + Document {
+impl<'a,D> Repeater<'a,D> for Logical<'a,D> where D: 'a + Document {
     fn repeat(&self) -> Input<'a,D> {
-        let x = Shr{ lhs: self.lhs.repeat(), rhs: self.rhs.repeat() };
-        Box::new(x)
+        if self.d_entry.is_some() {
+            panic!("cannot repeat Logical after the statement is prepared");
+        }
+        let input = self.input.repeat();
+        Box::new(Logical::new(input, self.thunks, &self.env, self.or))
     }
 }
 
-impl<'a,D> Iterator for Shr<'a,D> where D: Document {
+impl<'a,D> Iterator for Logical<'a,D> where D: 'a + Document {
     type Item=Entry<D>;
 
     fn next(&mut self) -> Option<Entry<D>> {
-        let d_lhs = self.lhs.next()?;
-        let d_rhs = self.rhs.next()?;
-        let doc = d_lhs.doc.clone() >> d_rhs.doc.clone();
-        let entries = vec![d_lhs, d_rhs];
-        Some(Entry::new_merged(entries, doc))
+        generate(self)
     }
 }
 
-impl<'a,D> Pipeline<'a,D> for Shr<'a,D> where D: 'a + Document {
+impl<'a,D> Pipeline<'a,D> for Logical<'a,D> where D: 'a + Document {
 }
 
 
-pub struct Shl<'a,D> where D: Document {
-    lhs: Input<'a,D>,
-    rhs: Input<'a,D>,
+/// Shared single-entry source, to push entries one at a time through a
+/// sub-pipeline. Every reader, including repeated readers, sees each pushed
+/// entry exactly once.
+pub struct Feed<D> where D: Document {
+    state: Rc<RefCell<(u64, Option<Entry<D>>)>>,
+    seen: u64,
 }
 
-impl<'a,D> Shl<'a,D> where D: Document {
-    pub fn new(lhs: Input<'a,D>, rhs: Input<'a,D>) -> Shl<'a,D> {
-        Shl{lhs, rhs}
+impl<D> Feed<D> where D: Document {
+    pub fn new() -> Feed<D> {
+        Feed{state: Rc::new(RefCell::new((0, None))), seen: 0}
+    }
+
+    pub fn reader(&self) -> Feed<D> {
+        let seen = self.state.borrow().0;
+        Feed{state: Rc::clone(&self.state), seen}
+    }
+
+    pub fn push(&self, entry: Entry<D>) {
+        let mut state = self.state.borrow_mut();
+        state.0 += 1;
+        state.1 = Some(entry);
     }
 }
 
-impl<'a,D> Repeater<'a,D> for Shl<'a,D> where D: 'a + Document {
+impl<'a,D> Repeater<'a,D> for Feed<D> where D: 'a + Document {
     fn repeat(&self) -> Input<'a,D> {
-        let x = Shl{ lhs: self.lhs.repeat(), rhs: self.rhs.repeat() };
-        Box::new(x)
+        Box::new(self.reader())
     }
 }
 
-impl<'a,D> Iterator for Shl<'a,D> where D: Document {
+impl<D> Iterator for Feed<D> where D: Document {
     type Item=Entry<D>;
 
     fn next(&mut self) -> Option<Entry<D>> {
-        let d_lhs = self.lhs.next()?;
-        let d_rhs = self.rhs.next()?;
-        let doc = d_lhs.doc.clone() << d_rhs.doc.clone();
-        let entries = vec![d_lhs, d_rhs];
-        Some(Entry::new_merged(entries, doc))
+        let state = self.state.borrow();
+        if state.0 > self.seen {
+            self.seen = state.0;
+            state.1.clone()
+        } else {
+            None
+        }
     }
 }
 
-impl<'a,D> Pipeline<'a,D> for Shl<'a,D> where D: 'a + Document {
+impl<'a,D> Pipeline<'a,D> for Feed<D> where D: 'a + Document {
 }
 
 
-pub struct Bitand<'a,D> where D: Document {
-    lhs: Input<'a,D>,
-    rhs: Input<'a,D>,
+/// Thunk prepared over a feed, to evaluate it for one entry at a time.
+pub struct Subquery<'a,D> where D: 'a + Document {
+    feed: Feed<D>,
+    output: Input<'a,D>,
 }
 
-impl<'a,D> Bitand<'a,D> where D: Document {
-    pub fn new(lhs: Input<'a,D>, rhs: Input<'a,D>) -> Bitand<'a,D> {
-        Bitand{lhs, rhs}
+impl<'a,D> Subquery<'a,D> where D: 'a + Document {
+    pub fn new(thunk: &'a Thunk, env: &Context<'a,D>) -> Subquery<'a,D> {
+        let feed = Feed::new();
+        let output = thunk.prepare(Box::new(feed.reader()), env);
+        Subquery{feed, output}
     }
-}
 
-impl<'a,D> Repeater<'a,D> for Bitand<'a,D> where D: 'a + Document {
-    fn repeat(&self) -> Input<'a,D> {
-        let x = Bitand{ lhs: self.lhs.repeat(), rhs: self.rhs.repeat() };
-        Box::new(x)
+    // prepare thunk ignoring its `?` suppression, if any.
+    fn new_stage(thunk: &'a Thunk, env: &Context<'a,D>) -> Subquery<'a,D> {
+        let feed = Feed::new();
+        let output = thunk.prepare_stage(Box::new(feed.reader()), env);
+        Subquery{feed, output}
     }
-}
 
-impl<'a,D> Iterator for Bitand<'a,D> where D: Document {
-    type Item=Entry<D>;
+    pub fn run(&mut self, entry: Entry<D>) -> Vec<Entry<D>> {
+        self.feed.push(entry);
+        self.output.by_ref().collect()
+    }
 
-    fn next(&mut self) -> Option<Entry<D>> {
-        let d_lhs = self.lhs.next()?;
-        let d_rhs = self.rhs.next()?;
-        let doc = d_lhs.doc.clone() & d_rhs.doc.clone();
-        let entries = vec![d_lhs, d_rhs];
-        Some(Entry::new_merged(entries, doc))
+    /// Output documents for `doc`, or the first error.
+    pub fn values(&mut self, doc: D) -> Result<Vec<D>,D> {
+        let mut values = Vec::new();
+        for entry in self.run(Entry::new(doc)).into_iter() {
+            match entry.error_value() {
+                Some(err) => return Err(err),
+                None => values.push(entry.doc),
+            }
+        }
+        Ok(values)
     }
-}
 
-impl<'a,D> Pipeline<'a,D> for Bitand<'a,D> where D: 'a + Document {
-}
+    /// Outputs for `entry`, evaluated as they are consumed. Outputs left
+    /// unconsumed would leak into the next entry, so use this only once.
+    pub fn stream(&mut self, entry: Entry<D>) -> &mut Input<'a,D> {
+        self.feed.push(entry);
+        &mut self.output
+    }
 
+    /// Evaluate for `entry`, its outputs are pulled one at a time with
+    /// `pull`, all of them before pushing the next entry.
+    pub fn push(&mut self, entry: Entry<D>) {
+        self.feed.push(entry);
+    }
 
-pub struct Bitor<'a,D> where D: Document {
-    lhs: Input<'a,D>,
-    rhs: Input<'a,D>,
+    /// Next output for the entry last pushed.
+    pub fn pull(&mut self) -> Option<Entry<D>> {
+        self.output.next()
+    }
 }
 
-impl<'a,D> Bitor<'a,D> where D: Document {
-    pub fn new(lhs: Input<'a,D>, rhs: Input<'a,D>) -> Bitor<'a,D> {
-        Bitor{lhs, rhs}
-    }
+// outputs of a subquery for an entry, pulled as they are consumed and
+// kept, to replay them from the start.
+struct Replay<'a,D> where D: 'a + Document {
+    query: Subquery<'a,D>,
+    outs: Vec<Entry<D>>,
+    pos: usize,
+    pushed: bool,
+    done: bool,
 }
 
-impl<'a,D> Repeater<'a,D> for Bitor<'a,D> where D: 'a + Document {
-    fn repeat(&self) -> Input<'a,D> {
-        let x = Bitor{ lhs: self.lhs.repeat(), rhs: self.rhs.repeat() };
-        Box::new(x)
+impl<'a,D> Replay<'a,D> where D: 'a + Document {
+    fn new(query: Subquery<'a,D>) -> Replay<'a,D> {
+        Replay{query, outs: vec![], pos: 0, pushed: false, done: false}
     }
-}
 
-impl<'a,D> Iterator for Bitor<'a,D> where D: Document {
-    type Item=Entry<D>;
+    // forget the outputs, for the next entry.
+    fn reset(&mut self) {
+        self.outs.clear();
+        self.pos = 0;
+        self.pushed = false;
+        self.done = false;
+    }
+
+    // replay outputs from the start, evaluating for `entry` on first call.
+    fn rewind(&mut self, entry: &Entry<D>) {
+        if !self.pushed {
+            self.query.push(entry.clone());
+            self.pushed = true;
+        }
+        self.pos = 0;
+    }
 
     fn next(&mut self) -> Option<Entry<D>> {
-        let d_lhs = self.lhs.next()?;
-        let d_rhs = self.rhs.next()?;
-        let doc = d_lhs.doc.clone() | d_rhs.doc.clone();
-        let entries = vec![d_lhs, d_rhs];
-        Some(Entry::new_merged(entries, doc))
+        if self.pos < self.outs.len() {
+            self.pos += 1;
+            return Some(self.outs[self.pos - 1].clone())
+        } else if self.done || !self.pushed {
+            return None
+        }
+        match self.query.pull() {
+            Some(entry) => {
+                self.outs.push(entry.clone());
+                self.pos += 1;
+                Some(entry)
+            },
+            None => { self.done = true; None },
+        }
     }
 }
 
-impl<'a,D> Pipeline<'a,D> for Bitor<'a,D> where D: 'a + Document {
+// every combination of outputs of the argument thunks, evaluated on the
+// input, the first argument varying slowest. Arguments are evaluated as
+// the combinations are consumed, through fresh subqueries for the input.
+struct ArgCombinations<'a,D> where D: 'a + Document {
+    d_entry: Entry<D>,
+    args: Vec<Replay<'a,D>>,
+    row: Vec<D>,
+    first: bool,
 }
 
+impl<'a,D> ArgCombinations<'a,D> where D: 'a + Document {
+    fn new<I>(thunks: I, env: &Context<'a,D>, d_entry: &Entry<D>)
+        -> ArgCombinations<'a,D>
+        where I: IntoIterator<Item=&'a Thunk>
+    {
+        let args = thunks.into_iter()
+            .map(|thunk| Replay::new(Subquery::new(thunk, env)))
+            .collect();
+        let d_entry = Entry::new(d_entry.doc.clone());
+        ArgCombinations{d_entry, args, row: vec![], first: true}
+    }
 
-pub struct Bitxor<'a,D> where D: Document {
-    lhs: Input<'a,D>,
-    rhs: Input<'a,D>,
+    // next combination, or the first error from the arguments.
+    fn next(&mut self) -> Option<Result<Vec<D>,D>> {
+        let n = self.args.len();
+        // advance the last argument that has more outputs, and restart the
+        // arguments after it.
+        let mut i = n;
+        if self.first {
+            self.first = false;
+            i = 0;
+        } else {
+            loop {
+                if i == 0 { return None }
+                i -= 1;
+                if let Some(entry) = self.args[i].next() {
+                    self.row.truncate(i);
+                    match entry.error_value() {
+                        Some(err) => return Some(Err(err)),
+                        None => self.row.push(entry.doc),
+                    }
+                    i += 1;
+                    break
+                }
+            }
+        }
+        for arg in self.args[i..].iter_mut() {
+            arg.rewind(&self.d_entry);
+            let entry = arg.next()?;
+            match entry.error_value() {
+                Some(err) => return Some(Err(err)),
+                None => self.row.push(entry.doc),
+            }
+        }
+        Some(Ok(self.row.clone()))
+    }
 }
 
-impl<'a,D> Bitxor<'a,D> where D: Document {
-    pub fn new(lhs: Input<'a,D>, rhs: Input<'a,D>) -> Bitxor<'a,D> {
-        Bitxor{lhs, rhs}
-    }
+
+// stages with any number of outputs for every input, computed as they
+// are consumed, so that outputs not consumed are never evaluated.
+trait Generator<D> where D: Document {
+    fn ahead(&mut self) -> &mut Ahead<D>;
+
+    // move to the next input, None if there are no more inputs.
+    fn start(&mut self) -> Option<()>;
+
+    // next output for the current input, None after its last output.
+    fn pull(&mut self) -> Option<Entry<D>>;
 }
 
-impl<'a,D> Repeater<'a,D> for Bitxor<'a,D> where D: 'a + Document {
-    fn repeat(&self) -> Input<'a,D> {
-        let x = Bitxor{ lhs: self.lhs.repeat(), rhs: self.rhs.repeat() };
-        Box::new(x)
+// next output of the generator, positioned as `entry::fixpositions` does
+// for all the outputs of an input, by pulling one output ahead.
+fn generate<D,G>(gen: &mut G) -> Option<Entry<D>>
+    where D: Document, G: Generator<D>
+{
+    loop {
+        if !gen.ahead().active {
+            gen.start()?;
+            gen.ahead().active = true;
+        }
+        let entry = match gen.pull() {
+            Some(entry) => gen.ahead().push(entry),
+            None => gen.ahead().finish(),
+        };
+        if entry.is_some() { return entry }
     }
 }
 
-impl<'a,D> Iterator for Bitxor<'a,D> where D: Document {
-    type Item=Entry<D>;
+struct Ahead<D> where D: Document {
+    active: bool,
+    entry: Option<Entry<D>>,
+    n: usize,
+}
 
-    fn next(&mut self) -> Option<Entry<D>> {
-        let d_lhs = self.lhs.next()?;
-        let d_rhs = self.rhs.next()?;
-        let doc = d_lhs.doc.clone() ^ d_rhs.doc.clone();
-        let entries = vec![d_lhs, d_rhs];
-        Some(Entry::new_merged(entries, doc))
+impl<D> Ahead<D> where D: Document {
+    fn new() -> Ahead<D> {
+        Ahead{active: false, entry: None, n: 0}
+    }
+
+    fn push(&mut self, mut entry: Entry<D>) -> Option<Entry<D>> {
+        entry.set_iter_position(IterPosition::Item);
+        self.n += 1;
+        self.entry.replace(entry)
     }
-}
 
-impl<'a,D> Pipeline<'a,D> for Bitxor<'a,D> where D: 'a + 'a + Document {
+    fn finish(&mut self) -> Option<Entry<D>> {
+        let mut entry = self.entry.take();
+        if let (Some(entry), true) = (entry.as_mut(), self.n > 1) {
+            entry.set_iter_position(IterPosition::End);
+        }
+        self.active = false;
+        self.n = 0;
+        entry
+    }
 }
 
 
-pub struct Eq<'a,D> where D: Document {
-    lhs: Input<'a,D>,
-    rhs: Input<'a,D>,
+pub struct Bind<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    thunks: (&'a Thunk, &'a str, &'a Thunk),
+    env: Context<'a,D>,
+    slot: Slot<D>,
+    source: Subquery<'a,D>,
+    body: Subquery<'a,D>,
+    iter: Option<vec::IntoIter<Entry<D>>>,
 }
 
-impl<'a,D> Eq<'a,D> where D: Document {
-    pub fn new(lhs: Input<'a,D>, rhs: Input<'a,D>) -> Eq<'a,D> {
-        Eq{lhs, rhs}
+impl<'a,D> Bind<'a,D> where D: 'a + Document {
+    pub fn new(
+        input: Input<'a,D>,
+        thunks: (&'a Thunk, &'a str, &'a Thunk),
+        env: &Context<'a,D>) -> Bind<'a,D>
+    {
+        let (source_thunk, name, body_thunk) = thunks;
+        // source is evaluated outside the scope of its own binding.
+        let source = Subquery::new(source_thunk, env);
+        let (body_env, slot) = env.bind(name);
+        let body = Subquery::new(body_thunk, &body_env);
+
+        let env = env.clone();
+        Bind{input, thunks, env, slot, source, body, iter: None}
+    }
+
+    fn values(&mut self) -> Option<Vec<Entry<D>>> {
+        let d_entry = self.input.next()?;
+        if d_entry.has_error() { return Some(vec![d_entry]) }
+
+        let mut entries = Vec::new();
+        for value in self.source.run(d_entry.clone()).into_iter() {
+            if value.has_error() { entries.push(value); continue }
+            *self.slot.borrow_mut() = value.doc;
+            entries.extend(self.body.run(d_entry.clone()));
+        }
+        Some(entry::fixpositions(entries))
     }
 }
 
-impl<'a,D> Repeater<'a,D> for Eq<'a,D> where D: 'a + Document {
+impl<'a,D> Repeater<'a,D> for Bind<'a,D> where D: 'a + Document {
     fn repeat(&self) -> Input<'a,D> {
-        let x = Eq{ lhs: self.lhs.repeat(), rhs: self.rhs.repeat() };
-        Box::new(x)
+        if let Some(_) = self.iter {
+            panic!("cannot repeat Bind after the statement is prepared");
+        }
+        Box::new(Bind::new(self.input.repeat(), self.thunks, &self.env))
     }
 }
 
-impl<'a,D> Iterator for Eq<'a,D> where D: Document {
+impl<'a,D> Iterator for Bind<'a,D> where D: 'a + Document {
     type Item=Entry<D>;
 
     fn next(&mut self) -> Option<Entry<D>> {
-        let d_lhs = self.lhs.next()?;
-        let d_rhs = self.rhs.next()?;
-        let doc = From::from(d_lhs.doc.clone() == d_rhs.doc.clone());
-        let entries = vec![d_lhs, d_rhs];
-        Some(Entry::new_merged(entries, doc))
+        let entry = loop {
+            match self.iter.as_mut().and_then(|iter| iter.next()) {
+                Some(entry) => break entry,
+                None => { self.iter = Some(self.values()?.into_iter()); },
+            }
+        };
+        Some(entry)
     }
 }
 
-impl<'a,D> Pipeline<'a,D> for Eq<'a,D> where D: 'a + Document {
+impl<'a,D> Pipeline<'a,D> for Bind<'a,D> where D: 'a + Document {
 }
 
 
-pub struct Ne<'a,D> where D: Document {
-    lhs: Input<'a,D>,
-    rhs: Input<'a,D>,
+pub struct Var<'a,D> where D: Document {
+    input: Input<'a,D>,
+    name: String,
+    slot: Option<Slot<D>>,
 }
 
-impl<'a,D> Ne<'a,D> where D: Document {
-    pub fn new(lhs: Input<'a,D>, rhs: Input<'a,D>) -> Ne<'a,D> {
-        Ne{lhs, rhs}
+impl<'a,D> Var<'a,D> where D: Document {
+    pub fn new(input: Input<'a,D>, name: String, env: &Context<'a,D>)
+        -> Var<'a,D>
+    {
+        let slot = match env.get(&name) {
+            None if name == "ENV" => {
+                Some(Rc::new(RefCell::new(builtin_path::environ())))
+            },
+            slot => slot,
+        };
+        Var{input, name, slot}
     }
 }
 
-impl<'a,D> Repeater<'a,D> for Ne<'a,D> where D: 'a + Document {
+impl<'a,D> Repeater<'a,D> for Var<'a,D> where D: 'a + Document {
     fn repeat(&self) -> Input<'a,D> {
-        let x = Ne{ lhs: self.lhs.repeat(), rhs: self.rhs.repeat() };
-        Box::new(x)
+        let (name, slot) = (self.name.clone(), self.slot.clone());
+        Box::new(Var{input: self.input.repeat(), name, slot})
     }
 }
 
-impl<'a,D> Iterator for Ne<'a,D> where D: Document {
+impl<'a,D> Iterator for Var<'a,D> where D: Document {
     type Item=Entry<D>;
 
     fn next(&mut self) -> Option<Entry<D>> {
-        let d_lhs = self.lhs.next()?;
-        let d_rhs = self.rhs.next()?;
-        let doc = From::from(d_lhs.doc.clone() != d_rhs.doc.clone());
-        let entries = vec![d_lhs, d_rhs];
-        Some(Entry::new_merged(entries, doc))
+        let mut d_entry = self.input.next()?;
+        if d_entry.has_error() { return Some(d_entry) }
+
+        match self.slot.as_ref() {
+            Some(slot) => d_entry.doc = slot.borrow().clone(),
+            None => {
+                d_entry.doc = D::null();
+                d_entry.set_error(format!("${} is not defined", self.name));
+            },
+        }
+        Some(d_entry)
     }
 }
 
-impl<'a,D> Pipeline<'a,D> for Ne<'a,D> where D: 'a + Document {
+impl<'a,D> Pipeline<'a,D> for Var<'a,D> where D: 'a + Document {
 }
 
 
-pub struct Lt<'a,D> where D: Document {
-    lhs: Input<'a,D>,
-    rhs: Input<'a,D>,
+pub struct Call<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    func: Func<'a,D>,
+    args: &'a [Thunk],
+    env: Context<'a,D>,
+    body: Option<Subquery<'a,D>>,
+    iter: Option<vec::IntoIter<Entry<D>>>,
 }
 
-impl<'a,D> Lt<'a,D> where D: Document {
-    pub fn new(lhs: Input<'a,D>, rhs: Input<'a,D>) -> Lt<'a,D> {
-        Lt{lhs, rhs}
+impl<'a,D> Call<'a,D> where D: 'a + Document {
+    pub fn new(
+        input: Input<'a,D>,
+        func: Func<'a,D>,
+        args: &'a [Thunk],
+        env: &Context<'a,D>) -> Call<'a,D>
+    {
+        let env = env.clone();
+        Call{input, func, args, env, body: None, iter: None}
+    }
+
+    fn values(&mut self) -> Option<Vec<Entry<D>>> {
+        let d_entry = self.input.next()?;
+        if d_entry.has_error() { return Some(vec![d_entry]) }
+
+        // body is prepared lazily, otherwise recursive functions will
+        // never finish preparing.
+        if self.body.is_none() {
+            let env = self.func.call_env(self.args, &self.env);
+            self.body = Some(Subquery::new(self.func.body, &env));
+        }
+        let entries = self.body.as_mut().unwrap().run(d_entry);
+        Some(entry::fixpositions(entries))
     }
 }
 
-impl<'a,D> Repeater<'a,D> for Lt<'a,D> where D: 'a + Document {
+impl<'a,D> Repeater<'a,D> for Call<'a,D> where D: 'a + Document {
     fn repeat(&self) -> Input<'a,D> {
-        let x = Lt{ lhs: self.lhs.repeat(), rhs: self.rhs.repeat() };
-        Box::new(x)
+        if let Some(_) = self.iter {
+            panic!("cannot repeat Call after the statement is prepared");
+        }
+        let (func, args) = (self.func.clone(), self.args);
+        Box::new(Call::new(self.input.repeat(), func, args, &self.env))
     }
 }
 
-impl<'a,D> Iterator for Lt<'a,D> where D: Document {
+impl<'a,D> Iterator for Call<'a,D> where D: 'a + Document {
     type Item=Entry<D>;
 
     fn next(&mut self) -> Option<Entry<D>> {
-        let d_lhs = self.lhs.next()?;
-        let d_rhs = self.rhs.next()?;
-        let doc = From::from(d_lhs.doc.clone() < d_rhs.doc.clone());
-        let entries = vec![d_lhs, d_rhs];
-        Some(Entry::new_merged(entries, doc))
+        let entry = loop {
+            match self.iter.as_mut().and_then(|iter| iter.next()) {
+                Some(entry) => break entry,
+                None => { self.iter = Some(self.values()?.into_iter()); },
+            }
+        };
+        Some(entry)
     }
 }
 
-impl<'a,D> Pipeline<'a,D> for Lt<'a,D> where D: 'a + Document {
+impl<'a,D> Pipeline<'a,D> for Call<'a,D> where D: 'a + Document {
 }
 
 
-pub struct Le<'a,D> where D: Document {
-    lhs: Input<'a,D>,
-    rhs: Input<'a,D>,
+pub struct If<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    thunks: (&'a Thunk, &'a Thunk, &'a Thunk),
+    env: Context<'a,D>,
+    cond: Subquery<'a,D>,
+    then: Subquery<'a,D>,
+    otherwise: Subquery<'a,D>,
+    iter: Option<vec::IntoIter<Entry<D>>>,
 }
 
-impl<'a,D> Le<'a,D> where D: Document {
-    pub fn new(lhs: Input<'a,D>, rhs: Input<'a,D>) -> Le<'a,D> {
-        Le{lhs, rhs}
+impl<'a,D> If<'a,D> where D: 'a + Document {
+    pub fn new(
+        input: Input<'a,D>,
+        thunks: (&'a Thunk, &'a Thunk, &'a Thunk),
+        env: &Context<'a,D>) -> If<'a,D>
+    {
+        let cond = Subquery::new(thunks.0, env);
+        let then = Subquery::new(thunks.1, env);
+        let otherwise = Subquery::new(thunks.2, env);
+        let env = env.clone();
+        If{input, thunks, env, cond, then, otherwise, iter: None}
+    }
+
+    fn values(&mut self) -> Option<Vec<Entry<D>>> {
+        let d_entry = self.input.next()?;
+        if d_entry.has_error() { return Some(vec![d_entry]) }
+
+        // every output from the condition selects a branch.
+        let mut entries = Vec::new();
+        for c_entry in self.cond.run(d_entry.clone()).into_iter() {
+            if c_entry.has_error() {
+                entries.push(c_entry)
+            } else if is_truthy(&c_entry.doc) {
+                entries.extend(self.then.run(d_entry.clone()))
+            } else {
+                entries.extend(self.otherwise.run(d_entry.clone()))
+            }
+        }
+        Some(entry::fixpositions(entries))
     }
 }
 
-impl<'a,D> Repeater<'a,D> for Le<'a,D> where D: 'a + Document {
+impl<'a,D> Repeater<'a,D> for If<'a,D> where D: 'a + Document {
     fn repeat(&self) -> Input<'a,D> {
-        let x = Le{ lhs: self.lhs.repeat(), rhs: self.rhs.repeat() };
-        Box::new(x)
+        if let Some(_) = self.iter {
+            panic!("cannot repeat If after the statement is prepared");
+        }
+        Box::new(If::new(self.input.repeat(), self.thunks, &self.env))
     }
 }
 
-impl<'a,D> Iterator for Le<'a,D> where D: Document {
+impl<'a,D> Iterator for If<'a,D> where D: 'a + Document {
     type Item=Entry<D>;
 
     fn next(&mut self) -> Option<Entry<D>> {
-        let d_lhs = self.lhs.next()?;
-        let d_rhs = self.rhs.next()?;
-        let doc = From::from(d_lhs.doc.clone() >= d_rhs.doc.clone());
-        let entries = vec![d_lhs, d_rhs];
-        Some(Entry::new_merged(entries, doc))
+        let entry = loop {
+            match self.iter.as_mut().and_then(|iter| iter.next()) {
+                Some(entry) => break entry,
+                None => { self.iter = Some(self.values()?.into_iter()); },
+            }
+        };
+        Some(entry)
     }
 }
 
-impl<'a,D> Pipeline<'a,D> for Le<'a,D> where D: 'a + Document {
+impl<'a,D> Pipeline<'a,D> for If<'a,D> where D: 'a + Document {
 }
 
 
-pub struct Gt<'a,D> where D: Document {
-    lhs: Input<'a,D>,
-    rhs: Input<'a,D>,
+pub struct Alternative<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    thunks: (&'a Thunk, &'a Thunk),
+    env: Context<'a,D>,
+    lhs: Subquery<'a,D>,
+    rhs: Subquery<'a,D>,
+    iter: Option<vec::IntoIter<Entry<D>>>,
 }
 
-impl<'a,D> Gt<'a,D> where D: Document {
-    pub fn new(lhs: Input<'a,D>, rhs: Input<'a,D>) -> Gt<'a,D> {
-        Gt{lhs, rhs}
+impl<'a,D> Alternative<'a,D> where D: 'a + Document {
+    pub fn new(
+        input: Input<'a,D>,
+        thunks: (&'a Thunk, &'a Thunk),
+        env: &Context<'a,D>) -> Alternative<'a,D>
+    {
+        let lhs = Subquery::new(thunks.0, env);
+        let rhs = Subquery::new(thunks.1, env);
+        let env = env.clone();
+        Alternative{input, thunks, env, lhs, rhs, iter: None}
+    }
+
+    fn values(&mut self) -> Option<Vec<Entry<D>>> {
+        let d_entry = self.input.next()?;
+        if d_entry.has_error() { return Some(vec![d_entry]) }
+
+        // errors, null and false from lhs are dropped.
+        let entries: Vec<Entry<D>> = self.lhs.run(d_entry.clone())
+            .into_iter()
+            .filter(|e| !e.has_error() && is_truthy(&e.doc))
+            .collect();
+        if entries.len() > 0 {
+            Some(entry::fixpositions(entries))
+        } else {
+            Some(entry::fixpositions(self.rhs.run(d_entry)))
+        }
     }
 }
 
-impl<'a,D> Repeater<'a,D> for Gt<'a,D> where D: 'a + Document {
+impl<'a,D> Repeater<'a,D> for Alternative<'a,D> where D: 'a + Document {
     fn repeat(&self) -> Input<'a,D> {
-        let x = Gt{ lhs: self.lhs.repeat(), rhs: self.rhs.repeat() };
-        Box::new(x)
+        if let Some(_) = self.iter {
+            panic!("cannot repeat Alternative after the statement is prepared");
+        }
+        let input = self.input.repeat();
+        Box::new(Alternative::new(input, self.thunks, &self.env))
     }
 }
 
-impl<'a,D> Iterator for Gt<'a,D> where D: Document {
+impl<'a,D> Iterator for Alternative<'a,D> where D: 'a + Document {
     type Item=Entry<D>;
 
     fn next(&mut self) -> Option<Entry<D>> {
-        let d_lhs = self.lhs.next()?;
-        let d_rhs = self.rhs.next()?;
-        let doc = From::from(d_lhs.doc.clone() > d_rhs.doc.clone());
-        let entries = vec![d_lhs, d_rhs];
-        Some(Entry::new_merged(entries, doc))
+        let entry = loop {
+            match self.iter.as_mut().and_then(|iter| iter.next()) {
+                Some(entry) => break entry,
+                None => { self.iter = Some(self.values()?.into_iter()); },
+            }
+        };
+        Some(entry)
     }
 }
 
-impl<'a,D> Pipeline<'a,D> for Gt<'a,D> where D: 'a + Document {
+impl<'a,D> Pipeline<'a,D> for Alternative<'a,D> where D: 'a + Document {
 }
 
 
-pub struct Ge<'a,D> where D: Document {
-    lhs: Input<'a,D>,
-    rhs: Input<'a,D>,
+/// `lhs, rhs`, all the outputs of lhs followed by all the outputs of rhs,
+/// both evaluated on the same input. Outputs are evaluated as they are
+/// consumed.
+pub struct Comma<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    thunks: (&'a Thunk, &'a Thunk),
+    env: Context<'a,D>,
+    lhs: Subquery<'a,D>,
+    rhs: Subquery<'a,D>,
+    ahead: Ahead<D>,
+    d_entry: Option<Entry<D>>,
+    error: Option<Entry<D>>,
+}
+
+impl<'a,D> Comma<'a,D> where D: 'a + Document {
+    pub fn new(
+        input: Input<'a,D>,
+        thunks: (&'a Thunk, &'a Thunk),
+        env: &Context<'a,D>) -> Comma<'a,D>
+    {
+        let lhs = Subquery::new(thunks.0, env);
+        let rhs = Subquery::new(thunks.1, env);
+        Comma{
+            input, thunks, env: env.clone(), lhs, rhs,
+            ahead: Ahead::new(), d_entry: None, error: None,
+        }
+    }
 }
 
-impl<'a,D> Ge<'a,D> where D: Document {
-    pub fn new(lhs: Input<'a,D>, rhs: Input<'a,D>) -> Ge<'a,D> {
-        Ge{lhs, rhs}
+impl<'a,D> Generator<D> for Comma<'a,D> where D: 'a + Document {
+    fn ahead(&mut self) -> &mut Ahead<D> {
+        &mut self.ahead
+    }
+
+    fn start(&mut self) -> Option<()> {
+        let d_entry = self.input.next()?;
+        self.error = None;
+        self.d_entry = None;
+        if d_entry.has_error() {
+            self.error = Some(d_entry);
+        } else {
+            self.lhs.push(d_entry.clone());
+            self.d_entry = Some(d_entry);
+        }
+        Some(())
+    }
+
+    fn pull(&mut self) -> Option<Entry<D>> {
+        if let Some(entry) = self.error.take() { return Some(entry) }
+        if let Some(entry) = self.lhs.pull() { return Some(entry) }
+        // rhs is evaluated once lhs has no more outputs.
+        if let Some(d_entry) = self.d_entry.take() {
+            self.rhs.push(d_entry);
+        }
+        self.rhs.pull()
     }
 }
 
-impl<'a,D> Repeater<'a,D> for Ge<'a,D> where D: 'a + Document {
+impl<'a,D> Repeater<'a,D> for Comma<'a,D> where D: 'a + Document {
     fn repeat(&self) -> Input<'a,D> {
-        let x = Ge{ lhs: self.lhs.repeat(), rhs: self.rhs.repeat() };
-        Box::new(x)
+        if self.ahead.active {
+            panic!("cannot repeat Comma after the statement is prepared");
+        }
+        let input = self.input.repeat();
+        Box::new(Comma::new(input, self.thunks, &self.env))
     }
 }
 
-impl<'a,D> Iterator for Ge<'a,D> where D: Document {
+impl<'a,D> Iterator for Comma<'a,D> where D: 'a + Document {
     type Item=Entry<D>;
 
     fn next(&mut self) -> Option<Entry<D>> {
-        let d_lhs = self.lhs.next()?;
-        let d_rhs = self.rhs.next()?;
-        let doc = From::from(d_lhs.doc.clone() >= d_rhs.doc.clone());
-        let entries = vec![d_lhs, d_rhs];
-        Some(Entry::new_merged(entries, doc))
+        generate(self)
     }
 }
 
-impl<'a,D> Pipeline<'a,D> for Ge<'a,D> where D: 'a + Document {
+impl<'a,D> Pipeline<'a,D> for Comma<'a,D> where D: 'a + Document {
 }
 
 
-pub struct And<'a,D> where D: Document {
-    lhs: Input<'a,D>,
-    rhs: Input<'a,D>,
+pub struct Reduce<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    thunks: (&'a Thunk, &'a str, &'a Thunk, &'a Thunk),
+    env: Context<'a,D>,
+    slot: Slot<D>,
+    source: Subquery<'a,D>,
+    init: Subquery<'a,D>,
+    update: Subquery<'a,D>,
+    iter: Option<vec::IntoIter<Entry<D>>>,
 }
 
-impl<'a,D> And<'a,D> where D: Document {
-    pub fn new(lhs: Input<'a,D>, rhs: Input<'a,D>) -> And<'a,D> {
-        And{lhs, rhs}
+impl<'a,D> Reduce<'a,D> where D: 'a + Document {
+    pub fn new(
+        input: Input<'a,D>,
+        thunks: (&'a Thunk, &'a str, &'a Thunk, &'a Thunk),
+        env: &Context<'a,D>) -> Reduce<'a,D>
+    {
+        let (source, name, init, update) = thunks;
+        let source = Subquery::new(source, env);
+        let init = Subquery::new(init, env);
+        let (body_env, slot) = env.bind(name);
+        let update = Subquery::new(update, &body_env);
+        let env = env.clone();
+        Reduce{input, thunks, env, slot, source, init, update, iter: None}
+    }
+
+    fn values(&mut self) -> Option<Vec<Entry<D>>> {
+        let d_entry = self.input.next()?;
+        if d_entry.has_error() { return Some(vec![d_entry]) }
+
+        let values = self.source.run(d_entry.clone());
+        let mut entries = Vec::new();
+        for mut acc in self.init.run(d_entry).into_iter() {
+            for value in values.iter() {
+                if acc.has_error() { break }
+                if value.has_error() { acc = value.clone(); break }
+                *self.slot.borrow_mut() = value.doc.clone();
+                // state is the last output of update, null if none.
+                let mut outs = self.update.run(acc);
+                acc = match outs.iter().position(|e| e.has_error()) {
+                    Some(i) => outs.remove(i),
+                    None => outs.pop().unwrap_or(Entry::new(D::null())),
+                };
+            }
+            entries.push(acc);
+        }
+        Some(entry::fixpositions(entries))
     }
 }
 
-impl<'a,D> Repeater<'a,D> for And<'a,D> where D: 'a + Document {
+impl<'a,D> Repeater<'a,D> for Reduce<'a,D> where D: 'a + Document {
     fn repeat(&self) -> Input<'a,D> {
-        let x = And{ lhs: self.lhs.repeat(), rhs: self.rhs.repeat() };
-        Box::new(x)
+        if let Some(_) = self.iter {
+            panic!("cannot repeat Reduce after the statement is prepared");
+        }
+        Box::new(Reduce::new(self.input.repeat(), self.thunks, &self.env))
     }
 }
 
-impl<'a,D> Iterator for And<'a,D> where D: Document {
+impl<'a,D> Iterator for Reduce<'a,D> where D: 'a + Document {
     type Item=Entry<D>;
 
     fn next(&mut self) -> Option<Entry<D>> {
-        let d_lhs = self.lhs.next()?;
-        let d_rhs = self.rhs.next()?;
-        let doc: D = From::from(d_lhs.doc.clone().and(d_rhs.doc.clone()));
-        let entries = vec![d_lhs, d_rhs];
-        Some(Entry::new_merged(entries, doc))
+        let entry = loop {
+            match self.iter.as_mut().and_then(|iter| iter.next()) {
+                Some(entry) => break entry,
+                None => { self.iter = Some(self.values()?.into_iter()); },
+            }
+        };
+        Some(entry)
     }
 }
 
-impl<'a,D> Pipeline<'a,D> for And<'a,D> where D: 'a + Document {
+impl<'a,D> Pipeline<'a,D> for Reduce<'a,D> where D: 'a + Document {
 }
 
 
-pub struct Or<'a,D> where D: Document {
-    lhs: Input<'a,D>,
-    rhs: Input<'a,D>,
+pub struct Foreach<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    thunks: (&'a Thunk, &'a str, &'a Thunk, &'a Thunk, &'a Thunk),
+    env: Context<'a,D>,
+    slot: Slot<D>,
+    source: Subquery<'a,D>,
+    init: Subquery<'a,D>,
+    update: Subquery<'a,D>,
+    extract: Subquery<'a,D>,
+    iter: Option<vec::IntoIter<Entry<D>>>,
 }
 
-impl<'a,D> Or<'a,D> where D: Document {
-    pub fn new(lhs: Input<'a,D>, rhs: Input<'a,D>) -> Or<'a,D> {
-        Or{lhs, rhs}
+impl<'a,D> Foreach<'a,D> where D: 'a + Document {
+    pub fn new(
+        input: Input<'a,D>,
+        thunks: (&'a Thunk, &'a str, &'a Thunk, &'a Thunk, &'a Thunk),
+        env: &Context<'a,D>) -> Foreach<'a,D>
+    {
+        let (source, name, init, update, extract) = thunks;
+        let source = Subquery::new(source, env);
+        let init = Subquery::new(init, env);
+        let (body_env, slot) = env.bind(name);
+        let update = Subquery::new(update, &body_env);
+        let extract = Subquery::new(extract, &body_env);
+        let env = env.clone();
+        Foreach{
+            input, thunks, env, slot, source, init, update, extract,
+            iter: None,
+        }
+    }
+
+    fn values(&mut self) -> Option<Vec<Entry<D>>> {
+        let d_entry = self.input.next()?;
+        if d_entry.has_error() { return Some(vec![d_entry]) }
+
+        let values = self.source.run(d_entry.clone());
+        let mut entries = Vec::new();
+        for mut acc in self.init.run(d_entry).into_iter() {
+            if acc.has_error() { entries.push(acc); continue }
+            'outer: for value in values.iter() {
+                if value.has_error() { entries.push(value.clone()); break }
+                *self.slot.borrow_mut() = value.doc.clone();
+                // every output of update is a new state to extract from.
+                for state in self.update.run(acc.clone()).into_iter() {
+                    if state.has_error() { entries.push(state); break 'outer }
+                    acc = state;
+                    entries.extend(self.extract.run(acc.clone()));
+                }
+            }
+        }
+        Some(entry::fixpositions(entries))
     }
 }
 
-impl<'a,D> Repeater<'a,D> for Or<'a,D> where D: 'a + Document {
+impl<'a,D> Repeater<'a,D> for Foreach<'a,D> where D: 'a + Document {
     fn repeat(&self) -> Input<'a,D> {
-        let x = Or{ lhs: self.lhs.repeat(), rhs: self.rhs.repeat() };
-        Box::new(x)
+        if let Some(_) = self.iter {
+            panic!("cannot repeat Foreach after the statement is prepared");
+        }
+        Box::new(Foreach::new(self.input.repeat(), self.thunks, &self.env))
+    }
+}
+
+impl<'a,D> Iterator for Foreach<'a,D> where D: 'a + Document {
+    type Item=Entry<D>;
+
+    fn next(&mut self) -> Option<Entry<D>> {
+        let entry = loop {
+            match self.iter.as_mut().and_then(|iter| iter.next()) {
+                Some(entry) => break entry,
+                None => { self.iter = Some(self.values()?.into_iter()); },
+            }
+        };
+        Some(entry)
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for Foreach<'a,D> where D: 'a + Document {
+}
+
+
+pub struct Try<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    thunks: (&'a Thunk, Option<&'a Thunk>),
+    env: Context<'a,D>,
+    body: Subquery<'a,D>,
+    catch: Option<Subquery<'a,D>>,
+    iter: Option<vec::IntoIter<Entry<D>>>,
+}
+
+impl<'a,D> Try<'a,D> where D: 'a + Document {
+    pub fn new(
+        input: Input<'a,D>,
+        thunks: (&'a Thunk, Option<&'a Thunk>),
+        env: &Context<'a,D>) -> Try<'a,D>
+    {
+        let (body, catch) = match thunks.1 {
+            Some(handler) => {
+                let catch = Some(Subquery::new(handler, env));
+                (Subquery::new(thunks.0, env), catch)
+            },
+            // `expr?` is same as `try expr`
+            None => (Subquery::new_stage(thunks.0, env), None),
+        };
+        let env = env.clone();
+        Try{input, thunks, env, body, catch, iter: None}
+    }
+
+    fn values(&mut self) -> Option<Vec<Entry<D>>> {
+        let d_entry = self.input.next()?;
+        // errors from upstream are not raised by the body.
+        if d_entry.has_error() { return Some(vec![d_entry]) }
+
+        let mut entries = Vec::new();
+        for entry in self.body.run(d_entry).into_iter() {
+            if !entry.has_error() { entries.push(entry); continue }
+            // first error stops the body, handler gets the error value.
+            if let Some(catch) = self.catch.as_mut() {
+                let err = entry.error_value().unwrap();
+                entries.extend(catch.run(Entry::new(err)));
+            }
+            break
+        }
+        Some(entry::fixpositions(entries))
+    }
+}
+
+impl<'a,D> Repeater<'a,D> for Try<'a,D> where D: 'a + Document {
+    fn repeat(&self) -> Input<'a,D> {
+        if let Some(_) = self.iter {
+            panic!("cannot repeat Try after the statement is prepared");
+        }
+        Box::new(Try::new(self.input.repeat(), self.thunks, &self.env))
+    }
+}
+
+impl<'a,D> Iterator for Try<'a,D> where D: 'a + Document {
+    type Item=Entry<D>;
+
+    fn next(&mut self) -> Option<Entry<D>> {
+        let entry = loop {
+            match self.iter.as_mut().and_then(|iter| iter.next()) {
+                Some(entry) => break entry,
+                None => { self.iter = Some(self.values()?.into_iter()); },
+            }
+        };
+        Some(entry)
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for Try<'a,D> where D: 'a + Document {
+}
+
+
+pub struct Update<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    thunks: (&'a Thunk, &'a Thunk),
+    op: UpdateOp,
+    env: Context<'a,D>,
+    rhs: Subquery<'a,D>,
+    iter: Option<vec::IntoIter<Entry<D>>>,
+}
+
+impl<'a,D> Update<'a,D> where D: 'a + Document {
+    pub fn new(
+        input: Input<'a,D>,
+        thunks: (&'a Thunk, &'a Thunk),
+        op: UpdateOp,
+        env: &Context<'a,D>) -> Update<'a,D>
+    {
+        let rhs = Subquery::new(thunks.1, env);
+        let env = env.clone();
+        Update{input, thunks, op, env, rhs, iter: None}
+    }
+
+    fn modify(&mut self, mut doc: D, paths: Vec<Vec<D>>) -> Result<D,D> {
+        let mut dels = Vec::new();
+        for p in paths.into_iter() {
+            let old = path::getpath(&doc, &p)?;
+            // first output of rhs is the new value, none deletes the path.
+            match self.rhs.run(Entry::new(old)).into_iter().next() {
+                Some(entry) => match entry.error_value() {
+                    Some(err) => return Err(err),
+                    None => path::setpath(&mut doc, &p, entry.doc)?,
+                },
+                None => dels.push(p),
+            }
+        }
+        path::delpaths(&mut doc, dels)?;
+        Ok(doc)
+    }
+
+    fn assign(&self, mut doc: D, paths: &Vec<Vec<D>>, value: D)
+        -> Result<D,String>
+    {
+        for p in paths.iter() {
+            let old = path::getpath(&doc, p)?;
+            let value = match self.op {
+                UpdateOp::Assign => value.clone(),
+                UpdateOp::Add => old + value.clone(),
+                UpdateOp::Sub => old - value.clone(),
+                UpdateOp::Mul => old * value.clone(),
+                UpdateOp::Div => old / value.clone(),
+                UpdateOp::Rem => old % value.clone(),
+                UpdateOp::Alt if is_truthy(&old) => old,
+                UpdateOp::Alt => value.clone(),
+                UpdateOp::Modify => unreachable!(),
+            };
+            path::setpath(&mut doc, p, value)?
+        }
+        Ok(doc)
+    }
+
+    fn values(&mut self) -> Option<Vec<Entry<D>>> {
+        let mut d_entry = self.input.next()?;
+        if d_entry.has_error() { return Some(vec![d_entry]) }
+
+        let doc = d_entry.doc.clone();
+        let paths: Vec<Vec<D>> =
+            match path::paths_of(self.thunks.0, &self.env, vec![], doc) {
+                Ok(items) => items.into_iter().map(|(p, _)| p).collect(),
+                Err(err) => {
+                    d_entry.doc = D::null();
+                    d_entry.set_error_value(err);
+                    return Some(vec![d_entry])
+                },
+            };
+
+        if let UpdateOp::Modify = self.op {
+            match self.modify(d_entry.doc.clone(), paths) {
+                Ok(doc) => d_entry.doc = doc,
+                Err(err) => {
+                    d_entry.doc = D::null();
+                    d_entry.set_error_value(err);
+                },
+            }
+            return Some(vec![d_entry])
+        }
+
+        // every output of rhs, evaluated on the input, is assigned.
+        let mut entries = Vec::new();
+        for value in self.rhs.run(d_entry.clone()).into_iter() {
+            if value.has_error() { entries.push(value); continue }
+            let mut entry = d_entry.clone();
+            match self.assign(d_entry.doc.clone(), &paths, value.doc) {
+                Ok(doc) => entry.doc = doc,
+                Err(err) => { entry.doc = D::null(); entry.set_error(err) },
+            }
+            entries.push(entry);
+        }
+        Some(entry::fixpositions(entries))
+    }
+}
+
+impl<'a,D> Repeater<'a,D> for Update<'a,D> where D: 'a + Document {
+    fn repeat(&self) -> Input<'a,D> {
+        if let Some(_) = self.iter {
+            panic!("cannot repeat Update after the statement is prepared");
+        }
+        let input = self.input.repeat();
+        Box::new(Update::new(input, self.thunks, self.op, &self.env))
     }
 }
 
-impl<'a,D> Iterator for Or<'a,D> where D: Document {
+impl<'a,D> Iterator for Update<'a,D> where D: 'a + Document {
     type Item=Entry<D>;
 
     fn next(&mut self) -> Option<Entry<D>> {
-        let d_lhs = self.lhs.next()?;
-        let d_rhs = self.rhs.next()?;
-        let doc: D = From::from(d_lhs.doc.clone().or(d_rhs.doc.clone()));
-        let entries = vec![d_lhs, d_rhs];
-        Some(Entry::new_merged(entries, doc))
+        let entry = loop {
+            match self.iter.as_mut().and_then(|iter| iter.next()) {
+                Some(entry) => break entry,
+                None => { self.iter = Some(self.values()?.into_iter()); },
+            }
+        };
+        Some(entry)
     }
 }
 
-impl<'a,D> Pipeline<'a,D> for Or<'a,D> where D: 'a + Document {
+impl<'a,D> Pipeline<'a,D> for Update<'a,D> where D: 'a + Document {
 }
 
 
@@ -1577,166 +2368,2085 @@ impl<'a,D> Iterator for BuiltinKeys<'a,D> where D: Document {
 
 impl<'a,D> Pipeline<'a,D> for BuiltinKeys<'a,D> where D: 'a + Document {
 }
+pub struct BuiltinError<'a,D> where D: Document {
+    arg_input: Option<Input<'a,D>>,
+}
+
+impl<'a,D> BuiltinError<'a,D> where D: Document {
+    pub fn new(mut args: Vec<Input<D>>) -> BuiltinError<D> {
+        if args.len() == 1 {
+            BuiltinError{arg_input: Some(args.remove(0))}
+        } else {
+            BuiltinError{arg_input: None}
+        }
+    }
+}
 
+impl<'a,D> Repeater<'a,D> for BuiltinError<'a,D> where D: 'a + Document {
+    fn repeat(&self) -> Input<'a,D> {
+        let arg_input = match self.arg_input.as_ref() {
+            Some(input) => Some(input.repeat()),
+            None => None
+        };
+        Box::new(BuiltinError{arg_input})
+    }
+}
+
+impl<'a,D> Iterator for BuiltinError<'a,D> where D: Document {
+    type Item=Entry<D>;
 
+    fn next(&mut self) -> Option<Entry<D>> {
+        if self.arg_input.is_none() {
+            let mut entry = Entry::new(D::null());
+            entry.set_error(format!("invalid number of args for error"));
+            return Some(entry)
+        }
+        let mut d_entry = self.arg_input.as_mut().unwrap().next()?;
+        if d_entry.has_error() { return Some(d_entry) }
 
-//fn builtin_has<D>(args: &mut Vec<Thunk>, doc: D)
-//    -> Result<Input<D>> where D: Document
-//{
-//    assert_args_len(&args, 1)?;
-//    let item = args.index_mut(0)(doc.clone())?.remove(0);
-//    let dt = doc.doctype();
-//    match dt {
-//        Doctype::Array => {
-//            let out = <D as ItemIterator<D>>::iter(&doc).unwrap()
-//                .any(|value: &D| value == &item);
-//            Ok(vec![From::from(out)])
-//        },
-//        Doctype::Object => {
-//            let out = <D as ItemIterator<Property<D>>>::iter(&doc).unwrap()
-//                .any(|x| {
-//                    let key: D = From::from(x.key_ref().clone());
-//                    key == item
-//                });
-//            Ok(vec![From::from(out)])
-//        },
-//        _ => Err(Error::Op(None, format!("{:?} not iterable", dt))),
-//    }
-//}
-//
-//fn builtin_indoc<D>(args: &mut Vec<Thunk>, item: D)
-//    -> Result<Input<D>> where D: Document
-//{
-//    assert_args_len(&args, 1)?;
-//    let doc = args.index_mut(0)(item.clone())?.remove(0);
-//    let dt = doc.doctype();
-//    match dt {
-//        Doctype::Array => {
-//            let out = <D as ItemIterator<D>>::iter(&doc).unwrap()
-//                .any(|value: &D| value == &item);
-//            Ok(vec![From::from(out)])
-//        },
-//        Doctype::Object => {
-//            let out = <D as ItemIterator<Property<D>>>::iter(&doc).unwrap()
-//                .any(|x| {
-//                    let key: D = From::from(x.key_ref().clone());
-//                    key == item
-//                });
-//            Ok(vec![From::from(out)])
-//        },
-//        _ => Err(Error::Op(None, format!("{:?} not iterable", dt))),
-//    }
-//}
-//
-//fn builtin_map<D>(args: &mut Vec<Thunk>, doc: D)
-//    -> Result<Input<D>> where D: Document
-//{
-//    assert_args_len(&args, 1)?;
-//    let thunk = args.index_mut(0);
-//    let dt = doc.doctype();
-//    match dt {
-//        Doctype::String | Doctype::Array => {
-//            let mut out = Vec::new();
-//            for value in <D as ItemIterator<D>>::into_iter(doc).unwrap() {
-//                out.push(thunk(value)?.remove(0));
-//            }
-//            Ok(vec![From::from(out)])
-//        },
-//        Doctype::Object => {
-//            let mut out = Vec::new();
-//            for x in <D as ItemIterator<Property<D>>>::into_iter(doc).unwrap() {
-//                let key = x.key_ref().clone();
-//                out.push(Property::new(key, thunk(x.value())?.remove(0)));
-//            }
-//            Ok(vec![From::from(out)])
-//        },
-//        _ => Err(Error::Op(None, format!("cannot map over {:?}", dt))),
-//    }
-//}
-//
-//fn builtin_any<D>(args: &mut Vec<Thunk>, doc: D)
-//    -> Result<Input<D>> where D: Document
-//{
-//    assert_args_len(&args, 1)?;
-//    let thunk = args.index_mut(0);
-//    let dt = doc.doctype();
-//    match dt {
-//        Doctype::String | Doctype::Array => {
-//            for value in <D as ItemIterator<D>>::into_iter(doc).unwrap() {
-//                let out = thunk(value)?.remove(0);
-//                if out.boolean().unwrap_or(false) {
-//                    return Ok(vec![From::from(true)])
-//                }
-//            }
-//            return Ok(vec![From::from(false)])
-//        },
-//        Doctype::Object => {
-//            for x in <D as ItemIterator<Property<D>>>::into_iter(doc).unwrap() {
-//                let out = thunk(x.value())?.remove(0);
-//                if out.boolean().unwrap_or(false) {
-//                    return Ok(vec![From::from(true)])
-//                }
-//            }
-//            return Ok(vec![From::from(false)])
-//        },
-//        _ => Err(Error::Op(None, format!("cannot iterate over {:?}", dt))),
-//    }
-//}
-//
-//fn builtin_all<D>(args: &mut Vec<Thunk>, doc: D)
-//    -> Result<Input<D>> where D: Document
-//{
-//    assert_args_len(&args, 1)?;
-//    let thunk = args.index_mut(0);
-//    let dt = doc.doctype();
-//    match dt {
-//        Doctype::String | Doctype::Array => {
-//            for value in <D as ItemIterator<D>>::into_iter(doc).unwrap() {
-//                let out = thunk(value)?.remove(0);
-//                if out.boolean().unwrap_or(false) == false {
-//                    return Ok(vec![From::from(false)])
-//                }
-//            }
-//            return Ok(vec![From::from(true)])
-//        },
-//        Doctype::Object => {
-//            for x in <D as ItemIterator<Property<D>>>::into_iter(doc).unwrap() {
-//                let out = thunk(x.value())?.remove(0);
-//                if out.boolean().unwrap_or(false) == false {
-//                    return Ok(vec![From::from(false)])
-//                }
-//            }
-//            return Ok(vec![From::from(true)])
-//        },
-//        _ => Err(Error::Op(None, format!("cannot iterate over {:?}", dt))),
-//    }
-//}
-//
-//fn assert_args_len(args: &Vec<Thunk>, n: usize) -> Result<()> {
-//    if args.len() != n {
-//        let err = format!("expected {} args, got {}", n, args.len());
-//        return Err(Error::InvalidArg(err))
-//    }
-//    Ok(())
-//}
-//
-//#[allow(dead_code)] // TODO: Can be removed.
-//fn assert_iterator<D>(outs: &Input<D>) -> Result<()> where D: Document {
-//    if outs.len() < 2 {
-//        let err = format!("output is not an iterator {}", outs.len());
-//        return Err(Error::InvalidArg(err))
-//    }
-//    Ok(())
-//}
-//
-//#[allow(dead_code)]
-//fn assert_singular<D>(outs: &Input<D>) -> Result<()> where D: Document {
-//    if outs.len() == 1 {
-//        let err = format!("output is an iterator {}", outs.len());
-//        return Err(Error::InvalidArg(err))
-//    }
-//    Ok(())
-//}
+        let msg = d_entry.doc;
+        d_entry.doc = D::null();
+        d_entry.set_error_value(msg);
+        Some(d_entry)
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for BuiltinError<'a,D> where D: 'a + Document {
+}
+
+
+pub struct BuiltinDel<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    thunks: &'a [Thunk],
+    env: Context<'a,D>,
+}
+
+impl<'a,D> BuiltinDel<'a,D> where D: 'a + Document {
+    pub fn new(input: Input<'a,D>, thunks: &'a [Thunk], env: &Context<'a,D>)
+        -> BuiltinDel<'a,D>
+    {
+        BuiltinDel{input, thunks, env: env.clone()}
+    }
+
+    fn paths(&self, doc: &D) -> Result<Vec<Vec<D>>,D> {
+        let mut paths = Vec::new();
+        for thunk in self.thunks.iter() {
+            let items = path::paths_of(thunk, &self.env, vec![], doc.clone())?;
+            paths.extend(items.into_iter().map(|(p, _)| p));
+        }
+        Ok(paths)
+    }
+}
+
+impl<'a,D> Repeater<'a,D> for BuiltinDel<'a,D> where D: 'a + Document {
+    fn repeat(&self) -> Input<'a,D> {
+        let input = self.input.repeat();
+        Box::new(BuiltinDel::new(input, self.thunks, &self.env))
+    }
+}
+
+impl<'a,D> Iterator for BuiltinDel<'a,D> where D: 'a + Document {
+    type Item=Entry<D>;
+
+    fn next(&mut self) -> Option<Entry<D>> {
+        let mut d_entry = self.input.next()?;
+        if d_entry.has_error() { return Some(d_entry) }
+
+        let res = match self.paths(&d_entry.doc) {
+            Ok(paths) => {
+                path::delpaths(&mut d_entry.doc, paths).map_err(From::from)
+            },
+            Err(err) => Err(err),
+        };
+        if let Err(err) = res {
+            d_entry.doc = D::null();
+            d_entry.set_error_value(err);
+        }
+        Some(d_entry)
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for BuiltinDel<'a,D> where D: 'a + Document {
+}
+
+
+pub struct BuiltinToEntries<'a,D> where D: Document {
+    arg_input: Option<Input<'a,D>>,
+}
+
+impl<'a,D> BuiltinToEntries<'a,D> where D: Document {
+    pub fn new(mut args: Vec<Input<D>>) -> BuiltinToEntries<D> {
+        if args.len() == 1 {
+            BuiltinToEntries{arg_input: Some(args.remove(0))}
+        } else {
+            BuiltinToEntries{arg_input: None}
+        }
+    }
+}
+
+impl<'a,D> Repeater<'a,D> for BuiltinToEntries<'a,D> where D: 'a + Document {
+    fn repeat(&self) -> Input<'a,D> {
+        let arg_input = match self.arg_input.as_ref() {
+            Some(input) => Some(input.repeat()),
+            None => None
+        };
+        Box::new(BuiltinToEntries{arg_input})
+    }
+}
+
+impl<'a,D> Iterator for BuiltinToEntries<'a,D> where D: Document {
+    type Item=Entry<D>;
+
+    fn next(&mut self) -> Option<Entry<D>> {
+        if self.arg_input.is_none() {
+            let mut entry = Entry::new(D::null());
+            entry.set_error(format!("invalid number of args for to_entries"));
+            return Some(entry)
+        }
+        let mut d_entry = self.arg_input.as_mut().unwrap().next()?;
+        if d_entry.has_error() { return Some(d_entry) }
+
+        match to_entries(d_entry.doc) {
+            Ok(doc) => d_entry.doc = doc,
+            Err(err) => { d_entry.doc = D::null(); d_entry.set_error(err) },
+        }
+        Some(d_entry)
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for BuiltinToEntries<'a,D> where D: 'a + Document {
+}
+
+
+pub struct BuiltinFromEntries<'a,D> where D: Document {
+    arg_input: Option<Input<'a,D>>,
+}
+
+impl<'a,D> BuiltinFromEntries<'a,D> where D: Document {
+    pub fn new(mut args: Vec<Input<D>>) -> BuiltinFromEntries<D> {
+        if args.len() == 1 {
+            BuiltinFromEntries{arg_input: Some(args.remove(0))}
+        } else {
+            BuiltinFromEntries{arg_input: None}
+        }
+    }
+}
+
+impl<'a,D> Repeater<'a,D> for BuiltinFromEntries<'a,D>
+    where D: 'a + Document
+{
+    fn repeat(&self) -> Input<'a,D> {
+        let arg_input = match self.arg_input.as_ref() {
+            Some(input) => Some(input.repeat()),
+            None => None
+        };
+        Box::new(BuiltinFromEntries{arg_input})
+    }
+}
+
+impl<'a,D> Iterator for BuiltinFromEntries<'a,D> where D: Document {
+    type Item=Entry<D>;
+
+    fn next(&mut self) -> Option<Entry<D>> {
+        if self.arg_input.is_none() {
+            let mut entry = Entry::new(D::null());
+            let err = format!("invalid number of args for from_entries");
+            entry.set_error(err);
+            return Some(entry)
+        }
+        let mut d_entry = self.arg_input.as_mut().unwrap().next()?;
+        if d_entry.has_error() { return Some(d_entry) }
+
+        match from_entries(d_entry.doc) {
+            Ok(doc) => d_entry.doc = doc,
+            Err(err) => { d_entry.doc = D::null(); d_entry.set_error(err) },
+        }
+        Some(d_entry)
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for BuiltinFromEntries<'a,D>
+    where D: 'a + Document
+{
+}
+
+
+pub struct BuiltinWithEntries<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    thunk: &'a Thunk,
+    env: Context<'a,D>,
+    func: Subquery<'a,D>,
+}
+
+impl<'a,D> BuiltinWithEntries<'a,D> where D: 'a + Document {
+    pub fn new(input: Input<'a,D>, thunk: &'a Thunk, env: &Context<'a,D>)
+        -> BuiltinWithEntries<'a,D>
+    {
+        let func = Subquery::new(thunk, env);
+        BuiltinWithEntries{input, thunk, env: env.clone(), func}
+    }
+
+    fn with_entries(&mut self, doc: D) -> Result<D,D> {
+        let entries = to_entries(doc)?.array().unwrap();
+        let mut outs = Vec::new();
+        for item in entries.into_iter() {
+            for entry in self.func.run(Entry::new(item)).into_iter() {
+                match entry.error_value() {
+                    Some(err) => return Err(err),
+                    None => outs.push(entry.doc),
+                }
+            }
+        }
+        Ok(from_entries(From::from(outs))?)
+    }
+}
+
+impl<'a,D> Repeater<'a,D> for BuiltinWithEntries<'a,D>
+    where D: 'a + Document
+{
+    fn repeat(&self) -> Input<'a,D> {
+        let input = self.input.repeat();
+        Box::new(BuiltinWithEntries::new(input, self.thunk, &self.env))
+    }
+}
+
+impl<'a,D> Iterator for BuiltinWithEntries<'a,D> where D: 'a + Document {
+    type Item=Entry<D>;
+
+    fn next(&mut self) -> Option<Entry<D>> {
+        let mut d_entry = self.input.next()?;
+        if d_entry.has_error() { return Some(d_entry) }
+
+        match self.with_entries(d_entry.doc.clone()) {
+            Ok(doc) => d_entry.doc = doc,
+            Err(err) => {
+                d_entry.doc = D::null();
+                d_entry.set_error_value(err);
+            },
+        }
+        Some(d_entry)
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for BuiltinWithEntries<'a,D>
+    where D: 'a + Document
+{
+}
+
+
+/// Builtin computing its output from the input document alone, input
+/// is the subject argument as in `length(.)`.
+pub struct BuiltinFn1<'a,D> where D: Document {
+    input: Input<'a,D>,
+    func: fn(D) -> Result<D,String>,
+}
+
+impl<'a,D> BuiltinFn1<'a,D> where D: Document {
+    pub fn new(input: Input<'a,D>, func: fn(D) -> Result<D,String>)
+        -> BuiltinFn1<'a,D>
+    {
+        BuiltinFn1{input, func}
+    }
+}
+
+impl<'a,D> Repeater<'a,D> for BuiltinFn1<'a,D> where D: 'a + Document {
+    fn repeat(&self) -> Input<'a,D> {
+        Box::new(BuiltinFn1::new(self.input.repeat(), self.func))
+    }
+}
+
+impl<'a,D> Iterator for BuiltinFn1<'a,D> where D: Document {
+    type Item=Entry<D>;
+
+    fn next(&mut self) -> Option<Entry<D>> {
+        let mut d_entry = self.input.next()?;
+        if d_entry.has_error() { return Some(d_entry) }
+
+        let doc = mem::replace(&mut d_entry.doc, D::null());
+        match (self.func)(doc) {
+            Ok(doc) => d_entry.doc = doc,
+            Err(err) => d_entry.set_error(err),
+        }
+        Some(d_entry)
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for BuiltinFn1<'a,D> where D: 'a + Document {
+}
+
+
+/// Builtin computing its output from the input document and an argument
+/// evaluated on the same input, as in `split(",")`, or from two arguments
+/// evaluated on the input, as in `pow(.x; 2)`. There is an output for
+/// every combination of outputs of the arguments.
+pub struct BuiltinFn2<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    thunks: &'a [Thunk],
+    env: Context<'a,D>,
+    func: Fn2<D>,
+    ahead: Ahead<D>,
+    d_entry: Option<Entry<D>>,
+    error: Option<Entry<D>>,
+    args: Option<ArgCombinations<'a,D>>,
+}
+
+pub type Fn2<D> = fn(D, D) -> Result<D,String>;
+
+impl<'a,D> BuiltinFn2<'a,D> where D: 'a + Document {
+    pub fn new(
+        input: Input<'a,D>,
+        thunks: &'a [Thunk],
+        env: &Context<'a,D>,
+        func: Fn2<D>) -> BuiltinFn2<'a,D>
+    {
+        BuiltinFn2{
+            input, thunks, env: env.clone(), func, ahead: Ahead::new(),
+            d_entry: None, error: None, args: None,
+        }
+    }
+}
+
+impl<'a,D> Generator<D> for BuiltinFn2<'a,D> where D: 'a + Document {
+    fn ahead(&mut self) -> &mut Ahead<D> {
+        &mut self.ahead
+    }
+
+    fn start(&mut self) -> Option<()> {
+        let d_entry = self.input.next()?;
+        self.args = None;
+        self.error = None;
+        if d_entry.has_error() {
+            self.error = Some(d_entry.clone());
+        } else {
+            let (thunks, env) = (self.thunks, &self.env);
+            self.args = Some(ArgCombinations::new(thunks, env, &d_entry));
+        }
+        self.d_entry = Some(d_entry);
+        Some(())
+    }
+
+    fn pull(&mut self) -> Option<Entry<D>> {
+        if let Some(entry) = self.error.take() { return Some(entry) }
+        let mut entry = self.d_entry.clone()?;
+        let mut args = match self.args.as_mut().and_then(|a| a.next()) {
+            Some(Ok(args)) => args,
+            Some(Err(err)) => {
+                self.args = None;
+                entry.doc = D::null();
+                entry.set_error_value(err);
+                return Some(entry)
+            },
+            None => { self.args = None; return None },
+        };
+        let arg = args.pop().unwrap();
+        let doc = args.pop().unwrap_or_else(|| entry.doc.clone());
+        match (self.func)(doc, arg) {
+            Ok(doc) => entry.doc = doc,
+            Err(err) => {
+                self.args = None;
+                entry.doc = D::null();
+                entry.set_error(err);
+            },
+        }
+        Some(entry)
+    }
+}
+
+impl<'a,D> Repeater<'a,D> for BuiltinFn2<'a,D> where D: 'a + Document {
+    fn repeat(&self) -> Input<'a,D> {
+        if self.d_entry.is_some() {
+            panic!("cannot repeat BuiltinFn2 after statement is prepared");
+        }
+        let input = self.input.repeat();
+        let (thunks, func) = (self.thunks, self.func);
+        Box::new(BuiltinFn2::new(input, thunks, &self.env, func))
+    }
+}
+
+impl<'a,D> Iterator for BuiltinFn2<'a,D> where D: 'a + Document {
+    type Item=Entry<D>;
+
+    fn next(&mut self) -> Option<Entry<D>> {
+        generate(self)
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for BuiltinFn2<'a,D> where D: 'a + Document {
+}
+
+
+/// String interpolation, `@name "text \(expr)"`, outputs of each
+/// expression are formatted with `@name` and joined with the text.
+pub struct Format<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    name: &'a str,
+    parts: &'a [StrPart],
+    env: Context<'a,D>,
+    subs: Vec<Option<Subquery<'a,D>>>,
+    iter: Option<vec::IntoIter<Entry<D>>>,
+}
+
+impl<'a,D> Format<'a,D> where D: 'a + Document {
+    pub fn new(
+        input: Input<'a,D>,
+        name: &'a str,
+        parts: &'a [StrPart],
+        env: &Context<'a,D>) -> Format<'a,D>
+    {
+        let subs = parts.iter().map(|part| match part {
+            StrPart::Lit(_) => None,
+            StrPart::Expr(thunk) => Some(Subquery::new(thunk, env)),
+        }).collect();
+        let env = env.clone();
+        Format{input, name, parts, env, subs, iter: None}
+    }
+
+    fn format(&mut self, entry: &Entry<D>) -> Result<Vec<String>,D> {
+        let mut outs = vec![String::new()];
+        for (part, sub) in self.parts.iter().zip(self.subs.iter_mut()) {
+            let texts = match (part, sub) {
+                (StrPart::Lit(text), _) => vec![text.clone()],
+                (StrPart::Expr(_), Some(sub)) => {
+                    let mut texts = Vec::new();
+                    for entry in sub.run(entry.clone()).into_iter() {
+                        if let Some(err) = entry.error_value() {
+                            return Err(err)
+                        }
+                        texts.push(builtin_str::format(self.name, entry.doc)?);
+                    }
+                    texts
+                },
+                (StrPart::Expr(_), None) => unreachable!(),
+            };
+            // earlier parts vary faster than later parts.
+            let mut next = Vec::new();
+            for text in texts.iter() {
+                next.extend(outs.iter().map(|out| out.clone() + text));
+            }
+            outs = next;
+        }
+        Ok(outs)
+    }
+
+    fn values(&mut self) -> Option<Vec<Entry<D>>> {
+        let mut d_entry = self.input.next()?;
+        if d_entry.has_error() { return Some(vec![d_entry]) }
+
+        match self.format(&d_entry) {
+            Ok(texts) => {
+                let entries = texts.into_iter().map(|text| {
+                    let mut entry = d_entry.clone();
+                    entry.doc = From::from(text);
+                    entry
+                }).collect();
+                Some(entry::fixpositions(entries))
+            },
+            Err(err) => {
+                d_entry.doc = D::null();
+                d_entry.set_error_value(err);
+                Some(vec![d_entry])
+            },
+        }
+    }
+}
+
+impl<'a,D> Repeater<'a,D> for Format<'a,D> where D: 'a + Document {
+    fn repeat(&self) -> Input<'a,D> {
+        if let Some(_) = self.iter {
+            panic!("cannot repeat Format after the statement is prepared");
+        }
+        let input = self.input.repeat();
+        Box::new(Format::new(input, self.name, self.parts, &self.env))
+    }
+}
+
+impl<'a,D> Iterator for Format<'a,D> where D: 'a + Document {
+    type Item=Entry<D>;
+
+    fn next(&mut self) -> Option<Entry<D>> {
+        let entry = loop {
+            match self.iter.as_mut().and_then(|iter| iter.next()) {
+                Some(entry) => break entry,
+                None => { self.iter = Some(self.values()?.into_iter()); },
+            }
+        };
+        Some(entry)
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for Format<'a,D> where D: 'a + Document {
+}
+
+
+/// Regular expression builtins, `test`, `match`, `capture`, `scan`,
+/// `split/2`, `sub` and `gsub`. Patterns are compiled once and cached for
+/// the lifetime of the stage. There are outputs for every combination of
+/// outputs of the pattern and flags, evaluated on the input.
+pub struct BuiltinRegex<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    op: RegexOp,
+    // pattern and optionally flags.
+    thunks: Vec<&'a Thunk>,
+    // replacement for sub and gsub.
+    thunk: Option<&'a Thunk>,
+    env: Context<'a,D>,
+    repl: Option<Subquery<'a,D>>,
+    patterns: HashMap<(String, String), Pattern>,
+    ahead: Ahead<D>,
+    d_entry: Option<Entry<D>>,
+    error: Option<Entry<D>>,
+    args: Option<ArgCombinations<'a,D>>,
+    outs: vec::IntoIter<D>,
+}
+
+impl<'a,D> BuiltinRegex<'a,D> where D: 'a + Document {
+    pub fn new(
+        input: Input<'a,D>,
+        op: RegexOp,
+        thunks: Vec<&'a Thunk>,
+        thunk: Option<&'a Thunk>,
+        env: &Context<'a,D>) -> BuiltinRegex<'a,D>
+    {
+        let repl = thunk.map(|thunk| Subquery::new(thunk, env));
+        BuiltinRegex{
+            input, op, thunks, thunk, env: env.clone(), repl,
+            patterns: HashMap::new(), ahead: Ahead::new(), d_entry: None,
+            error: None, args: None, outs: vec![].into_iter(),
+        }
+    }
+
+    fn apply(&mut self, doc: D, args: Vec<D>) -> Result<Vec<D>,D> {
+        let text = match doc.doctype() {
+            Doctype::String => doc.string().unwrap(),
+            dt => {
+                let err = format!("{:?} cannot be matched, not a string", dt);
+                return Err(From::from(err))
+            },
+        };
+        let mut args = args.into_iter();
+        let re = match args.next().map(|re| (re.doctype(), re.string())) {
+            Some((_, Some(re))) => re,
+            Some((dt, None)) => {
+                let err = format!("{:?} cannot be used as regex", dt);
+                return Err(From::from(err))
+            },
+            None => unreachable!(),
+        };
+        let flags = match args.next().map(|fl| (fl.doctype(), fl.string())) {
+            Some((_, Some(flags))) => flags,
+            Some((Doctype::Null, None)) | None => "".to_string(),
+            Some((dt, None)) => {
+                let err = format!("{:?} is not a valid modifier", dt);
+                return Err(From::from(err))
+            },
+        };
+
+        let (op, key) = (self.op, (re, flags));
+        if !self.patterns.contains_key(&key) {
+            let pattern = Pattern::compile(&key.0, &key.1, op)?;
+            self.patterns.insert(key.clone(), pattern);
+        }
+        let pattern = &self.patterns[&key];
+
+        let outs = match op {
+            RegexOp::Test => vec![From::from(pattern.is_match(&text))],
+            RegexOp::Match => pattern.matches(&text),
+            RegexOp::Capture => pattern.named_captures(&text),
+            RegexOp::Scan => pattern.scan(&text),
+            RegexOp::Split => vec![pattern.split(&text)],
+            RegexOp::Sub | RegexOp::Gsub => {
+                let repl = self.repl.as_mut().unwrap();
+                let out =
+                    pattern.replace(&text, |caps| replacement(repl, caps))?;
+                vec![From::from(out)]
+            },
+        };
+        Ok(outs)
+    }
+}
+
+// first output of the replacement filter, applied on the captures.
+fn replacement<'a,D>(repl: &mut Subquery<'a,D>, caps: D) -> Result<String,D>
+    where D: 'a + Document
+{
+    let entry = match repl.run(Entry::new(caps)).into_iter().next() {
+        Some(entry) => entry,
+        None => return Ok("".to_string()),
+    };
+    if let Some(err) = entry.error_value() { return Err(err) }
+    match entry.doc.doctype() {
+        Doctype::String => Ok(entry.doc.string().unwrap()),
+        dt => Err(From::from(format!("cannot sub with {:?}", dt))),
+    }
+}
+
+impl<'a,D> Generator<D> for BuiltinRegex<'a,D> where D: 'a + Document {
+    fn ahead(&mut self) -> &mut Ahead<D> {
+        &mut self.ahead
+    }
+
+    fn start(&mut self) -> Option<()> {
+        let d_entry = self.input.next()?;
+        self.outs = vec![].into_iter();
+        self.args = None;
+        self.error = None;
+        if d_entry.has_error() {
+            self.error = Some(d_entry.clone());
+        } else {
+            let thunks = self.thunks.iter().cloned();
+            let args = ArgCombinations::new(thunks, &self.env, &d_entry);
+            self.args = Some(args);
+        }
+        self.d_entry = Some(d_entry);
+        Some(())
+    }
+
+    fn pull(&mut self) -> Option<Entry<D>> {
+        if let Some(entry) = self.error.take() { return Some(entry) }
+        loop {
+            let mut entry = self.d_entry.clone()?;
+            if let Some(doc) = self.outs.next() {
+                entry.doc = doc;
+                return Some(entry)
+            }
+            let args = match self.args.as_mut().and_then(|a| a.next()) {
+                Some(Ok(args)) => args,
+                Some(Err(err)) => {
+                    self.args = None;
+                    entry.doc = D::null();
+                    entry.set_error_value(err);
+                    return Some(entry)
+                },
+                None => { self.args = None; return None },
+            };
+            match self.apply(entry.doc.clone(), args) {
+                Ok(docs) => self.outs = docs.into_iter(),
+                Err(err) => {
+                    self.args = None;
+                    entry.doc = D::null();
+                    entry.set_error_value(err);
+                    return Some(entry)
+                },
+            }
+        }
+    }
+}
+
+impl<'a,D> Repeater<'a,D> for BuiltinRegex<'a,D> where D: 'a + Document {
+    fn repeat(&self) -> Input<'a,D> {
+        if self.d_entry.is_some() {
+            panic!("cannot repeat BuiltinRegex after statement is prepared");
+        }
+        let input = self.input.repeat();
+        let (op, thunks, thunk) = (self.op, self.thunks.clone(), self.thunk);
+        Box::new(BuiltinRegex::new(input, op, thunks, thunk, &self.env))
+    }
+}
+
+impl<'a,D> Iterator for BuiltinRegex<'a,D> where D: 'a + Document {
+    type Item=Entry<D>;
+
+    fn next(&mut self) -> Option<Entry<D>> {
+        generate(self)
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for BuiltinRegex<'a,D> where D: 'a + Document {
+}
+
+
+pub struct BuiltinInvalid<'a,D> where D: Document {
+    input: Input<'a,D>,
+    name: String,
+    arity: usize,
+}
+
+impl<'a,D> BuiltinInvalid<'a,D> where D: Document {
+    pub fn new(input: Input<D>, name: String, arity: usize)
+        -> BuiltinInvalid<D>
+    {
+        BuiltinInvalid{input, name, arity}
+    }
+}
+
+impl<'a,D> Repeater<'a,D> for BuiltinInvalid<'a,D> where D: 'a + Document {
+    fn repeat(&self) -> Input<'a,D> {
+        let (name, arity) = (self.name.clone(), self.arity);
+        Box::new(BuiltinInvalid{input: self.input.repeat(), name, arity})
+    }
+}
+
+impl<'a,D> Iterator for BuiltinInvalid<'a,D> where D: Document {
+    type Item=Entry<D>;
+
+    fn next(&mut self) -> Option<Entry<D>> {
+        let mut d_entry = self.input.next()?;
+        if d_entry.has_error() { return Some(d_entry) }
+
+        d_entry.doc = D::null();
+        d_entry.set_error(
+            format!("{}/{} is not defined", self.name, self.arity)
+        );
+        Some(d_entry)
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for BuiltinInvalid<'a,D> where D: 'a + Document {
+}
+
+
+/// `map(f)`, apply `f` on every item of an array, or every value of an
+/// object keeping the keys.
+pub struct BuiltinMap<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    thunk: &'a Thunk,
+    env: Context<'a,D>,
+    func: Subquery<'a,D>,
+}
+
+impl<'a,D> BuiltinMap<'a,D> where D: 'a + Document {
+    pub fn new(input: Input<'a,D>, thunk: &'a Thunk, env: &Context<'a,D>)
+        -> BuiltinMap<'a,D>
+    {
+        let func = Subquery::new(thunk, env);
+        BuiltinMap{input, thunk, env: env.clone(), func}
+    }
+
+    fn map(&mut self, doc: D) -> Result<D,D> {
+        let dt = doc.doctype();
+        match dt {
+            Doctype::String | Doctype::Array => {
+                let mut out = Vec::new();
+                for value in <D as ItemIterator<D>>::into_iter(doc).unwrap() {
+                    for entry in self.func.run(Entry::new(value)).into_iter() {
+                        match entry.error_value() {
+                            Some(err) => return Err(err),
+                            None => out.push(entry.doc),
+                        }
+                    }
+                }
+                Ok(From::from(out))
+            },
+            Doctype::Object => {
+                let mut out = Vec::new();
+                let props = <D as ItemIterator<Property<D>>>::into_iter(doc);
+                for x in props.unwrap() {
+                    let key = x.key_ref().clone();
+                    // first output is the new value, none drops the key.
+                    let entry = self.func.run(Entry::new(x.value()));
+                    match entry.into_iter().next() {
+                        Some(entry) => match entry.error_value() {
+                            Some(err) => return Err(err),
+                            None => out.push(Property::new(key, entry.doc)),
+                        },
+                        None => (),
+                    }
+                }
+                Ok(From::from(out))
+            },
+            _ => Err(From::from(format!("cannot map over {:?}", dt))),
+        }
+    }
+}
+
+impl<'a,D> Repeater<'a,D> for BuiltinMap<'a,D> where D: 'a + Document {
+    fn repeat(&self) -> Input<'a,D> {
+        let input = self.input.repeat();
+        Box::new(BuiltinMap::new(input, self.thunk, &self.env))
+    }
+}
+
+impl<'a,D> Iterator for BuiltinMap<'a,D> where D: 'a + Document {
+    type Item=Entry<D>;
+
+    fn next(&mut self) -> Option<Entry<D>> {
+        let mut d_entry = self.input.next()?;
+        if d_entry.has_error() { return Some(d_entry) }
+
+        match self.map(d_entry.doc.clone()) {
+            Ok(doc) => d_entry.doc = doc,
+            Err(err) => {
+                d_entry.doc = D::null();
+                d_entry.set_error_value(err);
+            },
+        }
+        Some(d_entry)
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for BuiltinMap<'a,D> where D: 'a + Document {
+}
+
+
+/// `any(f)` and, with `all`, `all(f)`. Stops at the first item that
+/// decides the outcome.
+pub struct BuiltinAny<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    thunk: &'a Thunk,
+    env: Context<'a,D>,
+    all: bool,
+    func: Subquery<'a,D>,
+}
+
+impl<'a,D> BuiltinAny<'a,D> where D: 'a + Document {
+    pub fn new(
+        input: Input<'a,D>, thunk: &'a Thunk, env: &Context<'a,D>, all: bool)
+        -> BuiltinAny<'a,D>
+    {
+        let func = Subquery::new(thunk, env);
+        BuiltinAny{input, thunk, env: env.clone(), all, func}
+    }
+
+    fn any(&mut self, doc: D) -> Result<bool,D> {
+        let dt = doc.doctype();
+        let values: Vec<D> = match dt {
+            Doctype::String | Doctype::Array => {
+                <D as ItemIterator<D>>::into_iter(doc).unwrap().collect()
+            },
+            Doctype::Object => {
+                <D as ItemIterator<Property<D>>>::into_iter(doc).unwrap()
+                    .map(|x| x.value())
+                    .collect()
+            },
+            _ => {
+                let err = format!("cannot iterate over {:?}", dt);
+                return Err(From::from(err))
+            },
+        };
+        for value in values.into_iter() {
+            for entry in self.func.run(Entry::new(value)).into_iter() {
+                if let Some(err) = entry.error_value() { return Err(err) }
+                // any looks for a true item, all for a false item.
+                if is_truthy(&entry.doc) != self.all { return Ok(!self.all) }
+            }
+        }
+        Ok(self.all)
+    }
+}
+
+impl<'a,D> Repeater<'a,D> for BuiltinAny<'a,D> where D: 'a + Document {
+    fn repeat(&self) -> Input<'a,D> {
+        let input = self.input.repeat();
+        Box::new(BuiltinAny::new(input, self.thunk, &self.env, self.all))
+    }
+}
+
+impl<'a,D> Iterator for BuiltinAny<'a,D> where D: 'a + Document {
+    type Item=Entry<D>;
+
+    fn next(&mut self) -> Option<Entry<D>> {
+        let mut d_entry = self.input.next()?;
+        if d_entry.has_error() { return Some(d_entry) }
+
+        match self.any(d_entry.doc.clone()) {
+            Ok(out) => d_entry.doc = From::from(out),
+            Err(err) => {
+                d_entry.doc = D::null();
+                d_entry.set_error_value(err);
+            },
+        }
+        Some(d_entry)
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for BuiltinAny<'a,D> where D: 'a + Document {
+}
+
+
+/// Builtin with any number of outputs, computed from the input document
+/// and its arguments evaluated on the same input, as in `combinations(2)`.
+/// It is applied on every combination of outputs of the arguments, as its
+/// outputs are consumed.
+pub struct BuiltinStream<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    thunks: &'a [Thunk],
+    env: Context<'a,D>,
+    func: Stream<D>,
+    ahead: Ahead<D>,
+    d_entry: Option<Entry<D>>,
+    error: Option<Entry<D>>,
+    args: Option<ArgCombinations<'a,D>>,
+    outs: vec::IntoIter<D>,
+}
+
+pub type Stream<D> = fn(D, Vec<D>) -> Result<Vec<D>,String>;
+
+impl<'a,D> BuiltinStream<'a,D> where D: 'a + Document {
+    pub fn new(
+        input: Input<'a,D>,
+        thunks: &'a [Thunk],
+        env: &Context<'a,D>,
+        func: Stream<D>) -> BuiltinStream<'a,D>
+    {
+        BuiltinStream{
+            input, thunks, env: env.clone(), func, ahead: Ahead::new(),
+            d_entry: None, error: None, args: None, outs: vec![].into_iter(),
+        }
+    }
+}
+
+impl<'a,D> Generator<D> for BuiltinStream<'a,D> where D: 'a + Document {
+    fn ahead(&mut self) -> &mut Ahead<D> {
+        &mut self.ahead
+    }
+
+    fn start(&mut self) -> Option<()> {
+        let d_entry = self.input.next()?;
+        self.outs = vec![].into_iter();
+        self.args = None;
+        self.error = None;
+        if d_entry.has_error() {
+            self.error = Some(d_entry.clone());
+        } else {
+            let (thunks, env) = (self.thunks, &self.env);
+            self.args = Some(ArgCombinations::new(thunks, env, &d_entry));
+        }
+        self.d_entry = Some(d_entry);
+        Some(())
+    }
+
+    fn pull(&mut self) -> Option<Entry<D>> {
+        if let Some(entry) = self.error.take() { return Some(entry) }
+        loop {
+            let mut entry = self.d_entry.clone()?;
+            if let Some(doc) = self.outs.next() {
+                entry.doc = doc;
+                return Some(entry)
+            }
+            let args = match self.args.as_mut().and_then(|a| a.next()) {
+                Some(Ok(args)) => args,
+                Some(Err(err)) => {
+                    self.args = None;
+                    entry.doc = D::null();
+                    entry.set_error_value(err);
+                    return Some(entry)
+                },
+                None => { self.args = None; return None },
+            };
+            match (self.func)(entry.doc.clone(), args) {
+                Ok(docs) => self.outs = docs.into_iter(),
+                Err(err) => {
+                    self.args = None;
+                    entry.doc = D::null();
+                    entry.set_error(err);
+                    return Some(entry)
+                },
+            }
+        }
+    }
+}
+
+impl<'a,D> Repeater<'a,D> for BuiltinStream<'a,D> where D: 'a + Document {
+    fn repeat(&self) -> Input<'a,D> {
+        if self.d_entry.is_some() {
+            panic!("cannot repeat BuiltinStream after statement is prepared");
+        }
+        let input = self.input.repeat();
+        let (thunks, func) = (self.thunks, self.func);
+        Box::new(BuiltinStream::new(input, thunks, &self.env, func))
+    }
+}
+
+impl<'a,D> Iterator for BuiltinStream<'a,D> where D: 'a + Document {
+    type Item=Entry<D>;
+
+    fn next(&mut self) -> Option<Entry<D>> {
+        generate(self)
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for BuiltinStream<'a,D> where D: 'a + Document {
+}
+
+
+/// `range(upto)`, `range(from; upto)` and `range(from; upto; by)`, numbers
+/// from `from`, or 0, upto `upto` in steps of `by`, or 1, for every
+/// combination of outputs of the arguments evaluated on the input. Numbers
+/// are counted as they are consumed.
+pub struct BuiltinRange<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    thunks: &'a [Thunk],
+    env: Context<'a,D>,
+    ahead: Ahead<D>,
+    d_entry: Option<Entry<D>>,
+    error: Option<Entry<D>>,
+    ends: Option<ArgCombinations<'a,D>>,
+    range: Option<builtin_array::Range>,
+}
+
+impl<'a,D> BuiltinRange<'a,D> where D: 'a + Document {
+    pub fn new(input: Input<'a,D>, thunks: &'a [Thunk], env: &Context<'a,D>)
+        -> BuiltinRange<'a,D>
+    {
+        BuiltinRange{
+            input, thunks, env: env.clone(), ahead: Ahead::new(),
+            d_entry: None, error: None, ends: None, range: None,
+        }
+    }
+}
+
+impl<'a,D> Generator<D> for BuiltinRange<'a,D> where D: 'a + Document {
+    fn ahead(&mut self) -> &mut Ahead<D> {
+        &mut self.ahead
+    }
+
+    fn start(&mut self) -> Option<()> {
+        let mut d_entry = self.input.next()?;
+        self.range = None;
+        self.ends = None;
+        self.error = None;
+        if d_entry.has_error() {
+            self.error = Some(d_entry.clone());
+        } else {
+            let (thunks, env) = (self.thunks, &self.env);
+            self.ends = Some(ArgCombinations::new(thunks, env, &d_entry));
+        }
+        d_entry.doc = D::null();
+        self.d_entry = Some(d_entry);
+        Some(())
+    }
+
+    fn pull(&mut self) -> Option<Entry<D>> {
+        if let Some(entry) = self.error.take() { return Some(entry) }
+        loop {
+            if let Some(doc) = self.range.as_mut().and_then(|r| r.pull()) {
+                let mut entry = self.d_entry.clone()?;
+                entry.doc = doc;
+                return Some(entry)
+            }
+            let mut entry = self.d_entry.clone()?;
+            let mut ends = match self.ends.as_mut().and_then(|e| e.next()) {
+                Some(Ok(ends)) => ends.into_iter(),
+                Some(Err(err)) => {
+                    self.ends = None;
+                    entry.set_error_value(err);
+                    return Some(entry)
+                },
+                None => { self.ends = None; return None },
+            };
+            let one = || From::from(1_i128);
+            let (from, upto, by) =
+                match (ends.next()?, ends.next(), ends.next()) {
+                    (upto, None, _) => (From::from(0_i128), upto, one()),
+                    (from, Some(upto), None) => (from, upto, one()),
+                    (from, Some(upto), Some(by)) => (from, upto, by),
+                };
+            match builtin_array::Range::new(from, upto, by) {
+                Ok(range) => self.range = Some(range),
+                Err(err) => {
+                    self.ends = None;
+                    entry.set_error(err);
+                    return Some(entry)
+                },
+            }
+        }
+    }
+}
+
+impl<'a,D> Repeater<'a,D> for BuiltinRange<'a,D> where D: 'a + Document {
+    fn repeat(&self) -> Input<'a,D> {
+        if self.d_entry.is_some() {
+            panic!("cannot repeat BuiltinRange after statement is prepared");
+        }
+        let input = self.input.repeat();
+        Box::new(BuiltinRange::new(input, self.thunks, &self.env))
+    }
+}
+
+impl<'a,D> Iterator for BuiltinRange<'a,D> where D: 'a + Document {
+    type Item=Entry<D>;
+
+    fn next(&mut self) -> Option<Entry<D>> {
+        generate(self)
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for BuiltinRange<'a,D> where D: 'a + Document {
+}
+
+
+/// `sort_by(f)`, `group_by(f)` and the like, key for every item of the
+/// input array is the array of all outputs of `f` applied on the item.
+pub struct BuiltinBy<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    thunk: &'a Thunk,
+    env: Context<'a,D>,
+    by: fn(Vec<D>, Vec<D>) -> D,
+    func: Subquery<'a,D>,
+}
+
+impl<'a,D> BuiltinBy<'a,D> where D: 'a + Document {
+    pub fn new(
+        input: Input<'a,D>,
+        thunk: &'a Thunk,
+        env: &Context<'a,D>,
+        by: fn(Vec<D>, Vec<D>) -> D) -> BuiltinBy<'a,D>
+    {
+        let func = Subquery::new(thunk, env);
+        BuiltinBy{input, thunk, env: env.clone(), by, func}
+    }
+
+    fn apply(&mut self, doc: D) -> Result<D,D> {
+        let items = builtin_array::array("ordered", doc)?;
+        let mut keys = Vec::with_capacity(items.len());
+        for item in items.iter() {
+            let mut key = Vec::new();
+            for entry in self.func.run(Entry::new(item.clone())).into_iter() {
+                match entry.error_value() {
+                    Some(err) => return Err(err),
+                    None => key.push(entry.doc),
+                }
+            }
+            keys.push(From::from(key));
+        }
+        Ok((self.by)(items, keys))
+    }
+}
+
+impl<'a,D> Repeater<'a,D> for BuiltinBy<'a,D> where D: 'a + Document {
+    fn repeat(&self) -> Input<'a,D> {
+        let input = self.input.repeat();
+        Box::new(BuiltinBy::new(input, self.thunk, &self.env, self.by))
+    }
+}
+
+impl<'a,D> Iterator for BuiltinBy<'a,D> where D: 'a + Document {
+    type Item=Entry<D>;
+
+    fn next(&mut self) -> Option<Entry<D>> {
+        let mut d_entry = self.input.next()?;
+        if d_entry.has_error() { return Some(d_entry) }
+
+        match self.apply(d_entry.doc.clone()) {
+            Ok(doc) => d_entry.doc = doc,
+            Err(err) => {
+                d_entry.doc = D::null();
+                d_entry.set_error_value(err);
+            },
+        }
+        Some(d_entry)
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for BuiltinBy<'a,D> where D: 'a + Document {
+}
+
+
+/// `limit(n; f)`, `first(f)`, `last(f)` and `nth(n; f)`, pick outputs of
+/// `f` applied on the input, `n` is evaluated on the same input.
+pub struct BuiltinTake<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    arg_input: Option<Input<'a,D>>,
+    thunk: &'a Thunk,
+    env: Context<'a,D>,
+    take: Take<'a,D>,
+    iter: Option<vec::IntoIter<Entry<D>>>,
+}
+
+type Take<'a,D> =
+    fn(&mut Input<'a,D>, Option<D>) -> Result<Vec<Entry<D>>,String>;
+
+impl<'a,D> BuiltinTake<'a,D> where D: 'a + Document {
+    pub fn new(
+        input: Input<'a,D>,
+        arg_input: Option<Input<'a,D>>,
+        thunk: &'a Thunk,
+        env: &Context<'a,D>,
+        take: Take<'a,D>) -> BuiltinTake<'a,D>
+    {
+        BuiltinTake{input, arg_input, thunk, env: env.clone(), take, iter: None}
+    }
+
+    fn values(&mut self) -> Option<Vec<Entry<D>>> {
+        let mut d_entry = self.input.next()?;
+        let a_entry = match self.arg_input.as_mut() {
+            Some(arg_input) => Some(arg_input.next()?),
+            None => None,
+        };
+        if d_entry.has_error() { return Some(vec![d_entry]) }
+        if let Some(true) = a_entry.as_ref().map(|a| a.has_error()) {
+            return Some(vec![a_entry.unwrap()])
+        }
+
+        // fresh subquery for every input, so that outputs not taken are
+        // never evaluated.
+        let mut func = Subquery::new(self.thunk, &self.env);
+        let outs = func.stream(Entry::new(d_entry.doc.clone()));
+        match (self.take)(outs, a_entry.map(|a_entry| a_entry.doc)) {
+            Ok(outs) => {
+                let entries = outs.into_iter().map(|out| {
+                    let mut entry = d_entry.clone();
+                    match out.error_value() {
+                        Some(err) => {
+                            entry.doc = D::null();
+                            entry.set_error_value(err);
+                        },
+                        None => entry.doc = out.doc,
+                    }
+                    entry
+                }).collect();
+                Some(entry::fixpositions(entries))
+            },
+            Err(err) => {
+                d_entry.doc = D::null();
+                d_entry.set_error(err);
+                Some(vec![d_entry])
+            },
+        }
+    }
+}
+
+impl<'a,D> Repeater<'a,D> for BuiltinTake<'a,D> where D: 'a + Document {
+    fn repeat(&self) -> Input<'a,D> {
+        if let Some(_) = self.iter {
+            panic!("cannot repeat BuiltinTake after statement is prepared");
+        }
+        let input = self.input.repeat();
+        let arg_input = self.arg_input.as_ref().map(|arg| arg.repeat());
+        let (thunk, take) = (self.thunk, self.take);
+        Box::new(BuiltinTake::new(input, arg_input, thunk, &self.env, take))
+    }
+}
+
+impl<'a,D> Iterator for BuiltinTake<'a,D> where D: 'a + Document {
+    type Item=Entry<D>;
+
+    fn next(&mut self) -> Option<Entry<D>> {
+        let entry = loop {
+            match self.iter.as_mut().and_then(|iter| iter.next()) {
+                Some(entry) => break entry,
+                None => { self.iter = Some(self.values()?.into_iter()); },
+            }
+        };
+        Some(entry)
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for BuiltinTake<'a,D> where D: 'a + Document {
+}
+
+
+/// `until(cond; update)`, apply `update` on the input until `cond` is
+/// true. Only the first output of `cond` and `update` are used.
+pub struct BuiltinUntil<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    thunks: (&'a Thunk, &'a Thunk),
+    env: Context<'a,D>,
+    cond: Subquery<'a,D>,
+    update: Subquery<'a,D>,
+}
+
+impl<'a,D> BuiltinUntil<'a,D> where D: 'a + Document {
+    pub fn new(
+        input: Input<'a,D>,
+        thunks: (&'a Thunk, &'a Thunk),
+        env: &Context<'a,D>) -> BuiltinUntil<'a,D>
+    {
+        let cond = Subquery::new(thunks.0, env);
+        let update = Subquery::new(thunks.1, env);
+        BuiltinUntil{input, thunks, env: env.clone(), cond, update}
+    }
+
+    // None if either `cond` or `update` has no output.
+    fn apply(&mut self, mut doc: D) -> Option<Result<D,D>> {
+        loop {
+            let entry = self.cond.run(Entry::new(doc.clone())).into_iter()
+                .next()?;
+            if let Some(err) = entry.error_value() { return Some(Err(err)) }
+            if is_truthy(&entry.doc) { return Some(Ok(doc)) }
+
+            let entry = self.update.run(Entry::new(doc)).into_iter().next()?;
+            if let Some(err) = entry.error_value() { return Some(Err(err)) }
+            doc = entry.doc;
+        }
+    }
+}
+
+impl<'a,D> Repeater<'a,D> for BuiltinUntil<'a,D> where D: 'a + Document {
+    fn repeat(&self) -> Input<'a,D> {
+        let input = self.input.repeat();
+        Box::new(BuiltinUntil::new(input, self.thunks, &self.env))
+    }
+}
+
+impl<'a,D> Iterator for BuiltinUntil<'a,D> where D: 'a + Document {
+    type Item=Entry<D>;
+
+    fn next(&mut self) -> Option<Entry<D>> {
+        loop {
+            let mut d_entry = self.input.next()?;
+            if d_entry.has_error() { return Some(d_entry) }
+
+            match self.apply(d_entry.doc.clone()) {
+                Some(Ok(doc)) => d_entry.doc = doc,
+                Some(Err(err)) => {
+                    d_entry.doc = D::null();
+                    d_entry.set_error_value(err);
+                },
+                None => continue,
+            }
+            break Some(d_entry)
+        }
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for BuiltinUntil<'a,D> where D: 'a + Document {
+}
+
+
+/// Pass through the input documents matching a predicate, as in `arrays`
+/// and `empty`.
+pub struct BuiltinFilter<'a,D> where D: Document {
+    input: Input<'a,D>,
+    pred: fn(&D) -> bool,
+}
+
+impl<'a,D> BuiltinFilter<'a,D> where D: Document {
+    pub fn new(input: Input<'a,D>, pred: fn(&D) -> bool)
+        -> BuiltinFilter<'a,D>
+    {
+        BuiltinFilter{input, pred}
+    }
+}
+
+impl<'a,D> Repeater<'a,D> for BuiltinFilter<'a,D> where D: 'a + Document {
+    fn repeat(&self) -> Input<'a,D> {
+        Box::new(BuiltinFilter::new(self.input.repeat(), self.pred))
+    }
+}
+
+impl<'a,D> Iterator for BuiltinFilter<'a,D> where D: Document {
+    type Item=Entry<D>;
+
+    fn next(&mut self) -> Option<Entry<D>> {
+        loop {
+            let d_entry = self.input.next()?;
+            if d_entry.has_error() || (self.pred)(&d_entry.doc) {
+                break Some(d_entry)
+            }
+        }
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for BuiltinFilter<'a,D> where D: 'a + Document {
+}
+
+
+/// `select(f)`, pass through the input document once for every true
+/// output of `f` applied on it.
+pub struct BuiltinSelect<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    thunk: &'a Thunk,
+    env: Context<'a,D>,
+    func: Subquery<'a,D>,
+    iter: Option<vec::IntoIter<Entry<D>>>,
+}
+
+impl<'a,D> BuiltinSelect<'a,D> where D: 'a + Document {
+    pub fn new(input: Input<'a,D>, thunk: &'a Thunk, env: &Context<'a,D>)
+        -> BuiltinSelect<'a,D>
+    {
+        let func = Subquery::new(thunk, env);
+        BuiltinSelect{input, thunk, env: env.clone(), func, iter: None}
+    }
+
+    fn values(&mut self) -> Option<Vec<Entry<D>>> {
+        let d_entry = self.input.next()?;
+        if d_entry.has_error() { return Some(vec![d_entry]) }
+
+        let mut entries = Vec::new();
+        for out in self.func.run(Entry::new(d_entry.doc.clone())).into_iter() {
+            match out.error_value() {
+                Some(err) => {
+                    let mut entry = d_entry.clone();
+                    entry.doc = D::null();
+                    entry.set_error_value(err);
+                    entries.push(entry);
+                    break
+                },
+                None if is_truthy(&out.doc) => entries.push(d_entry.clone()),
+                None => (),
+            }
+        }
+        Some(entry::fixpositions(entries))
+    }
+}
+
+impl<'a,D> Repeater<'a,D> for BuiltinSelect<'a,D> where D: 'a + Document {
+    fn repeat(&self) -> Input<'a,D> {
+        if let Some(_) = self.iter {
+            panic!("cannot repeat BuiltinSelect after statement is prepared");
+        }
+        let input = self.input.repeat();
+        Box::new(BuiltinSelect::new(input, self.thunk, &self.env))
+    }
+}
+
+impl<'a,D> Iterator for BuiltinSelect<'a,D> where D: 'a + Document {
+    type Item=Entry<D>;
+
+    fn next(&mut self) -> Option<Entry<D>> {
+        let entry = loop {
+            match self.iter.as_mut().and_then(|iter| iter.next()) {
+                Some(entry) => break entry,
+                None => { self.iter = Some(self.values()?.into_iter()); },
+            }
+        };
+        Some(entry)
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for BuiltinSelect<'a,D> where D: 'a + Document {
+}
+
+
+/// `path(f)`, paths of the outputs of `f` within the input document, as
+/// arrays of keys and offsets.
+pub struct BuiltinPathOf<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    thunk: &'a Thunk,
+    env: Context<'a,D>,
+    iter: Option<vec::IntoIter<Entry<D>>>,
+}
+
+impl<'a,D> BuiltinPathOf<'a,D> where D: 'a + Document {
+    pub fn new(input: Input<'a,D>, thunk: &'a Thunk, env: &Context<'a,D>)
+        -> BuiltinPathOf<'a,D>
+    {
+        BuiltinPathOf{input, thunk, env: env.clone(), iter: None}
+    }
+
+    fn values(&mut self) -> Option<Vec<Entry<D>>> {
+        let d_entry = self.input.next()?;
+        if d_entry.has_error() { return Some(vec![d_entry]) }
+
+        let doc = d_entry.doc.clone();
+        let res = path::paths_of(self.thunk, &self.env, vec![], doc)
+            .map(|items| items.into_iter().map(|x| From::from(x.0)).collect());
+        Some(outputs(d_entry, res))
+    }
+}
+
+impl<'a,D> Repeater<'a,D> for BuiltinPathOf<'a,D> where D: 'a + Document {
+    fn repeat(&self) -> Input<'a,D> {
+        if let Some(_) = self.iter {
+            panic!("cannot repeat BuiltinPathOf after statement is prepared");
+        }
+        let input = self.input.repeat();
+        Box::new(BuiltinPathOf::new(input, self.thunk, &self.env))
+    }
+}
+
+impl<'a,D> Iterator for BuiltinPathOf<'a,D> where D: 'a + Document {
+    type Item=Entry<D>;
+
+    fn next(&mut self) -> Option<Entry<D>> {
+        let entry = loop {
+            match self.iter.as_mut().and_then(|iter| iter.next()) {
+                Some(entry) => break entry,
+                None => { self.iter = Some(self.values()?.into_iter()); },
+            }
+        };
+        Some(entry)
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for BuiltinPathOf<'a,D> where D: 'a + Document {
+}
+
+
+/// `paths(f)`, paths to the values within the input document for which
+/// `f` is true, except the empty path to the input itself.
+pub struct BuiltinPaths<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    thunk: &'a Thunk,
+    env: Context<'a,D>,
+    func: Subquery<'a,D>,
+    iter: Option<vec::IntoIter<Entry<D>>>,
+}
+
+impl<'a,D> BuiltinPaths<'a,D> where D: 'a + Document {
+    pub fn new(input: Input<'a,D>, thunk: &'a Thunk, env: &Context<'a,D>)
+        -> BuiltinPaths<'a,D>
+    {
+        let func = Subquery::new(thunk, env);
+        BuiltinPaths{input, thunk, env: env.clone(), func, iter: None}
+    }
+
+    fn paths(&mut self, doc: D) -> Result<Vec<D>,D> {
+        let mut out = Vec::new();
+        for (p, value) in path::all_paths_at(vec![], doc).into_iter().skip(1) {
+            for entry in self.func.run(Entry::new(value)).into_iter() {
+                if let Some(err) = entry.error_value() { return Err(err) }
+                if is_truthy(&entry.doc) { out.push(From::from(p)); break }
+            }
+        }
+        Ok(out)
+    }
+
+    fn values(&mut self) -> Option<Vec<Entry<D>>> {
+        let d_entry = self.input.next()?;
+        if d_entry.has_error() { return Some(vec![d_entry]) }
+
+        let res = self.paths(d_entry.doc.clone());
+        Some(outputs(d_entry, res))
+    }
+}
+
+impl<'a,D> Repeater<'a,D> for BuiltinPaths<'a,D> where D: 'a + Document {
+    fn repeat(&self) -> Input<'a,D> {
+        if let Some(_) = self.iter {
+            panic!("cannot repeat BuiltinPaths after statement is prepared");
+        }
+        let input = self.input.repeat();
+        Box::new(BuiltinPaths::new(input, self.thunk, &self.env))
+    }
+}
+
+impl<'a,D> Iterator for BuiltinPaths<'a,D> where D: 'a + Document {
+    type Item=Entry<D>;
+
+    fn next(&mut self) -> Option<Entry<D>> {
+        let entry = loop {
+            match self.iter.as_mut().and_then(|iter| iter.next()) {
+                Some(entry) => break entry,
+                None => { self.iter = Some(self.values()?.into_iter()); },
+            }
+        };
+        Some(entry)
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for BuiltinPaths<'a,D> where D: 'a + Document {
+}
+
+
+/// `recurse(f)` and `recurse(f; cond)`, the input document and
+/// recursively the outputs of `f` for which `cond` is true, depth first.
+pub struct BuiltinRecurse<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    thunks: (&'a Thunk, Option<&'a Thunk>),
+    env: Context<'a,D>,
+    func: Subquery<'a,D>,
+    cond: Option<Subquery<'a,D>>,
+    iter: Option<vec::IntoIter<Entry<D>>>,
+}
+
+impl<'a,D> BuiltinRecurse<'a,D> where D: 'a + Document {
+    pub fn new(
+        input: Input<'a,D>,
+        thunks: (&'a Thunk, Option<&'a Thunk>),
+        env: &Context<'a,D>) -> BuiltinRecurse<'a,D>
+    {
+        let func = Subquery::new(thunks.0, env);
+        let cond = thunks.1.map(|thunk| Subquery::new(thunk, env));
+        BuiltinRecurse{input, thunks, env: env.clone(), func, cond, iter: None}
+    }
+
+    fn recurse(&mut self, doc: D) -> Result<Vec<D>,D> {
+        let (mut out, mut stack) = (Vec::new(), vec![doc]);
+        while let Some(doc) = stack.pop() {
+            let mut nexts = Vec::new();
+            for entry in self.func.run(Entry::new(doc.clone())).into_iter() {
+                if let Some(err) = entry.error_value() { return Err(err) }
+                if self.is_selected(&entry.doc)? { nexts.push(entry.doc) }
+            }
+            out.push(doc);
+            stack.extend(nexts.into_iter().rev());
+        }
+        Ok(out)
+    }
+
+    fn is_selected(&mut self, doc: &D) -> Result<bool,D> {
+        let cond = match self.cond.as_mut() {
+            Some(cond) => cond,
+            None => return Ok(true),
+        };
+        for entry in cond.run(Entry::new(doc.clone())).into_iter() {
+            if let Some(err) = entry.error_value() { return Err(err) }
+            if is_truthy(&entry.doc) { return Ok(true) }
+        }
+        Ok(false)
+    }
+
+    fn values(&mut self) -> Option<Vec<Entry<D>>> {
+        let d_entry = self.input.next()?;
+        if d_entry.has_error() { return Some(vec![d_entry]) }
+
+        let res = self.recurse(d_entry.doc.clone());
+        Some(outputs(d_entry, res))
+    }
+}
+
+impl<'a,D> Repeater<'a,D> for BuiltinRecurse<'a,D> where D: 'a + Document {
+    fn repeat(&self) -> Input<'a,D> {
+        if let Some(_) = self.iter {
+            panic!("cannot repeat BuiltinRecurse after statement is prepared");
+        }
+        let input = self.input.repeat();
+        Box::new(BuiltinRecurse::new(input, self.thunks, &self.env))
+    }
+}
+
+impl<'a,D> Iterator for BuiltinRecurse<'a,D> where D: 'a + Document {
+    type Item=Entry<D>;
+
+    fn next(&mut self) -> Option<Entry<D>> {
+        let entry = loop {
+            match self.iter.as_mut().and_then(|iter| iter.next()) {
+                Some(entry) => break entry,
+                None => { self.iter = Some(self.values()?.into_iter()); },
+            }
+        };
+        Some(entry)
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for BuiltinRecurse<'a,D> where D: 'a + Document {
+}
+
+
+/// `walk(f)`, apply `f` bottom up on every value within the input
+/// document. Array items take all the outputs of `f`, object values take
+/// the first output and keys without output are dropped.
+pub struct BuiltinWalk<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    thunk: &'a Thunk,
+    env: Context<'a,D>,
+    func: Subquery<'a,D>,
+    iter: Option<vec::IntoIter<Entry<D>>>,
+}
+
+impl<'a,D> BuiltinWalk<'a,D> where D: 'a + Document {
+    pub fn new(input: Input<'a,D>, thunk: &'a Thunk, env: &Context<'a,D>)
+        -> BuiltinWalk<'a,D>
+    {
+        let func = Subquery::new(thunk, env);
+        BuiltinWalk{input, thunk, env: env.clone(), func, iter: None}
+    }
+
+    fn walk(&mut self, doc: D) -> Result<Vec<D>,D> {
+        let doc = match doc.doctype() {
+            Doctype::Array => {
+                let mut items = Vec::new();
+                for item in doc.array().unwrap().into_iter() {
+                    items.extend(self.walk(item)?);
+                }
+                From::from(items)
+            },
+            Doctype::Object => {
+                let mut props = Vec::new();
+                for prop in doc.object().unwrap().into_iter() {
+                    let key = prop.key_ref().clone();
+                    let values = self.walk(prop.value())?;
+                    if let Some(value) = values.into_iter().next() {
+                        props.push(Property::new(key, value))
+                    }
+                }
+                From::from(props)
+            },
+            _ => doc,
+        };
+        let mut out = Vec::new();
+        for entry in self.func.run(Entry::new(doc)).into_iter() {
+            match entry.error_value() {
+                Some(err) => return Err(err),
+                None => out.push(entry.doc),
+            }
+        }
+        Ok(out)
+    }
+
+    fn values(&mut self) -> Option<Vec<Entry<D>>> {
+        let d_entry = self.input.next()?;
+        if d_entry.has_error() { return Some(vec![d_entry]) }
+
+        let res = self.walk(d_entry.doc.clone());
+        Some(outputs(d_entry, res))
+    }
+}
+
+impl<'a,D> Repeater<'a,D> for BuiltinWalk<'a,D> where D: 'a + Document {
+    fn repeat(&self) -> Input<'a,D> {
+        if let Some(_) = self.iter {
+            panic!("cannot repeat BuiltinWalk after statement is prepared");
+        }
+        let input = self.input.repeat();
+        Box::new(BuiltinWalk::new(input, self.thunk, &self.env))
+    }
+}
+
+impl<'a,D> Iterator for BuiltinWalk<'a,D> where D: 'a + Document {
+    type Item=Entry<D>;
+
+    fn next(&mut self) -> Option<Entry<D>> {
+        let entry = loop {
+            match self.iter.as_mut().and_then(|iter| iter.next()) {
+                Some(entry) => break entry,
+                None => { self.iter = Some(self.values()?.into_iter()); },
+            }
+        };
+        Some(entry)
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for BuiltinWalk<'a,D> where D: 'a + Document {
+}
+
+
+/// `INDEX(stream; f)`, object of the outputs of `stream` keyed by `f`,
+/// later outputs replace earlier ones with the same key. `GROUP_BY(stream;
+/// f)` keeps all of them, as arrays.
+pub struct BuiltinIndex<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    thunks: (&'a Thunk, &'a Thunk),
+    env: Context<'a,D>,
+    group: bool,
+    stream: Subquery<'a,D>,
+    func: Subquery<'a,D>,
+}
+
+impl<'a,D> BuiltinIndex<'a,D> where D: 'a + Document {
+    pub fn new(
+        input: Input<'a,D>,
+        thunks: (&'a Thunk, &'a Thunk),
+        env: &Context<'a,D>,
+        group: bool) -> BuiltinIndex<'a,D>
+    {
+        let stream = Subquery::new(thunks.0, env);
+        let func = Subquery::new(thunks.1, env);
+        BuiltinIndex{input, thunks, env: env.clone(), group, stream, func}
+    }
+
+    fn apply(&mut self, doc: D) -> Result<D,D> {
+        let mut groups: Vec<(String, Vec<D>)> = Vec::new();
+        let mut offs: HashMap<String, usize> = HashMap::new();
+        for row in self.stream.values(doc)?.into_iter() {
+            for key in self.func.values(row.clone())?.into_iter() {
+                let key = collate::key(&key);
+                match offs.get(&key) {
+                    Some(off) => groups[*off].1.push(row.clone()),
+                    None => {
+                        offs.insert(key.clone(), groups.len());
+                        groups.push((key, vec![row.clone()]));
+                    },
+                }
+            }
+        }
+        let props: Vec<Property<D>> = groups.into_iter()
+            .map(|(key, mut rows)| {
+                let value = match self.group {
+                    true => From::from(rows),
+                    false => rows.pop().unwrap(),
+                };
+                Property::new(key, value)
+            })
+            .collect();
+        Ok(From::from(props))
+    }
+}
+
+impl<'a,D> Repeater<'a,D> for BuiltinIndex<'a,D> where D: 'a + Document {
+    fn repeat(&self) -> Input<'a,D> {
+        let input = self.input.repeat();
+        Box::new(BuiltinIndex::new(input, self.thunks, &self.env, self.group))
+    }
+}
+
+impl<'a,D> Iterator for BuiltinIndex<'a,D> where D: 'a + Document {
+    type Item=Entry<D>;
+
+    fn next(&mut self) -> Option<Entry<D>> {
+        let mut d_entry = self.input.next()?;
+        if d_entry.has_error() { return Some(d_entry) }
+
+        match self.apply(d_entry.doc.clone()) {
+            Ok(doc) => d_entry.doc = doc,
+            Err(err) => {
+                d_entry.doc = D::null();
+                d_entry.set_error_value(err);
+            },
+        }
+        Some(d_entry)
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for BuiltinIndex<'a,D> where D: 'a + Document {
+}
+
+
+/// `IN(s)`, whether the input is one of the outputs of `s`, and `IN(source;
+/// s)`, whether any output of `source` is. Outputs of `source` after the
+/// first match are not evaluated.
+pub struct BuiltinIn<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    thunks: (Option<&'a Thunk>, &'a Thunk),
+    env: Context<'a,D>,
+    func: Subquery<'a,D>,
+}
+
+impl<'a,D> BuiltinIn<'a,D> where D: 'a + Document {
+    pub fn new(
+        input: Input<'a,D>,
+        thunks: (Option<&'a Thunk>, &'a Thunk),
+        env: &Context<'a,D>) -> BuiltinIn<'a,D>
+    {
+        let func = Subquery::new(thunks.1, env);
+        BuiltinIn{input, thunks, env: env.clone(), func}
+    }
+
+    fn apply(&mut self, doc: D) -> Result<bool,D> {
+        let set: HashSet<String> = self.func.values(doc.clone())?.iter()
+            .map(collate::encode)
+            .collect();
+        let thunk = match self.thunks.0 {
+            Some(thunk) => thunk,
+            None => return Ok(set.contains(&collate::encode(&doc))),
+        };
+        let mut source = Subquery::new(thunk, &self.env);
+        for entry in source.stream(Entry::new(doc)) {
+            if let Some(err) = entry.error_value() { return Err(err) }
+            if set.contains(&collate::encode(&entry.doc)) { return Ok(true) }
+        }
+        Ok(false)
+    }
+}
+
+impl<'a,D> Repeater<'a,D> for BuiltinIn<'a,D> where D: 'a + Document {
+    fn repeat(&self) -> Input<'a,D> {
+        let input = self.input.repeat();
+        Box::new(BuiltinIn::new(input, self.thunks, &self.env))
+    }
+}
+
+impl<'a,D> Iterator for BuiltinIn<'a,D> where D: 'a + Document {
+    type Item=Entry<D>;
+
+    fn next(&mut self) -> Option<Entry<D>> {
+        let mut d_entry = self.input.next()?;
+        if d_entry.has_error() { return Some(d_entry) }
+
+        match self.apply(d_entry.doc.clone()) {
+            Ok(ok) => d_entry.doc = From::from(ok),
+            Err(err) => {
+                d_entry.doc = D::null();
+                d_entry.set_error_value(err);
+            },
+        }
+        Some(d_entry)
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for BuiltinIn<'a,D> where D: 'a + Document {
+}
+
+
+/// `JOIN($idx; stream; f; join)`, pairs `[row, $idx[row | f]]` for the
+/// outputs of `stream`, passed through `join` if any. With `collect` the
+/// pairs are collected into a single array, as in `JOIN($idx; f)`.
+pub struct BuiltinJoin<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    thunks: (&'a Thunk, &'a Thunk, &'a Thunk, Option<&'a Thunk>),
+    env: Context<'a,D>,
+    collect: bool,
+    index: Subquery<'a,D>,
+    stream: Subquery<'a,D>,
+    func: Subquery<'a,D>,
+    join: Option<Subquery<'a,D>>,
+    iter: Option<vec::IntoIter<Entry<D>>>,
+}
+
+impl<'a,D> BuiltinJoin<'a,D> where D: 'a + Document {
+    pub fn new(
+        input: Input<'a,D>,
+        thunks: (&'a Thunk, &'a Thunk, &'a Thunk, Option<&'a Thunk>),
+        env: &Context<'a,D>,
+        collect: bool) -> BuiltinJoin<'a,D>
+    {
+        let index = Subquery::new(thunks.0, env);
+        let stream = Subquery::new(thunks.1, env);
+        let func = Subquery::new(thunks.2, env);
+        let join = thunks.3.map(|thunk| Subquery::new(thunk, env));
+        BuiltinJoin{
+            input, thunks, env: env.clone(), collect, index, stream, func,
+            join, iter: None,
+        }
+    }
+
+    fn apply(&mut self, doc: D) -> Result<Vec<D>,D> {
+        let mut out = Vec::new();
+        for index in self.index.values(doc.clone())?.into_iter() {
+            let index: HashMap<String, D> = match index.doctype() {
+                Doctype::Object => index.object().unwrap().into_iter()
+                    .map(|prop| (prop.key_ref().clone(), prop.value()))
+                    .collect(),
+                dt => {
+                    let err = format!("{:?} cannot be used as join index", dt);
+                    return Err(From::from(err))
+                },
+            };
+            for row in self.stream.values(doc.clone())?.into_iter() {
+                for key in self.func.values(row.clone())?.into_iter() {
+                    let value = index.get(&collate::key(&key)).cloned();
+                    let pair = vec![row.clone(), value.unwrap_or(D::null())];
+                    let pair: D = From::from(pair);
+                    match self.join.as_mut() {
+                        Some(join) => out.extend(join.values(pair)?),
+                        None => out.push(pair),
+                    }
+                }
+            }
+        }
+        if self.collect { out = vec![From::from(out)] }
+        Ok(out)
+    }
+
+    fn values(&mut self) -> Option<Vec<Entry<D>>> {
+        let d_entry = self.input.next()?;
+        if d_entry.has_error() { return Some(vec![d_entry]) }
+
+        let res = self.apply(d_entry.doc.clone());
+        Some(outputs(d_entry, res))
+    }
+}
+
+impl<'a,D> Repeater<'a,D> for BuiltinJoin<'a,D> where D: 'a + Document {
+    fn repeat(&self) -> Input<'a,D> {
+        if let Some(_) = self.iter {
+            panic!("cannot repeat BuiltinJoin after statement is prepared");
+        }
+        let input = self.input.repeat();
+        let (thunks, collect) = (self.thunks, self.collect);
+        Box::new(BuiltinJoin::new(input, thunks, &self.env, collect))
+    }
+}
+
+impl<'a,D> Iterator for BuiltinJoin<'a,D> where D: 'a + Document {
+    type Item=Entry<D>;
+
+    fn next(&mut self) -> Option<Entry<D>> {
+        let entry = loop {
+            match self.iter.as_mut().and_then(|iter| iter.next()) {
+                Some(entry) => break entry,
+                None => { self.iter = Some(self.values()?.into_iter()); },
+            }
+        };
+        Some(entry)
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for BuiltinJoin<'a,D> where D: 'a + Document {
+}
+
+// entries for the outputs of a builtin on `d_entry`, or the error.
+fn outputs<D>(mut d_entry: Entry<D>, res: Result<Vec<D>,D>) -> Vec<Entry<D>>
+    where D: Document
+{
+    match res {
+        Ok(docs) => {
+            let entries = docs.into_iter().map(|doc| {
+                let mut entry = d_entry.clone();
+                entry.doc = doc;
+                entry
+            }).collect();
+            entry::fixpositions(entries)
+        },
+        Err(err) => {
+            d_entry.doc = D::null();
+            d_entry.set_error_value(err);
+            vec![d_entry]
+        },
+    }
+}
+
+/// Only null and false are false, everything else is true.
+pub fn is_truthy<D>(doc: &D) -> bool where D: Document {
+    match doc.doctype() {
+        Doctype::Null => false,
+        Doctype::Bool => doc.clone().boolean().unwrap(),
+        _ => true,
+    }
+}
+
+/// `has(key)`, whether key is among the object's keys or the array's
+/// items.
+pub fn has<D>(doc: D, key: D) -> Result<D,String> where D: Document {
+    let dt = doc.doctype();
+    let out = match dt {
+        Doctype::Array => {
+            <D as ItemIterator<D>>::iter(&doc).unwrap()
+                .any(|value: &D| value == &key)
+        },
+        Doctype::Object => {
+            <D as ItemIterator<Property<D>>>::iter(&doc).unwrap()
+                .any(|x| {
+                    let k: D = From::from(x.key_ref().clone());
+                    k == key
+                })
+        },
+        _ => return Err(format!("{:?} not iterable", dt)),
+    };
+    Ok(From::from(out))
+}
+
+/// `in(doc)`, same as `has` with the input as key.
+pub fn is_in<D>(key: D, doc: D) -> Result<D,String> where D: Document {
+    has(doc, key)
+}
+
+fn to_entries<D>(doc: D) -> Result<D,String> where D: Document {
+    let dt = doc.doctype();
+    let items: Vec<(D, D)> = match dt {
+        Doctype::Object => {
+            doc.object().unwrap().into_iter()
+                .map(|prop| (From::from(prop.key_ref().clone()), prop.value()))
+                .collect()
+        },
+        Doctype::Array => {
+            doc.array().unwrap().into_iter().enumerate()
+                .map(|(i, value)| (From::from(i as i128), value))
+                .collect()
+        },
+        _ => return Err(format!("cannot find entries for {:?}", dt)),
+    };
+    let entries: Vec<D> = items.into_iter().map(|(key, value)| {
+        let mut entry: D = From::from(Vec::<Property<D>>::new());
+        entry.set("key", key);
+        entry.set("value", value);
+        entry
+    }).collect();
+    Ok(From::from(entries))
+}
+
+fn from_entries<D>(doc: D) -> Result<D,String> where D: Document {
+    let dt = doc.doctype();
+    if dt != Doctype::Array {
+        return Err(format!("cannot build object from {:?}", dt))
+    }
+
+    let mut obj: D = From::from(Vec::<Property<D>>::new());
+    for entry in doc.array().unwrap().into_iter() {
+        let dt = entry.doctype();
+        if dt != Doctype::Object {
+            return Err(format!("entry must be an object, not {:?}", dt))
+        }
+        let key = ["key", "k", "name", "Name", "K", "Key"].iter()
+            .filter_map(|k| entry.get_ref(k)).next()
+            .cloned().unwrap_or(D::null());
+        let key = match key.doctype() {
+            Doctype::String => key.string().unwrap(),
+            Doctype::Null | Doctype::Bool | Doctype::Integer => {
+                format!("{:?}", key)
+            },
+            dt => return Err(format!("cannot use {:?} as object key", dt)),
+        };
+        let value = ["value", "v", "Value", "V"].iter()
+            .filter_map(|k| entry.get_ref(k)).next()
+            .cloned().unwrap_or(D::null());
+        obj.set(&key, value);
+    }
+    Ok(obj)
+}
 
 fn docvalues<D>(doc: D) -> Option<Vec<D>> where D: Document {
     let dt = doc.doctype();
@@ -0,0 +1,526 @@
+// Path expressions, a path is a list of object keys and array offsets
+// locating a value within a document, like `["a", 0, "b"]` for `.a[0].b`.
+
+use std::{cmp, mem};
+
+use db::{Document, Doctype};
+use context::Context;
+use entry::Entry;
+use ops::{self, Subquery};
+use prop::Property;
+use query::{Thunk, UNARY_BUILTINS, NULLARY_BUILTINS};
+use builtin_type;
+use util;
+
+pub type Path<D> = Vec<D>;
+
+/// Evaluate `thunk` as a path expression on `doc`, yielding the path and
+/// value for every output. `path` locates `doc` within its root document.
+/// Errors are returned as error values, like the ones raised by `error()`.
+pub fn paths_of<'a,D>(
+    thunk: &'a Thunk, env: &Context<'a,D>, path: Path<D>, doc: D)
+    -> Result<Vec<(Path<D>, D)>, D>
+    where D: 'a + Document
+{
+    if thunk.is_optional() {
+        return Ok(do_paths_of(thunk, env, path, doc).unwrap_or(vec![]))
+    }
+    do_paths_of(thunk, env, path, doc)
+}
+
+fn do_paths_of<'a,D>(
+    thunk: &'a Thunk, env: &Context<'a,D>, path: Path<D>, doc: D)
+    -> Result<Vec<(Path<D>, D)>, D>
+    where D: 'a + Document
+{
+    use query::Thunk::*;
+
+    match thunk {
+        Empty | Identity => Ok(vec![(path, doc)]),
+        Recurse => Ok(all_paths_at(path, doc)),
+        IndexShortcut(Some(key), _, _) => Ok(vec![key_path(path, doc, key)?]),
+        IndexShortcut(None, Some(off), _) => {
+            Ok(vec![offset_path(path, doc, *off)?])
+        },
+        IndexPath(keys) => {
+            let mut item = (path, doc);
+            for key in keys.iter() {
+                item = match key {
+                    (Some(key), _) => key_path(item.0, item.1, key)?,
+                    (None, Some(off)) => offset_path(item.0, item.1, *off)?,
+                    (None, None) => unreachable!(),
+                };
+            }
+            Ok(vec![item])
+        },
+        Identifier(name, _) => match env.get_func(name, 0) {
+            Some(func) => {
+                let env = func.call_env(&[], env);
+                paths_of(func.body, &env, path, doc)
+            },
+            None if name == "recurse" => Ok(all_paths_at(path, doc)),
+            // selectors like `nulls` and `empty` are path expressions.
+            None => match builtin_type::selector(name) {
+                Some(pred) if pred(&doc) => Ok(vec![(path, doc)]),
+                Some(_) => Ok(vec![]),
+                None if UNARY_BUILTINS.contains(&name.as_str()) ||
+                    NULLARY_BUILTINS.contains(&name.as_str()) => {
+                    Err(From::from(format!("invalid path expression")))
+                },
+                None => Ok(vec![key_path(path, doc, name)?]),
+            },
+        },
+        IterateValues(_) => {
+            let dt = doc.doctype();
+            match dt {
+                Doctype::Array => {
+                    let values = doc.array().unwrap();
+                    Ok(values.into_iter().enumerate().map(|(i, value)| {
+                        (extend(&path, From::from(i as i128)), value)
+                    }).collect())
+                },
+                Doctype::Object => {
+                    let props = doc.object().unwrap();
+                    Ok(props.into_iter().map(|prop| {
+                        let key = From::from(prop.key_ref().clone());
+                        (extend(&path, key), prop.value())
+                    }).collect())
+                },
+                _ => Err(From::from(format!("cannot iterate {:?}", dt))),
+            }
+        },
+        Iterate(thunks, _) => {
+            let mut items = Vec::new();
+            for thunk in thunks.iter() {
+                items.extend(paths_of(thunk, env, path.clone(), doc.clone())?);
+            }
+            Ok(items)
+        },
+        Comma(lthunk, rthunk) => {
+            let mut items = paths_of(lthunk, env, path.clone(), doc.clone())?;
+            items.extend(paths_of(rthunk, env, path, doc)?);
+            Ok(items)
+        },
+        Pipe(lthunk, rthunk) => {
+            let mut items = Vec::new();
+            for (path, doc) in paths_of(lthunk, env, path, doc)?.into_iter() {
+                items.extend(paths_of(rthunk, env, path, doc)?);
+            }
+            Ok(items)
+        },
+        If(cond, then, otherwise) => {
+            let mut items = Vec::new();
+            for value in values_of(cond, env, doc.clone())?.into_iter() {
+                let thunk =
+                    if ops::is_truthy(&value) { then } else { otherwise };
+                items.extend(paths_of(thunk, env, path.clone(), doc.clone())?);
+            }
+            Ok(items)
+        },
+        Alternative(lthunk, rthunk) => {
+            let items: Vec<(Path<D>, D)> =
+                paths_of(lthunk, env, path.clone(), doc.clone())
+                .unwrap_or(vec![])
+                .into_iter()
+                .filter(|(_, value)| ops::is_truthy(value))
+                .collect();
+            if items.len() > 0 { return Ok(items) }
+            paths_of(rthunk, env, path, doc)
+        },
+        Try(body, None) => {
+            Ok(paths_of(body, env, path, doc).unwrap_or(vec![]))
+        },
+        Bind(source, name, body) => {
+            let (body_env, slot) = env.bind(name);
+            let mut items = Vec::new();
+            for value in values_of(source, env, doc.clone())?.into_iter() {
+                *slot.borrow_mut() = value;
+                let (path, doc) = (path.clone(), doc.clone());
+                items.extend(paths_of(body, &body_env, path, doc)?);
+            }
+            Ok(items)
+        },
+        Def(name, params, body, rest) => {
+            paths_of(rest, &env.define(name, params, body), path, doc)
+        },
+        Share(thunk) => paths_of(thunk, env, path, doc),
+        Builtin(name, thunks) => match env.get_func(name, thunks.len()) {
+            Some(func) => {
+                let env = func.call_env(thunks, env);
+                paths_of(func.body, &env, path, doc)
+            },
+            None => match (name.as_str(), thunks.as_slice()) {
+                ("select", [cond]) => {
+                    let values = values_of(cond, env, doc.clone())?;
+                    let items = values.into_iter()
+                        .filter(|value| ops::is_truthy(value))
+                        .map(|_| (path.clone(), doc.clone()))
+                        .collect();
+                    Ok(items)
+                },
+                ("recurse", [f]) => recurse_paths_of(f, None, env, path, doc),
+                ("recurse", [f, cond]) => {
+                    recurse_paths_of(f, Some(cond), env, path, doc)
+                },
+                _ => Err(From::from(format!("invalid path expression"))),
+            },
+        },
+        _ => Err(From::from(format!("invalid path expression"))),
+    }
+}
+
+// `recurse(f; cond)`, `doc` and recursively the outputs of `f` for which
+// `cond` is true, depth first.
+fn recurse_paths_of<'a,D>(
+    f: &'a Thunk, cond: Option<&'a Thunk>, env: &Context<'a,D>,
+    path: Path<D>, doc: D) -> Result<Vec<(Path<D>, D)>, D>
+    where D: 'a + Document
+{
+    let mut items = Vec::new();
+    let mut stack = vec![(path, doc)];
+    while let Some((path, doc)) = stack.pop() {
+        items.push((path.clone(), doc.clone()));
+        let mut nexts = Vec::new();
+        for (path, doc) in paths_of(f, env, path, doc)?.into_iter() {
+            let ok = match cond {
+                Some(cond) => {
+                    let values = values_of(cond, env, doc.clone())?;
+                    values.iter().any(|value| ops::is_truthy(value))
+                },
+                None => true,
+            };
+            if ok { nexts.push((path, doc)) }
+        }
+        stack.extend(nexts.into_iter().rev());
+    }
+    Ok(items)
+}
+
+/// Evaluate `thunk` on `doc`, return its outputs or the first error.
+pub fn values_of<'a,D>(thunk: &'a Thunk, env: &Context<'a,D>, doc: D)
+    -> Result<Vec<D>, D>
+    where D: 'a + Document
+{
+    let mut values = Vec::new();
+    for entry in Subquery::new(thunk, env).run(Entry::new(doc)).into_iter() {
+        match entry.error_value() {
+            Some(err) => return Err(err),
+            None => values.push(entry.doc),
+        }
+    }
+    Ok(values)
+}
+
+/// Get the value at `path`, missing values and values under null are null.
+pub fn getpath<D>(doc: &D, path: &[D]) -> Result<D, String>
+    where D: Document
+{
+    let mut doc = doc;
+    for key in path.iter() {
+        let dt = doc.doctype();
+        let next = match (dt, key.doctype()) {
+            (Doctype::Null, _) => return Ok(D::null()),
+            (Doctype::Object, Doctype::String) => {
+                doc.get_ref(key.string_ref().unwrap())
+            },
+            (Doctype::Array, Doctype::Integer) => {
+                let off = key.clone().integer().unwrap() as isize;
+                doc.index_ref(off)
+            },
+            _ => return Err(cannot_index(key, dt)),
+        };
+        match next {
+            Some(next) => doc = next,
+            None => return Ok(D::null()),
+        }
+    }
+    Ok(doc.clone())
+}
+
+/// Set the value at `path`, creating the missing containers on the way.
+pub fn setpath<D>(doc: &mut D, path: &[D], value: D) -> Result<(), String>
+    where D: Document
+{
+    if path.len() == 0 {
+        *doc = value;
+        return Ok(())
+    }
+
+    let key = &path[0];
+    match (doc.doctype(), key.doctype()) {
+        (Doctype::Null, Doctype::String) => {
+            *doc = From::from(Vec::<Property<D>>::new());
+        },
+        (Doctype::Null, Doctype::Integer) => {
+            *doc = From::from(Vec::<D>::new());
+        },
+        _ => (),
+    }
+
+    let dt = doc.doctype();
+    match (dt, key.doctype()) {
+        (Doctype::Object, Doctype::String) => {
+            let key = key.string_ref().unwrap();
+            if doc.get_ref(key).is_none() {
+                doc.set(key, D::null());
+            }
+            setpath(doc.get_mut(key).unwrap(), &path[1..], value)
+        },
+        (Doctype::Array, Doctype::Integer) => {
+            let n = doc.len().unwrap() as i128;
+            let off = key.clone().integer().unwrap();
+            let off = if off < 0 { off + n } else { off };
+            if off < 0 {
+                return Err(format!("out of bounds negative array index"))
+            } else if off >= n {
+                let mut values = mem::replace(doc, D::null()).array().unwrap();
+                values.resize(off as usize + 1, D::null());
+                *doc = From::from(values);
+            }
+            let item = doc.index_mut(off as isize).unwrap();
+            setpath(item, &path[1..], value)
+        },
+        _ => Err(cannot_index(key, dt)),
+    }
+}
+
+/// Delete the values at `paths`, missing paths are ignored.
+pub fn delpaths<D>(doc: &mut D, mut paths: Vec<Path<D>>) -> Result<(), String>
+    where D: Document
+{
+    // delete from the last path, so that array offsets remain valid.
+    paths.sort_by(|a, b| b.partial_cmp(a).unwrap_or(cmp::Ordering::Equal));
+    for path in paths.iter() {
+        delpath(doc, path)?
+    }
+    Ok(())
+}
+
+fn delpath<D>(doc: &mut D, path: &[D]) -> Result<(), String>
+    where D: Document
+{
+    if path.len() == 0 {
+        *doc = D::null();
+        return Ok(())
+    }
+
+    let (key, dt) = (&path[0], doc.doctype());
+    let item = match (dt, key.doctype()) {
+        (Doctype::Null, _) => return Ok(()),
+        (Doctype::Object, Doctype::String) => {
+            let key = key.string_ref().unwrap();
+            if path.len() == 1 {
+                let props = mem::replace(doc, D::null()).object().unwrap();
+                let props: Vec<Property<D>> = props.into_iter()
+                    .filter(|prop| prop.key_ref() != key)
+                    .collect();
+                *doc = From::from(props);
+                return Ok(())
+            }
+            doc.get_mut(key)
+        },
+        (Doctype::Array, Doctype::Integer) => {
+            let off = key.clone().integer().unwrap() as isize;
+            if path.len() == 1 {
+                let mut values = mem::replace(doc, D::null()).array().unwrap();
+                if let Some(off) = util::normalized_offset(off, values.len()) {
+                    values.remove(off);
+                }
+                *doc = From::from(values);
+                return Ok(())
+            }
+            doc.index_mut(off)
+        },
+        _ => return Err(cannot_index(key, dt)),
+    };
+    match item {
+        Some(item) => delpath(item, &path[1..]),
+        None => Ok(()),
+    }
+}
+
+/// Paths of all the values within `doc`, including `doc` itself, in the
+/// same order as `..`. `path` locates `doc` within its root document.
+pub fn all_paths_at<D>(path: Path<D>, doc: D) -> Vec<(Path<D>, D)>
+    where D: Document
+{
+    let mut items = Vec::new();
+    recurse_paths(path, doc, &mut items);
+    items
+}
+
+/// JSON Pointer, as in RFC 6901, for `path`.
+pub fn to_pointer<D>(path: &[D]) -> Result<String, String>
+    where D: Document
+{
+    let mut out = String::new();
+    for key in path.iter() {
+        out.push('/');
+        match (key.doctype(), key.string_ref(), key.clone().integer()) {
+            (_, Some(key), _) => {
+                out.push_str(&key.replace("~", "~0").replace("/", "~1"))
+            },
+            (_, _, Some(off)) if off >= 0 => out.push_str(&off.to_string()),
+            (dt, _, _) => {
+                let err = format!("{:?} cannot be in a JSON Pointer", dt);
+                return Err(err)
+            },
+        }
+    }
+    Ok(out)
+}
+
+/// Path for JSON Pointer `pointer` into `doc`. Tokens are offsets into
+/// arrays, `-` being the offset past the last item, and keys otherwise.
+pub fn from_pointer<D>(doc: &D, pointer: &str) -> Result<Path<D>, String>
+    where D: Document
+{
+    if pointer.len() == 0 { return Ok(vec![]) }
+    if !pointer.starts_with("/") {
+        return Err(format!("{:?} is not a valid JSON Pointer", pointer))
+    }
+    let mut path = Vec::new();
+    let mut doc = Some(doc);
+    for token in pointer[1..].split('/') {
+        let token = token.replace("~1", "/").replace("~0", "~");
+        let (key, next): (D, _) = match doc.map(|doc| doc.doctype()) {
+            Some(Doctype::Array) => {
+                let items = doc.unwrap().array_ref().unwrap();
+                let off = match token.as_str() {
+                    "-" => Some(items.len()),
+                    t if t == "0" || !t.starts_with("0") => t.parse().ok(),
+                    _ => None,
+                };
+                let off = off.ok_or(
+                    format!("{:?} is not a valid array index", token)
+                )?;
+                (From::from(off as i128), items.get(off))
+            },
+            Some(Doctype::Object) => {
+                let next = doc.unwrap().get_ref(&token);
+                (From::from(token), next)
+            },
+            _ => (From::from(token), None),
+        };
+        path.push(key);
+        doc = next;
+    }
+    Ok(path)
+}
+
+fn key_path<D>(path: Path<D>, doc: D, key: &str) -> Result<(Path<D>, D), D>
+    where D: Document
+{
+    let dt = doc.doctype();
+    let value = match dt {
+        Doctype::Null => D::null(),
+        Doctype::Object => doc.get(key).unwrap_or(D::null()),
+        _ => {
+            let err = format!("cannot index {} into {:?}", key, dt);
+            return Err(From::from(err))
+        },
+    };
+    Ok((extend(&path, From::from(key.to_string())), value))
+}
+
+fn offset_path<D>(path: Path<D>, doc: D, off: isize)
+    -> Result<(Path<D>, D), D>
+    where D: Document
+{
+    let dt = doc.doctype();
+    let (off, value) = match dt {
+        Doctype::Null => (off, D::null()),
+        Doctype::Array => {
+            let n = doc.len().unwrap();
+            match util::normalized_offset(off, n) {
+                Some(i) => (i as isize, doc.index(i as isize).unwrap()),
+                None if off >= 0 => (off, D::null()),
+                None => {
+                    let err = format!("out of bounds negative array index");
+                    return Err(From::from(err))
+                },
+            }
+        },
+        _ => {
+            let err = format!("cannot index {} into {:?}", off, dt);
+            return Err(From::from(err))
+        },
+    };
+    Ok((extend(&path, From::from(off as i128)), value))
+}
+
+fn recurse_paths<D>(path: Path<D>, doc: D, items: &mut Vec<(Path<D>, D)>)
+    where D: Document
+{
+    items.push((path.clone(), doc.clone()));
+    match doc.doctype() {
+        Doctype::Array => {
+            let values = doc.array().unwrap();
+            for (i, value) in values.into_iter().enumerate() {
+                let path = extend(&path, From::from(i as i128));
+                recurse_paths(path, value, items)
+            }
+        },
+        Doctype::Object => {
+            for prop in doc.object().unwrap().into_iter() {
+                let key = From::from(prop.key_ref().clone());
+                recurse_paths(extend(&path, key), prop.value(), items)
+            }
+        },
+        _ => (),
+    }
+}
+
+fn extend<D>(path: &Path<D>, key: D) -> Path<D> where D: Document {
+    let mut path = path.clone();
+    path.push(key);
+    path
+}
+
+fn cannot_index<D>(key: &D, dt: Doctype) -> String where D: Document {
+    format!("cannot index {:?} into {:?}", key, dt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::Value;
+    use json::Json;
+
+    fn path(text: &str) -> Vec<Json> {
+        text.parse::<Json>().unwrap().array().unwrap()
+    }
+
+    #[test]
+    fn test_setpath() {
+        let mut doc: Json = r#"{"a":[1,2]}"#.parse().unwrap();
+        setpath(&mut doc, &path(r#"["a",1]"#), Json::Integer(20)).unwrap();
+        setpath(&mut doc, &path(r#"["b","c"]"#), Json::Bool(true)).unwrap();
+        setpath(&mut doc, &path(r#"["a",3]"#), Json::Integer(4)).unwrap();
+        setpath(&mut doc, &path(r#"["a",-1]"#), Json::Integer(40)).unwrap();
+        let refval = r#"{"a":[1,20,null,40],"b":{"c":true}}"#;
+        assert_eq!(refval, &format!("{}", doc));
+
+        let err = setpath(&mut doc, &path(r#"["a","x"]"#), Json::Null);
+        assert_eq!(Err(r#"cannot index "x" into Array"#.to_string()), err);
+        let err = setpath(&mut doc, &path(r#"["a",-5]"#), Json::Null);
+        assert_eq!(Err("out of bounds negative array index".to_string()), err);
+
+        let doc: Json = r#"{"a":[1,2]}"#.parse().unwrap();
+        assert_eq!(Ok(Json::Integer(2)), getpath(&doc, &path(r#"["a",1]"#)));
+        assert_eq!(Ok(Json::Null), getpath(&doc, &path(r#"["x","y"]"#)));
+        assert_eq!(Ok(Json::Null), getpath(&doc, &path(r#"["a",5]"#)));
+    }
+
+    #[test]
+    fn test_delpaths() {
+        let text = r#"{"a":[1,2,3],"b":1,"c":{"d":1}}"#;
+        let mut doc: Json = text.parse().unwrap();
+        let paths = vec![
+            path(r#"["a",0]"#), path(r#"["a",2]"#), path(r#"["b"]"#),
+            path(r#"["c","d"]"#), path(r#"["x","y"]"#),
+        ];
+        delpaths(&mut doc, paths).unwrap();
+        assert_eq!(r#"{"a":[2],"c":{}}"#, &format!("{}", doc));
+    }
+}
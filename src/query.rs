@@ -1,10 +1,14 @@
-use std::{cmp};
+use std::{cmp, error, fmt};
 use std::str::FromStr;
 
 use nom::{self, {types::CompleteStr as NS}};
 
 use query_nom::parse_program_nom;
+use query_check;
+use query_opt;
 use db::{Document, Input};
+use context::Context;
+use lex::Lex;
 use ops;
 
 // TODO: Better to replace panic with assert!() macro.
@@ -17,20 +21,139 @@ impl Expr {
     pub fn prepare<'a,D>(&'a self, input: Input<'a,D>) -> Input<'a,D>
         where D: 'a + Document
     {
-        return self.thunk.prepare(input)
+        return self.thunk.prepare(input, &Context::new())
+    }
+
+    /// Tree of thunks the query is prepared from, after optimization, one
+    /// thunk per line indented under its parent.
+    pub fn explain(&self) -> String {
+        query_opt::explain(&self.thunk)
     }
 }
 
 impl FromStr for Expr {
-    type Err=String;
+    type Err=Error;
 
-    fn from_str(text: &str) -> Result<Expr,String> {
+    fn from_str(text: &str) -> Result<Expr,Error> {
         let thunk: Thunk = text.parse()?;
-        Ok(Expr{ thunk })
+        Ok(Expr{ thunk: query_opt::optimize(thunk) })
     }
 }
 
 
+/// Error parsing a query, located by its `Span` within the query text.
+#[derive(Debug)]
+pub enum Error {
+    /// Invalid syntax, with what was expected or found instead.
+    Syntax(Span, String),
+    /// Call to a function that is not defined, with the number of
+    /// arguments.
+    UnknownFunction(Span, String, usize),
+    /// Call to a builtin with a number of arguments it does not take, with
+    /// the numbers it takes.
+    Arity(Span, String, usize, Vec<usize>),
+    /// Reference to a `$variable` that is not bound in its scope.
+    UnknownVariable(Span, String),
+    /// `@name` that is not one of the format strings.
+    UnknownFormat(Span, String),
+}
+
+impl Error {
+    pub fn span(&self) -> &Span {
+        match self {
+            Error::Syntax(span, _) => span,
+            Error::UnknownFunction(span, _, _) => span,
+            Error::Arity(span, _, _, _) => span,
+            Error::UnknownVariable(span, _) => span,
+            Error::UnknownFormat(span, _) => span,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            Error::Syntax(_, msg) => msg.clone(),
+            Error::UnknownFunction(_, name, n) => {
+                format!("{}/{} is not defined", name, n)
+            },
+            Error::Arity(_, name, n, arities) => {
+                let mut ns: Vec<String> =
+                    arities.iter().map(|n| n.to_string()).collect();
+                let last = ns.pop().unwrap_or(String::new());
+                let ns = match ns.len() {
+                    0 if last == "1" => format!("{} argument", last),
+                    0 => format!("{} arguments", last),
+                    _ => format!("{} or {} arguments", ns.join(", "), last),
+                };
+                format!("{}/{} is not defined, {} takes {}", name, n, name, ns)
+            },
+            Error::UnknownVariable(_, name) => {
+                format!("${} is not defined", name)
+            },
+            Error::UnknownFormat(_, name) => {
+                format!("@{} is not a valid format", name)
+            },
+        };
+        let span = self.span();
+        write!(f, "{}\n{}", span.lex.format(&msg), span.snippet())
+    }
+}
+
+impl error::Error for Error {}
+
+/// Location of `len` bytes at `lex` within the query text, along with the
+/// line of text for the snippet.
+#[derive(Debug)]
+pub struct Span {
+    pub lex: Lex,
+    pub len: usize,
+    pub line: String,
+}
+
+impl Span {
+    pub fn new(text: &str, off: usize, len: usize) -> Span {
+        let lex = Lex::locate(text, off);
+        let line = text.lines().nth(lex.row - 1).unwrap_or("").to_string();
+        Span{lex, len, line}
+    }
+
+    /// Line of text with the located bytes underlined by carets.
+    pub fn snippet(&self) -> String {
+        let col = self.lex.col - 1;
+        let pad: String = self.line.chars().take(col)
+            .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+            .collect();
+        let rest = self.line.chars().count().saturating_sub(col);
+        let len = cmp::max(1, cmp::min(self.len, rest));
+        format!("  {}\n  {}{}", self.line, pad, "^".repeat(len))
+    }
+}
+
+
+/// Assignment operators, `=` assigns the value of rhs evaluated on the
+/// input, `|=` updates each path with rhs evaluated on its old value, rest
+/// combine the old value with rhs evaluated on the input.
+#[derive(Debug,Clone,Copy)]
+pub(crate) enum UpdateOp {
+    Assign,
+    Modify,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Alt,
+}
+
+/// Part of an interpolated string, literal text or an expression whose
+/// outputs are formatted into the text.
+#[derive(Debug,Clone)]
+pub(crate) enum StrPart {
+    Lit(String),
+    Expr(Thunk),
+}
+
 #[derive(Debug,Clone)]
 pub(crate) enum Thunk where {
     // Primary thunks
@@ -72,13 +195,62 @@ pub(crate) enum Thunk where {
     BitOr(Box<Thunk>, Box<Thunk>),
     And(Box<Thunk>, Box<Thunk>),
     Or(Box<Thunk>, Box<Thunk>),
+    Comma(Box<Thunk>, Box<Thunk>),
     Pipe(Box<Thunk>, Box<Thunk>),
+    // Variables, `source as $name | body`
+    Bind(Box<Thunk>, String, Box<Thunk>),
+    Var(String),
+    // Conditionals, `if c then a else b end` and `a // b`
+    If(Box<Thunk>, Box<Thunk>, Box<Thunk>),
+    Alternative(Box<Thunk>, Box<Thunk>),
+    // Aggregation, `reduce source as $name (init; update)` and
+    // `foreach source as $name (init; update; extract)`
+    Reduce(Box<Thunk>, String, Box<Thunk>, Box<Thunk>),
+    Foreach(Box<Thunk>, String, Box<Thunk>, Box<Thunk>, Box<Thunk>),
+    // Errors, `try body catch handler`
+    Try(Box<Thunk>, Option<Box<Thunk>>),
+    // Functions, `def name(params): body; rest`
+    Def(String, Vec<String>, Box<Thunk>, Box<Thunk>),
+    // Assignment, `path = value`, `path |= update`, `path += value` ...
+    Update(Box<Thunk>, Box<Thunk>, UpdateOp),
+    // Strings, `"text \(expr)"` and `@name`, `@name "text \(expr)"`
+    Format(String, Vec<StrPart>),
     // Builtins
     Builtin(String, Vec<Thunk>),
+    // Optimizations, index lookups fused into a path, `.a.b.[0]`, and
+    // thunk whose repeats of the input share its outputs.
+    IndexPath(Vec<(Option<String>, Option<isize>)>),
+    Share(Box<Thunk>),
 }
 
 impl Thunk {
-    fn prepare<'a, D>(&'a self, input: Input<'a, D>) -> Input<D>
+    pub(crate) fn prepare<'a, D>(
+        &'a self, input: Input<'a, D>, env: &Context<'a, D>) -> Input<'a, D>
+        where
+        D: 'a + Document,
+    {
+        if self.is_optional() {
+            Box::new(ops::Try::new(input, (self, None), env))
+        } else {
+            self.prepare_stage(input, env)
+        }
+    }
+
+    /// Whether errors from this thunk are suppressed with `?`.
+    pub(crate) fn is_optional(&self) -> bool {
+        use query::Thunk::*;
+
+        match self {
+            Null(opt) | Bool(_, opt) | Integer(_, opt) | Float(_, opt) => *opt,
+            String(_, opt) | IndexShortcut(_, _, opt) => *opt,
+            Identifier(_, opt) | Slice(_, _, opt) | IterateValues(opt) => *opt,
+            Iterate(_, opt) | List(_, opt) | Dict(_, opt) => *opt,
+            _ => false,
+        }
+    }
+
+    pub(crate) fn prepare_stage<'a, D>(
+        &'a self, input: Input<'a, D>, env: &Context<'a, D>) -> Input<'a, D>
         where
         D: 'a + Document,
     {
@@ -99,176 +271,1588 @@ impl Thunk {
             IndexShortcut(s, n, _opt) => {
                 Box::new(ops::Index::new(input, s.clone(), *n))
             },
-            Identifier(s, _opt) => {
-                Box::new(ops::Identifier::new(input, s.clone()))
+            Identifier(s, _opt) => match env.get_func(s, 0) {
+                Some(func) => Box::new(ops::Call::new(input, func, &[], env)),
+                // `length` is the same as `length(.)`.
+                None if UNARY_BUILTINS.contains(&s.as_str()) => {
+                    let arg = input.repeat();
+                    builtin(s, input, vec![arg])
+                },
+                // `any` is the same as `any(.)`.
+                None if s == "any" || s == "all" => {
+                    let (thunk, all) = (&IDENTITY, s == "all");
+                    Box::new(ops::BuiltinAny::new(input, thunk, env, all))
+                },
+                None if NULLARY_BUILTINS.contains(&s.as_str()) => {
+                    match stream_builtin(s, 0) {
+                        Some(func) => {
+                            let stage =
+                                ops::BuiltinStream::new(input, &[], env, func);
+                            Box::new(stage)
+                        },
+                        None => builtin(s, input, vec![]),
+                    }
+                },
+                None => Box::new(ops::Identifier::new(input, s.clone())),
             },
             Slice(a, b, _opt) => Box::new(ops::Slice::new(input, *a, *b)),
             IterateValues(_opt) => Box::new(ops::IterValues::new(input)),
             Iterate(thunks, _opt) => {
                 let mut inputs = Vec::new();
                 for thunk in thunks.iter() {
-                    inputs.push(thunk.prepare(input.repeat()));
+                    inputs.push(thunk.prepare(input.repeat(), env));
                 }
                 Box::new(ops::Iter::new(inputs))
             },
-            List(thunks, _opt) => {
-                let mut inputs = Vec::new();
-                for thunk in thunks.iter() {
-                    inputs.push(thunk.prepare(input.repeat()));
-                }
-                Box::new(ops::List::new(inputs))
-            },
-            Dict(thunks, _opt) => {
-                let mut inputs = Vec::new();
-                for (kt, vt) in thunks.iter() {
-                    inputs.push(
-                        (kt.prepare(input.repeat()), vt.prepare(input.repeat()))
-                    );
-                }
-                Box::new(ops::Dict::new(inputs))
-            },
+            List(thunks, _opt) => Box::new(ops::List::new(input, thunks, env)),
+            Dict(thunks, _opt) => Box::new(ops::Dict::new(input, thunks, env)),
 
-            Neg(thunk) => Box::new(ops::Neg::new(thunk.prepare(input))),
-            Not(thunk) => Box::new(ops::Not::new(thunk.prepare(input))),
+            Neg(thunk) => Box::new(ops::Neg::new(thunk.prepare(input, env))),
+            Not(thunk) => Box::new(ops::Not::new(thunk.prepare(input, env))),
 
-            Mul(lthunk, rthunk) => {
-                let lhs = lthunk.prepare(input.repeat());
-                let rhs = rthunk.prepare(input.repeat());
-                Box::new(ops::Mul::new(lhs, rhs))
-            },
-            Div(lthunk, rthunk) => {
-                let lhs = lthunk.prepare(input.repeat());
-                let rhs = rthunk.prepare(input.repeat());
-                Box::new(ops::Div::new(lhs, rhs))
-            },
-            Rem(lthunk, rthunk) => {
-                let lhs = lthunk.prepare(input.repeat());
-                let rhs = rthunk.prepare(input.repeat());
-                Box::new(ops::Rem::new(lhs, rhs))
-            },
-            Add(lthunk, rthunk) => {
-                let lhs = lthunk.prepare(input.repeat());
-                let rhs = rthunk.prepare(input.repeat());
-                Box::new(ops::Add::new(lhs, rhs))
-            },
-            Sub(lthunk, rthunk) => {
-                let lhs = lthunk.prepare(input.repeat());
-                let rhs = rthunk.prepare(input.repeat());
-                Box::new(ops::Sub::new(lhs, rhs))
-            },
-            Shr(lthunk, rthunk) => {
-                let lhs = lthunk.prepare(input.repeat());
-                let rhs = rthunk.prepare(input.repeat());
-                Box::new(ops::Shr::new(lhs, rhs))
+            Mul(lhs, rhs) => binary(input, (lhs, rhs), env, ops::mul),
+            Div(lhs, rhs) => binary(input, (lhs, rhs), env, ops::div),
+            Rem(lhs, rhs) => binary(input, (lhs, rhs), env, ops::rem),
+            Add(lhs, rhs) => binary(input, (lhs, rhs), env, ops::add),
+            Sub(lhs, rhs) => binary(input, (lhs, rhs), env, ops::sub),
+            Shr(lhs, rhs) => binary(input, (lhs, rhs), env, ops::shr),
+            Shl(lhs, rhs) => binary(input, (lhs, rhs), env, ops::shl),
+            BitAnd(lhs, rhs) => binary(input, (lhs, rhs), env, ops::bitand),
+            BitXor(lhs, rhs) => binary(input, (lhs, rhs), env, ops::bitxor),
+            BitOr(lhs, rhs) => binary(input, (lhs, rhs), env, ops::bitor),
+            Eq(lhs, rhs) => binary(input, (lhs, rhs), env, ops::eq),
+            Ne(lhs, rhs) => binary(input, (lhs, rhs), env, ops::ne),
+            Lt(lhs, rhs) => binary(input, (lhs, rhs), env, ops::lt),
+            Le(lhs, rhs) => binary(input, (lhs, rhs), env, ops::le),
+            Gt(lhs, rhs) => binary(input, (lhs, rhs), env, ops::gt),
+            Ge(lhs, rhs) => binary(input, (lhs, rhs), env, ops::ge),
+            And(lhs, rhs) => {
+                let thunks = (lhs.as_ref(), rhs.as_ref());
+                Box::new(ops::Logical::new(input, thunks, env, false))
             },
-            Shl(lthunk, rthunk) => {
-                let lhs = lthunk.prepare(input.repeat());
-                let rhs = rthunk.prepare(input.repeat());
-                Box::new(ops::Shl::new(lhs, rhs))
+            Or(lhs, rhs) => {
+                let thunks = (lhs.as_ref(), rhs.as_ref());
+                Box::new(ops::Logical::new(input, thunks, env, true))
             },
-            BitAnd(lthunk, rthunk) => {
-                let lhs = lthunk.prepare(input.repeat());
-                let rhs = rthunk.prepare(input.repeat());
-                Box::new(ops::Bitand::new(lhs, rhs))
+            Comma(lthunk, rthunk) => {
+                let thunks = (lthunk.as_ref(), rthunk.as_ref());
+                Box::new(ops::Comma::new(input, thunks, env))
             },
-            BitXor(lthunk, rthunk) => {
-                let lhs = lthunk.prepare(input.repeat());
-                let rhs = rthunk.prepare(input.repeat());
-                Box::new(ops::Bitxor::new(lhs, rhs))
+            Pipe(lthunk, rthunk) => {
+                rthunk.prepare(lthunk.prepare(input, env), env)
             },
-            BitOr(lthunk, rthunk) => {
-                let lhs = lthunk.prepare(input.repeat());
-                let rhs = rthunk.prepare(input.repeat());
-                Box::new(ops::Bitor::new(lhs, rhs))
+            Bind(source, name, body) => {
+                let thunks = (source.as_ref(), name.as_str(), body.as_ref());
+                Box::new(ops::Bind::new(input, thunks, env))
             },
-            Eq(lthunk, rthunk) => {
-                let lhs = lthunk.prepare(input.repeat());
-                let rhs = rthunk.prepare(input.repeat());
-                Box::new(ops::Eq::new(lhs, rhs))
+            Var(name) => Box::new(ops::Var::new(input, name.clone(), env)),
+            If(cond, then, otherwise) => {
+                let thunks = (cond.as_ref(), then.as_ref(), otherwise.as_ref());
+                Box::new(ops::If::new(input, thunks, env))
             },
-            Ne(lthunk, rthunk) => {
-                let lhs = lthunk.prepare(input.repeat());
-                let rhs = rthunk.prepare(input.repeat());
-                Box::new(ops::Ne::new(lhs, rhs))
+            Alternative(lthunk, rthunk) => {
+                let thunks = (lthunk.as_ref(), rthunk.as_ref());
+                Box::new(ops::Alternative::new(input, thunks, env))
             },
-            Lt(lthunk, rthunk) => {
-                let lhs = lthunk.prepare(input.repeat());
-                let rhs = rthunk.prepare(input.repeat());
-                Box::new(ops::Lt::new(lhs, rhs))
+            Reduce(source, name, init, update) => {
+                let thunks = (
+                    source.as_ref(), name.as_str(),
+                    init.as_ref(), update.as_ref(),
+                );
+                Box::new(ops::Reduce::new(input, thunks, env))
             },
-            Le(lthunk, rthunk) => {
-                let lhs = lthunk.prepare(input.repeat());
-                let rhs = rthunk.prepare(input.repeat());
-                Box::new(ops::Le::new(lhs, rhs))
+            Foreach(source, name, init, update, extract) => {
+                let thunks = (
+                    source.as_ref(), name.as_str(),
+                    init.as_ref(), update.as_ref(), extract.as_ref(),
+                );
+                Box::new(ops::Foreach::new(input, thunks, env))
             },
-            Gt(lthunk, rthunk) => {
-                let lhs = lthunk.prepare(input.repeat());
-                let rhs = rthunk.prepare(input.repeat());
-                Box::new(ops::Gt::new(lhs, rhs))
+            Try(body, catch) => {
+                let catch = catch.as_ref().map(|c| c.as_ref());
+                let thunks = (body.as_ref(), catch);
+                Box::new(ops::Try::new(input, thunks, env))
             },
-            Ge(lthunk, rthunk) => {
-                let lhs = lthunk.prepare(input.repeat());
-                let rhs = rthunk.prepare(input.repeat());
-                Box::new(ops::Ge::new(lhs, rhs))
+            Def(name, params, body, rest) => {
+                rest.prepare(input, &env.define(name, params, body))
             },
-            And(lthunk, rthunk) => {
-                let lhs = lthunk.prepare(input.repeat());
-                let rhs = rthunk.prepare(input.repeat());
-                Box::new(ops::And::new(lhs, rhs))
+            Update(lhs, rhs, op) => {
+                let thunks = (lhs.as_ref(), rhs.as_ref());
+                Box::new(ops::Update::new(input, thunks, *op, env))
             },
-            Or(lthunk, rthunk) => {
-                let lhs = lthunk.prepare(input.repeat());
-                let rhs = rthunk.prepare(input.repeat());
-                Box::new(ops::Or::new(lhs, rhs))
+            Format(name, parts) => {
+                Box::new(ops::Format::new(input, name, parts, env))
             },
-            Pipe(lthunk, rthunk) => rthunk.prepare(lthunk.prepare(input)),
 
             Builtin(name, thunks) => {
+                if let Some(func) = env.get_func(name, thunks.len()) {
+                    return Box::new(ops::Call::new(input, func, thunks, env))
+                }
+                // builtins taking their argument as a path or a filter.
+                match (name.as_str(), thunks.as_slice()) {
+                    // `del(a, b)` deletes the paths of `a` and `b`.
+                    ("del", [_]) => {
+                        let stage = ops::BuiltinDel::new(input, thunks, env);
+                        return Box::new(stage)
+                    },
+                    ("with_entries", [thunk]) => {
+                        let stage =
+                            ops::BuiltinWithEntries::new(input, thunk, env);
+                        return Box::new(stage)
+                    },
+                    ("map", [thunk]) => {
+                        return Box::new(ops::BuiltinMap::new(input, thunk, env))
+                    },
+                    ("any", [thunk]) | ("all", [thunk]) => {
+                        let all = name == "all";
+                        let stage =
+                            ops::BuiltinAny::new(input, thunk, env, all);
+                        return Box::new(stage)
+                    },
+                    ("select", [thunk]) => {
+                        let stage = ops::BuiltinSelect::new(input, thunk, env);
+                        return Box::new(stage)
+                    },
+                    ("sort_by", [thunk]) | ("group_by", [thunk]) |
+                    ("unique_by", [thunk]) | ("min_by", [thunk]) |
+                    ("max_by", [thunk]) => {
+                        use builtin_array as a;
+
+                        let by: fn(Vec<D>, Vec<D>) -> D = match name.as_str() {
+                            "sort_by" => a::sort_by,
+                            "group_by" => a::group_by,
+                            "unique_by" => a::unique_by,
+                            "min_by" => a::min_by,
+                            _ => a::max_by,
+                        };
+                        let stage = ops::BuiltinBy::new(input, thunk, env, by);
+                        return Box::new(stage)
+                    },
+                    ("limit", [n, thunk]) | ("nth", [n, thunk]) => {
+                        use builtin_array as a;
+
+                        let take = match name.as_str() {
+                            "limit" => a::limit,
+                            _ => a::nth_of,
+                        };
+                        let arg = Some(n.prepare(input.repeat(), env));
+                        let stage =
+                            ops::BuiltinTake::new(input, arg, thunk, env, take);
+                        return Box::new(stage)
+                    },
+                    ("first", [thunk]) | ("last", [thunk]) => {
+                        use builtin_array as a;
+
+                        let take = match name.as_str() {
+                            "first" => a::first_of,
+                            _ => a::last_of,
+                        };
+                        let arg = None;
+                        let stage =
+                            ops::BuiltinTake::new(input, arg, thunk, env, take);
+                        return Box::new(stage)
+                    },
+                    ("range", [_]) | ("range", [_, _]) |
+                    ("range", [_, _, _]) => {
+                        let stage = ops::BuiltinRange::new(input, thunks, env);
+                        return Box::new(stage)
+                    },
+                    ("until", [cond, update]) => {
+                        let thunks = (cond, update);
+                        let stage = ops::BuiltinUntil::new(input, thunks, env);
+                        return Box::new(stage)
+                    },
+                    ("path", [thunk]) => {
+                        let stage = ops::BuiltinPathOf::new(input, thunk, env);
+                        return Box::new(stage)
+                    },
+                    ("paths", [thunk]) => {
+                        let stage = ops::BuiltinPaths::new(input, thunk, env);
+                        return Box::new(stage)
+                    },
+                    ("recurse", [thunk]) => {
+                        let thunks = (thunk, None);
+                        let stage =
+                            ops::BuiltinRecurse::new(input, thunks, env);
+                        return Box::new(stage)
+                    },
+                    ("recurse", [thunk, cond]) => {
+                        let thunks = (thunk, Some(cond));
+                        let stage =
+                            ops::BuiltinRecurse::new(input, thunks, env);
+                        return Box::new(stage)
+                    },
+                    ("INDEX", [f]) | ("GROUP_BY", [f]) => {
+                        let (thunks, group) =
+                            ((&ITERATE_VALUES, f), name == "GROUP_BY");
+                        let stage =
+                            ops::BuiltinIndex::new(input, thunks, env, group);
+                        return Box::new(stage)
+                    },
+                    ("INDEX", [stream, f]) | ("GROUP_BY", [stream, f]) => {
+                        let (thunks, group) = ((stream, f), name == "GROUP_BY");
+                        let stage =
+                            ops::BuiltinIndex::new(input, thunks, env, group);
+                        return Box::new(stage)
+                    },
+                    ("IN", [s]) => {
+                        let stage = ops::BuiltinIn::new(input, (None, s), env);
+                        return Box::new(stage)
+                    },
+                    ("IN", [source, s]) => {
+                        let thunks = (Some(source), s);
+                        let stage = ops::BuiltinIn::new(input, thunks, env);
+                        return Box::new(stage)
+                    },
+                    ("JOIN", [idx, f]) => {
+                        let thunks = (idx, &ITERATE_VALUES, f, None);
+                        let stage =
+                            ops::BuiltinJoin::new(input, thunks, env, true);
+                        return Box::new(stage)
+                    },
+                    ("JOIN", [idx, stream, f]) => {
+                        let thunks = (idx, stream, f, None);
+                        let stage =
+                            ops::BuiltinJoin::new(input, thunks, env, false);
+                        return Box::new(stage)
+                    },
+                    ("JOIN", [idx, stream, f, join]) => {
+                        let thunks = (idx, stream, f, Some(join));
+                        let stage =
+                            ops::BuiltinJoin::new(input, thunks, env, false);
+                        return Box::new(stage)
+                    },
+                    ("walk", [thunk]) => {
+                        let stage = ops::BuiltinWalk::new(input, thunk, env);
+                        return Box::new(stage)
+                    },
+                    _ => (),
+                }
+                if let Some(stage) = regex_builtin(name, thunks, &input, env) {
+                    return stage
+                }
+                if let Some(func) = stream_builtin(name, thunks.len()) {
+                    let stage =
+                        ops::BuiltinStream::new(input, thunks, env, func);
+                    return Box::new(stage)
+                }
+                if let Some(func) = builtin2(name, thunks.len()) {
+                    let stage = ops::BuiltinFn2::new(input, thunks, env, func);
+                    return Box::new(stage)
+                }
                 let mut args = Vec::new();
                 for thunk in thunks.iter() {
-                    args.push(thunk.prepare(input.repeat()))
-                }
-                match name.as_str() {
-                    "length" => Box::new(ops::BuiltinLength::new(args)),
-                    "chars" => Box::new(ops::BuiltinChars::new(args)),
-                    "keys" => Box::new(ops::BuiltinKeys::new(args)),
-                    //"has" => BuiltinHas::new(args),
-                    //"in" => BuiltinIn::new(args),
-                    //"map" => BuiltinMap::new(args),
-                    //"any" => BuiltinAny::new(args),
-                    //"all" => BuiltinAll::new(args),
-                    _ => unreachable!(), // TODO: implement BuiltinInvalid
+                    args.push(thunk.prepare(input.repeat(), env))
                 }
+                builtin(name, input, args)
+            },
+
+            IndexPath(keys) => Box::new(ops::IndexPath::new(input, keys)),
+            Share(thunk) => {
+                thunk.prepare(Box::new(ops::Shared::new(input)), env)
             },
-            // _ => unreachable!(),
         }
     }
 }
 
+// `.[]`, the default stream for `INDEX(f)`, `GROUP_BY(f)` and
+// `JOIN($idx; f)`.
+static ITERATE_VALUES: Thunk = Thunk::IterateValues(false);
+
+// `.`, the default filter for `any` and `all`.
+static IDENTITY: Thunk = Thunk::Identity;
+
+// binary operator `op` applied on the outputs of `lhs` and `rhs`.
+fn binary<'a,D>(
+    input: Input<'a,D>,
+    (lhs, rhs): (&'a Box<Thunk>, &'a Box<Thunk>),
+    env: &Context<'a,D>,
+    op: fn(D, D) -> D) -> Input<'a,D>
+    where D: 'a + Document
+{
+    let thunks = (lhs.as_ref(), rhs.as_ref());
+    Box::new(ops::Binary::new(input, thunks, env, op))
+}
+
+/// Builtins called with arguments, and the numbers of arguments they take.
+/// Calls are checked against these at parse time.
+pub(crate) const CALL_BUILTINS: [(&'static str, &'static [usize]); 69] = [
+    // arguments as filters or paths.
+    ("del", &[1]), ("with_entries", &[1]), ("map", &[1]), ("any", &[1]),
+    ("all", &[1]), ("select", &[1]), ("sort_by", &[1]), ("group_by", &[1]),
+    ("unique_by", &[1]), ("min_by", &[1]), ("max_by", &[1]),
+    ("limit", &[2]), ("nth", &[1, 2]), ("first", &[1]), ("last", &[1]),
+    ("until", &[2]), ("path", &[1]), ("paths", &[1]), ("recurse", &[1, 2]),
+    ("walk", &[1]), ("INDEX", &[1, 2]), ("GROUP_BY", &[1, 2]),
+    ("IN", &[1, 2]), ("JOIN", &[2, 3, 4]),
+    // regular expressions.
+    ("test", &[1, 2]), ("match", &[1, 2]), ("capture", &[1, 2]),
+    ("scan", &[1, 2]), ("split", &[1, 2]), ("sub", &[2, 3]),
+    ("gsub", &[2, 3]),
+    // arguments by value, as streams.
+    ("range", &[1, 2, 3]), ("combinations", &[1]), ("dateadd", &[2]),
+    ("datesub", &[2]), ("setpath", &[2]),
+    // subject argument, same as calling them on the input document.
+    ("length", &[1]), ("chars", &[1]), ("keys", &[1]), ("error", &[1]),
+    ("to_entries", &[1]), ("from_entries", &[1]), ("has", &[1]),
+    ("in", &[1]), ("ascii_downcase", &[1]), ("ascii_upcase", &[1]),
+    ("explode", &[1]), ("implode", &[1]), ("tostring", &[1]),
+    ("tonumber", &[1]),
+    // input document and an argument.
+    ("join", &[1]), ("ltrimstr", &[1]), ("rtrimstr", &[1]),
+    ("startswith", &[1]), ("endswith", &[1]), ("flatten", &[1]),
+    ("indices", &[1]), ("index", &[1]), ("rindex", &[1]),
+    ("contains", &[1]), ("inside", &[1]), ("strftime", &[1]),
+    ("strptime", &[1]), ("getpath", &[1]), ("delpaths", &[1]),
+    // math with both operands as arguments.
+    ("pow", &[2]), ("atan2", &[2]), ("div", &[2]), ("mod", &[2]),
+];
+
+/// Builtins that can be called without arguments, on the input document.
+pub(crate) const UNARY_BUILTINS: [&'static str; 12] = [
+    "length", "chars", "keys", "error", "to_entries", "from_entries",
+    "ascii_downcase", "ascii_upcase", "explode", "implode", "tostring",
+    "tonumber",
+];
+
+/// Builtins without arguments, that are not the same as calling them on
+/// the input document, like `flatten` and `flatten(depth)`.
+pub(crate) const NULLARY_BUILTINS: [&'static str; 66] = [
+    "sort", "unique", "min", "max", "reverse", "flatten", "add",
+    "transpose", "first", "last", "combinations", "type", "not", "empty",
+    "arrays", "objects", "iterables", "booleans", "numbers", "strings",
+    "nulls", "values", "scalars", "isinfinite", "isnan", "isnormal",
+    "infinite", "nan", "tojson", "fromjson", "floor", "ceil", "round",
+    "fabs", "sqrt", "log", "log2", "log10", "exp", "exp2", "exp10", "sin",
+    "cos", "tan", "asin", "acos", "atan", "sinh", "cosh", "tanh",
+    "significand", "now", "todate", "todateiso8601", "date", "fromdate",
+    "fromdateiso8601", "gmtime", "mktime", "paths", "leaf_paths",
+    "recurse", "env", "topointer", "any", "all",
+];
+
+// `test`, `match`, `capture`, `scan` with optional flags, `split/2` and
+// `sub`, `gsub` with replacement filter and optional flags.
+fn regex_builtin<'a,D>(
+    name: &str, thunks: &'a [Thunk], input: &Input<'a,D>, env: &Context<'a,D>)
+    -> Option<Input<'a,D>>
+    where D: 'a + Document
+{
+    use builtin_regex::RegexOp;
+
+    let op = RegexOp::from_name(name)?;
+    let (args, thunk): (Vec<&Thunk>, _) = match (op, thunks) {
+        (RegexOp::Split, [re, flags]) => (vec![re, flags], None),
+        (RegexOp::Split, _) => return None,
+        (RegexOp::Sub, [re, repl]) | (RegexOp::Gsub, [re, repl]) => {
+            (vec![re], Some(repl))
+        },
+        (RegexOp::Sub, [re, repl, flags]) |
+        (RegexOp::Gsub, [re, repl, flags]) => (vec![re, flags], Some(repl)),
+        (RegexOp::Sub, _) | (RegexOp::Gsub, _) => return None,
+        (_, [re]) => (vec![re], None),
+        (_, [re, flags]) => (vec![re, flags], None),
+        (_, _) => return None,
+    };
+    let stage = ops::BuiltinRegex::new(input.repeat(), op, args, thunk, env);
+    Some(Box::new(stage))
+}
+
+// builtins with any number of outputs, taking `n` arguments by value.
+fn stream_builtin<D>(name: &str, n: usize) -> Option<ops::Stream<D>>
+    where D: Document
+{
+    use builtin_array as a;
+    use builtin_path as p;
+    use builtin_time as tm;
+
+    let stream: ops::Stream<D> = match (name, n) {
+        ("combinations", 0) | ("combinations", 1) => a::combinations,
+        ("dateadd", 2) => tm::dateadd,
+        ("datesub", 2) => tm::datesub,
+        ("paths", 0) => p::paths,
+        ("leaf_paths", 0) => p::leaf_paths,
+        ("setpath", 2) => p::setpath,
+        _ => return None,
+    };
+    Some(stream)
+}
+
+// builtins taking their arguments by value, evaluated on the input.
+fn builtin<'a,D>(name: &str, input: Input<'a,D>, mut args: Vec<Input<'a,D>>)
+    -> Input<'a,D>
+    where D: 'a + Document
+{
+    use builtin_str as s;
+    use builtin_array as a;
+    use builtin_type as t;
+    use builtin_math as m;
+    use builtin_path as p;
+    use builtin_time as tm;
+
+    if let (Some(pred), 0) = (t::selector(name), args.len()) {
+        return Box::new(ops::BuiltinFilter::new(input, pred))
+    }
+    let fn1: fn(D) -> Result<D,String> = match (name, args.len()) {
+        ("length", 1) => return Box::new(ops::BuiltinLength::new(args)),
+        ("chars", 1) => return Box::new(ops::BuiltinChars::new(args)),
+        ("keys", 1) => return Box::new(ops::BuiltinKeys::new(args)),
+        ("error", 1) => return Box::new(ops::BuiltinError::new(args)),
+        ("to_entries", 1) => {
+            return Box::new(ops::BuiltinToEntries::new(args))
+        },
+        ("from_entries", 1) => {
+            return Box::new(ops::BuiltinFromEntries::new(args))
+        },
+        ("ascii_downcase", 1) => s::ascii_downcase,
+        ("ascii_upcase", 1) => s::ascii_upcase,
+        ("explode", 1) => s::explode,
+        ("implode", 1) => s::implode,
+        ("tostring", 1) => s::tostring,
+        ("tonumber", 1) => s::tonumber,
+        ("sort", 0) => a::sort,
+        ("unique", 0) => a::unique,
+        ("min", 0) => a::min,
+        ("max", 0) => a::max,
+        ("reverse", 0) => a::reverse,
+        ("flatten", 0) => a::flatten,
+        ("add", 0) => a::add,
+        ("transpose", 0) => a::transpose,
+        ("first", 0) => a::first,
+        ("last", 0) => a::last,
+        ("type", 0) => t::type_of,
+        ("not", 0) => t::not,
+        ("isinfinite", 0) => t::isinfinite,
+        ("isnan", 0) => t::isnan,
+        ("isnormal", 0) => t::isnormal,
+        ("infinite", 0) => t::infinite,
+        ("nan", 0) => t::nan,
+        ("tojson", 0) => t::tojson,
+        ("fromjson", 0) => t::fromjson,
+        ("floor", 0) => m::floor,
+        ("ceil", 0) => m::ceil,
+        ("round", 0) => m::round,
+        ("fabs", 0) => m::fabs,
+        ("sqrt", 0) => m::sqrt,
+        ("log", 0) => m::log,
+        ("log2", 0) => m::log2,
+        ("log10", 0) => m::log10,
+        ("exp", 0) => m::exp,
+        ("exp2", 0) => m::exp2,
+        ("exp10", 0) => m::exp10,
+        ("sin", 0) => m::sin,
+        ("cos", 0) => m::cos,
+        ("tan", 0) => m::tan,
+        ("asin", 0) => m::asin,
+        ("acos", 0) => m::acos,
+        ("atan", 0) => m::atan,
+        ("sinh", 0) => m::sinh,
+        ("cosh", 0) => m::cosh,
+        ("tanh", 0) => m::tanh,
+        ("significand", 0) => m::significand,
+        ("now", 0) => tm::now,
+        ("todate", 0) | ("todateiso8601", 0) | ("date", 0) => tm::todate,
+        ("fromdate", 0) | ("fromdateiso8601", 0) => tm::fromdate,
+        ("gmtime", 0) => tm::gmtime,
+        ("mktime", 0) => tm::mktime,
+        ("recurse", 0) => return Box::new(ops::Recurse::new(input)),
+        ("env", 0) => p::env,
+        ("topointer", 0) => p::topointer,
+        // unknown name or wrong number of arguments is not defined.
+        (_, n) => {
+            let name = name.to_string();
+            return Box::new(ops::BuiltinInvalid::new(input, name, n))
+        },
+    };
+    // subject argument, as in `length(.)`, or the input document.
+    let subject = if args.len() > 0 { args.remove(0) } else { input };
+    Box::new(ops::BuiltinFn1::new(subject, fn1))
+}
+
+// builtins computing one output from the input document and an argument,
+// or from two arguments, taking `n` arguments by value.
+fn builtin2<D>(name: &str, n: usize) -> Option<ops::Fn2<D>>
+    where D: Document
+{
+    use builtin_str as s;
+    use builtin_array as a;
+    use builtin_path as p;
+    use builtin_time as tm;
+
+    let fn2: ops::Fn2<D> = match (name, n) {
+        ("has", 1) => ops::has,
+        ("in", 1) => ops::is_in,
+        ("split", 1) => s::split,
+        ("join", 1) => s::join,
+        ("ltrimstr", 1) => s::ltrimstr,
+        ("rtrimstr", 1) => s::rtrimstr,
+        ("startswith", 1) => s::startswith,
+        ("endswith", 1) => s::endswith,
+        ("flatten", 1) => a::flatten_depth,
+        ("nth", 1) => a::nth,
+        ("indices", 1) => a::indices,
+        ("index", 1) => a::index,
+        ("rindex", 1) => a::rindex,
+        ("contains", 1) => a::contains,
+        ("inside", 1) => a::inside,
+        ("strftime", 1) => tm::strftime,
+        ("strptime", 1) => tm::strptime,
+        ("getpath", 1) => p::getpath,
+        ("delpaths", 1) => p::delpaths,
+        (_, 2) => return math2(name),
+        _ => return None,
+    };
+    Some(fn2)
+}
+
+// math builtins taking both the operands as arguments, as in `pow(2; 10)`.
+fn math2<D>(name: &str) -> Option<ops::Fn2<D>>
+    where D: Document
+{
+    use builtin_math as m;
+
+    let fn2: ops::Fn2<D> = match name {
+        "pow" => m::pow,
+        "atan2" => m::atan2,
+        "div" => m::div,
+        "mod" => m::modulo,
+        _ => return None,
+    };
+    Some(fn2)
+}
+
 impl FromStr for Thunk {
-    type Err=String;
+    type Err=Error;
 
-    fn from_str(text: &str) -> Result<Thunk,String> {
+    fn from_str(text: &str) -> Result<Thunk,Error> {
         use nom::simple_errors::Context as NomContext;
 
-        let res = parse_program_nom(NS(text));
-        match res {
-            Ok((_x, thunk)) => Ok(thunk),
-            Err(err) => match err {
-                nom::Err::Incomplete(_) => {
-                    Err(format!("{}", err))
-                },
-                nom::Err::Error(NomContext::Code(rem, _)) => {
-                    let rem_till = cmp::min(5, rem.len());
-                    Err(format!("error at {:}", &rem[..rem_till]))
-                },
-                nom::Err::Failure(NomContext::Code(rem, _)) => {
-                    let rem_till = cmp::min(5, rem.len());
-                    Err(format!("failure at {}", &rem[..rem_till]))
-                },
+        // byte offset where parsing stopped, remaining input other than
+        // white space is an error.
+        let off = match parse_program_nom(NS(text)) {
+            Ok((rem, thunk)) if rem.trim().is_empty() => {
+                query_check::check_names(text, &thunk)?;
+                return Ok(thunk)
+            },
+            Ok((rem, _)) => text.len() - rem.len(),
+            Err(nom::Err::Incomplete(_)) => text.len(),
+            Err(nom::Err::Error(NomContext::Code(rem, _))) |
+            Err(nom::Err::Failure(NomContext::Code(rem, _))) => {
+                text.len() - rem.len()
+            },
+        };
+        Err(query_check::syntax_error(text, off))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use json::Json;
+    use input_mem::InputMem;
+
+    fn run(program: &str, doc: &str) -> Vec<String> {
+        let expr: Expr = program.parse().unwrap();
+        let mem = InputMem::new(vec![doc.parse::<Json>().unwrap()]);
+        let outs: Vec<String> = expr.prepare(mem.repeat()).map(|entry| {
+            if entry.has_error() {
+                format!("error: {}", entry.op.get_ref("errors").unwrap())
+            } else {
+                format!("{}", entry.doc)
             }
+        }).collect();
+        outs
+    }
+
+    fn parse_error(program: &str) -> String {
+        match program.parse::<Expr>() {
+            Ok(_) => panic!("{:?} parsed without error", program),
+            Err(err) => format!("{}", err),
         }
     }
+
+    #[test]
+    fn test_query_bind() {
+        let doc = r#"{"a":1,"b":2}"#;
+        assert_eq!(vec!["3"], run(".a as $x | .b | $x + .", doc));
+        assert_eq!(vec!["1"], run(".a as $x | .b as $y | $x", doc));
+
+        let outs = run(".[] as $x | $x * 2", "[1,2,3]");
+        assert_eq!(vec!["2", "4", "6"], outs);
+
+        let doc = r#"{"n":10,"items":[1,2]}"#;
+        let outs = run(". as $top | .items | .[] | . * ($top | .n)", doc);
+        assert_eq!(vec!["10", "20"], outs);
+    }
+
+    #[test]
+    fn test_query_bind_scope() {
+        let outs = run("1 as $x | (2 as $x | $x) + $x", "null");
+        assert_eq!(vec!["3"], outs);
+
+        // binding is not visible in its own source, nor after its body.
+        let outs = run(".a as $x | $x as $x | $x", r#"{"a":4}"#);
+        assert_eq!(vec!["4"], outs);
+        let err = parse_error("(1 as $x | $x) | $x");
+        assert!(err.starts_with("$x is not defined at offset:17"), "{}", err);
+        let err = parse_error("$x as $x | 1");
+        assert!(err.starts_with("$x is not defined at offset:0"), "{}", err);
+    }
+
+    #[test]
+    fn test_query_def() {
+        let doc = r#"{"a":1,"b":2}"#;
+        assert_eq!(vec!["2"], run("def inc: . + 1; .a | inc", doc));
+        assert_eq!(vec!["3"], run("def f(g): g + 1; f(.b)", doc));
+        let outs = run("def f($x; $y): $x * 10 + $y; f(.a; .b)", doc);
+        assert_eq!(vec!["12"], outs);
+        // value parameters are also available as filters.
+        assert_eq!(vec!["4"], run("def f($x): x + $x; f(.b)", doc));
+
+        // filter arguments are evaluated in the caller's scope.
+        let prog = "1 as $x | def f(g): 2 as $x | g; f($x)";
+        assert_eq!(vec!["1"], run(prog, "null"));
+        // functions see the scope of their definition.
+        let prog = "1 as $x | def f: $x; 2 as $x | f";
+        assert_eq!(vec!["1"], run(prog, "null"));
+        // shadowing and arity
+        let prog = "def f: 1; def f(a): 2; def g: f; def f: 3; [g, f, f(.)]";
+        assert_eq!(vec!["[1,3,2]"], run(prog, "null"));
+        // definition is not visible outside its scope, `f` is a lookup.
+        let outs = run("(def f: 1; f) | f", "null");
+        assert_eq!(vec![r#"error: ["cannot index f into Integer"]"#], outs);
+
+        // arguments are separated by `;`, `,` is the comma operator.
+        let prog = "def f(g): [g]; f(.a, .b)";
+        assert_eq!(vec!["[1,2]"], run(prog, doc));
+        let err = parse_error("def f(a; b): a; f(1, 2)");
+        assert!(err.starts_with("f/1 is not defined"), "{}", err);
+        parse_error("def f(a, b): a; f(1; 2)");
+        let err = parse_error("del(.a; .b)");
+        assert!(err.starts_with("del/2 is not defined"), "{}", err);
+    }
+
+    #[test]
+    fn test_query_if() {
+        let prog = "if .a then .b elif .c then .c else 0 end";
+        assert_eq!(vec!["2"], run(prog, r#"{"a":1,"b":2,"c":3}"#));
+        assert_eq!(vec!["3"], run(prog, r#"{"a":null,"b":2,"c":3}"#));
+        assert_eq!(vec!["0"], run(prog, r#"{"a":false,"b":2,"c":false}"#));
+        // zero and empty string are true.
+        assert_eq!(vec!["1"], run(r#"if 0 then 1 else 2 end"#, "null"));
+        assert_eq!(vec!["1"], run(r#"if "" then 1 else 2 end"#, "null"));
+        // missing else is identity.
+        assert_eq!(vec!["5"], run("if . == 1 then 10 end", "5"));
+
+        // every output from condition selects a branch.
+        let prog = "if .[] then 1 else 2 end";
+        assert_eq!(vec!["1", "2", "2", "1"], run(prog, "[true,false,null,0]"));
+
+        // only the selected branch is evaluated.
+        let prog = r#"if . == 1 then "one" else .x end"#;
+        assert_eq!(vec![r#""one""#], run(prog, "1"));
+    }
+
+    #[test]
+    fn test_query_alternative() {
+        assert_eq!(vec!["1"], run(".a // 2", r#"{"a":1}"#));
+        assert_eq!(vec!["2"], run(".a // 2", r#"{"a":null}"#));
+        assert_eq!(vec!["2"], run(".a // 2", r#"{"a":false}"#));
+        assert_eq!(vec!["2"], run(".a.b // 2", r#"{"a":10}"#));
+        assert_eq!(vec!["3"], run(".a // .b // 3", r#"{"a":null,"b":false}"#));
+        assert_eq!(vec!["1", "2"], run(".[] // 3", "[null,1,false,2]"));
+        assert_eq!(vec!["3"], run(".[] // 3", "[null,false]"));
+        assert_eq!(vec!["6"], run("8 // 2 | . - 2", "null"));
+    }
+
+    #[test]
+    fn test_query_reduce() {
+        let prog = "reduce .[] as $x (0; . + $x)";
+        assert_eq!(vec!["10"], run(prog, "[1,2,3,4]"));
+        assert_eq!(vec!["0"], run(prog, "[]"));
+
+        let doc = r#"[{"k":"a"},{"k":"b"},{"k":"a"}]"#;
+        let prog = r#"reduce .[] as $r (0; if ($r|.k) == "a" then .+1 end)"#;
+        assert_eq!(vec!["2"], run(prog, doc));
+
+        // init is evaluated against the input, $x is not visible there.
+        let prog = "reduce .[] as $x (.[0]; . * $x)";
+        assert_eq!(vec!["96"], run(prog, "[2,4,6]"));
+        // update producing nothing resets the state to null.
+        let prog = "reduce .[] as $x (.; .[] | .[])";
+        assert_eq!(vec!["null"], run(prog, "[[]]"));
+        // errors abort the reduction.
+        let prog = "reduce .[] as $x (0; . + ($x | .a))";
+        let outs = run(prog, "[1]");
+        assert_eq!(vec![r#"error: ["cannot index a into Integer"]"#], outs);
+    }
+
+    #[test]
+    fn test_query_foreach() {
+        let prog = "foreach .[] as $x (0; . + $x)";
+        assert_eq!(vec!["1", "3", "6"], run(prog, "[1,2,3]"));
+        let prog = "foreach .[] as $x (0; . + $x; [$x, .])";
+        let outs = run(prog, "[1,2,3]");
+        assert_eq!(vec!["[1,1]", "[2,3]", "[3,6]"], outs);
+        assert_eq!(Vec::<String>::new(), run(prog, "[]"));
+    }
+
+    #[test]
+    fn test_query_try() {
+        let doc = r#"{"a":{"b":1}}"#;
+        let none: Vec<String> = vec![];
+        assert_eq!(none, run(".x?", "10"));
+        assert_eq!(none, run(".[]?", "10"));
+        assert_eq!(vec!["1"], run(".[]?", "[1]"));
+        assert_eq!(vec!["1"], run(".a.b?", doc));
+        assert_eq!(none, run("try .x", "10"));
+        assert_eq!(none, run("try error(\"x\")", "10"));
+
+        let outs = run(r#"try .x catch "oops""#, "10");
+        assert_eq!(vec![r#""oops""#], outs);
+        let outs = run(r#"try error("bad") catch ."#, "10");
+        assert_eq!(vec![r#""bad""#], outs);
+        let outs = run(r#"try error({"code":1}) catch .code"#, "10");
+        assert_eq!(vec!["1"], outs);
+        let outs = run(r#"try .x catch ."#, "10");
+        assert_eq!(vec![r#""cannot index x into Integer""#], outs);
+
+        // body stops at the first error.
+        let prog = r#"try (.[] | if . == 2 then error("two") end) catch ."#;
+        assert_eq!(vec!["1", r#""two""#], run(prog, "[1,2,3]"));
+        // error caught by `?` is not seen by outer catch.
+        let outs = run(r#"try .x? catch "oops""#, "10");
+        assert_eq!(none, outs);
+
+        let outs = run(r#"error("bad") | . + 1"#, "10");
+        assert_eq!(vec![r#"error: ["bad"]"#], outs);
+
+        // `?` after parentheses and calls.
+        assert_eq!(none, run(r#"(.a | error("x"))?"#, doc));
+        let doc = r#"[{"a":1},2,{"a":3}]"#;
+        assert_eq!(vec!["[1,3]"], run("[.[] | (.a)?]", doc));
+        // body stops at the first error.
+        assert_eq!(vec!["[1]"], run("[(.[] | .a)?]", doc));
+        assert_eq!(vec!["[true,true]"], run(r#"[.[] | has("a")?]"#, doc));
+        assert_eq!(none, run(r#"error("x")?"#, "10"));
+        assert_eq!(vec!["[3]"], run("[.[] | tonumber?]", r#"["a","3"]"#));
+        let outs = run(r#"[.[] | ltrimstr("a")?]"#, r#"["ab",1]"#);
+        assert_eq!(vec![r#"["b",1]"#], outs);
+    }
+
+    #[test]
+    fn test_query_def_recursive() {
+        let prog = "def last: .[] | last; last";
+        let outs = run(prog, "[[[1]]]");
+        assert_eq!(vec![r#"error: ["cannot iterate Integer"]"#], outs);
+
+        let err = parse_error("nosuchfn(.)");
+        assert!(err.starts_with("nosuchfn/1 is not defined at offset:0"));
+    }
+
+    #[test]
+    fn test_query_assign() {
+        let doc = r#"{"a":{"b":1},"c":[1,2]}"#;
+        let outs = run(".a.b = 10", doc);
+        assert_eq!(vec![r#"{"a":{"b":10},"c":[1,2]}"#], outs);
+        let outs = run(".a = .c", doc);
+        assert_eq!(vec![r#"{"a":[1,2],"c":[1,2]}"#], outs);
+        let outs = run(".x.y = 1", "null");
+        assert_eq!(vec![r#"{"x":{"y":1}}"#], outs);
+        assert_eq!(vec!["[null,null,1]"], run(".[2] = 1", "null"));
+        // one output for every value of the rhs.
+        let outs = run(".a = .b.[]", r#"{"b":[1,2]}"#);
+        let refs = vec![r#"{"a":1,"b":[1,2]}"#, r#"{"a":2,"b":[1,2]}"#];
+        assert_eq!(refs, outs);
+
+        assert_eq!(vec!["[11,12]"], run(".[] += 10", "[1,2]"));
+        assert_eq!(vec!["[-9,-8]"], run(".[] -= 10", "[1,2]"));
+        assert_eq!(vec!["[10,20]"], run(".[] *= 10", "[1,2]"));
+        assert_eq!(vec!["[1,0]"], run(".[] %= 2", "[1,2]"));
+        let outs = run(".a //= 1 | .b //= 2", r#"{"a":false,"b":3}"#);
+        assert_eq!(vec![r#"{"a":1,"b":3}"#], outs);
+        // rhs is evaluated on the input, not the old value.
+        let outs = run(".a += .b", r#"{"a":1,"b":2}"#);
+        assert_eq!(vec![r#"{"a":3,"b":2}"#], outs);
+
+        let outs = run(".a.b = 1", r#"{"a":[1]}"#);
+        assert_eq!(vec![r#"error: ["cannot index b into Array"]"#], outs);
+        let outs = run("(.a | length) = 1", "{}");
+        assert_eq!(vec![r#"error: ["invalid path expression"]"#], outs);
+    }
+
+    #[test]
+    fn test_query_update() {
+        let doc = r#"{"a":{"b":1},"c":[1,2]}"#;
+        let outs = run(".a.b |= . + 1", doc);
+        assert_eq!(vec![r#"{"a":{"b":2},"c":[1,2]}"#], outs);
+        let outs = run(".c.[] |= . * 2", doc);
+        assert_eq!(vec![r#"{"a":{"b":1},"c":[2,4]}"#], outs);
+        let outs = run(".. |= (if . == 1 then 10 else . end)", doc);
+        assert_eq!(vec![r#"{"a":{"b":10},"c":[10,2]}"#], outs);
+        // only the first output is used.
+        assert_eq!(vec![r#"{"a":1}"#], run(".a |= .[]", r#"{"a":[1,2]}"#));
+        // no output deletes the path.
+        let prog = r#".[] |= (if . > 1 then try error("x") else . end)"#;
+        assert_eq!(vec!["[1]"], run(prog, "[1,2,3]"));
+        let outs = run(r#".a |= error("bad")"#, "{}");
+        assert_eq!(vec![r#"error: ["bad"]"#], outs);
+    }
+
+    #[test]
+    fn test_query_del_entries() {
+        let doc = r#"{"a":1,"b":2,"c":[1,2,3]}"#;
+        assert_eq!(vec![r#"{"b":2,"c":[1,2,3]}"#], run("del(.a)", doc));
+        let outs = run("del(.a, .c.[0, 2])", doc);
+        assert_eq!(vec![r#"{"b":2,"c":[2]}"#], outs);
+        assert_eq!(vec!["[]"], run("del(.[])", "[1,2]"));
+        assert_eq!(vec!["null"], run("del(.)", "[1,2]"));
+
+        let outs = run("to_entries", r#"{"a":1,"b":2}"#);
+        let entries = r#"[{"key":"a","value":1},{"key":"b","value":2}]"#;
+        assert_eq!(vec![entries], outs);
+        assert_eq!(vec![r#"{"a":1,"b":2}"#], run("from_entries", entries));
+        let doc = r#"[{"k":"a","v":1},{"name":1},{"key":null,"value":3}]"#;
+        let outs = run("from_entries", doc);
+        assert_eq!(vec![r#"{"1":null,"a":1,"null":3}"#], outs);
+        let outs = run("to_entries", "[10]");
+        assert_eq!(vec![r#"[{"key":0,"value":10}]"#], outs);
+
+        let prog = r#"with_entries(.key = "x" + .key)"#;
+        let outs = run(prog, r#"{"a":1,"b":2}"#);
+        assert_eq!(vec![r#"{"xa":1,"xb":2}"#], outs);
+        let outs = run("with_entries(.value += 1)", r#"{"a":1}"#);
+        assert_eq!(vec![r#"{"a":2}"#], outs);
+        let outs = run("to_entries", "1");
+        assert_eq!(vec![r#"error: ["cannot find entries for Integer"]"#], outs);
+    }
+
+    #[test]
+    fn test_query_builtin_has() {
+        let doc = r#"{"foo": 1, "abcd": 2, "Foo": 3}"#;
+        assert_eq!(vec!["true"], run(r#"has("foo")"#, doc));
+        assert_eq!(vec!["false"], run(r#"has("bar")"#, doc));
+        assert_eq!(vec!["true"], run(r#"has("foo")"#, r#"["foo", 1]"#));
+        assert_eq!(vec!["true"], run("has(1)", "[1, 2]"));
+        let outs = run("has(1)", "10");
+        assert_eq!(vec![r#"error: ["Integer not iterable"]"#], outs);
+
+        let outs = run(r#".[] | in({"foo": 42})"#, r#"["foo", "bar"]"#);
+        assert_eq!(vec!["true", "false"], outs);
+        assert_eq!(vec!["[false,true]"], run("map(in([1,0]))", "[2, 0]"));
+        // key is evaluated for each input, an output for each of its
+        // outputs.
+        assert_eq!(vec!["[]"], run("[.[] | has(empty)]", r#"[{"a":1},[]]"#));
+        let outs = run("[.[] | has(keys | .[])]", r#"[{"a":1,"b":2},{}]"#);
+        assert_eq!(vec!["[true,true]"], outs);
+    }
+
+    #[test]
+    fn test_query_builtin_map() {
+        let outs = run("map(.)", r#"["foo", "bar"]"#);
+        assert_eq!(vec![r#"["foo","bar"]"#], outs);
+        assert_eq!(vec!["[2,3,4]"], run("map(.+1)", "[1, 2, 3]"));
+        let outs = run("map(.+1)", r#"{"foo":1, "bar":2}"#);
+        assert_eq!(vec![r#"{"bar":3,"foo":2}"#], outs);
+        assert_eq!(vec!["[1,2,3,4]"], run("map(.[])", "[[1,2],[3,4]]"));
+        let outs = run("map(.+1)", "null");
+        assert_eq!(vec![r#"error: ["cannot map over Null"]"#], outs);
+    }
+
+    #[test]
+    fn test_query_builtin_any_all() {
+        assert_eq!(vec!["true"], run("any(. == 1)", "[1, 2]"));
+        assert_eq!(vec!["false"], run("any(. == 1)", "[2, 3]"));
+        let doc = r#"{"x": "a", "y": "b"}"#;
+        assert_eq!(vec!["true"], run(r#"any(. == "a")"#, doc));
+        assert_eq!(vec!["false"], run(r#"all(. == "a")"#, doc));
+        assert_eq!(vec!["true"], run("all(. == 1)", "[1, 1]"));
+        assert_eq!(vec!["true"], run("all(. == 1)", "[]"));
+        assert_eq!(vec!["false"], run("any(. == 1)", "[]"));
+        // stops at the first item deciding the outcome.
+        let outs = run(r#"any(if . then true else error("x") end)"#, "[1,0]");
+        assert_eq!(vec!["true"], outs);
+        let outs = run("all(.)", "10");
+        assert_eq!(vec![r#"error: ["cannot iterate over Integer"]"#], outs);
+
+        // without arguments, same as `any(.)` and `all(.)`.
+        let outs = run("[.[] | [any, all]]", "[[], [true,false], [1,{}]]");
+        assert_eq!(vec!["[[false,true],[true,false],[true,true]]"], outs);
+        let outs = run("any", r#"{"a":null,"b":false}"#);
+        assert_eq!(vec!["false"], outs);
+        let outs = run("all", "null");
+        assert_eq!(vec![r#"error: ["cannot iterate over Null"]"#], outs);
+        let outs = run("def any: 1; any", "[true]");
+        assert_eq!(vec!["1"], outs);
+    }
+
+    #[test]
+    fn test_query_builtin_arity() {
+        let err = parse_error("has(1; 2)");
+        let refs = "has/2 is not defined, has takes 1 argument";
+        assert!(err.starts_with(refs), "{}", err);
+        let err = parse_error("map(.; .)");
+        let refs = "map/2 is not defined, map takes 1 argument";
+        assert!(err.starts_with(refs), "{}", err);
+        let err = parse_error("length(.; .)");
+        assert!(err.starts_with("length/2 is not defined"), "{}", err);
+        let err = parse_error("JOIN(.)");
+        assert!(err.starts_with("JOIN/1 is not defined, JOIN takes 2, 3 or 4"));
+        // user definitions are looked up before builtins.
+        let outs = run("def map(f; g): [f, g]; map(1; 2)", "null");
+        assert_eq!(vec!["[1,2]"], outs);
+    }
+
+    #[test]
+    fn test_query_optimized() {
+        let doc = r#"{"a":{"b":[1,{"c":2}]}}"#;
+        assert_eq!(vec!["2"], run(".a.b.[1].c", doc));
+        assert_eq!(vec!["2"], run(".a | .b | .[1] | .c", doc));
+        let outs = run(".a.b.[0].c", doc);
+        assert_eq!(vec![r#"error: ["cannot index c into Integer"]"#], outs);
+        let outs = run(".a.b.[0] |= 10", doc);
+        assert_eq!(vec![r#"{"a":{"b":[10,{"c":2}]}}"#], outs);
+
+        // outputs of the input shared by the repeats, item by item.
+        let outs = run(r#".[] | has("a")"#, r#"[{"a":1},{"b":2}]"#);
+        assert_eq!(vec!["true", "false"], outs);
+        let outs = run(".[] | .[0, 1]", "[[1,2],[3,4]]");
+        assert_eq!(vec!["1", "2", "3", "4"], outs);
+        let outs = run(r#".[] | length"#, r#"[[1,2],"abc",{"a":1}]"#);
+        assert_eq!(vec!["2", "3", "1"], outs);
+        let outs = run(".[] | [.[0, 1]] | add", "[[1,2],[3,4]]");
+        assert_eq!(vec!["3", "7"], outs);
+    }
+
+    #[test]
+    fn test_call_builtins() {
+        for (name, arities) in CALL_BUILTINS.iter() {
+            for n in arities.iter() {
+                let args = vec!["empty"; *n].join("; ");
+                let prog = format!("{}({})", name, args);
+                for out in run(&prog, "null") {
+                    assert!(!out.contains("is not defined"), "{}", prog);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_query_errors() {
+        let err = parse_error("[.a, (.b | length]");
+        let refs = "expected `)`, found `]` at offset:17 line:1 col:18\n\
+            \x20 [.a, (.b | length]\n\
+            \x20                  ^";
+        assert_eq!(refs, err);
+        let err = parse_error(".a | map(.b");
+        assert!(err.starts_with("expected `)` at offset:11 line:1 col:12"));
+        let err = parse_error(".a)");
+        assert!(err.starts_with("unexpected `)` at offset:2"), "{}", err);
+        let err = parse_error(r#""abc"#);
+        assert!(err.starts_with("expected `\"` at offset:4"), "{}", err);
+        let err = parse_error("if . then 1 else 2");
+        assert!(err.starts_with("expected `end` at offset:18"), "{}", err);
+
+        let prog = ".a\n| select(.b)\n| nosuch(.c; 1)";
+        let refs = "nosuch/2 is not defined at offset:18 line:3 col:3\n\
+            \x20 | nosuch(.c; 1)\n\
+            \x20   ^^^^^^";
+        assert_eq!(refs, parse_error(prog));
+        // calls within string interpolations are located too.
+        let err = parse_error(r#""\(map(.)) \(map(.; .))""#);
+        assert!(err.starts_with("map/2 is not defined, map takes 1 \
+            argument at offset:13 line:1 col:14"), "{}", err);
+        // definitions are in scope for the body and what follows.
+        assert!("def f(g): g | f(g); f(.)".parse::<Expr>().is_ok());
+        let err = parse_error("def f: 1; f(2)");
+        assert!(err.starts_with("f/1 is not defined at offset:10"), "{}", err);
+
+        // variables are bound in their scope.
+        let err = parse_error(".a as $x | [$x, $y]");
+        let refs = "$y is not defined at offset:16 line:1 col:17\n\
+            \x20 .a as $x | [$x, $y]\n\
+            \x20                 ^^";
+        assert_eq!(refs, err);
+        let err = parse_error("reduce .[] as $x ($x; . + $x)");
+        assert!(err.starts_with("$x is not defined at offset:18"), "{}", err);
+        let err = parse_error("def f($a): $a; $a");
+        assert!(err.starts_with("$a is not defined at offset:15"), "{}", err);
+        let prog = r#"foreach .[] as $x (0; . + $x; [$x, $ENV | type])"#;
+        assert!(prog.parse::<Expr>().is_ok());
+        // formats are located, also within interpolations.
+        let err = parse_error(r#"@csv, "\(@nosuch "x")""#);
+        let refs = "@nosuch is not a valid format at offset:9 line:1 col:10\n\
+            \x20 @csv, \"\\(@nosuch \"x\")\"\n\
+            \x20          ^^^^^^^";
+        assert_eq!(refs, err);
+    }
+
+    #[test]
+    fn test_query_strings() {
+        let outs = run(r#"split(", ")"#, r#""a, b, c""#);
+        assert_eq!(vec![r#"["a","b","c"]"#], outs);
+        assert_eq!(vec![r#"["a","b"]"#], run(r#"split("")"#, r#""ab""#));
+        let outs = run(r#"join("-")"#, r#"["a",1,null,true]"#);
+        assert_eq!(vec![r#""a-1--true""#], outs);
+        let outs = run(r#"join("-")"#, r#"[[1]]"#);
+        assert_eq!(vec![r#"error: ["cannot join Array"]"#], outs);
+        let doc = r#"["foobar", "barfoo", 1]"#;
+        let outs = run(r#"map(ltrimstr("foo"))"#, doc);
+        assert_eq!(vec![r#"["bar","barfoo",1]"#], outs);
+        let outs = run(r#"map(rtrimstr("foo"))"#, doc);
+        assert_eq!(vec![r#"["foobar","bar",1]"#], outs);
+        let outs = run(r#".[0] | [startswith("foo"), endswith("foo")]"#, doc);
+        assert_eq!(vec!["[true,false]"], outs);
+        let outs = run(r#"startswith("foo")"#, "1");
+        let err = r#"error: ["startswith() requires string inputs"]"#;
+        assert_eq!(vec![err], outs);
+        // argument is evaluated for each input, an output for each of its
+        // outputs.
+        let prog = r#"[.[] | ltrimstr(if . == "ab" then empty else "a" end)]"#;
+        assert_eq!(vec![r#"["c"]"#], run(prog, r#"["ab","ac"]"#));
+        let prog = "[.[] | ltrimstr(explode | .[] | [.] | implode)]";
+        let outs = run(prog, r#"["ab","cd"]"#);
+        assert_eq!(vec![r#"["b","ab","d","cd"]"#], outs);
+
+        let outs = run("[ascii_downcase, ascii_upcase]", r#""aBc-Ü""#);
+        assert_eq!(vec![r#"["abc-Ü","ABC-Ü"]"#], outs);
+        assert_eq!(vec!["[97,233]"], run("explode", r#""aé""#));
+        assert_eq!(vec![r#""aé""#], run("explode | implode", r#""aé""#));
+        let outs = run("implode", "[-1]");
+        let err = r#"error: ["cannot implode, invalid codepoint"]"#;
+        assert_eq!(vec![err], outs);
+
+        assert_eq!(vec![r#""[1,\"a\"]""#], run("tostring", r#"[1,"a"]"#));
+        assert_eq!(vec![r#""a""#], run("tostring", r#""a""#));
+        assert_eq!(vec!["12"], run("tonumber", r#""12""#));
+        assert_eq!(vec!["1.5e0"], run("tonumber", r#""1.5""#));
+        let outs = run("tonumber", r#""x""#);
+        assert_eq!(vec![r#"error: ["cannot parse \"x\" as number"]"#], outs);
+    }
+
+    #[test]
+    fn test_query_format() {
+        let outs = run(r#""a \(.x) b \(.y + 1)""#, r#"{"x":"s","y":1}"#);
+        assert_eq!(vec![r#""a s b 2""#], outs);
+        let outs = run(r#""\(.[])-\(.[])""#, "[1,2]");
+        let refs = vec![r#""1-1""#, r#""2-1""#, r#""1-2""#, r#""2-2""#];
+        assert_eq!(refs, outs);
+        let outs = run(r#""x\\(y)\t""#, "null");
+        assert_eq!(vec![r#""x\\(y)\t""#], outs);
+        let outs = run(r#""\(error("bad"))""#, "null");
+        assert_eq!(vec![r#"error: ["bad"]"#], outs);
+
+        assert_eq!(vec![r#""aGk=""#], run("@base64", r#""hi""#));
+        assert_eq!(vec![r#""hi""#], run("@base64 | @base64d", r#""hi""#));
+        assert_eq!(vec![r#""a%20b""#], run("@uri", r#""a b""#));
+        let outs = run(r#"@uri "q=\(.q)&v=1""#, r#"{"q":"a&b"}"#);
+        assert_eq!(vec![r#""q=a%26b&v=1""#], outs);
+        assert_eq!(vec![r#""1,\"a\"""#], run("@csv", r#"[1,"a"]"#));
+        assert_eq!(vec![r#""1\ta""#], run("@tsv", r#"[1,"a"]"#));
+        assert_eq!(vec![r#""&lt;p&gt;""#], run("@html", r#""<p>""#));
+        assert_eq!(vec![r#""'a' 1""#], run("@sh", r#"["a",1]"#));
+        let outs = run(r#"@sh "echo \(.)""#, r#""it's""#);
+        assert_eq!(vec![r#""echo 'it'\\''s'""#], outs);
+        assert_eq!(vec![r#""[1]""#], run("@json", "[1]"));
+        let err = parse_error("@foo");
+        assert!(err.starts_with("@foo is not a valid format"), "{}", err);
+    }
+
+    #[test]
+    fn test_query_regex() {
+        let doc = r#""foo bar FOO""#;
+        assert_eq!(vec!["true"], run(r#"test("bar")"#, doc));
+        assert_eq!(vec!["false"], run(r#"test("baz")"#, doc));
+        assert_eq!(vec!["true"], run(r#"test("B A R"; "ix")"#, doc));
+        let outs = run(r#"match("foo"; "gi") | .offset"#, doc);
+        assert_eq!(vec!["0", "8"], outs);
+        let prog = r#"match("(o)+") | [.length, .captures.[0].string]"#;
+        let outs = run(prog, doc);
+        assert_eq!(vec![r#"[2,"o"]"#], outs);
+
+        let outs = run(r#"capture("(?P<a>\\w+) (?P<b>\\w+)")"#, doc);
+        assert_eq!(vec![r#"{"a":"foo","b":"bar"}"#], outs);
+        let outs = run(r#"scan("[a-z]+")"#, doc);
+        assert_eq!(vec![r#""foo""#, r#""bar""#], outs);
+        let outs = run(r#"scan("(o)(o)")"#, doc);
+        assert_eq!(vec![r#"["o","o"]"#], outs);
+        let outs = run(r#"split(" +"; null)"#, r#""a  b c""#);
+        assert_eq!(vec![r#"["a","b","c"]"#], outs);
+        // split/1 splits on literal text.
+        assert_eq!(vec![r#"["a b"]"#], run(r#"split(" +")"#, r#""a b""#));
+
+        let outs = run(r#"sub("o"; "0")"#, doc);
+        assert_eq!(vec![r#""f0o bar FOO""#], outs);
+        let outs = run(r#"gsub("o"; "0"; "i")"#, doc);
+        assert_eq!(vec![r#""f00 bar F00""#], outs);
+        let outs = run(r#"gsub("(?P<x>[a-z]+)"; "<\(.x)>")"#, doc);
+        assert_eq!(vec![r#""<foo> <bar> FOO""#], outs);
+        let outs = run(r#"gsub(""; "-"; "n")"#, doc);
+        assert_eq!(vec![doc], outs);
+
+        // pattern and flags are evaluated for each input, there are outputs
+        // for each of their outputs.
+        let prog = r#". as $d | .s | [test($d.p.[])]"#;
+        let outs = run(prog, r#"{"s":"abc","p":["b","x"]}"#);
+        assert_eq!(vec!["[true,false]"], outs);
+        let prog = r#"[.[] | test(if . == "ab" then empty else "c" end)]"#;
+        assert_eq!(vec!["[true]"], run(prog, r#"["ab","ac"]"#));
+        let prog = r#".[1] as $f | .[0] | [test("A"; $f.[])]"#;
+        assert_eq!(vec!["[false,true]"], run(prog, r#"["a",["","i"]]"#));
+
+        let outs = run(r#"test("a")"#, "1");
+        let err = r#"error: ["Integer cannot be matched, not a string"]"#;
+        assert_eq!(vec![err], outs);
+        let outs = run(r#"test("a"; "q")"#, doc);
+        assert_eq!(vec![r#"error: ["q is not a valid modifier"]"#], outs);
+        let outs = run(r#"sub("o"; 1)"#, doc);
+        assert_eq!(vec![r#"error: ["cannot sub with Integer"]"#], outs);
+        let err = parse_error(r#"test("a"; "g"; 1)"#);
+        assert!(err.starts_with("test/3 is not defined, test takes 1 or 2"));
+    }
+
+    #[test]
+    fn test_query_array() {
+        let doc = r#"[3,"a",null,[1],1.5,{"a":1},true,false,1]"#;
+        let outs = run("sort", doc);
+        let refs = r#"[null,false,true,1,1.5e0,3,"a",[1],{"a":1}]"#;
+        assert_eq!(vec![refs], outs);
+        assert_eq!(vec!["[1,2,3]"], run("unique", "[3,1,2,1,3.0]"));
+        assert_eq!(vec!["1"], run("min", "[3,1,2]"));
+        assert_eq!(vec!["3"], run("max", "[3,1,2]"));
+        assert_eq!(vec!["null"], run("min", "[]"));
+        assert_eq!(vec![r#""cba""#], run("reverse", r#""abc""#));
+        assert_eq!(vec!["[1,2,[3]]"], run("flatten(1)", "[1,[2,[3]]]"));
+        assert_eq!(vec!["[1,2,3]"], run("flatten", "[1,[2,[3]]]"));
+        assert_eq!(vec!["6"], run("add", "[1,null,2,3]"));
+        assert_eq!(vec![r#""ab""#], run("add", r#"["a","b"]"#));
+        assert_eq!(vec!["[[1,3],[2,null]]"], run("transpose", "[[1,2],[3]]"));
+        assert_eq!(vec!["1"], run("first", "[1,2,3]"));
+        assert_eq!(vec!["3"], run("last", "[1,2,3]"));
+        assert_eq!(vec!["2"], run("nth(1)", "[1,2,3]"));
+
+        let outs = run(r#"indices(", ")"#, r#""a, b, c""#);
+        assert_eq!(vec!["[1,4]"], outs);
+        assert_eq!(vec!["[1,3]"], run("indices([1,2])", "[0,1,2,1,2]"));
+        assert_eq!(vec!["1"], run("index(1)", "[0,1,2,1]"));
+        assert_eq!(vec!["3"], run("rindex(1)", "[0,1,2,1]"));
+        let doc = r#"{"a":[1,2,"xyz"],"b":true}"#;
+        let outs = run(r#"contains({"a":["y",1]})"#, doc);
+        assert_eq!(vec!["true"], outs);
+        assert_eq!(vec!["false"], run(r#"contains({"b":false})"#, doc));
+        assert_eq!(vec!["true"], run(r#"inside("abc")"#, r#""b""#));
+        let outs = run(r#"contains("a")"#, "[1]");
+        let err = r#"error: ["cannot check if Array contains String"]"#;
+        assert_eq!(vec![err], outs);
+
+        assert_eq!(vec!["0", "1", "2"], run("range(3)", "null"));
+        assert_eq!(vec!["2", "3"], run("range(2; 4)", "null"));
+        let outs = run("[range(.[]; .[] + 2)]", "[0,1]");
+        assert_eq!(vec!["[0,1,0,1,2,1,1,2]"], outs);
+        assert_eq!(vec!["[0,0,1]"], run("[range(.[])]", "[1,2]"));
+        assert_eq!(vec!["[0,3,6,9]"], run("[range(0; 10; 3)]", "null"));
+        assert_eq!(vec!["[5,3,1]"], run("[range(5; 0; -2)]", "null"));
+        assert_eq!(vec!["[]"], run("[range(0; 10; 0)]", "null"));
+        assert_eq!(vec!["[]"], run("[range(0; 10; -1)]", "null"));
+        let outs = run("[range(0; 1; 0.25)]", "null");
+        assert_eq!(vec!["[0e0,2.5e-1,5e-1,7.5e-1]"], outs);
+        let outs = run("[range(0; 4; .[])]", "[2,3]");
+        assert_eq!(vec!["[0,2,0,3]"], outs);
+        let outs = run(r#"range(0; 1; "a")"#, "null");
+        let err = r#"error: ["range expects numbers, not String"]"#;
+        assert_eq!(vec![err], outs);
+        let outs = run(r#"range("a")"#, "null");
+        let err = r#"error: ["range expects numbers, not String"]"#;
+        assert_eq!(vec![err], outs);
+        let outs = run("combinations", "[[1,2],[3,4]]");
+        assert_eq!(vec!["[1,3]", "[1,4]", "[2,3]", "[2,4]"], outs);
+        let outs = run("combinations(2)", "[0,1]");
+        assert_eq!(vec!["[0,0]", "[0,1]", "[1,0]", "[1,1]"], outs);
+        let outs = run("[combinations(.[])]", "[1,2]");
+        assert_eq!(vec!["[[1],[2],[1,1],[1,2],[2,1],[2,2]]"], outs);
+        assert_eq!(vec!["[]"], run("[combinations(empty)]", "[1,2]"));
+        let outs = run(r#"combinations(error("x"))"#, "[1,2]");
+        assert_eq!(vec![r#"error: ["x"]"#], outs);
+
+        let outs = run("sort", "{}");
+        let err = r#"error: ["Object cannot be sorted, not an array"]"#;
+        assert_eq!(vec![err], outs);
+    }
+
+    #[test]
+    fn test_query_array_by() {
+        let doc = r#"[{"a":2,"b":1},{"a":1,"b":2},{"a":2,"b":0}]"#;
+        let outs = run("sort_by(.a) | map(.b)", doc);
+        assert_eq!(vec!["[2,1,0]"], outs);
+        let outs = run("sort_by([.a, .b]) | map(.b)", doc);
+        assert_eq!(vec!["[2,0,1]"], outs);
+        let outs = run("group_by(.a) | map(length)", doc);
+        assert_eq!(vec!["[1,2]"], outs);
+        let outs = run("unique_by(.a) | map(.b)", doc);
+        assert_eq!(vec!["[2,1]"], outs);
+        assert_eq!(vec!["2"], run("min_by(.a) | .b", doc));
+        assert_eq!(vec!["0"], run("max_by(.a) | .b", doc));
+        let outs = run("sort_by(error(\"x\"))", doc);
+        assert_eq!(vec![r#"error: ["x"]"#], outs);
+
+        assert_eq!(vec!["0", "1"], run("limit(2; range(10))", "null"));
+        assert_eq!(vec!["3"], run("first(range(3; 9))", "null"));
+        assert_eq!(vec!["8"], run("last(range(3; 9))", "null"));
+        assert_eq!(vec!["5"], run("nth(2; range(3; 9))", "null"));
+        assert_eq!(vec!["0"], run("first(range(10000000000))", "null"));
+        let outs = run("limit(3; range(1.5; 1e300))", "null");
+        assert_eq!(vec!["1.5e0", "2.5e0", "3.5e0"], outs);
+        assert_eq!(Vec::<String>::new(), run("first(.[])", "[]"));
+        assert_eq!(vec!["16"], run("until(. > 10; . * 2)", "1"));
+    }
+
+    #[test]
+    fn test_query_select() {
+        let doc = r#"[{"age":20,"n":"a"},{"age":40,"n":"b"},{"age":35}]"#;
+        let outs = run(".[] | select(.age > 30) | .age", doc);
+        assert_eq!(vec!["40", "35"], outs);
+        assert_eq!(vec!["[]"], run("map(select(.age > 50))", doc));
+        let outs = run(r#"del(.[] | select(.age < 30)) | length"#, doc);
+        assert_eq!(vec!["2"], outs);
+        let outs = run(r#"select(.[] | .age > 30)"#, "[{\"age\":40}]");
+        assert_eq!(vec![r#"[{"age":40}]"#], outs);
+        assert_eq!(Vec::<String>::new(), run("empty", "1"));
+        assert_eq!(vec!["false", "true"], run(".[] | not", "[1,null]"));
+
+        let doc = r#"[null,true,1,1.5,"a",[],{}]"#;
+        let outs = run(".[] | type", doc);
+        let refs = vec![
+            r#""null""#, r#""boolean""#, r#""number""#, r#""number""#,
+            r#""string""#, r#""array""#, r#""object""#,
+        ];
+        assert_eq!(refs, outs);
+        assert_eq!(vec!["[]", "{}"], run(".[] | iterables", doc));
+        assert_eq!(vec!["1", "1.5e0"], run(".[] | numbers", doc));
+        assert_eq!(vec!["null"], run(".[] | nulls", doc));
+        assert_eq!(6, run(".[] | values", doc).len());
+        assert_eq!(5, run(".[] | scalars", doc).len());
+        let outs = run("del((.[] | iterables), (.[] | strings))", doc);
+        assert_eq!(vec![r#"[null,true,1,1.5e0]"#], outs);
+        let outs = run("del(.[] | scalars | nulls)", "[null,true,1]");
+        assert_eq!(vec!["[true,1]"], outs);
+
+        assert_eq!(vec!["true"], run("infinite | isinfinite", "null"));
+        assert_eq!(vec!["true"], run("nan | isnan", "null"));
+        assert_eq!(vec!["false"], run("0 | isnormal", "null"));
+        let outs = run("isnan", r#""a""#);
+        let err = r#"error: ["isnan() cannot be applied to String"]"#;
+        assert_eq!(vec![err], outs);
+        let outs = run("tojson | fromjson", r#"{"a":[1,"x"]}"#);
+        assert_eq!(vec![r#"{"a":[1,"x"]}"#], outs);
+        assert_eq!(vec![r#""[1,2]""#], run("tojson", "[1,2]"));
+        assert_eq!(vec![r#""[null,1]""#], run("[nan, 1] | tojson", "null"));
+        // malformed JSON text is an error of the entry.
+        let prog = r#"[.[] | try fromjson catch "bad"]"#;
+        let outs = run(prog, r#"["[1,","1 2","[1]"]"#);
+        assert_eq!(vec![r#"["bad","bad",[1]]"#], outs);
+    }
+
+    #[test]
+    fn test_query_math() {
+        let outs = run("[floor, (. * -1 | floor), ceil, round, fabs]", "3.5");
+        assert_eq!(vec!["[3,-4,4,4,3.5e0]"], outs);
+        assert_eq!(vec!["[-7,-7,7]"], run("[floor, round, fabs]", "-7"));
+        assert_eq!(vec!["3e0"], run("sqrt", "9"));
+        assert_eq!(vec!["1.024e3"], run("pow(2; 10)", "null"));
+        // an output for every combination of outputs of the operands.
+        let outs = run("[pow(.[] | length; 2)]", r#"["ab","abc"]"#);
+        assert_eq!(vec!["[4e0,9e0]"], outs);
+        let outs = run("[pow(.[]; .[])]", "[2,3]");
+        assert_eq!(vec!["[4e0,8e0,9e0,2.7e1]"], outs);
+        assert_eq!(vec!["[]"], run("[atan2(empty; 1)]", "null"));
+        assert_eq!(vec!["0e0"], run("exp | log", "0"));
+        assert_eq!(vec!["2e0"], run("log10", "100"));
+        assert_eq!(vec!["0e0"], run("sin", "0"));
+        assert_eq!(vec!["1.5e0"], run("significand", "12"));
+        let outs = run("[div(.a; .b), mod(.a; .b)]", r#"{"a":-7,"b":2}"#);
+        assert_eq!(vec!["[-3,-1]"], outs);
+        let doc = r#"{"a":170141183460469231731687303715884105727,"b":1}"#;
+        let outs = run("div(.a; .b)", doc);
+        assert_eq!(vec!["170141183460469231731687303715884105727"], outs);
+        let outs = run("div(1; 0)", "null");
+        assert_eq!(vec![r#"error: ["1 cannot be divided by zero"]"#], outs);
+        let outs = run("mod(1.5; 1)", "null");
+        assert_eq!(vec![r#"error: ["mod() requires integer inputs"]"#], outs);
+        let outs = run("sqrt", r#""a""#);
+        let err = r#"error: ["sqrt() cannot be applied to String"]"#;
+        assert_eq!(vec![err], outs);
+        // overflowing integer arithmetic falls back to float.
+        let max = "170141183460469231731687303715884105727";
+        assert_eq!(vec!["1.7014118346046923e38"], run(". + 1", max));
+    }
+
+    #[test]
+    fn test_query_date() {
+        let (secs, date) = ("1425599507", r#""2015-03-05T23:51:47Z""#);
+        assert_eq!(vec![date], run("todate", secs));
+        assert_eq!(vec![secs], run("fromdate", date));
+        let outs = run("fromdate", r#""2015-03-06T00:51:47.5+01:00""#);
+        assert_eq!(vec!["1.4255995075e9"], outs);
+        assert_eq!(vec!["[2015,2,5,23,51,47,4,63]"], run("gmtime", secs));
+        assert_eq!(vec![secs], run("gmtime | mktime", secs));
+        assert_eq!(vec![secs], run("gmtime | mktime", date));
+        let outs = run(r#"strftime("%Y-%m-%dT%H:%M:%SZ")"#, secs);
+        assert_eq!(vec![date], outs);
+        let outs = run(r#"strptime("%d %b %Y %T %z") | mktime"#,
+                       r#""06 Mar 2015 05:21:47 +0530""#);
+        assert_eq!(vec![secs], outs);
+        assert_eq!(vec!["true"], run("now | . > 1425599507", "null"));
+        // broken down time, as from gmtime and strptime.
+        assert_eq!(vec![date], run("gmtime | todate", secs));
+        let outs = run(r#"strptime("%Y %j") | todate"#, r#""2015 063""#);
+        assert_eq!(vec![r#""2015-03-04T00:00:00Z""#], outs);
+        let outs = run(r#"gmtime | strftime("%F %T (%j)")"#, secs);
+        assert_eq!(vec![r#""2015-03-05 23:51:47 (064)""#], outs);
+        let outs = run("todate", "[2015,2,5,23,51,47.5,4,63]");
+        assert_eq!(vec![r#""2015-03-05T23:51:47.5Z""#], outs);
+        let err = r#"error: ["todate() requires broken down time"]"#;
+        assert_eq!(vec![err], run("todate", "[2015,2]"));
+
+        let outs = run(r#"dateadd("days"; 2)"#, secs);
+        assert_eq!(vec!["1425772307"], outs);
+        let outs = run(r#"datesub("hours"; 1.5)"#, date);
+        assert_eq!(vec![r#""2015-03-05T22:21:47Z""#], outs);
+
+        let outs = run("fromdate", r#""2015-02-30""#);
+        assert_eq!(vec![r#"error: ["date is out of range"]"#], outs);
+        let outs = run(r#"strptime("%Y")"#, r#""x""#);
+        let err = r#"error: ["\"x\" does not match format \"%Y\""]"#;
+        assert_eq!(vec![err], outs);
+        let outs = run(r#"dateadd("years"; 1)"#, secs);
+        assert_eq!(vec![r#"error: ["\"years\" is not a valid unit"]"#], outs);
+    }
+
+    #[test]
+    fn test_query_paths() {
+        let doc = r#"{"a":[{"b":1},null],"c":"x"}"#;
+        assert_eq!(vec![r#"["a",0,"b"]"#], run("path(.a.[0].b)", doc));
+        let refs = vec![
+            r#"["a"]"#, r#"["a",0]"#, r#"["a",0,"b"]"#, r#"["a",1]"#,
+            r#"["c"]"#,
+        ];
+        assert_eq!(refs, run("paths", doc));
+        let outs = run("paths(type == \"number\")", doc);
+        assert_eq!(vec![r#"["a",0,"b"]"#], outs);
+        let outs = run("leaf_paths", doc);
+        assert_eq!(vec![r#"["a",0,"b"]"#, r#"["a",1]"#, r#"["c"]"#], outs);
+
+        assert_eq!(vec!["1"], run(r#"getpath(["a",0,"b"])"#, doc));
+        assert_eq!(vec!["1"], run(r#"getpath("/a/0/b")"#, doc));
+        assert_eq!(vec!["null"], run(r#"getpath(["x","y"])"#, doc));
+        let outs = run(r#"setpath(["a",1]; 2) | .a"#, doc);
+        assert_eq!(vec![r#"[{"b":1},2]"#], outs);
+        let outs = run(r#"setpath("/a/-"; 3) | .a"#, doc);
+        assert_eq!(vec![r#"[{"b":1},null,3]"#], outs);
+        let outs = run(r#"delpaths([["a"], "/c"])"#, doc);
+        assert_eq!(vec!["{}"], outs);
+        let outs = run("topointer", r#"["a/b","c~d",0]"#);
+        assert_eq!(vec![r#""/a~1b/c~0d/0""#], outs);
+        let outs = run("topointer", "[null]");
+        let err = r#"error: ["Null cannot be in a JSON Pointer"]"#;
+        assert_eq!(vec![err], outs);
+
+        let outs = run("recurse(.[]?; . != null)", "[[1],null]");
+        assert_eq!(vec!["[[1],null]", "[1]", "1"], outs);
+        let outs = run("recurse(if . < 2 then . + 1 else empty end)", "0");
+        assert_eq!(vec!["0", "1", "2"], outs);
+        let outs = run("del(.. | nulls)", doc);
+        assert_eq!(vec![r#"{"a":[{"b":1}],"c":"x"}"#], outs);
+        let outs = run("path(recurse(.[]?; type == \"array\"))", doc);
+        assert_eq!(vec!["[]", r#"["a"]"#], outs);
+
+        let prog = "walk(if type == \"number\" then . * 10 else . end)";
+        assert_eq!(vec![r#"{"a":[{"b":10},null],"c":"x"}"#], run(prog, doc));
+        let prog = "walk(if type == \"array\" then sort else . end)";
+        assert_eq!(vec!["[3,[1,2]]"], run(prog, "[[2,1],3]"));
+
+        assert_eq!(vec![r#""object""#], run("$ENV | type", "null"));
+        assert_eq!(vec!["true"], run("env == $ENV", "null"));
+    }
+
+    #[test]
+    fn test_query_list() {
+        assert_eq!(vec!["[]"], run("[]", "null"));
+        assert_eq!(vec!["[]"], run("[.[] | select(. > 5)]", "[1,2]"));
+        assert_eq!(vec!["[1,2,1,2]"], run("[.[], .[]]", "[1,2]"));
+        assert_eq!(vec!["[0]", "[0,1]"], run(".[] | [range(.)]", "[1,2]"));
+        let outs = run("[paths]", r#"{"a":[1]}"#);
+        assert_eq!(vec![r#"[["a"],["a",0]]"#], outs);
+        let outs = run("[.[] | error(\"x\")]", "[1,2]");
+        assert_eq!(vec![r#"error: ["x"]"#], outs);
+    }
+
+    #[test]
+    fn test_query_le() {
+        for (doc, le, ge) in [("1", "true", "true"), ("2", "false", "true"),
+                              ("0", "true", "false")].iter() {
+            assert_eq!(vec![*le], run(". <= 1", doc), "{} <= 1", doc);
+            assert_eq!(vec![*ge], run(". >= 1", doc), "{} >= 1", doc);
+        }
+        let prog = r#"[.[] | if . <= 1 then "le" else "gt" end]"#;
+        assert_eq!(vec![r#"["le","le","gt"]"#], run(prog, "[0,1,2]"));
+        let prog = "[.[] | select(. >= 1 && . < 2)]";
+        assert_eq!(vec!["[1]"], run(prog, "[0,1,2]"));
+        assert_eq!(vec!["true"], run("1 <= 1 == true", "null"));
+    }
+
+    #[test]
+    fn test_query_comma() {
+        // lhs varies fastest, as in other binary operators.
+        let outs = run("(1,2) + (10,20)", "null");
+        assert_eq!(vec!["11", "12", "21", "22"], outs);
+        // `,` binds tighter than `|` and looser than the operators.
+        assert_eq!(vec!["10", "20"], run("1, 2 | . * 10", "null"));
+        assert_eq!(vec!["[2,3]"], run("[1, 2 | . + 1]", "null"));
+        assert_eq!(vec!["1", "2"], run(". as $x | $x, 2", "1"));
+        assert_eq!(vec!["[1,2,1,2]"], run("def f: 1, 2; [f, f]", "null"));
+        let outs = run("[.[] | (., . * 10), (empty, .)]", "[1,2]");
+        assert_eq!(vec!["[1,10,1,2,20,2]"], outs);
+        let refs = vec![r#"{"a":1,"b":2}"#, r#"{"a":1,"b":3}"#];
+        assert_eq!(refs, run(r#"{"a": 1, "b": (2, 3)}"#, "null"));
+        assert_eq!(vec!["[5,6]"], run("[.[0, 1]]", "[5,6]"));
+
+        // rhs is evaluated as its outputs are consumed.
+        let outs = run(r#"[limit(2; 1, 2, error("x"))]"#, "null");
+        assert_eq!(vec!["[1,2]"], outs);
+        let outs = run(r#"error("x"), 1"#, "null");
+        assert_eq!(vec![r#"error: ["x"]"#, "1"], outs);
+        let outs = run("[path(.a, .b.[0])]", "null");
+        assert_eq!(vec![r#"[["a"],["b",0]]"#], outs);
+    }
+
+    #[test]
+    fn test_query_cartesian() {
+        // lhs varies fastest, as in jq.
+        let outs = run(".[] + (.[] * 10)", "[1,2]");
+        assert_eq!(vec!["11", "12", "21", "22"], outs);
+        let outs = run(".[] == .[]", "[1,2]");
+        assert_eq!(vec!["true", "false", "false", "true"], outs);
+        assert_eq!(Vec::<String>::new(), run(".[] - empty", "[1,2]"));
+        assert_eq!(Vec::<String>::new(), run("empty * .[]", "[1,2]"));
+        assert_eq!(vec!["[11,12,21,22]"], run("[.[] + (.[] * 10)]", "[1,2]"));
+
+        // earlier properties vary slowest, keys before values.
+        let refs = vec![
+            r#"{"a":1,"b":10}"#, r#"{"a":1,"b":20}"#,
+            r#"{"a":2,"b":10}"#, r#"{"a":2,"b":20}"#,
+        ];
+        assert_eq!(refs, run(r#"{"a": .[], "b": (.[] * 10)}"#, "[1,2]"));
+        let refs = vec![r#"{"x":1}"#, r#"{"x":2}"#, r#"{"y":1}"#, r#"{"y":2}"#];
+        let prog = "{(.[0] | .[]): (.[1] | .[])}";
+        assert_eq!(refs, run(prog, r#"[["x","y"],[1,2]]"#));
+        assert_eq!(Vec::<String>::new(), run(r#"{"a": empty}"#, "null"));
+        let outs = run("{(1): 2}", "null");
+        assert_eq!(vec![r#"error: ["Integer cannot be an object key"]"#], outs);
+    }
+
+    #[test]
+    fn test_query_logical() {
+        assert_eq!(vec!["false"], run("true && false", "null"));
+        assert_eq!(vec!["true"], run("false || true", "null"));
+        assert_eq!(vec!["true"], run(r#"0 && """#, "null"));
+        assert_eq!(vec!["false"], run("null || false", "null"));
+        assert_eq!(vec!["true"], run(".a || [] && {}", r#"{"a":null}"#));
+
+        // rhs is not evaluated when lhs decides the result.
+        assert_eq!(vec!["false"], run("false && error(\"x\")", "null"));
+        assert_eq!(vec!["true"], run("1 || error(\"x\")", "null"));
+        assert_eq!(vec!["true", "true"], run(".[] || error(\"x\")", "[1,2]"));
+        let outs = run("true && error(\"x\")", "null");
+        assert_eq!(vec![r#"error: ["x"]"#], outs);
+
+        // for every output of lhs, all the outputs of rhs if needed.
+        let outs = run(".[] && true", "[true,false,null]");
+        assert_eq!(vec!["true", "false", "false"], outs);
+        let outs = run(".[] || .[]", "[false,true]");
+        assert_eq!(vec!["false", "true", "true"], outs);
+        assert_eq!(Vec::<String>::new(), run("true && empty", "null"));
+    }
+
+    #[test]
+    fn test_query_lazy() {
+        // generators under `first` and `limit` are evaluated only as far
+        // as the outputs are taken, these would not finish otherwise.
+        let n = 10000000000_i128;
+        let prog = format!("first(range({}) + 1)", n);
+        assert_eq!(vec!["1"], run(&prog, "null"));
+        let prog = format!("first(1 + range({}))", n);
+        assert_eq!(vec!["1"], run(&prog, "null"));
+        let prog = format!("limit(3; .[] * range({}))", n);
+        assert_eq!(vec!["0", "0", "1"], run(&prog, "[1,2]"));
+        let prog = format!("first(range({}) || false)", n);
+        assert_eq!(vec!["true"], run(&prog, "null"));
+        let prog = format!("first(.a && range({}))", n);
+        assert_eq!(vec!["true"], run(&prog, r#"{"a":1}"#));
+        let prog = format!("first(range(range({}); 10))", n);
+        assert_eq!(vec!["0"], run(&prog, "null"));
+        let prog = format!("first(combinations(range({})))", n);
+        assert_eq!(vec!["[]"], run(&prog, "[1,2]"));
+    }
+
+    #[test]
+    fn test_query_sql() {
+        let doc = r#"{
+            "users": [{"id":1,"name":"a"},{"id":2,"name":"b"}],
+            "orders": [{"uid":2,"n":10},{"uid":1.0,"n":20},{"uid":3,"n":30}]
+        }"#;
+        let outs = run("INDEX(.users.[]; .id)", doc);
+        let refs = r#"{"1":{"id":1,"name":"a"},"2":{"id":2,"name":"b"}}"#;
+        assert_eq!(vec![refs], outs);
+        let outs = run("INDEX(.name)", r#"[{"name":"x","v":1},{"name":"x"}]"#);
+        assert_eq!(vec![r#"{"x":{"name":"x"}}"#], outs);
+        let outs = run("GROUP_BY(. % 2)", "[1,2,3]");
+        assert_eq!(vec![r#"{"0":[2],"1":[1,3]}"#], outs);
+
+        assert_eq!(vec!["true"], run(".[0] | IN(1.0)", "[1]"));
+        assert_eq!(vec!["false"], run(r#".[0] | IN("1")"#, "[1]"));
+        assert_eq!(vec!["true"], run("IN(.[]; 2)", "[1,2]"));
+        assert_eq!(vec!["false"], run("IN(.[]; 3)", "[1,2]"));
+        // source is not evaluated after the first match.
+        let outs = run("IN(.[] | if . > 1 then error(\"x\") else . end; 1)",
+                       "[1,2]");
+        assert_eq!(vec!["true"], outs);
+
+        let prog = "INDEX(.users.[]; .id) as $idx \
+                    | JOIN($idx; .orders.[]; .uid)";
+        let outs = run(prog, doc);
+        let refs = vec![
+            r#"[{"n":10,"uid":2},{"id":2,"name":"b"}]"#,
+            r#"[{"n":20,"uid":1e0},{"id":1,"name":"a"}]"#,
+            r#"[{"n":30,"uid":3},null]"#,
+        ];
+        assert_eq!(refs, outs);
+        let prog = "INDEX(.users.[]; .id) as $idx \
+                    | JOIN($idx; .orders.[]; .uid; .[1] | type)";
+        let refs = vec![r#""object""#, r#""object""#, r#""null""#];
+        assert_eq!(refs, run(prog, doc));
+        let prog = "INDEX(.users.[]; .id) as $idx | .orders | JOIN($idx; .uid)";
+        let outs = run(prog, doc);
+        assert_eq!(1, outs.len());
+        assert!(outs[0].starts_with(r#"[[{"n":10,"uid":2},{"id":2"#));
+        // numbers as strings are the same as the keys of the index.
+        let doc = r#"[{"k":1.5},{"k":2.0}]"#;
+        let prog = "INDEX(.k) as $idx | JOIN($idx; .k | tostring)";
+        let refs = r#"[[{"k":1.5e0},{"k":1.5e0}],[{"k":2e0},{"k":2e0}]]"#;
+        assert_eq!(vec![refs], run(prog, doc));
+        let prog = r#"[.[].k | [tostring, "\(.)", @text, ([.] | join(""))]]"#;
+        let refs = r#"[["1.5","1.5","1.5","1.5"],["2","2","2","2"]]"#;
+        assert_eq!(vec![refs], run(prog, doc));
+        let outs = run("JOIN(.; .[]; .)", "[1]");
+        let err = r#"error: ["Array cannot be used as join index"]"#;
+        assert_eq!(vec![err], outs);
+
+        // outputs after the limit are not evaluated.
+        let prog = ".[] | if . > 2 then error(\"x\") else . end";
+        let outs = run(&format!("limit(2; {})", prog), "[1,2,3]");
+        assert_eq!(vec!["1", "2"], outs);
+        assert_eq!(vec!["1"], run(&format!("first({})", prog), "[1,2,3]"));
+        assert_eq!(vec!["2"], run(&format!("nth(1; {})", prog), "[1,2,3]"));
+        assert_eq!(Vec::<String>::new(), run("limit(0; error(\"x\"))", "1"));
+    }
 }
 
 //#[cfg(test)]
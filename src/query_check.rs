@@ -0,0 +1,387 @@
+// Checks on the query text and the parsed thunks, so that mistakes are
+// reported at parse time, with the span of query text they refer to.
+//
+// nom only tells the remaining input where parsing stopped, which is
+// often well past the actual mistake. A light tokenizer over the query
+// text, that treats string literals as opaque except for their `\(...)`
+// interpolations, is used to find unbalanced delimiters and the token to
+// blame. Calls to functions are checked against the definitions in scope
+// and the builtins with the number of arguments they take, variables
+// against the ones bound in scope and formats against the format strings.
+
+use builtin_str::FORMATS;
+use query::{Error, Span, StrPart, Thunk, CALL_BUILTINS};
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+enum Kind {
+    Word,
+    Punct,
+    Str,
+    Num,
+}
+
+#[derive(Debug,Clone,Copy)]
+struct Token<'a> {
+    kind: Kind,
+    text: &'a str,
+    off: usize,
+}
+
+impl<'a> Token<'a> {
+    fn is(&self, kind: Kind, text: &str) -> bool {
+        self.kind == kind && self.text == text
+    }
+}
+
+/// Error for query `text` that nom failed to parse beyond byte offset
+/// `off`.
+pub(crate) fn syntax_error(text: &str, off: usize) -> Error {
+    let tokens = match tokenize(text) {
+        Ok(tokens) => tokens,
+        Err(_) => {
+            let msg = "expected `\"`".to_string();
+            return Error::Syntax(Span::new(text, text.len(), 0), msg)
+        },
+    };
+    if let Some(err) = check_delimiters(text, &tokens) {
+        return err
+    }
+    match tokens.iter().find(|t| t.off + t.text.len() > off) {
+        Some(t) => {
+            let msg = format!("unexpected `{}`", t.text);
+            Error::Syntax(Span::new(text, t.off, t.text.len()), msg)
+        },
+        None => {
+            let msg = "unexpected end of query".to_string();
+            Error::Syntax(Span::new(text, text.len(), 0), msg)
+        },
+    }
+}
+
+/// Check that the functions called in `thunk`, parsed from query `text`,
+/// are defined with the number of arguments they are called with, that
+/// variables are bound in their scope and that formats are valid.
+pub(crate) fn check_names(text: &str, thunk: &Thunk) -> Result<(),Error> {
+    let mut scope = Scope{funcs: vec![], vars: vec![], seen: vec![]};
+    let undefined = match walk(thunk, &mut scope) {
+        Ok(()) => return Ok(()),
+        Err(undefined) => undefined,
+    };
+    let key = match &undefined {
+        Undefined::Call(name, _) => name.clone(),
+        Undefined::Var(name) => format!("${}", name),
+        Undefined::Format(name) => format!("@{}", name),
+    };
+    let nth = scope.seen.iter().filter(|s| **s == key).count() - 1;
+    let (off, len) = find_name(text, &key, nth).unwrap_or((0, 0));
+    let span = Span::new(text, off, len);
+    match undefined {
+        Undefined::Call(name, n) => {
+            match CALL_BUILTINS.iter().find(|(b, _)| *b == name) {
+                Some((_, arities)) => {
+                    Err(Error::Arity(span, name, n, arities.to_vec()))
+                },
+                None => Err(Error::UnknownFunction(span, name, n)),
+            }
+        },
+        Undefined::Var(name) => Err(Error::UnknownVariable(span, name)),
+        Undefined::Format(name) => Err(Error::UnknownFormat(span, name)),
+    }
+}
+
+// names in scope while walking the thunks, `funcs` has the functions
+// defined so far, `vars` the variables bound and `seen` the names walked
+// so far, `$` prefixed for variables and `@` for formats, to locate them
+// in the text.
+struct Scope {
+    funcs: Vec<(String, usize)>,
+    vars: Vec<String>,
+    seen: Vec<String>,
+}
+
+enum Undefined {
+    Call(String, usize),
+    Var(String),
+    Format(String),
+}
+
+// pre-order walk over the thunks, in the order of the query text.
+fn walk(thunk: &Thunk, scope: &mut Scope) -> Result<(),Undefined> {
+    use query::Thunk::*;
+
+    match thunk {
+        Empty | Identity | Recurse | Null(_) | Bool(_, _) | Integer(_, _) |
+        Float(_, _) | String(_, _) | IndexShortcut(_, _, _) |
+        Identifier(_, _) | Slice(_, _, _) | IterateValues(_) |
+        IndexPath(_) => {
+            Ok(())
+        },
+        Var(name) => {
+            scope.seen.push(format!("${}", name));
+            match name.as_str() {
+                "ENV" => Ok(()),
+                name if scope.vars.iter().any(|v| v == name) => Ok(()),
+                name => Err(Undefined::Var(name.to_string())),
+            }
+        },
+        Iterate(thunks, _) | List(thunks, _) => {
+            thunks.iter().map(|t| walk(t, scope)).collect()
+        },
+        Dict(props, _) => {
+            for (key, value) in props.iter() {
+                walk(key, scope)?;
+                walk(value, scope)?;
+            }
+            Ok(())
+        },
+        Neg(thunk) | Not(thunk) | Share(thunk) => walk(thunk, scope),
+        Mul(lhs, rhs) | Div(lhs, rhs) | Rem(lhs, rhs) | Add(lhs, rhs) |
+        Sub(lhs, rhs) | Eq(lhs, rhs) | Ne(lhs, rhs) | Lt(lhs, rhs) |
+        Le(lhs, rhs) | Gt(lhs, rhs) | Ge(lhs, rhs) | Shr(lhs, rhs) |
+        Shl(lhs, rhs) | BitAnd(lhs, rhs) | BitXor(lhs, rhs) |
+        BitOr(lhs, rhs) | And(lhs, rhs) | Or(lhs, rhs) | Comma(lhs, rhs) |
+        Pipe(lhs, rhs) | Alternative(lhs, rhs) | Update(lhs, rhs, _) => {
+            walk(lhs, scope)?;
+            walk(rhs, scope)
+        },
+        Bind(source, name, body) => {
+            walk(source, scope)?;
+            scope.seen.push(format!("${}", name));
+            bind(scope, name, |scope| walk(body, scope))
+        },
+        If(cond, then, otherwise) => {
+            walk(cond, scope)?;
+            walk(then, scope)?;
+            walk(otherwise, scope)
+        },
+        Reduce(source, name, init, update) => {
+            walk(source, scope)?;
+            scope.seen.push(format!("${}", name));
+            walk(init, scope)?;
+            bind(scope, name, |scope| walk(update, scope))
+        },
+        Foreach(source, name, init, update, extract) => {
+            walk(source, scope)?;
+            scope.seen.push(format!("${}", name));
+            walk(init, scope)?;
+            bind(scope, name, |scope| {
+                walk(update, scope)?;
+                walk(extract, scope)
+            })
+        },
+        Try(body, catch) => {
+            walk(body, scope)?;
+            match catch {
+                Some(catch) => walk(catch, scope),
+                None => Ok(()),
+            }
+        },
+        Def(name, params, body, rest) => {
+            let depth = scope.funcs.len();
+            // recursive calls see the function, and the body sees the
+            // parameters as functions without arguments.
+            scope.funcs.push((name.clone(), params.len()));
+            scope.funcs.extend(params.iter().map(|p| (p.clone(), 0)));
+            walk(body, scope)?;
+            scope.funcs.truncate(depth + 1);
+            walk(rest, scope)?;
+            scope.funcs.truncate(depth);
+            Ok(())
+        },
+        Format(name, parts) => {
+            scope.seen.push(format!("@{}", name));
+            if !FORMATS.contains(&name.as_str()) {
+                return Err(Undefined::Format(name.clone()))
+            }
+            for part in parts.iter() {
+                if let StrPart::Expr(thunk) = part {
+                    walk(thunk, scope)?;
+                }
+            }
+            Ok(())
+        },
+        Builtin(name, args) => {
+            scope.seen.push(name.clone());
+            if !is_defined(&scope.funcs, name, args.len()) {
+                return Err(Undefined::Call(name.clone(), args.len()))
+            }
+            args.iter().map(|t| walk(t, scope)).collect()
+        },
+    }
+}
+
+// walk with variable `name` bound, `init` of `reduce` and `foreach` is
+// walked before it is bound.
+fn bind<F>(scope: &mut Scope, name: &str, walk: F) -> Result<(),Undefined>
+    where F: FnOnce(&mut Scope) -> Result<(),Undefined>
+{
+    scope.vars.push(name.to_string());
+    let res = walk(scope);
+    scope.vars.pop();
+    res
+}
+
+fn is_defined(funcs: &[(String, usize)], name: &str, n: usize) -> bool {
+    if funcs.iter().any(|(s, m)| s == name && *m == n) {
+        return true
+    }
+    match CALL_BUILTINS.iter().find(|(b, _)| *b == name) {
+        Some((_, arities)) => arities.contains(&n),
+        None => false,
+    }
+}
+
+// offset and length of the `nth` occurence of `key` in `text`, a `$`
+// variable, an `@` format or a function name followed by `(` and not a
+// `.name` key or the name in `def`.
+fn find_name(text: &str, key: &str, nth: usize) -> Option<(usize, usize)> {
+    let tokens = tokenize(text).ok()?;
+    let call = !key.starts_with('$') && !key.starts_with('@');
+    let mut names = tokens.iter().enumerate().filter(|(i, t)| {
+        let before = if *i > 0 { tokens.get(i - 1) } else { None };
+        let after = tokens.get(i + 1);
+        t.is(Kind::Word, key) && (!call || {
+            after.map_or(false, |a| a.is(Kind::Punct, "(")) &&
+            !before.map_or(false, |b| {
+                b.is(Kind::Punct, ".") || b.is(Kind::Word, "def")
+            })
+        })
+    });
+    names.nth(nth).map(|(_, t)| (t.off, t.text.len()))
+}
+
+// first unbalanced `(`, `[`, `{` or `if ... end` in the query.
+fn check_delimiters(text: &str, tokens: &[Token]) -> Option<Error> {
+    let mut stack: Vec<(Token, &str)> = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        let keyed = i > 0 && tokens[i - 1].is(Kind::Punct, ".");
+        let close = match (token.kind, token.text) {
+            (Kind::Punct, "(") => { stack.push((*token, ")")); continue },
+            (Kind::Punct, "[") => { stack.push((*token, "]")); continue },
+            (Kind::Punct, "{") => { stack.push((*token, "}")); continue },
+            (Kind::Word, "if") if !keyed => {
+                stack.push((*token, "end"));
+                continue
+            },
+            (Kind::Punct, ")") | (Kind::Punct, "]") | (Kind::Punct, "}") => {
+                token.text
+            },
+            (Kind::Word, "end") if !keyed => token.text,
+            _ => continue,
+        };
+        let span = Span::new(text, token.off, token.text.len());
+        match stack.pop() {
+            Some((_, expected)) if expected == close => (),
+            Some((_, expected)) => {
+                let msg = format!("expected `{}`, found `{}`", expected, close);
+                return Some(Error::Syntax(span, msg))
+            },
+            None => {
+                let msg = format!("unexpected `{}`", close);
+                return Some(Error::Syntax(span, msg))
+            },
+        }
+    }
+    stack.pop().map(|(_, expected)| {
+        let msg = format!("expected `{}`", expected);
+        Error::Syntax(Span::new(text, text.len(), 0), msg)
+    })
+}
+
+// tokens in the code regions of `text`, error if a string literal is not
+// terminated.
+fn tokenize<'a>(text: &'a str) -> Result<Vec<Token<'a>>,()> {
+    let mut tokens = Vec::new();
+    tokenize_code(text, 0, false, &mut tokens)?;
+    Ok(tokens)
+}
+
+// tokenize code from byte offset `off` up to the end of text, or to the
+// unbalanced `)` closing an interpolation if `nested`. Return the offset
+// after the last character consumed.
+fn tokenize_code<'a>(
+    text: &'a str, off: usize, nested: bool, tokens: &mut Vec<Token<'a>>)
+    -> Result<usize,()>
+{
+    let (mut off, mut depth) = (off, 0);
+    while let Some(ch) = text[off..].chars().next() {
+        let start = off;
+        let kind = match ch {
+            ch if ch.is_whitespace() => {
+                off += ch.len_utf8();
+                continue
+            },
+            '"' => {
+                off = tokenize_string(text, off + 1, tokens)?;
+                Kind::Str
+            },
+            ')' if nested && depth == 0 => return Ok(off + 1),
+            ch if ch.is_ascii_digit() => {
+                off += scan(&text[off..], |ch, prev| {
+                    ch.is_ascii_alphanumeric() || ch == '.' ||
+                    ((ch == '-' || ch == '+') && (prev == 'e' || prev == 'E'))
+                });
+                Kind::Num
+            },
+            ch if ch.is_alphabetic() || "_$@".contains(ch) => {
+                off += ch.len_utf8();
+                off += scan(&text[off..], |ch, _| {
+                    ch.is_alphanumeric() || ch == '_'
+                });
+                Kind::Word
+            },
+            ch => {
+                match ch {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => (),
+                }
+                off += ch.len_utf8();
+                Kind::Punct
+            },
+        };
+        // tokens of interpolations are pushed before their string.
+        let token = Token{kind, text: &text[start..off], off: start};
+        match kind {
+            Kind::Str => {
+                let at = tokens.iter().rposition(|t| t.off < start)
+                    .map_or(0, |i| i + 1);
+                tokens.insert(at, token);
+            },
+            _ => tokens.push(token),
+        }
+    }
+    if nested { Err(()) } else { Ok(off) }
+}
+
+// skip the string literal from byte offset `off`, just after the opening
+// quote, tokenizing its interpolations. Return the offset after the
+// closing quote.
+fn tokenize_string<'a>(text: &'a str, off: usize, tokens: &mut Vec<Token<'a>>)
+    -> Result<usize,()>
+{
+    let mut off = off;
+    loop {
+        let mut chars = text[off..].chars();
+        match (chars.next(), chars.next()) {
+            (Some('"'), _) => return Ok(off + 1),
+            (Some('\\'), Some('(')) => {
+                off = tokenize_code(text, off + 2, true, tokens)?
+            },
+            (Some('\\'), Some(ch)) => off += 1 + ch.len_utf8(),
+            (Some(ch), _) => off += ch.len_utf8(),
+            (None, _) => return Err(()),
+        }
+    }
+}
+
+// number of bytes in the prefix of `text` whose characters satisfy `pred`,
+// given the character and the one before it.
+fn scan<F>(text: &str, pred: F) -> usize where F: Fn(char, char) -> bool {
+    let mut prev = '\0';
+    for (i, ch) in text.char_indices() {
+        if !pred(ch, prev) { return i }
+        prev = ch;
+    }
+    text.len()
+}
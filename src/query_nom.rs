@@ -2,7 +2,7 @@ use nom::{self, {types::CompleteStr as NS}, IResult};
 
 use lex::Lex;
 use json::{self, Json};
-use query::{Thunk};
+use query::{Thunk, UpdateOp, StrPart};
 
 named!(nom_dot(NS) -> NS, ws!(tag!(".")));
 named!(nom_dotdot(NS) -> NS, ws!(tag!("..")));
@@ -15,12 +15,40 @@ named!(nom_open_brace(NS) -> NS, ws!(tag!("{")));
 named!(nom_clos_brace(NS) -> NS, ws!(tag!("}")));
 named!(nom_open_paran(NS) -> NS, ws!(tag!("(")));
 named!(nom_clos_paran(NS) -> NS, ws!(tag!(")")));
+named!(nom_semicolon(NS) -> NS, ws!(tag!(";")));
 named!(nom_opt(NS) -> Option<NS>, opt!(ws!(tag!("?"))));
 named!(nom_identifier(NS) -> NS, ws!(re_find!(r"^[A-Za-z_][0-9A-Za-z_]*")));
+named!(nom_kw_if(NS) -> NS, ws!(re_find!(r"^if\b")));
+named!(nom_kw_then(NS) -> NS, ws!(re_find!(r"^then\b")));
+named!(nom_kw_elif(NS) -> NS, ws!(re_find!(r"^elif\b")));
+named!(nom_kw_else(NS) -> NS, ws!(re_find!(r"^else\b")));
+named!(nom_kw_end(NS) -> NS, ws!(re_find!(r"^end\b")));
+named!(nom_kw_try(NS) -> NS, ws!(re_find!(r"^try\b")));
+named!(nom_kw_catch(NS) -> NS, ws!(re_find!(r"^catch\b")));
+named!(nom_kw_as(NS) -> NS, ws!(re_find!(r"^as\b")));
+named!(nom_kw_reduce(NS) -> NS, ws!(re_find!(r"^reduce\b")));
+named!(nom_kw_foreach(NS) -> NS, ws!(re_find!(r"^foreach\b")));
+named!(nom_variable(NS) -> NS, ws!(re_find!(r"^\$[A-Za-z_][0-9A-Za-z_]*")));
 
-named!(nom_null(NS) -> NS, ws!(tag!("null")));
-named!(nom_true(NS) -> NS, ws!(tag!("true")));
-named!(nom_false(NS) -> NS, ws!(tag!("false")));
+const KEYWORDS: [&'static str; 11] = [
+    "as", "def", "if", "then", "elif", "else", "end", "reduce", "foreach",
+    "try", "catch",
+];
+
+// identifier that can name a function or a field, but not a keyword.
+fn nom_name(text: NS) -> nom::IResult<NS, NS> {
+    let (rem, name) = nom_identifier(text)?;
+    if KEYWORDS.contains(&name.as_ref()) {
+        let ctxt = nom::Context::Code(text, nom::ErrorKind::Custom(0));
+        return Err(nom::Err::Error(ctxt))
+    }
+    Ok((rem, name))
+}
+
+// literals are whole words, `nulls` is an identifier.
+named!(nom_null(NS) -> NS, ws!(re_find!(r"^null\b")));
+named!(nom_true(NS) -> NS, ws!(re_find!(r"^true\b")));
+named!(nom_false(NS) -> NS, ws!(re_find!(r"^false\b")));
 named!(nom_index(NS) -> isize,
     flat_map!(ws!(re_find!(r#"^\d+"#)), parse_to!(isize))
 );
@@ -72,14 +100,22 @@ fn nom_json_string(text: NS) -> nom::IResult<NS, String> {
 
 //---- Expression Operations, in PEG grammar
 //
-//    Expr       <- Or PipeExpr*
-//    PipeExpr   <- ('|') Or
+//    Expr       <- Comma PipeExpr*
+//    PipeExpr   <- ('|') Comma
+//    Comma      <- Term (',' Term)*
+//    Value      <- Term ('|' Term)*, object values without `,`
+//    Term       <- FuncDef | Update ('as' Variable '|' Expr)?
+//    FuncDef    <- 'def' Ident ('(' Param (';' Param)* ')')? ':' Expr ';' Expr
+//    Param      <- Ident | Variable
+//    Update     <- Alt (UpdateOp Alt)?
+//    UpdateOp   <- '|=' | '//=' | '+=' | '-=' | '*=' | '/=' | '%=' | '='
+//    Alt        <- Or ('//' Alt)?
 //    Or         <- And OrExpr*
 //    OrExpr     <- ('||') And
 //    And        <- Compar AndExpr*
 //    AndExpr    <- ('&&') Compar
 //    Compar     <- BitOr ComparExpr*
-//    ComparExpr <- ('==' | '!=' | '<' | '<=' | '>' | '>=' ) BitOr
+//    ComparExpr <- ('==' | '!=' | '<=' | '>=' | '<' | '>' ) BitOr
 //    BitOr      <- BitXor BitOrExpr*
 //    BitOrExpr  <- ('bor') BitXor
 //    BitXor     <- BitAnd BitXorExpr*
@@ -90,9 +126,12 @@ fn nom_json_string(text: NS) -> nom::IResult<NS, String> {
 //    ShiftExpr  <- ('<<' | '>>') Add
 //    Add        <- Mul AddExpr*
 //    AddExpr    <- ('+' | '-') Mul
-//    Mul        <- Primary MulExpr*
-//    MulExpr    <- ('*'|'/'|'%') Primary
-//    Primary    <- '(' Expr ')'
+//    Mul        <- Postfix MulExpr*
+//    MulExpr    <- ('*'|'/'|'%') Postfix
+//    Postfix    <- Primary Suffix*
+//    Suffix     <- nom_slice | nom_iterate | nom_index_short, that follow
+//                  without whitespace, so `.a.b = 1` assigns to `.a.b`
+//    Primary    <- '(' Expr ')' '?'?
 //                | nom_literal
 //                | nom_slice
 //                | nom_iterate
@@ -101,12 +140,22 @@ fn nom_json_string(text: NS) -> nom::IResult<NS, String> {
 //                | nom_dot
 //                | '-' Primary
 //                | '!' Primary
+//                | Variable
+//                | 'if' Expr 'then' Expr ('elif' Expr 'then' Expr)*
+//                  ('else' Expr)? 'end'
+//                | 'reduce' Expr 'as' Variable '(' Expr ';' Expr ')'
+//                | 'foreach' Expr 'as' Variable
+//                  '(' Expr ';' Expr (';' Expr)? ')'
+//                | 'try' Postfix ('catch' Postfix)?
+//                | '@' Ident String?
+//                | Ident '(' Expr (';' Expr)* ')' '?'?
+//                | String, with `\(Expr)` interpolated
 //                | <and many more can be added>
 
 named!(nom_expr(NS) -> Thunk,
     map!(
         do_parse!(
-               lhs: nom_or >>
+               lhs: nom_comma_expr >>
             thunks: many0!(nom_pipe_expr) >>
             (lhs, thunks)
         ),
@@ -123,10 +172,119 @@ named!(nom_expr(NS) -> Thunk,
 named!(nom_pipe_expr(NS) -> Thunk,
     do_parse!(
               ws!(opt!(tag!("|"))) >>
-        expr: nom_or >>
+        expr: nom_comma_expr >>
         (expr)
     )
 );
+named!(nom_comma_expr(NS) -> Thunk,
+    map!(
+        do_parse!(
+               lhs: nom_term >>
+            thunks: many0!(preceded!(nom_comma, nom_term)) >>
+            (lhs, thunks)
+        ),
+        |(mut lhs, thunks)| {
+            for rhs in thunks {
+                lhs = Thunk::Comma(Box::new(lhs), Box::new(rhs));
+            }
+            lhs
+        }
+    )
+);
+named!(nom_value_expr(NS) -> Thunk,
+    map!(
+        do_parse!(
+               lhs: nom_term >>
+            thunks: many0!(preceded!(ws!(opt!(tag!("|"))), nom_term)) >>
+            (lhs, thunks)
+        ),
+        |(mut lhs, thunks)| {
+            for rhs in thunks {
+                lhs = Thunk::Pipe(Box::new(lhs), Box::new(rhs));
+            }
+            lhs
+        }
+    )
+);
+named!(nom_term(NS) -> Thunk,
+    alt!(
+        nom_def_expr |
+        map!(
+            do_parse!(
+                source: nom_update >>
+                  bind: opt!(nom_bind_expr) >>
+                (source, bind)
+            ),
+            |(source, bind)| match bind {
+                Some((name, body)) => {
+                    Thunk::Bind(Box::new(source), name, Box::new(body))
+                },
+                None => source,
+            }
+        )
+    )
+);
+named!(nom_bind_expr(NS) -> (String, Thunk),
+    do_parse!(
+              nom_kw_as >>
+        name: nom_variable >>
+              ws!(tag!("|")) >>
+        body: nom_expr >>
+        (variable_name(name), body)
+    )
+);
+
+named!(nom_def_expr(NS) -> Thunk,
+    do_parse!(
+                ws!(re_find!(r"^def\b")) >>
+          name: nom_name >>
+        params: opt!(delimited!(
+                    nom_open_paran,
+                    separated_nonempty_list!(nom_semicolon, nom_param),
+                    nom_clos_paran
+                )) >>
+                nom_colon >>
+          body: nom_expr >>
+                nom_semicolon >>
+          rest: nom_expr >>
+        (def_to_thunk(name, params.unwrap_or(vec![]), body, rest))
+    )
+);
+named!(nom_param(NS) -> NS, alt!(nom_variable | nom_name));
+
+named!(nom_update(NS) -> Thunk,
+    do_parse!(
+        lhs: nom_alternative >>
+        rhs: opt!(pair!(nom_update_op, nom_alternative)) >>
+        (match rhs {
+            Some((op, rhs)) => Thunk::Update(Box::new(lhs), Box::new(rhs), op),
+            None => lhs,
+        })
+    )
+);
+named!(nom_update_op(NS) -> UpdateOp,
+    ws!(alt!(
+        value!(UpdateOp::Modify, tag!("|=")) |
+        value!(UpdateOp::Alt, tag!("//=")) |
+        value!(UpdateOp::Add, tag!("+=")) |
+        value!(UpdateOp::Sub, tag!("-=")) |
+        value!(UpdateOp::Mul, tag!("*=")) |
+        value!(UpdateOp::Div, tag!("/=")) |
+        value!(UpdateOp::Rem, tag!("%=")) |
+        value!(UpdateOp::Assign, terminated!(tag!("="), not!(tag!("="))))
+    ))
+);
+
+named!(nom_alternative(NS) -> Thunk,
+    do_parse!(
+        lhs: nom_or >>
+        rhs: opt!(preceded!(ws!(tag!("//")), nom_alternative)) >>
+        (match rhs {
+            Some(rhs) => Thunk::Alternative(Box::new(lhs), Box::new(rhs)),
+            None => lhs,
+        })
+    )
+);
 
 named!(nom_or(NS) -> Thunk,
     map!(
@@ -200,8 +358,8 @@ named!(nom_compare(NS) -> Thunk,
 named!(nom_compare_expr(NS) -> (NS, Thunk),
     do_parse!(
           op: ws!(alt!(
-                tag!("==") | tag!("!=") | tag!("<") |
-                tag!("<=") | tag!(">") | tag!(">=")
+                tag!("==") | tag!("!=") | tag!("<=") |
+                tag!(">=") | tag!("<") | tag!(">")
               )) >>
         expr: nom_bitor >>
         (op, expr)
@@ -334,7 +492,7 @@ named!(nom_add_expr(NS) -> (NS, Thunk),
 named!(nom_mult_div_rem(NS) -> Thunk,
     map!(
         do_parse!(
-                thunk: nom_primary_expr >>
+                thunk: nom_postfix_expr >>
             op_thunks: many0!(nom_mult_expr) >>
             (thunk, op_thunks)
         ),
@@ -355,7 +513,7 @@ named!(nom_mult_div_rem(NS) -> Thunk,
 named!(nom_mult_expr(NS) -> (NS, Thunk),
     do_parse!(
           op: ws!(alt!(tag!("*") | tag!("/") | tag!("%"))) >>
-        expr: nom_primary_expr >>
+        expr: nom_postfix_expr >>
         (op, expr)
     )
 );
@@ -364,9 +522,10 @@ named!(nom_mult_expr(NS) -> (NS, Thunk),
 //------------------ PRIMAR EXPRESSIONS ------------------------
 
 
+// key must follow the dot without whitespace, `. as $x` is not `.as $x`.
 named!(nom_key(NS) -> (Option<String>, Option<isize>),
     alt!(
-        nom_identifier => { |s: NS| (Some((&s).to_string()), None) } |
+        re_find!(r"^[A-Za-z_][0-9A-Za-z_]*") => { |s: NS| (Some((&s).to_string()), None) } |
         nom_index => { |i: isize| (None, Some(i)) } |
         nom_json_string => { |s: String| {
             let off = s.parse().map(|x| Some(x)).unwrap_or(None);
@@ -378,7 +537,8 @@ named!(nom_primary_index_short(NS) ->
     ((Option<String>, Option<isize>), Option<NS>),
 
     do_parse!(
-             nom_dot  >>
+             opt!(nom::multispace) >>
+             tag!(".") >>
         key: nom_key  >>
         opt: nom_opt  >>
         (key, opt)
@@ -453,28 +613,8 @@ named!(nom_primary_slice6(NS) -> (isize, isize, Option<NS>),
         (0, isize::max_value(), opt)
     )
 );
-named!(nom_iterate_comma_item(NS) -> Thunk,
-    do_parse!(
-               nom_comma >>
-        thunk: nom_expr  >>
-        (thunk)
-    )
-);
 named!(nom_iterate_items(NS) -> Vec<Thunk>,
-    map!(
-        do_parse!(
-             item: nom_expr >>
-            items: opt!(many1!(nom_iterate_comma_item)) >>
-            (item, items)
-        ),
-        |(item, items)| {
-            //println!("........ iterate_items {:?} {:?}", item, items);
-            match items {
-                Some(mut items) => { items.insert(0, item); items },
-                None => vec![item],
-            }
-        }
-    )
+    map!(nom_expr, |thunk| comma_items(thunk, vec![]))
 );
 named!(nom_primary_full_iterate(NS) -> Option<NS>,
     do_parse!(
@@ -499,7 +639,10 @@ named!(nom_primary_collection1(NS) -> (Vec<Thunk>, Option<NS>),
     do_parse!(
         thunks: delimited!(
                     nom_open_sqr,
-                    separated_list!(nom_comma, nom_expr),
+                    map!(opt!(nom_expr), |thunk| match thunk {
+                        Some(thunk) => comma_items(thunk, vec![]),
+                        None => vec![],
+                    }),
                     nom_clos_sqr
                 )       >>
            opt: nom_opt >>
@@ -517,7 +660,7 @@ named!(nom_collection2_item(NS) -> (Thunk, Thunk),
         do_parse!(
             key: nom_object_key  >>
                  nom_colon >>
-            val: nom_expr  >>
+            val: nom_value_expr >>
             (key, val)
         ) |
         nom_identifier => { |s: NS| {
@@ -562,6 +705,141 @@ named!(nom_primary_unarynot_expr(NS) -> Thunk,
         (thunk)
     )
 );
+named!(nom_primary_if(NS) -> Thunk,
+    do_parse!(
+                   nom_kw_if >>
+             cond: nom_expr >>
+                   nom_kw_then >>
+             then: nom_expr >>
+            elifs: many0!(do_parse!(
+                             nom_kw_elif >>
+                       cond: nom_expr >>
+                             nom_kw_then >>
+                       then: nom_expr >>
+                       (cond, then)
+                   )) >>
+        otherwise: opt!(preceded!(nom_kw_else, nom_expr)) >>
+                   nom_kw_end >>
+        (if_to_thunk(cond, then, elifs, otherwise))
+    )
+);
+named!(nom_primary_reduce(NS) -> Thunk,
+    do_parse!(
+                nom_kw_reduce >>
+        source: nom_expr >>
+                nom_kw_as >>
+          name: nom_variable >>
+                nom_open_paran >>
+          init: nom_expr >>
+                nom_semicolon >>
+        update: nom_expr >>
+                nom_clos_paran >>
+        (Thunk::Reduce(
+            Box::new(source), variable_name(name),
+            Box::new(init), Box::new(update)
+        ))
+    )
+);
+named!(nom_primary_foreach(NS) -> Thunk,
+    do_parse!(
+                 nom_kw_foreach >>
+         source: nom_expr >>
+                 nom_kw_as >>
+           name: nom_variable >>
+                 nom_open_paran >>
+           init: nom_expr >>
+                 nom_semicolon >>
+         update: nom_expr >>
+        extract: opt!(preceded!(nom_semicolon, nom_expr)) >>
+                 nom_clos_paran >>
+        (Thunk::Foreach(
+            Box::new(source), variable_name(name),
+            Box::new(init), Box::new(update),
+            Box::new(extract.unwrap_or(Thunk::Identity))
+        ))
+    )
+);
+named!(nom_primary_try(NS) -> Thunk,
+    do_parse!(
+               nom_kw_try >>
+         body: nom_postfix_expr >>
+        catch: opt!(preceded!(nom_kw_catch, nom_postfix_expr)) >>
+        (Thunk::Try(Box::new(body), catch.map(|c| Box::new(c))))
+    )
+);
+// string literal with at least one `\(expr)`, plain strings are literals.
+fn nom_interp_string(text: NS) -> nom::IResult<NS, Vec<StrPart>> {
+    check_next_byte(text, b'"')?;
+    let bs = text.as_bytes();
+    let (mut parts, mut interp) = (Vec::new(), false);
+    let (mut i, mut start) = (1, 1);
+    loop {
+        match bs.get(i) {
+            Some(&b'\\') if bs.get(i+1) == Some(&b'(') => {
+                push_str_lit(&mut parts, &text[start..i], text)?;
+                let (rem, thunk) = nom_expr(NS(&text[i+2..]))?;
+                let (rem, _) = nom_interp_close(rem)?;
+                parts.push(StrPart::Expr(thunk));
+                i = text.len() - rem.len();
+                start = i;
+                interp = true;
+            },
+            Some(&b'\\') => i += 2,
+            Some(&b'"') => {
+                push_str_lit(&mut parts, &text[start..i], text)?;
+                i += 1;
+                break
+            },
+            Some(_) => i += 1,
+            None => {
+                let ctxt = nom::Context::Code(text, nom::ErrorKind::Custom(0));
+                return Err(nom::Err::Error(ctxt))
+            },
+        }
+    }
+    if !interp {
+        let ctxt = nom::Context::Code(text, nom::ErrorKind::Custom(0));
+        return Err(nom::Err::Error(ctxt))
+    }
+    Ok((NS(&text[i..]), parts))
+}
+named!(nom_interp_close(NS) -> NS,
+    preceded!(opt!(nom::multispace), tag!(")"))
+);
+
+fn push_str_lit<'a>(parts: &mut Vec<StrPart>, seg: &str, text: NS<'a>)
+    -> Result<(), nom::Err<NS<'a>>>
+{
+    if seg.len() == 0 { return Ok(()) }
+    let mut lex = Lex::new(0, 1, 1);
+    match json::parse_string(&format!("\"{}\"", seg), &mut lex) {
+        Ok(Json::String(s)) => { parts.push(StrPart::Lit(s)); Ok(()) },
+        _ => {
+            let ctxt = nom::Context::Code(text, nom::ErrorKind::Custom(0));
+            Err(nom::Err::Error(ctxt))
+        },
+    }
+}
+
+named!(nom_primary_interp(NS) -> Thunk,
+    map!(ws!(call!(nom_interp_string)), |parts| {
+        Thunk::Format("text".to_string(), parts)
+    })
+);
+named!(nom_primary_format(NS) -> Thunk,
+    do_parse!(
+         name: ws!(re_find!(r"^@[A-Za-z0-9_]+")) >>
+        parts: opt!(alt!(
+                 ws!(call!(nom_interp_string)) |
+                 ws!(nom_json_string) => { |s| vec![StrPart::Lit(s)] }
+               )) >>
+        ({
+            let parts = parts.unwrap_or(vec![StrPart::Expr(Thunk::Identity)]);
+            Thunk::Format(name[1..].to_string(), parts)
+        })
+    )
+);
+
 named!(nom_primary_literal(NS) -> (Thunk, Option<NS>),
     do_parse!(
         thunk: alt!(
@@ -577,10 +855,10 @@ named!(nom_primary_literal(NS) -> (Thunk, Option<NS>),
 );
 named!(nom_primary_builtins(NS) -> (NS, Vec<Thunk>),
     do_parse!(
-        funcname: nom_identifier >>
+        funcname: nom_name >>
             args: delimited!(
                     nom_open_paran,
-                    separated_list!(nom_comma, nom_expr),
+                    separated_list!(nom_semicolon, nom_expr),
                     nom_clos_paran
                   ) >>
         (funcname, args)
@@ -589,7 +867,7 @@ named!(nom_primary_builtins(NS) -> (NS, Vec<Thunk>),
 
 named!(nom_primary_identifier_opt(NS) -> (NS, Option<NS>),
     do_parse!(
-        thunk: nom_identifier >>
+        thunk: nom_name >>
           opt: nom_opt        >>
         (thunk, opt)
     )
@@ -601,6 +879,34 @@ named!(nom_primary_identifier_opt(NS) -> (NS, Option<NS>),
 // iterate operation,
 // array literal
 // collection list
+named!(nom_postfix_expr(NS) -> Thunk,
+    map!(
+        pair!(nom_primary_expr, many0!(nom_suffix_expr)),
+        |(mut lhs, thunks)| {
+            for rhs in thunks {
+                lhs = Thunk::Pipe(Box::new(lhs), Box::new(rhs));
+            }
+            lhs
+        }
+    )
+);
+named!(nom_suffix_expr(NS) -> Thunk,
+    preceded!(
+        peek!(tag!(".")),
+        alt!(
+            nom_primary_slice1 => { slice_to_thunk } |
+            nom_primary_slice2 => { slice_to_thunk } |
+            nom_primary_slice3 => { slice_to_thunk } |
+            nom_primary_slice4 => { slice_to_thunk } |
+            nom_primary_slice5 => { slice_to_thunk } |
+            nom_primary_slice6 => { slice_to_thunk } |
+            nom_primary_full_iterate => { full_iterate_to_thunk } |
+            nom_primary_iterate => { iterate_to_thunk } |
+            nom_primary_index_short => { index_short_to_thunk }
+        )
+    )
+);
+
 named!(nom_primary_expr(NS) -> Thunk,
     alt!(
         nom_primary_slice1 => { slice_to_thunk } |
@@ -612,15 +918,26 @@ named!(nom_primary_expr(NS) -> Thunk,
         nom_primary_full_iterate => { full_iterate_to_thunk } |
         nom_primary_iterate => { iterate_to_thunk } |
         nom_primary_index_short => { index_short_to_thunk } |
-        nom_primary_builtins => { builtin_to_thunk } |
+        nom_primary_if |
+        nom_primary_reduce |
+        nom_primary_foreach |
+        nom_primary_try |
+        pair!(nom_primary_builtins, nom_opt) => { |(call, opt)| {
+            optional_to_thunk(builtin_to_thunk(call), opt)
+        }} |
         nom_primary_collection1 => { collection1_to_thunk } |
         nom_primary_collection2 => { collection2_to_thunk } |
+        nom_primary_format |
+        nom_primary_interp |
         // nom_primary_literal should come before identifier
         nom_primary_literal => { literal_to_thunk } |
         nom_primary_identifier_opt => { identifier_to_literal } |
+        nom_variable => { |s| Thunk::Var(variable_name(s)) } |
         nom_primary_unarynot_expr => { |thunk| Thunk::Not(Box::new(thunk)) } |
         nom_primary_unaryneg_expr => { |thunk| Thunk::Neg(Box::new(thunk)) } |
-        nom_primary_paran_expr |
+        pair!(nom_primary_paran_expr, nom_opt) => { |(thunk, opt)| {
+            optional_to_thunk(thunk, opt)
+        }} |
         nom_dotdot => { |_| Thunk::Recurse } |
         nom_dot => { |_| Thunk::Identity }
     )
@@ -658,10 +975,48 @@ fn literal_to_thunk((thunk, opt): (Thunk, Option<NS>)) -> Thunk {
     }
 }
 
+fn variable_name(s: NS) -> String {
+    s[1..].to_string() // skip the `$` sigil
+}
+
+// `def f($a): body` is same as `def f(a): a as $a | body`
+fn def_to_thunk(name: NS, params: Vec<NS>, body: Thunk, rest: Thunk) -> Thunk {
+    let mut body = body;
+    for param in params.iter().rev().filter(|p| p.starts_with("$")) {
+        let name = variable_name(*param);
+        let source = Thunk::Identifier(name.clone(), false);
+        body = Thunk::Bind(Box::new(source), name, Box::new(body));
+    }
+    let params = params.into_iter()
+        .map(|p| p.trim_start_matches('$').to_string())
+        .collect();
+    Thunk::Def(name.to_string(), params, Box::new(body), Box::new(rest))
+}
+
+// `elif` is a nested `if` in the else branch, missing else is identity.
+fn if_to_thunk(
+    cond: Thunk, then: Thunk,
+    elifs: Vec<(Thunk, Thunk)>, otherwise: Option<Thunk>) -> Thunk
+{
+    let mut otherwise = otherwise.unwrap_or(Thunk::Identity);
+    for (c, t) in elifs.into_iter().rev() {
+        otherwise = Thunk::If(Box::new(c), Box::new(t), Box::new(otherwise));
+    }
+    Thunk::If(Box::new(cond), Box::new(then), Box::new(otherwise))
+}
+
 fn builtin_to_thunk((funcname, args): (NS, Vec<Thunk>)) -> Thunk {
     Thunk::Builtin(funcname.to_string(), args)
 }
 
+// `f?` is same as `try f`.
+fn optional_to_thunk(thunk: Thunk, opt: Option<NS>) -> Thunk {
+    match opt {
+        Some(_) => Thunk::Try(Box::new(thunk), None),
+        None => thunk,
+    }
+}
+
 fn index_short_to_thunk(
     ((key, off), opt): ((Option<String>, Option<isize>), Option<NS>))
     -> Thunk
@@ -711,6 +1066,19 @@ fn iterate_to_thunk((thunks, opt): (Vec<Thunk>, Option<NS>)) -> Thunk {
     Thunk::Iterate(thunks, opt1)
 }
 
+// items of `a, b, c` parsed as a single expression, `[a, b]` and
+// `.[a, b]` keep an item per comma.
+fn comma_items(thunk: Thunk, mut items: Vec<Thunk>) -> Vec<Thunk> {
+    match thunk {
+        Thunk::Comma(lhs, rhs) => {
+            let mut items = comma_items(*lhs, items);
+            items.push(*rhs);
+            items
+        },
+        thunk => { items.push(thunk); items },
+    }
+}
+
 fn collection1_to_thunk((thunks, opt): (Vec<Thunk>, Option<NS>)) -> Thunk {
     Thunk::List(thunks, opt.map_or(false, |_| true))
 }
@@ -0,0 +1,446 @@
+// Optimizer pass over the parsed thunks, rewriting the tree before stages
+// are prepared from it:
+//
+// * operators on literals are folded into a literal, like `1 + 2`.
+// * consecutive index lookups in a pipe are fused into a single path
+//   lookup, like `.a.b.[0]`.
+// * conditionals on literals are replaced by the branch taken, like
+//   `if true then a else b end`, `null // a`, `false and a`.
+// * thunks that repeat their input for each branch or argument, like
+//   `.a | .[0, 1]` or `.a | has("b")`, share the outputs of the input
+//   instead of evaluating it again for every repeat.
+//
+// `explain` renders the tree, one thunk per line.
+
+use json::Json;
+use ops;
+use query::{StrPart, Thunk, UNARY_BUILTINS};
+
+// functions defined with `def`, name and number of parameters.
+type Scope = Vec<(String, usize)>;
+
+// builtins that evaluate their arguments as filters or paths, rather than
+// on repeats of the input.
+const FILTER_BUILTINS: [&'static str; 22] = [
+    "del", "with_entries", "map", "any", "all", "select", "sort_by",
+    "group_by", "unique_by", "min_by", "max_by", "first", "last", "until",
+    "path", "paths", "recurse", "walk", "INDEX", "GROUP_BY", "IN", "JOIN",
+];
+
+/// Optimize the thunks parsed from a query, outputs of the optimized
+/// thunks are the same as the parsed ones.
+pub(crate) fn optimize(thunk: Thunk) -> Thunk {
+    rewrite(thunk, &mut Vec::new())
+}
+
+/// Tree of `thunk`, one thunk per line indented under its parent.
+pub(crate) fn explain(thunk: &Thunk) -> String {
+    let mut out = String::new();
+    do_explain(thunk, 0, &mut out);
+    out
+}
+
+// rewrite children before their parent, `scope` has the functions defined
+// with `def`, that shadow builtins of the same name.
+fn rewrite(thunk: Thunk, scope: &mut Scope) -> Thunk {
+    use query::Thunk::*;
+
+    let bx = |thunk: Box<Thunk>, scope: &mut Scope| {
+        Box::new(rewrite(*thunk, scope))
+    };
+    match thunk {
+        Iterate(thunks, opt) => Iterate(rewrite_all(thunks, scope), opt),
+        List(thunks, opt) => List(rewrite_all(thunks, scope), opt),
+        Dict(props, opt) => {
+            let props = props.into_iter()
+                .map(|(k, v)| (rewrite(k, scope), rewrite(v, scope)))
+                .collect();
+            Dict(props, opt)
+        },
+        Neg(thunk) => {
+            let thunk = rewrite(*thunk, scope);
+            match literal(&thunk).and_then(|doc| to_literal(-doc)) {
+                Some(thunk) => thunk,
+                None => Neg(Box::new(thunk)),
+            }
+        },
+        Not(thunk) => {
+            let thunk = rewrite(*thunk, scope);
+            match literal(&thunk).and_then(|doc| to_literal(!doc)) {
+                Some(thunk) => thunk,
+                None => Not(Box::new(thunk)),
+            }
+        },
+        Mul(l, r) => fold(bx(l, scope), bx(r, scope), Mul, ops::mul),
+        Div(l, r) => fold(bx(l, scope), bx(r, scope), Div, ops::div),
+        Rem(l, r) => fold(bx(l, scope), bx(r, scope), Rem, ops::rem),
+        Add(l, r) => fold(bx(l, scope), bx(r, scope), Add, ops::add),
+        Sub(l, r) => fold(bx(l, scope), bx(r, scope), Sub, ops::sub),
+        Eq(l, r) => fold(bx(l, scope), bx(r, scope), Eq, ops::eq),
+        Ne(l, r) => fold(bx(l, scope), bx(r, scope), Ne, ops::ne),
+        Lt(l, r) => fold(bx(l, scope), bx(r, scope), Lt, ops::lt),
+        Le(l, r) => fold(bx(l, scope), bx(r, scope), Le, ops::le),
+        Gt(l, r) => fold(bx(l, scope), bx(r, scope), Gt, ops::gt),
+        Ge(l, r) => fold(bx(l, scope), bx(r, scope), Ge, ops::ge),
+        Shr(l, r) => fold(bx(l, scope), bx(r, scope), Shr, ops::shr),
+        Shl(l, r) => fold(bx(l, scope), bx(r, scope), Shl, ops::shl),
+        BitAnd(l, r) => {
+            fold(bx(l, scope), bx(r, scope), BitAnd, ops::bitand)
+        },
+        BitXor(l, r) => {
+            fold(bx(l, scope), bx(r, scope), BitXor, ops::bitxor)
+        },
+        BitOr(l, r) => fold(bx(l, scope), bx(r, scope), BitOr, ops::bitor),
+        And(l, r) => logical(bx(l, scope), bx(r, scope), false),
+        Or(l, r) => logical(bx(l, scope), bx(r, scope), true),
+        Comma(l, r) => Comma(bx(l, scope), bx(r, scope)),
+        Pipe(l, r) => {
+            let mut terms = Vec::new();
+            flatten_pipe(Pipe(l, r), &mut terms);
+            let terms = rewrite_all(terms, scope);
+            pipe(fuse_indexes(terms), scope)
+        },
+        Bind(source, name, body) => {
+            Bind(bx(source, scope), name, bx(body, scope))
+        },
+        If(cond, then, otherwise) => {
+            let cond = rewrite(*cond, scope);
+            match literal(&cond) {
+                Some(doc) if ops::is_truthy(&doc) => rewrite(*then, scope),
+                Some(_) => rewrite(*otherwise, scope),
+                None => {
+                    let then = bx(then, scope);
+                    If(Box::new(cond), then, bx(otherwise, scope))
+                },
+            }
+        },
+        Alternative(l, r) => {
+            let l = rewrite(*l, scope);
+            match literal(&l) {
+                Some(doc) if ops::is_truthy(&doc) => l,
+                Some(_) => rewrite(*r, scope),
+                None => Alternative(Box::new(l), bx(r, scope)),
+            }
+        },
+        Reduce(source, name, init, update) => {
+            let (source, init) = (bx(source, scope), bx(init, scope));
+            Reduce(source, name, init, bx(update, scope))
+        },
+        Foreach(source, name, init, update, extract) => {
+            let (source, init) = (bx(source, scope), bx(init, scope));
+            let (update, extract) = (bx(update, scope), bx(extract, scope));
+            Foreach(source, name, init, update, extract)
+        },
+        Try(body, catch) => {
+            let catch = catch.map(|catch| bx(catch, scope));
+            Try(bx(body, scope), catch)
+        },
+        Def(name, params, body, rest) => {
+            let depth = scope.len();
+            scope.push((name.clone(), params.len()));
+            scope.extend(params.iter().map(|p| (p.clone(), 0)));
+            let body = bx(body, scope);
+            scope.truncate(depth + 1);
+            let rest = bx(rest, scope);
+            scope.truncate(depth);
+            Def(name, params, body, rest)
+        },
+        Update(lhs, rhs, op) => Update(bx(lhs, scope), bx(rhs, scope), op),
+        Format(name, parts) => {
+            let parts = parts.into_iter()
+                .map(|part| match part {
+                    StrPart::Expr(t) => StrPart::Expr(rewrite(t, scope)),
+                    part => part,
+                })
+                .collect();
+            Format(name, parts)
+        },
+        Builtin(name, args) => Builtin(name, rewrite_all(args, scope)),
+        Share(thunk) => Share(bx(thunk, scope)),
+        thunk => thunk,
+    }
+}
+
+fn rewrite_all(thunks: Vec<Thunk>, scope: &mut Scope)
+    -> Vec<Thunk>
+{
+    thunks.into_iter().map(|thunk| rewrite(thunk, scope)).collect()
+}
+
+// literal value of `thunk`, literals never fail so `?` is ignored.
+fn literal(thunk: &Thunk) -> Option<Json> {
+    match thunk {
+        Thunk::Null(_) => Some(Json::Null),
+        Thunk::Bool(val, _) => Some(Json::Bool(*val)),
+        Thunk::Integer(val, _) => Some(Json::Integer(*val)),
+        Thunk::Float(val, _) => Some(Json::Float(*val)),
+        Thunk::String(val, _) => Some(Json::from(val.clone())),
+        _ => None,
+    }
+}
+
+fn to_literal(doc: Json) -> Option<Thunk> {
+    match doc {
+        Json::Null => Some(Thunk::Null(false)),
+        Json::Bool(val) => Some(Thunk::Bool(val, false)),
+        Json::Integer(val) => Some(Thunk::Integer(val, false)),
+        Json::Float(val) => Some(Thunk::Float(val, false)),
+        Json::String(val) => Some(Thunk::String(val, false)),
+        _ => None,
+    }
+}
+
+// binary operator `op` on literal operands is folded into a literal.
+fn fold(
+    lhs: Box<Thunk>, rhs: Box<Thunk>,
+    thunk: fn(Box<Thunk>, Box<Thunk>) -> Thunk,
+    op: fn(Json, Json) -> Json) -> Thunk
+{
+    let doc = match (literal(&lhs), literal(&rhs)) {
+        (Some(l), Some(r)) => to_literal(op(l, r)),
+        _ => None,
+    };
+    doc.unwrap_or_else(|| thunk(lhs, rhs))
+}
+
+// `and`, `or` with a literal lhs that decides the result.
+fn logical(lhs: Box<Thunk>, rhs: Box<Thunk>, or: bool) -> Thunk {
+    let l = literal(&lhs).map(|doc| ops::is_truthy(&doc));
+    let r = literal(&rhs).map(|doc| ops::is_truthy(&doc));
+    match (l, r) {
+        (Some(l), _) if l == or => Thunk::Bool(or, false),
+        (Some(_), Some(r)) => Thunk::Bool(r, false),
+        _ if or => Thunk::Or(lhs, rhs),
+        _ => Thunk::And(lhs, rhs),
+    }
+}
+
+fn flatten_pipe(thunk: Thunk, terms: &mut Vec<Thunk>) {
+    match thunk {
+        Thunk::Pipe(l, r) => {
+            flatten_pipe(*l, terms);
+            flatten_pipe(*r, terms);
+        },
+        thunk => terms.push(thunk),
+    }
+}
+
+// index lookups, `.a`, `.[0]`, without `?`.
+fn index_keys(thunk: &Thunk) -> Option<Vec<(Option<String>, Option<isize>)>> {
+    match thunk {
+        Thunk::IndexShortcut(key, off, false) => {
+            Some(vec![(key.clone(), *off)])
+        },
+        Thunk::Iterate(thunks, false) if thunks.len() == 1 => {
+            index_keys(&thunks[0])
+        },
+        Thunk::IndexPath(keys) => Some(keys.clone()),
+        _ => None,
+    }
+}
+
+fn fuse_indexes(terms: Vec<Thunk>) -> Vec<Thunk> {
+    let mut fused = Vec::new();
+    let mut path = Vec::new();
+    for term in terms.into_iter() {
+        if let Some(keys) = index_keys(&term) {
+            path.push((term, keys));
+            continue
+        }
+        fused.extend(path_thunk(path.drain(..).collect()));
+        fused.push(term);
+    }
+    fused.extend(path_thunk(path));
+    fused
+}
+
+// single lookup is left as is.
+fn path_thunk(mut path: Vec<(Thunk, Vec<(Option<String>, Option<isize>)>)>)
+    -> Option<Thunk>
+{
+    match path.len() {
+        0 => None,
+        1 => Some(path.remove(0).0),
+        _ => {
+            let keys = path.into_iter().flat_map(|(_, keys)| keys).collect();
+            Some(Thunk::IndexPath(keys))
+        },
+    }
+}
+
+// rebuild the pipe, terms that repeat their input share the outputs of
+// the terms before them.
+fn pipe(terms: Vec<Thunk>, scope: &Scope) -> Thunk {
+    let mut terms = terms.into_iter();
+    let mut thunk = terms.next().unwrap();
+    for term in terms {
+        let term = match repeats_input(&term, scope) {
+            true => Thunk::Share(Box::new(term)),
+            false => term,
+        };
+        thunk = Thunk::Pipe(Box::new(thunk), Box::new(term));
+    }
+    thunk
+}
+
+fn repeats_input(thunk: &Thunk, scope: &Scope) -> bool {
+    let defined = |name: &str, n: usize| {
+        scope.iter().any(|(s, m)| s == name && *m == n)
+    };
+    match thunk {
+        Thunk::Iterate(thunks, _) => thunks.len() > 1,
+        Thunk::Identifier(name, _) => {
+            !defined(name, 0) && UNARY_BUILTINS.contains(&name.as_str())
+        },
+        Thunk::Builtin(name, args) => {
+            args.len() > 0 && !defined(name, args.len()) &&
+                !FILTER_BUILTINS.contains(&name.as_str())
+        },
+        _ => false,
+    }
+}
+
+fn do_explain(thunk: &Thunk, depth: usize, out: &mut String) {
+    let opt = if thunk.is_optional() { "?" } else { "" };
+    out.push_str(&format!("{}{}{}\n", "  ".repeat(depth), label(thunk), opt));
+    for child in children(thunk).into_iter() {
+        do_explain(child, depth + 1, out)
+    }
+}
+
+fn label(thunk: &Thunk) -> String {
+    use query::Thunk::*;
+
+    match thunk {
+        Empty => "empty".to_string(),
+        Identity => ".".to_string(),
+        Recurse => "..".to_string(),
+        Null(_) => "null".to_string(),
+        Bool(val, _) => val.to_string(),
+        Integer(val, _) => val.to_string(),
+        Float(val, _) => format!("{:?}", val),
+        String(val, _) => format!("{:?}", val),
+        IndexShortcut(key, off, _) => {
+            format!("index {}", path(&[(key.clone(), *off)]))
+        },
+        Identifier(name, _) => name.clone(),
+        Slice(a, b, _) => format!("slice .[{}:{}]", a, b),
+        IterateValues(_) => ".[]".to_string(),
+        Iterate(_, _) => "iterate".to_string(),
+        List(_, _) => "list".to_string(),
+        Dict(_, _) => "object".to_string(),
+        Neg(_) => "neg".to_string(),
+        Not(_) => "not".to_string(),
+        Mul(_, _) => "mul".to_string(),
+        Div(_, _) => "div".to_string(),
+        Rem(_, _) => "rem".to_string(),
+        Add(_, _) => "add".to_string(),
+        Sub(_, _) => "sub".to_string(),
+        Eq(_, _) => "eq".to_string(),
+        Ne(_, _) => "ne".to_string(),
+        Lt(_, _) => "lt".to_string(),
+        Le(_, _) => "le".to_string(),
+        Gt(_, _) => "gt".to_string(),
+        Ge(_, _) => "ge".to_string(),
+        Shr(_, _) => "shr".to_string(),
+        Shl(_, _) => "shl".to_string(),
+        BitAnd(_, _) => "bitand".to_string(),
+        BitXor(_, _) => "bitxor".to_string(),
+        BitOr(_, _) => "bitor".to_string(),
+        And(_, _) => "and".to_string(),
+        Or(_, _) => "or".to_string(),
+        Comma(_, _) => "comma".to_string(),
+        Pipe(_, _) => "pipe".to_string(),
+        Bind(_, name, _) => format!("bind ${}", name),
+        Var(name) => format!("${}", name),
+        If(_, _, _) => "if".to_string(),
+        Alternative(_, _) => "alternative".to_string(),
+        Reduce(_, name, _, _) => format!("reduce ${}", name),
+        Foreach(_, name, _, _, _) => format!("foreach ${}", name),
+        Try(_, _) => "try".to_string(),
+        Def(name, params, _, _) if params.len() == 0 => format!("def {}", name),
+        Def(name, params, _, _) => {
+            format!("def {}({})", name, params.join("; "))
+        },
+        Update(_, _, op) => format!("update {:?}", op),
+        Format(name, _) => format!("format @{}", name),
+        Builtin(name, args) => format!("{}/{}", name, args.len()),
+        IndexPath(keys) => format!("path {}", path(keys)),
+        Share(_) => "share".to_string(),
+    }
+}
+
+fn path(keys: &[(Option<String>, Option<isize>)]) -> String {
+    keys.iter()
+        .map(|key| match key {
+            (Some(key), _) => format!(".{}", key),
+            (None, Some(off)) => format!(".[{}]", off),
+            (None, None) => unreachable!(),
+        })
+        .collect()
+}
+
+fn children(thunk: &Thunk) -> Vec<&Thunk> {
+    use query::Thunk::*;
+
+    match thunk {
+        Iterate(thunks, _) | List(thunks, _) | Builtin(_, thunks) => {
+            thunks.iter().collect()
+        },
+        Dict(props, _) => props.iter().flat_map(|(k, v)| vec![k, v]).collect(),
+        Neg(thunk) | Not(thunk) | Share(thunk) => vec![thunk],
+        Mul(l, r) | Div(l, r) | Rem(l, r) | Add(l, r) | Sub(l, r) |
+        Eq(l, r) | Ne(l, r) | Lt(l, r) | Le(l, r) | Gt(l, r) | Ge(l, r) |
+        Shr(l, r) | Shl(l, r) | BitAnd(l, r) | BitXor(l, r) | BitOr(l, r) |
+        And(l, r) | Or(l, r) | Comma(l, r) | Pipe(l, r) | Bind(l, _, r) |
+        Alternative(l, r) | Def(_, _, l, r) | Update(l, r, _) => vec![l, r],
+        If(cond, then, otherwise) => vec![cond, then, otherwise],
+        Reduce(source, _, init, update) => vec![source, init, update],
+        Foreach(source, _, init, update, extract) => {
+            vec![source, init, update, extract]
+        },
+        Try(body, Some(catch)) => vec![body, catch],
+        Try(body, None) => vec![body],
+        Format(_, parts) => parts.iter()
+            .filter_map(|part| match part {
+                StrPart::Expr(thunk) => Some(thunk),
+                StrPart::Lit(_) => None,
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use query::Expr;
+
+    fn explain(program: &str) -> String {
+        program.parse::<Expr>().unwrap().explain()
+    }
+
+    #[test]
+    fn test_explain() {
+        let refs = "pipe\n  path .a.b.[0]\n  7\n";
+        assert_eq!(refs, explain(".a.b.[0] | 1 + 2 * 3"));
+        let refs = "pipe\n  pipe\n    index .a\n    index .b?\n  path .c.d\n";
+        assert_eq!(refs, explain(".a | .b? | .c.d"));
+        assert_eq!("index .a\n", explain("if true then .a else .b end"));
+        assert_eq!("index .x\n", explain("null // .x"));
+        assert_eq!("\"ab\"\n", explain(r#"if 1 < 2 then "a" + "b" end"#));
+        assert_eq!("format @text\n  3\n", explain(r#""x\(1 + 2)""#));
+
+        let refs = "pipe\n  index .a\n  share\n    has/1\n      \"b\"\n";
+        assert_eq!(refs, explain(r#".a | has("b")"#));
+        let refs = "pipe\n  .[]\n  share\n    iterate\n      index .[0]\n\
+            \x20     index .[1]\n";
+        assert_eq!(refs, explain(".[] | .[0, 1]"));
+        let refs = "comma\n  comma\n    index .a\n    1\n  list\n    1\n\
+            \x20   2\n";
+        assert_eq!(refs, explain(".a, 1, [1, 2]"));
+        // user definitions do not repeat the input.
+        let refs = "def length\n  1\n  pipe\n    index .a\n    length\n";
+        assert_eq!(refs, explain("def length: 1; .a | length"));
+        assert!(!explain(".a | map(.b)").contains("share"));
+    }
+}
@@ -0,0 +1,907 @@
+// JSON Schema validation, refer https://json-schema.org/draft/2020-12.
+//
+// Supports core, applicator and validation vocabularies. `$ref` and
+// `$dynamicRef` are resolved only within the same schema document, either
+// as JSON Pointer fragment `#/$defs/name` or as plain-name fragment
+// `#name` declared via `$anchor`. Annotation only keywords, like `format`
+// and `title`, are ignored, and so are `unevaluatedItems` and
+// `unevaluatedProperties`.
+//
+// Validation reports all violations, each located by JSON Pointer into
+// the document and into the schema.
+
+use std::{result, error, fmt};
+use std::collections::HashMap;
+
+use regex::Regex;
+
+use db::{Document, Doctype, Input, Pipeline, Repeater};
+use entry::Entry;
+
+
+pub type Result<T> = result::Result<T,Error>;
+
+// guard against `$ref` cycles that do not descend into the document.
+const MAX_DEPTH: usize = 256;
+
+const TYPES: [&str; 7] = [
+    "null", "boolean", "object", "array", "number", "string", "integer",
+];
+
+#[derive(Debug,Eq,PartialEq)]
+pub enum Error {
+    InvalidSchema(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Error::InvalidSchema;
+
+        match self {
+            InvalidSchema(s) => write!(f, "InvalidSchema:{}", s),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+
+/// A single failed assertion.
+#[derive(Debug,Clone,Eq,PartialEq)]
+pub struct Violation {
+    /// JSON Pointer to the offending value within the document.
+    pub instance: String,
+    /// JSON Pointer to the failed keyword within the schema.
+    pub keyword: String,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} {} ({})", self.instance, self.message, self.keyword)
+    }
+}
+
+
+pub struct Schema<D> where D: Document {
+    root: D,
+    anchors: HashMap<String, String>, // anchor name -> JSON Pointer
+    regexes: HashMap<String, Regex>,
+}
+
+impl<D> Schema<D> where D: Document {
+    pub fn new(root: D) -> Result<Schema<D>> {
+        match root.doctype() {
+            Doctype::Bool | Doctype::Object => (),
+            t => {
+                let err = format!("schema must be object or boolean, not {:?}", t);
+                return Err(Error::InvalidSchema(err))
+            },
+        }
+        let mut schema = Schema{
+            root, anchors: HashMap::new(), regexes: HashMap::new(),
+        };
+        let mut refs = vec![];
+        {
+            let (root, anchors, regexes) =
+                (&schema.root, &mut schema.anchors, &mut schema.regexes);
+            scan(root, "", anchors, regexes, &mut refs)?;
+        }
+        for (ptr, r) in refs.into_iter() {
+            if schema.resolve(&r).is_none() {
+                let err = format!("unresolved $ref {:?} at {:?}", r, ptr);
+                return Err(Error::InvalidSchema(err))
+            }
+        }
+        Ok(schema)
+    }
+
+    /// Validate `doc` and return all the violations, empty if valid.
+    pub fn validate(&self, doc: &D) -> Vec<Violation> {
+        let mut errs = vec![];
+        self.check(&self.root, doc, "", "", 0, &mut errs);
+        errs
+    }
+
+    pub fn is_valid(&self, doc: &D) -> bool {
+        self.validate(doc).len() == 0
+    }
+
+    /// Append a validation stage to the pipeline. Entries that fail
+    /// validation are passed downstream with all the violations
+    /// recorded via `Entry::set_error`.
+    pub fn prepare<'a>(&'a self, input: Input<'a,D>) -> Input<'a,D> {
+        Box::new(Validate::new(input, self))
+    }
+
+    fn resolve(&self, r: &str) -> Option<&D> {
+        if !r.starts_with('#') { return None }
+        let fragment = percent_decode(&r[1..])?;
+        if fragment.len() == 0 || fragment.starts_with('/') {
+            pointer_get(&self.root, &fragment)
+        } else {
+            pointer_get(&self.root, self.anchors.get(&fragment)?)
+        }
+    }
+
+    fn target(&self, r: &str) -> Result<&D> {
+        match self.resolve(r) {
+            Some(target) => Ok(target),
+            None => {
+                let err = format!("unresolved $ref {:?}", r);
+                Err(Error::InvalidSchema(err))
+            },
+        }
+    }
+
+    fn regex(&self, pattern: &str) -> Result<&Regex> {
+        match self.regexes.get(pattern) {
+            Some(re) => Ok(re),
+            None => {
+                let err = format!("uncompiled pattern {:?}", pattern);
+                Err(Error::InvalidSchema(err))
+            },
+        }
+    }
+
+    fn matches(&self, s: &D, doc: &D, depth: usize) -> bool {
+        let mut errs = vec![];
+        self.check(s, doc, "", "", depth, &mut errs);
+        errs.len() == 0
+    }
+
+    fn check(
+        &self, s: &D, doc: &D, ip: &str, kp: &str, depth: usize,
+        errs: &mut Vec<Violation>)
+    {
+        let push = |errs: &mut Vec<Violation>, kw: &str, message: String| {
+            let keyword = format!("{}/{}", kp, kw);
+            errs.push(Violation{ instance: ip.to_string(), keyword, message });
+        };
+
+        match s.doctype() {
+            Doctype::Bool if s.clone().boolean().unwrap() => return,
+            Doctype::Bool => {
+                let message = "value is not allowed by false schema".to_string();
+                let keyword = kp.to_string();
+                errs.push(Violation{ instance: ip.to_string(), keyword, message });
+                return
+            },
+            Doctype::Object => (),
+            _ => return,
+        }
+        if depth > MAX_DEPTH {
+            push(errs, "$ref", "schema nesting too deep".to_string());
+            return
+        }
+
+        for kw in ["$ref", "$dynamicRef"].iter() {
+            if let Some(target) = s.get_ref(kw).and_then(|r| r.string_ref()) {
+                match self.target(target) {
+                    Ok(target) => {
+                        let kp = format!("{}/{}", kp, kw);
+                        self.check(target, doc, ip, &kp, depth + 1, errs);
+                    },
+                    Err(err) => push(errs, kw, err.to_string()),
+                }
+            }
+        }
+
+        // any type
+
+        if let Some(types) = s.get_ref("type") {
+            let types: Vec<&String> = match types.array_ref() {
+                Some(types) => types.iter().filter_map(|t| t.string_ref()).collect(),
+                None => types.string_ref().into_iter().collect(),
+            };
+            if !types.iter().any(|t| is_type(t, doc)) {
+                let types: Vec<&str> = types.iter().map(|t| t.as_str()).collect();
+                let message = format!(
+                    "expected {}, found {}", types.join(" or "),
+                    doc.doctype().schema_name()
+                );
+                push(errs, "type", message);
+            }
+        }
+        if let Some(values) = s.get_ref("enum").and_then(|v| v.array_ref()) {
+            if !values.iter().any(|value| json_eq(value, doc)) {
+                let message = "value is not one of the enumerated values";
+                push(errs, "enum", message.to_string());
+            }
+        }
+        if let Some(value) = s.get_ref("const") {
+            if !json_eq(value, doc) {
+                push(errs, "const", "value does not match const".to_string());
+            }
+        }
+
+        // applicators for any type
+
+        if let Some(subs) = s.get_ref("allOf").and_then(|v| v.array_ref()) {
+            for (i, sub) in subs.iter().enumerate() {
+                let kp = format!("{}/allOf/{}", kp, i);
+                self.check(sub, doc, ip, &kp, depth + 1, errs);
+            }
+        }
+        if let Some(subs) = s.get_ref("anyOf").and_then(|v| v.array_ref()) {
+            if !subs.iter().any(|sub| self.matches(sub, doc, depth + 1)) {
+                let message = "value does not match any schema in anyOf";
+                push(errs, "anyOf", message.to_string());
+            }
+        }
+        if let Some(subs) = s.get_ref("oneOf").and_then(|v| v.array_ref()) {
+            let n = subs.iter().filter(|sub| self.matches(sub, doc, depth + 1)).count();
+            if n != 1 {
+                let message = format!(
+                    "value matches {} schemas in oneOf, expected exactly one", n
+                );
+                push(errs, "oneOf", message);
+            }
+        }
+        if let Some(sub) = s.get_ref("not") {
+            if self.matches(sub, doc, depth + 1) {
+                push(errs, "not", "value must not match schema in not".to_string());
+            }
+        }
+        if let Some(sub) = s.get_ref("if") {
+            let (kw, next) = if self.matches(sub, doc, depth + 1) {
+                ("then", s.get_ref("then"))
+            } else {
+                ("else", s.get_ref("else"))
+            };
+            if let Some(next) = next {
+                let kp = format!("{}/{}", kp, kw);
+                self.check(next, doc, ip, &kp, depth + 1, errs);
+            }
+        }
+
+        match doc.doctype() {
+            Doctype::Integer | Doctype::Float => {
+                self.check_number(s, doc, &push, errs)
+            },
+            Doctype::String => {
+                let text = doc.string_ref().unwrap();
+                let n = text.chars().count();
+                if let Some(max) = usize_kw(s, "maxLength") {
+                    if n > max {
+                        let message = format!("string is longer than {}", max);
+                        push(errs, "maxLength", message);
+                    }
+                }
+                if let Some(min) = usize_kw(s, "minLength") {
+                    if n < min {
+                        let message = format!("string is shorter than {}", min);
+                        push(errs, "minLength", message);
+                    }
+                }
+                let pattern = s.get_ref("pattern").and_then(|v| v.string_ref());
+                if let Some(pattern) = pattern {
+                    match self.regex(pattern) {
+                        Ok(re) if !re.is_match(text) => {
+                            let message = format!("string does not match {:?}", pattern);
+                            push(errs, "pattern", message);
+                        },
+                        Ok(_) => (),
+                        Err(err) => push(errs, "pattern", err.to_string()),
+                    }
+                }
+            },
+            Doctype::Array => {
+                self.check_array(s, doc, ip, kp, depth, &push, errs)
+            },
+            Doctype::Object => {
+                self.check_object(s, doc, ip, kp, depth, &push, errs)
+            },
+            _ => (),
+        }
+    }
+
+    fn check_number<F>(&self, s: &D, doc: &D, push: &F, errs: &mut Vec<Violation>)
+        where F: Fn(&mut Vec<Violation>, &str, String)
+    {
+        let x = number(doc).unwrap();
+        if let Some(m) = s.get_ref("multipleOf") {
+            let ok = match (doc.clone().integer(), m.clone().integer()) {
+                (Some(x), Some(m)) if m != 0 => {
+                    x.checked_rem(m).map_or(true, |r| r == 0)
+                },
+                _ => match number(m) {
+                    Some(m) => {
+                        let q = x / m;
+                        q.is_finite() && (q - q.round()).abs() < 1e-9
+                    },
+                    None => true,
+                },
+            };
+            if !ok {
+                push(errs, "multipleOf", format!("value is not a multiple of {:?}", m));
+            }
+        }
+        let bounds: [(&str, fn(f64, f64) -> bool, &str); 4] = [
+            ("maximum", |x, y| x <= y, "greater than"),
+            ("exclusiveMaximum", |x, y| x < y, "greater than or equal to"),
+            ("minimum", |x, y| x >= y, "less than"),
+            ("exclusiveMinimum", |x, y| x > y, "less than or equal to"),
+        ];
+        for (kw, ok, what) in bounds.iter() {
+            if let Some(bound) = s.get_ref(kw) {
+                match number(bound) {
+                    Some(y) if !ok(x, y) => {
+                        let message = format!("value is {} {:?}", what, bound);
+                        push(errs, kw, message);
+                    },
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    fn check_array<F>(
+        &self, s: &D, doc: &D, ip: &str, kp: &str, depth: usize, push: &F,
+        errs: &mut Vec<Violation>)
+        where F: Fn(&mut Vec<Violation>, &str, String)
+    {
+        let items = doc.array_ref().unwrap();
+        if let Some(max) = usize_kw(s, "maxItems") {
+            if items.len() > max {
+                push(errs, "maxItems", format!("array has more than {} items", max));
+            }
+        }
+        if let Some(min) = usize_kw(s, "minItems") {
+            if items.len() < min {
+                push(errs, "minItems", format!("array has fewer than {} items", min));
+            }
+        }
+        if let Some(true) = s.get_ref("uniqueItems").and_then(|v| v.clone().boolean()) {
+            'outer: for i in 0..items.len() {
+                for j in (i+1)..items.len() {
+                    if json_eq(&items[i], &items[j]) {
+                        let message = format!("items at {} and {} are equal", i, j);
+                        push(errs, "uniqueItems", message);
+                        break 'outer
+                    }
+                }
+            }
+        }
+
+        let mut n = 0;
+        if let Some(prefix) = s.get_ref("prefixItems").and_then(|v| v.array_ref()) {
+            for (i, (sub, item)) in prefix.iter().zip(items.iter()).enumerate() {
+                let ip = pointer_push(ip, &i.to_string());
+                let kp = format!("{}/prefixItems/{}", kp, i);
+                self.check(sub, item, &ip, &kp, depth + 1, errs);
+            }
+            n = prefix.len();
+        }
+        if let Some(sub) = s.get_ref("items") {
+            let kp = format!("{}/items", kp);
+            for (i, item) in items.iter().enumerate().skip(n) {
+                let ip = pointer_push(ip, &i.to_string());
+                self.check(sub, item, &ip, &kp, depth + 1, errs);
+            }
+        }
+        if let Some(sub) = s.get_ref("contains") {
+            let n = items.iter()
+                .filter(|item| self.matches(sub, item, depth + 1))
+                .count();
+            let min = usize_kw(s, "minContains").unwrap_or(1);
+            if n < min {
+                let message = format!(
+                    "expected at least {} items to match contains", min
+                );
+                push(errs, "minContains", message);
+            }
+            if let Some(max) = usize_kw(s, "maxContains") {
+                if n > max {
+                    let message = format!(
+                        "expected at most {} items to match contains", max
+                    );
+                    push(errs, "maxContains", message);
+                }
+            }
+        }
+    }
+
+    fn check_object<F>(
+        &self, s: &D, doc: &D, ip: &str, kp: &str, depth: usize, push: &F,
+        errs: &mut Vec<Violation>)
+        where F: Fn(&mut Vec<Violation>, &str, String)
+    {
+        let props = doc.object_ref().unwrap();
+        if let Some(max) = usize_kw(s, "maxProperties") {
+            if props.len() > max {
+                let message = format!("object has more than {} properties", max);
+                push(errs, "maxProperties", message);
+            }
+        }
+        if let Some(min) = usize_kw(s, "minProperties") {
+            if props.len() < min {
+                let message = format!("object has fewer than {} properties", min);
+                push(errs, "minProperties", message);
+            }
+        }
+        if let Some(required) = s.get_ref("required").and_then(|v| v.array_ref()) {
+            for key in required.iter().filter_map(|key| key.string_ref()) {
+                if doc.get_ref(key).is_none() {
+                    let message = format!("missing required property {:?}", key);
+                    push(errs, "required", message);
+                }
+            }
+        }
+        let deps = s.get_ref("dependentRequired").and_then(|v| v.object_ref());
+        if let Some(deps) = deps {
+            for dep in deps.iter().filter(|dep| doc.get_ref(dep.key_ref()).is_some()) {
+                let keys = dep.value_ref().array_ref().into_iter().flat_map(|ks| ks.iter());
+                for key in keys.filter_map(|key| key.string_ref()) {
+                    if doc.get_ref(key).is_none() {
+                        let message = format!(
+                            "missing property {:?} required by {:?}", key, dep.key_ref()
+                        );
+                        push(errs, "dependentRequired", message);
+                    }
+                }
+            }
+        }
+        if let Some(deps) = s.get_ref("dependentSchemas").and_then(|v| v.object_ref()) {
+            for dep in deps.iter().filter(|dep| doc.get_ref(dep.key_ref()).is_some()) {
+                let key = pointer_escape(dep.key_ref());
+                let kp = format!("{}/dependentSchemas/{}", kp, key);
+                self.check(dep.value_ref(), doc, ip, &kp, depth + 1, errs);
+            }
+        }
+
+        let patterns = s.get_ref("patternProperties").and_then(|v| v.object_ref());
+        let additional = s.get_ref("additionalProperties");
+        for prop in props.iter() {
+            let (key, value) = (prop.key_ref(), prop.value_ref());
+            let cip = pointer_push(ip, key);
+            let mut evaluated = false;
+            if let Some(sub) = s.get_ref("properties").and_then(|v| v.get_ref(key)) {
+                let kp = format!("{}/properties/{}", kp, pointer_escape(key));
+                self.check(sub, value, &cip, &kp, depth + 1, errs);
+                evaluated = true;
+            }
+            for pattern in patterns.iter().flat_map(|ps| ps.iter()) {
+                match self.regex(pattern.key_ref()) {
+                    Ok(re) if re.is_match(key) => {
+                        let kp = format!(
+                            "{}/patternProperties/{}", kp, pointer_escape(pattern.key_ref())
+                        );
+                        self.check(pattern.value_ref(), value, &cip, &kp, depth + 1, errs);
+                        evaluated = true;
+                    },
+                    Ok(_) => (),
+                    Err(err) => push(errs, "patternProperties", err.to_string()),
+                }
+            }
+            match additional {
+                Some(_) if evaluated => (),
+                Some(sub) if sub.clone().boolean() == Some(false) => {
+                    let message = format!(
+                        "additional property {:?} is not allowed", key
+                    );
+                    push(errs, "additionalProperties", message);
+                },
+                Some(sub) => {
+                    let kp = format!("{}/additionalProperties", kp);
+                    self.check(sub, value, &cip, &kp, depth + 1, errs);
+                },
+                None => (),
+            }
+            if let Some(sub) = s.get_ref("propertyNames") {
+                if !self.matches(sub, &From::from(key.clone()), depth + 1) {
+                    let message = format!("property name {:?} is not valid", key);
+                    push(errs, "propertyNames", message);
+                }
+            }
+        }
+    }
+}
+
+
+pub struct Validate<'a,D> where D: 'a + Document {
+    input: Input<'a,D>,
+    schema: &'a Schema<D>,
+}
+
+impl<'a,D> Validate<'a,D> where D: Document {
+    pub fn new(input: Input<'a,D>, schema: &'a Schema<D>) -> Validate<'a,D> {
+        Validate{input, schema}
+    }
+}
+
+impl<'a,D> Repeater<'a,D> for Validate<'a,D> where D: 'a + Document {
+    fn repeat(&self) -> Input<'a,D> {
+        Box::new(Validate{input: self.input.repeat(), schema: self.schema})
+    }
+}
+
+impl<'a,D> Iterator for Validate<'a,D> where D: Document {
+    type Item=Entry<D>;
+
+    fn next(&mut self) -> Option<Entry<D>> {
+        let mut d_entry = self.input.next()?;
+        if d_entry.has_error() { return Some(d_entry) }
+
+        let errs = self.schema.validate(&d_entry.doc);
+        if errs.len() > 0 {
+            let errs: Vec<String> = errs.iter().map(|e| e.to_string()).collect();
+            d_entry.set_error(format!("schema violation: {}", errs.join("; ")));
+        }
+        Some(d_entry)
+    }
+}
+
+impl<'a,D> Pipeline<'a,D> for Validate<'a,D> where D: 'a + Document {
+}
+
+
+// walk the schema document to gather anchors, compile regular expressions
+// and collect `$ref`s for resolution. Keys of `properties`, `$defs`,
+// `patternProperties` and `dependentSchemas` name sub-schemas and are not
+// taken as keywords.
+fn scan<D>(
+    s: &D, ptr: &str, anchors: &mut HashMap<String, String>,
+    regexes: &mut HashMap<String, Regex>, refs: &mut Vec<(String, String)>)
+    -> Result<()> where D: Document
+{
+    let compile = |regexes: &mut HashMap<String, Regex>, pattern: &str| {
+        if regexes.contains_key(pattern) { return Ok(()) }
+        match Regex::new(pattern) {
+            Ok(re) => { regexes.insert(pattern.to_string(), re); Ok(()) },
+            Err(err) => {
+                let err = format!("invalid pattern {:?}: {}", pattern, err);
+                Err(Error::InvalidSchema(err))
+            },
+        }
+    };
+
+    match s.doctype() {
+        Doctype::Object => {
+            for prop in s.object_ref().unwrap().iter() {
+                let (key, value) = (prop.key_ref().as_str(), prop.value_ref());
+                let cptr = pointer_push(ptr, key);
+                check_keyword(key, value, &cptr)?;
+                match (key, value.doctype()) {
+                    ("enum", _) | ("const", _) | ("default", _) |
+                    ("examples", _) => continue,
+                    ("$ref", Doctype::String) | ("$dynamicRef", Doctype::String) => {
+                        let r = value.string_ref().unwrap().clone();
+                        refs.push((cptr.clone(), r));
+                    },
+                    ("$anchor", Doctype::String) |
+                    ("$dynamicAnchor", Doctype::String) => {
+                        let name = value.string_ref().unwrap().clone();
+                        anchors.insert(name, ptr.to_string());
+                    },
+                    ("pattern", Doctype::String) => {
+                        compile(regexes, value.string_ref().unwrap())?;
+                    },
+                    ("properties", Doctype::Object) | ("$defs", Doctype::Object) |
+                    ("patternProperties", Doctype::Object) |
+                    ("dependentSchemas", Doctype::Object) => {
+                        for p in value.object_ref().unwrap().iter() {
+                            if key == "patternProperties" {
+                                compile(regexes, p.key_ref())?;
+                            }
+                            let pptr = pointer_push(&cptr, p.key_ref());
+                            scan(p.value_ref(), &pptr, anchors, regexes, refs)?;
+                        }
+                        continue
+                    },
+                    _ => (),
+                }
+                scan(value, &cptr, anchors, regexes, refs)?;
+            }
+        },
+        Doctype::Array => {
+            for (i, value) in s.array_ref().unwrap().iter().enumerate() {
+                let cptr = pointer_push(ptr, &i.to_string());
+                scan(value, &cptr, anchors, regexes, refs)?;
+            }
+        },
+        _ => (),
+    }
+    Ok(())
+}
+
+// check the shape of keyword values that validation relies on.
+fn check_keyword<D>(key: &str, value: &D, ptr: &str) -> Result<()>
+    where D: Document
+{
+    let is_type = |t: &D| t.string_ref().map_or(false, |t| TYPES.contains(&t.as_str()));
+    let ok = match key {
+        "type" => match value.array_ref() {
+            Some(types) => types.iter().all(is_type),
+            None => is_type(value),
+        },
+        "dependentRequired" => value.object_ref().map_or(false, |deps| {
+            deps.iter().all(|dep| match dep.value_ref().array_ref() {
+                Some(keys) => keys.iter().all(|k| k.doctype() == Doctype::String),
+                None => false,
+            })
+        }),
+        _ => true,
+    };
+    if !ok {
+        let err = format!("invalid value for {:?} at {:?}", key, ptr);
+        return Err(Error::InvalidSchema(err))
+    }
+    Ok(())
+}
+
+fn pointer_escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+fn pointer_push(ptr: &str, key: &str) -> String {
+    format!("{}/{}", ptr, pointer_escape(key))
+}
+
+fn pointer_get<'a, D>(doc: &'a D, ptr: &str) -> Option<&'a D> where D: Document {
+    if ptr.len() == 0 { return Some(doc) }
+    if !ptr.starts_with('/') { return None }
+
+    let mut doc = doc;
+    for token in ptr[1..].split('/') {
+        let token = token.replace("~1", "/").replace("~0", "~");
+        doc = match doc.doctype() {
+            Doctype::Object => doc.get_ref(&token)?,
+            Doctype::Array => doc.index_ref(token.parse::<isize>().ok()?)?,
+            _ => return None,
+        };
+    }
+    Some(doc)
+}
+
+fn percent_decode(text: &str) -> Option<String> {
+    let (bs, mut out) = (text.as_bytes(), Vec::with_capacity(text.len()));
+    let mut i = 0;
+    while i < bs.len() {
+        if bs[i] == b'%' {
+            let hex = text.get(i+1..i+3)?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bs[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+fn number<D>(doc: &D) -> Option<f64> where D: Document {
+    match doc.doctype() {
+        Doctype::Integer => Some(doc.clone().integer().unwrap() as f64),
+        Doctype::Float => doc.clone().float(),
+        _ => None,
+    }
+}
+
+fn usize_kw<D>(s: &D, kw: &str) -> Option<usize> where D: Document {
+    let n = s.get_ref(kw).and_then(number)?;
+    if n >= 0.0 { Some(n as usize) } else { None }
+}
+
+fn is_type<D>(name: &str, doc: &D) -> bool where D: Document {
+    match (name, doc.doctype()) {
+        ("number", Doctype::Integer) => true,
+        ("integer", Doctype::Float) => {
+            let f = doc.clone().float().unwrap();
+            f.is_finite() && f.fract() == 0.0
+        },
+        (name, doctype) => name == doctype.schema_name(),
+    }
+}
+
+// equality as per JSON data model, where 1 and 1.0 are equal.
+fn json_eq<D>(a: &D, b: &D) -> bool where D: Document {
+    match (a.doctype(), b.doctype()) {
+        (Doctype::Integer, Doctype::Float) | (Doctype::Float, Doctype::Integer) => {
+            number(a) == number(b)
+        },
+        (Doctype::Array, Doctype::Array) => {
+            let (a, b) = (a.array_ref().unwrap(), b.array_ref().unwrap());
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(x, y)| json_eq(x, y))
+        },
+        (Doctype::Object, Doctype::Object) => {
+            let (a, b) = (a.object_ref().unwrap(), b.object_ref().unwrap());
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(x, y)| {
+                x.key_ref() == y.key_ref() && json_eq(x.value_ref(), y.value_ref())
+            })
+        },
+        _ => a == b,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use json::Json;
+    use input_mem::InputMem;
+
+    fn schema(text: &str) -> Schema<Json> {
+        Schema::new(text.parse().unwrap()).unwrap()
+    }
+
+    fn violations(schema: &Schema<Json>, text: &str) -> Vec<(String, String)> {
+        schema.validate(&text.parse().unwrap()).into_iter()
+            .map(|v| (v.instance, v.keyword))
+            .collect()
+    }
+
+    #[test]
+    fn test_schema_validation() {
+        let s = schema(r#"{
+            "type": "object",
+            "required": ["id", "name"],
+            "properties": {
+                "id": {"type": "integer", "minimum": 1},
+                "name": {"type": "string", "minLength": 2, "pattern": "^[a-z]+$"},
+                "tags": {
+                    "type": "array", "items": {"type": "string"},
+                    "uniqueItems": true, "maxItems": 3
+                },
+                "kind": {"enum": ["a", "b", 1.0]}
+            },
+            "additionalProperties": false
+        }"#);
+
+        assert!(s.is_valid(&r#"{"id": 1, "name": "ab", "tags": ["x"], "kind": 1}"#.parse().unwrap()));
+        assert!(s.is_valid(&r#"{"id": 2.0, "name": "abc"}"#.parse().unwrap()));
+
+        let errs = violations(&s, r#"{"id": 0, "tags": ["x", 1, "x"], "extra": null}"#);
+        let refs = vec![
+            ("".to_string(), "/required".to_string()),
+            ("".to_string(), "/additionalProperties".to_string()),
+            ("/id".to_string(), "/properties/id/minimum".to_string()),
+            ("/tags".to_string(), "/properties/tags/uniqueItems".to_string()),
+            ("/tags/1".to_string(), "/properties/tags/items/type".to_string()),
+        ];
+        assert_eq!(errs, refs);
+
+        let errs = s.validate(&r#"{"id": 1, "name": "A1"}"#.parse().unwrap());
+        assert_eq!(errs.len(), 1);
+        assert_eq!(
+            errs[0].to_string(),
+            r#""/name" string does not match "^[a-z]+$" (/properties/name/pattern)"#
+        );
+
+        let errs = s.validate(&"[]".parse().unwrap());
+        assert_eq!(errs[0].message, "expected object, found array");
+    }
+
+    #[test]
+    fn test_schema_applicators() {
+        let s = schema(r#"{
+            "anyOf": [{"type": "string"}, {"type": "number"}],
+            "oneOf": [{"multipleOf": 3}, {"multipleOf": 5}],
+            "not": {"const": 30},
+            "if": {"type": "number", "exclusiveMaximum": 10},
+            "then": {"minimum": 5},
+            "else": {"maximum": 100}
+        }"#);
+        assert!(s.is_valid(&"9".parse().unwrap()));
+        assert!(s.is_valid(&"25".parse().unwrap()));
+        assert_eq!(violations(&s, "15"), vec![("".to_string(), "/oneOf".to_string())]);
+        assert_eq!(violations(&s, "30.0").len(), 2); // oneOf and not
+        assert_eq!(violations(&s, "3"), vec![("".to_string(), "/then/minimum".to_string())]);
+        assert_eq!(violations(&s, "110"), vec![("".to_string(), "/else/maximum".to_string())]);
+        assert_eq!(violations(&s, "null").len(), 2); // anyOf, and oneOf as both match
+
+        let s = schema(r#"{
+            "prefixItems": [{"type": "integer"}, {"type": "string"}],
+            "items": {"type": "boolean"},
+            "contains": {"const": true}, "maxContains": 1
+        }"#);
+        assert!(s.is_valid(&r#"[1, "a", true, false]"#.parse().unwrap()));
+        let errs = violations(&s, r#"["1", "a", true, true]"#);
+        let refs = vec![
+            ("/0".to_string(), "/prefixItems/0/type".to_string()),
+            ("".to_string(), "/maxContains".to_string()),
+        ];
+        assert_eq!(errs, refs);
+
+        let s = schema(r#"{
+            "patternProperties": {"^x-": {"type": "string"}},
+            "propertyNames": {"maxLength": 4},
+            "dependentRequired": {"a": ["b"]},
+            "dependentSchemas": {"b": {"minProperties": 3}}
+        }"#);
+        assert!(s.is_valid(&r#"{"x-a": "s", "c": 1}"#.parse().unwrap()));
+        let errs = violations(&s, r#"{"x-ab": 1, "a": 1, "toolong": 2}"#);
+        let refs = vec![
+            ("".to_string(), "/dependentRequired".to_string()),
+            ("".to_string(), "/propertyNames".to_string()),
+            ("/x-ab".to_string(), "/patternProperties/^x-/type".to_string()),
+        ];
+        assert_eq!(errs, refs);
+    }
+
+    #[test]
+    fn test_schema_ref() {
+        let s = schema(r##"{
+            "$defs": {
+                "node": {
+                    "$anchor": "node",
+                    "type": "object",
+                    "properties": {
+                        "value": {"$ref": "#/$defs/positive"},
+                        "children": {"type": "array", "items": {"$ref": "#node"}}
+                    }
+                },
+                "positive": {"type": "integer", "exclusiveMinimum": 0},
+                "a~b/c%d": {"type": "null"}
+            },
+            "properties": {
+                "root": {"$ref": "#/$defs/node"},
+                "odd": {"$ref": "#/$defs/a~0b~1c%25d"}
+            }
+        }"##);
+        let text = r##"{"root": {"value": 1, "children": [{"value": 2}, {"value": 0}]}, "odd": 1}"##;
+        let errs = violations(&s, text);
+        let refs = vec![
+            ("/odd".to_string(), "/properties/odd/$ref/type".to_string()),
+            (
+                "/root/children/1/value".to_string(),
+                "/properties/root/$ref/properties/children/items/$ref/properties/value/$ref/exclusiveMinimum".to_string()
+            ),
+        ];
+        assert_eq!(errs, refs);
+
+        let s: Result<Schema<Json>> = Schema::new(r##"{"$ref": "#/missing"}"##.parse().unwrap());
+        let err = r##"unresolved $ref "#/missing" at "/$ref""##.to_string();
+        assert_eq!(s.err(), Some(Error::InvalidSchema(err)));
+
+        let s: Result<Schema<Json>> = Schema::new(r##"{"pattern": "("}"##.parse().unwrap());
+        assert!(s.is_err());
+        assert!(Schema::new(Json::Integer(1)).is_err());
+
+        let s = schema("false");
+        assert_eq!(violations(&s, "1"), vec![("".to_string(), "".to_string())]);
+    }
+
+    #[test]
+    fn test_schema_keywords() {
+        // keyword names used as property names.
+        let s = schema(r#"{"properties": {"const": {"pattern": "^a"}}}"#);
+        assert!(s.is_valid(&r#"{"const": "ab"}"#.parse().unwrap()));
+        let errs = violations(&s, r#"{"const": "b"}"#);
+        let refs = vec![
+            ("/const".to_string(), "/properties/const/pattern".to_string()),
+        ];
+        assert_eq!(errs, refs);
+
+        let text = r##"{"properties": {"default": {"$ref": "#/missing"}}}"##;
+        let s: Result<Schema<Json>> = Schema::new(text.parse().unwrap());
+        let err = r##"unresolved $ref "#/missing" at "/properties/default/$ref""##;
+        assert_eq!(s.err(), Some(Error::InvalidSchema(err.to_string())));
+
+        for text in [r#"{"type": 1}"#, r#"{"type": ["string", 1]}"#,
+                     r#"{"type": "text"}"#, r#"{"dependentRequired": {"a": "b"}}"#,
+                     r#"{"dependentRequired": [1]}"#].iter() {
+            let s: Result<Schema<Json>> = Schema::new(text.parse().unwrap());
+            assert!(s.is_err(), "{}", text);
+        }
+
+        let s = schema(r#"{"multipleOf": -1}"#);
+        assert!(s.is_valid(&Json::Integer(i128::min_value())));
+    }
+
+    #[test]
+    fn test_schema_pipeline() {
+        let s = schema(r#"{"type": "object", "required": ["a"]}"#);
+        let mem: InputMem<Json> = vec!["{\"a\": 1}", "{}", "1"].into_iter()
+            .map(|text| text.parse().unwrap())
+            .collect();
+        let entries: Vec<Entry<Json>> = s.prepare(mem.repeat()).collect();
+        assert_eq!(entries.len(), 3);
+        let errors: Vec<bool> = entries.iter().map(|e| e.has_error()).collect();
+        assert_eq!(errors, vec![false, true, true]);
+
+        let errs = entries[1].op.get_ref("errors").unwrap();
+        let refval = r#"["schema violation: \"\" missing required property \"a\" (/required)"]"#;
+        assert_eq!(format!("{}", errs), refval);
+    }
+}
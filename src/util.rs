@@ -36,3 +36,92 @@ pub fn has_key<D>(obj: &D, key: &str) -> bool where D: Document {
     }
     false
 }
+
+static BASE64_CHARS: &'static [u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(((data.len() + 2) / 3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            if chunk.len() > 1 { chunk[1] } else { 0 },
+            if chunk.len() > 2 { chunk[2] } else { 0 },
+        ];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | (b[2] as u32);
+        out.push(BASE64_CHARS[((n >> 18) & 0x3f) as usize] as char);
+        out.push(BASE64_CHARS[((n >> 12) & 0x3f) as usize] as char);
+        if chunk.len() > 1 {
+            out.push(BASE64_CHARS[((n >> 6) & 0x3f) as usize] as char);
+        } else {
+            out.push('=');
+        }
+        if chunk.len() > 2 {
+            out.push(BASE64_CHARS[(n & 0x3f) as usize] as char);
+        } else {
+            out.push('=');
+        }
+    }
+    out
+}
+
+/// Decode standard base64 text, padding is optional. Return None if text
+/// has characters outside the alphabet.
+pub fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity((text.len() / 4) * 3);
+    let (mut n, mut bits) = (0_u32, 0);
+    for ch in text.trim_end_matches('=').bytes() {
+        let val = BASE64_CHARS.iter().position(|x| *x == ch)? as u32;
+        n = ((n << 6) | val) & 0xffff;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push(((n >> bits) & 0xff) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// Format nanoseconds since UNIX epoch as RFC 3339 timestamp in UTC,
+/// fractional seconds are printed only when non-zero.
+pub fn rfc3339(nanos: i128) -> String {
+    let (secs, nsec) = (nanos.div_euclid(1_000_000_000), nanos.rem_euclid(1_000_000_000));
+    let (days, secs) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    let (y, m, d) = civil_from_days(days);
+    let mut out = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        y, m, d, secs / 3600, (secs % 3600) / 60, secs % 60
+    );
+    if nsec > 0 {
+        out.push('.');
+        out.push_str(format!("{:09}", nsec).trim_end_matches('0'));
+    }
+    out.push('Z');
+    out
+}
+
+// days since UNIX epoch to proleptic gregorian (year, month, day), refer
+// http://howardhinnant.github.io/date_algorithms.html
+pub fn civil_from_days(days: i128) -> (i128, u32, u32) {
+    let z = days + 719468;
+    let (era, doe) = (z.div_euclid(146097), z.rem_euclid(146097));
+    let yoe = (doe - doe/1460 + doe/36524 - doe/146096) / 365;
+    let doy = doe - (365*yoe + yoe/4 - yoe/100);
+    let mp = (5*doy + 2) / 153;
+    let d = doy - (153*mp + 2)/5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era*400 + (if m <= 2 { 1 } else { 0 });
+    (y, m as u32, d as u32)
+}
+
+// proleptic gregorian (year, month, day) to days since UNIX epoch, inverse
+// of civil_from_days.
+pub fn days_from_civil(y: i128, m: u32, d: u32) -> i128 {
+    let (m, d) = (m as i128, d as i128);
+    let y = if m <= 2 { y - 1 } else { y };
+    let (era, yoe) = (y.div_euclid(400), y.rem_euclid(400));
+    let mp = if m > 2 { m - 3 } else { m + 9 };
+    let doy = (153*mp + 2)/5 + d - 1;
+    let doe = yoe*365 + yoe/4 - yoe/100 + doy;
+    era*146097 + doe - 719468
+}
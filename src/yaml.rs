@@ -0,0 +1,1018 @@
+// Reader for JSON compatible subset of YAML 1.2, refer https://yaml.org/spec/1.2/spec.html
+//
+// Supported:
+//
+// * Block mappings and sequences, including compact nesting `- a: 1`.
+// * Flow mappings and sequences, possibly spanning several lines.
+// * Plain, single-quoted and double-quoted scalars, with line folding.
+// * Literal `|` and folded `>` block scalars, with chomping and
+//   indentation indicators.
+// * Plain scalars are typed as per YAML 1.2 core schema.
+// * Multi-document streams, separated by `---` and terminated by `...`.
+//
+// Anchors, aliases, tags and complex keys are not supported. Mapping keys
+// are always read as strings.
+
+use std::{char, f64, io, mem};
+use std::io::BufRead;
+
+use json::{Json, Property, Error, Result};
+use lex::Lex;
+
+
+pub struct Yamls<R> where R: io::Read {
+    reader: io::BufReader<R>,
+    row: usize,
+    off: usize,
+    pending: Option<Line>, // content that follows a `---` marker.
+    explicit: bool,        // next document is started with `---` marker.
+    eof: bool,
+}
+
+impl<R> Yamls<R> where R: io::Read {
+    pub fn new(reader: R) -> Yamls<R> {
+        Yamls{
+            reader: io::BufReader::new(reader),
+            row: 0,
+            off: 0,
+            pending: None,
+            explicit: false,
+            eof: false,
+        }
+    }
+}
+
+impl<R> Iterator for Yamls<R> where R: io::Read {
+    type Item=Result<Json>;
+
+    fn next(&mut self) -> Option<Result<Json>> {
+        if self.eof { return None }
+
+        let mut lines: Vec<Line> = self.pending.take().into_iter().collect();
+        let mut explicit = mem::replace(&mut self.explicit, false);
+        loop {
+            let mut text = String::new();
+            let n = match self.reader.read_line(&mut text) {
+                Ok(0) => { self.eof = true; break },
+                Ok(n) => n,
+                Err(err) => {
+                    self.eof = true;
+                    return Some(Err(Error::Parse(format!("{}", err))))
+                },
+            };
+            let (row, off) = (self.row + 1, self.off);
+            self.row += 1;
+            self.off += n;
+            if text.ends_with('\n') { text.pop(); }
+            if text.ends_with('\r') { text.pop(); }
+
+            let has_content = lines.iter().any(|line| !line.is_blank());
+            if is_marker(&text, "---") {
+                let rest = Line::after_marker(row, off, text);
+                if has_content || explicit {
+                    self.explicit = true;
+                    self.pending = rest;
+                    return Some(parse_document(lines))
+                }
+                explicit = true;
+                lines.extend(rest);
+
+            } else if is_marker(&text, "...") {
+                if has_content || explicit {
+                    return Some(parse_document(lines))
+                }
+
+            } else if !text.starts_with('%') || has_content { // skip directives
+                lines.push(Line::new(row, off, text));
+            }
+        }
+
+        if explicit || lines.iter().any(|line| !line.is_blank()) {
+            Some(parse_document(lines))
+        } else {
+            None
+        }
+    }
+}
+
+impl Json {
+    /// Parse a single YAML document, empty text is parsed as null.
+    pub fn from_yaml(text: &str) -> Result<Json> {
+        let mut docs = Yamls::new(text.as_bytes());
+        let doc = docs.next().unwrap_or(Ok(Json::Null))?;
+        match docs.next() {
+            Some(_) => Err(Error::Parse("expected a single document".to_string())),
+            None => Ok(doc),
+        }
+    }
+}
+
+/// Parse all the documents in a YAML stream.
+pub fn parse_stream(text: &str) -> Result<Vec<Json>> {
+    Yamls::new(text.as_bytes()).collect()
+}
+
+
+struct Line {
+    row: usize,    // line number, starting from 1.
+    off: usize,    // stream offset to the start of this line.
+    indent: usize, // byte column where the node starts.
+    text: String,
+}
+
+impl Line {
+    fn new(row: usize, off: usize, text: String) -> Line {
+        let indent = text.len() - text.trim_start_matches(' ').len();
+        Line{ row, off, indent, text }
+    }
+
+    fn after_marker(row: usize, off: usize, text: String) -> Option<Line> {
+        let rest = &text[3..];
+        if strip_comment(rest).trim().is_empty() { return None }
+        let indent = 3 + (rest.len() - rest.trim_start().len());
+        Some(Line{ row, off, indent, text })
+    }
+
+    fn content(&self) -> &str {
+        &self.text[self.indent..]
+    }
+
+    fn is_blank(&self) -> bool {
+        strip_comment(self.content()).trim().is_empty()
+    }
+
+    fn leading_spaces(&self) -> usize {
+        self.text.len() - self.text.trim_start_matches(' ').len()
+    }
+}
+
+
+struct Parser {
+    lines: Vec<Line>,
+    pos: usize,
+}
+
+fn parse_document(lines: Vec<Line>) -> Result<Json> {
+    let mut p = Parser{ lines, pos: 0 };
+    let value = p.parse_node(-1)?;
+    p.skip_blanks();
+    if p.pos < p.lines.len() {
+        let col = p.lines[p.pos].indent;
+        return Err(p.error(p.pos, col, "unexpected content"))
+    }
+    Ok(value)
+}
+
+impl Parser {
+    fn error(&self, i: usize, col: usize, msg: &str) -> Error {
+        let line = &self.lines[i];
+        Error::Parse(Lex::new(line.off + col, line.row, col + 1).format(msg))
+    }
+
+    fn skip_blanks(&mut self) {
+        while self.pos < self.lines.len() && self.lines[self.pos].is_blank() {
+            self.pos += 1;
+        }
+    }
+
+    // parse node that is indented more than its parent.
+    fn parse_node(&mut self, parent: isize) -> Result<Json> {
+        self.skip_blanks();
+        if self.pos >= self.lines.len() { return Ok(Json::Null) }
+
+        let indent = self.lines[self.pos].indent;
+        if (indent as isize) <= parent { return Ok(Json::Null) }
+
+        let content = self.lines[self.pos].content();
+        if content.starts_with('\t') {
+            return Err(self.error(self.pos, indent, "tab in indentation"))
+        } else if is_seq_item(content) {
+            self.parse_block_seq(indent)
+        } else if key_colon(content).is_some() {
+            self.parse_block_map(indent)
+        } else {
+            self.parse_inline(parent)
+        }
+    }
+
+    fn parse_block_seq(&mut self, n: usize) -> Result<Json> {
+        let mut arr = Vec::new();
+        loop {
+            self.skip_blanks();
+            if self.pos >= self.lines.len() { break }
+
+            let indent = self.lines[self.pos].indent;
+            if indent < n { break }
+            if !is_seq_item(self.lines[self.pos].content()) { break }
+            if indent > n {
+                let msg = "bad indentation of sequence entry";
+                return Err(self.error(self.pos, indent, msg))
+            }
+
+            let rest = &self.lines[self.pos].content()[1..];
+            if strip_comment(rest).trim().is_empty() {
+                self.pos += 1;
+            } else {
+                let k = rest.len() - rest.trim_start().len();
+                self.lines[self.pos].indent = n + 1 + k;
+            }
+            arr.push(self.parse_node(n as isize)?);
+        }
+        Ok(Json::Array(arr))
+    }
+
+    fn parse_block_map(&mut self, n: usize) -> Result<Json> {
+        let mut props = Vec::new();
+        loop {
+            self.skip_blanks();
+            if self.pos >= self.lines.len() { break }
+
+            let indent = self.lines[self.pos].indent;
+            if indent < n { break }
+            if indent > n {
+                let msg = "bad indentation of mapping entry";
+                return Err(self.error(self.pos, indent, msg))
+            }
+            let colon = match key_colon(self.lines[self.pos].content()) {
+                Some(colon) => colon,
+                None if self.lines[self.pos].content().starts_with('\t') => {
+                    return Err(self.error(self.pos, indent, "tab in indentation"))
+                },
+                None => {
+                    return Err(self.error(self.pos, indent, "expected mapping key"))
+                },
+            };
+            let key = self.parse_key(self.pos, indent, colon)?;
+
+            let vcol = indent + colon + 1;
+            let rest = &self.lines[self.pos].text[vcol..];
+            let value = if strip_comment(rest).trim().is_empty() {
+                self.pos += 1;
+                self.skip_blanks();
+                let seq = self.pos < self.lines.len() &&
+                    self.lines[self.pos].indent == n &&
+                    is_seq_item(self.lines[self.pos].content());
+                if seq {
+                    self.parse_block_seq(n)?
+                } else {
+                    self.parse_node(n as isize)?
+                }
+            } else {
+                let k = rest.len() - rest.trim_start().len();
+                self.lines[self.pos].indent = vcol + k;
+                self.parse_inline(n as isize)?
+            };
+            props.push(Property::new(key, value));
+        }
+        Ok(From::from(props))
+    }
+
+    fn parse_key(&self, i: usize, indent: usize, colon: usize) -> Result<String> {
+        let key = self.lines[i].content()[..colon].trim_end();
+        match key.chars().next() {
+            Some('"') | Some('\'') => {
+                let mut flow = Flow{ text: key, pos: 0 };
+                match flow.parse_value() {
+                    Ok(Json::String(s)) => Ok(s),
+                    _ => Err(self.error(i, indent, "invalid mapping key")),
+                }
+            },
+            _ => Ok(key.to_string()),
+        }
+    }
+
+    fn parse_inline(&mut self, parent: isize) -> Result<Json> {
+        let (indent, ch) = {
+            let line = &self.lines[self.pos];
+            (line.indent, line.content().chars().next().unwrap())
+        };
+        match ch {
+            '[' | '{' | '"' | '\'' => self.parse_flow(),
+            '|' | '>' => self.parse_block_scalar(parent),
+            '&' | '*' | '!' | '?' => {
+                let msg = format!("unsupported yaml indicator {:?}", ch);
+                Err(self.error(self.pos, indent, &msg))
+            },
+            _ => self.parse_plain(parent),
+        }
+    }
+
+    fn parse_plain(&mut self, parent: isize) -> Result<Json> {
+        let mut text = strip_comment(self.lines[self.pos].content())
+            .trim()
+            .to_string();
+        self.pos += 1;
+
+        let mut blanks = 0;
+        while self.pos < self.lines.len() {
+            let line = &self.lines[self.pos];
+            let content = line.content().trim();
+            if content.is_empty() {
+                blanks += 1;
+                self.pos += 1;
+                continue
+            }
+            if (line.indent as isize) <= parent || content.starts_with('#') {
+                break
+            }
+            if key_colon(content).is_some() {
+                let msg = "bad indentation of mapping entry";
+                return Err(self.error(self.pos, line.indent, msg))
+            }
+            if blanks > 0 {
+                (0..blanks).for_each(|_| text.push('\n'));
+            } else {
+                text.push(' ');
+            }
+            text.push_str(strip_comment(content).trim());
+            blanks = 0;
+            self.pos += 1;
+        }
+        Ok(resolve_plain(&text))
+    }
+
+    fn parse_flow(&mut self) -> Result<Json> {
+        let start = self.pos;
+        let mut text = self.lines[start].content().to_string();
+        // (offset in text, line index, column in line)
+        let mut starts = vec![(0, start, self.lines[start].indent)];
+        let mut end = start;
+        loop {
+            let mut flow = Flow{ text: &text, pos: 0 };
+            let res = flow.parse_value();
+            let (at, msg) = match res {
+                Ok(value) => {
+                    let rest = &text[flow.pos..];
+                    if strip_comment(rest).trim().is_empty() {
+                        self.pos = end + 1;
+                        return Ok(value)
+                    }
+                    (flow.pos, "unexpected content after value".to_string())
+                },
+                Err(FlowError::Incomplete) if end + 1 < self.lines.len() => {
+                    end += 1;
+                    text.push('\n');
+                    starts.push((text.len(), end, 0));
+                    text.push_str(&self.lines[end].text);
+                    continue
+                },
+                Err(FlowError::Incomplete) => {
+                    (0, "incomplete flow collection or quoted scalar".to_string())
+                },
+                Err(FlowError::At(at, msg)) => (at, msg),
+            };
+            let &(off, i, col) = starts.iter()
+                .filter(|(off, _, _)| *off <= at)
+                .last()
+                .unwrap();
+            return Err(self.error(i, col + at - off, &msg))
+        }
+    }
+
+    fn parse_block_scalar(&mut self, parent: isize) -> Result<Json> {
+        let (literal, chomp, ind) = {
+            let header = strip_comment(self.lines[self.pos].content()).trim();
+            let literal = header.starts_with('|');
+            let (mut chomp, mut ind) = ('c', None);
+            for ch in header[1..].chars() {
+                match ch {
+                    '-' | '+' => chomp = ch,
+                    '1'..='9' => ind = ch.to_digit(10),
+                    _ => {
+                        let col = self.lines[self.pos].indent;
+                        let msg = "invalid block scalar header";
+                        return Err(self.error(self.pos, col, msg))
+                    },
+                }
+            }
+            (literal, chomp, ind)
+        };
+        self.pos += 1;
+
+        let base = if parent < 0 { 0 } else { parent as usize };
+        let indent = match ind {
+            Some(n) => base + (n as usize),
+            None => {
+                let line = self.lines[self.pos..].iter()
+                    .find(|line| line.text.trim().len() > 0);
+                match line {
+                    Some(line) if (line.leading_spaces() as isize) > parent => {
+                        line.leading_spaces()
+                    },
+                    _ => usize::max_value(),
+                }
+            },
+        };
+
+        let mut body: Vec<&str> = Vec::new();
+        while self.pos < self.lines.len() {
+            let line = &self.lines[self.pos];
+            if line.text.trim().is_empty() {
+                let n = line.text.len();
+                body.push(if n > indent { &line.text[indent..] } else { "" });
+            } else if line.leading_spaces() < indent {
+                break
+            } else {
+                body.push(&line.text[indent..]);
+            }
+            self.pos += 1;
+        }
+        let trailing = body.iter().rev().take_while(|l| l.is_empty()).count();
+        let n = body.len() - trailing;
+        let body = &body[..n];
+
+        let mut out = String::new();
+        if literal {
+            out.push_str(&body.join("\n"));
+        } else {
+            let (mut first, mut prev_more, mut empties) = (true, false, 0);
+            for line in body.iter() {
+                if line.is_empty() { empties += 1; continue }
+                let more = line.starts_with(' ') || line.starts_with('\t');
+                if first {
+                    (0..empties).for_each(|_| out.push('\n'));
+                } else if more || prev_more {
+                    (0..(empties + 1)).for_each(|_| out.push('\n'));
+                } else if empties > 0 {
+                    (0..empties).for_each(|_| out.push('\n'));
+                } else {
+                    out.push(' ');
+                }
+                out.push_str(line);
+                first = false;
+                prev_more = more;
+                empties = 0;
+            }
+        }
+        match chomp {
+            '-' => (),
+            '+' => (0..(trailing + 1)).for_each(|_| out.push('\n')),
+            _ if n > 0 => out.push('\n'),
+            _ => (),
+        }
+        Ok(Json::String(out))
+    }
+}
+
+
+enum FlowError {
+    Incomplete,
+    At(usize, String),
+}
+
+type FlowResult<T> = ::std::result::Result<T, FlowError>;
+
+// parse flow collections and quoted scalars.
+struct Flow<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Flow<'a> {
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn skip_ws(&mut self) {
+        while let Some(ch) = self.peek() {
+            match ch {
+                ' ' | '\t' | '\n' | '\r' => self.pos += 1,
+                '#' if self.pos == 0 || self.prev_is_ws() => {
+                    match self.text[self.pos..].find('\n') {
+                        Some(n) => self.pos += n,
+                        None => self.pos = self.text.len(),
+                    }
+                },
+                _ => break,
+            }
+        }
+    }
+
+    fn prev_is_ws(&self) -> bool {
+        match self.text[..self.pos].chars().last() {
+            Some(' ') | Some('\t') | Some('\n') => true,
+            _ => false,
+        }
+    }
+
+    fn parse_value(&mut self) -> FlowResult<Json> {
+        self.skip_ws();
+        match self.peek() {
+            None => Err(FlowError::Incomplete),
+            Some('[') => self.parse_seq(),
+            Some('{') => self.parse_map(),
+            Some('"') => Ok(Json::String(self.parse_double()?)),
+            Some('\'') => Ok(Json::String(self.parse_single()?)),
+            Some(ch @ '&') | Some(ch @ '*') | Some(ch @ '!') => {
+                let msg = format!("unsupported yaml indicator {:?}", ch);
+                Err(FlowError::At(self.pos, msg))
+            },
+            Some(ch @ ']') | Some(ch @ '}') | Some(ch @ ',') => {
+                Err(FlowError::At(self.pos, format!("unexpected {:?}", ch)))
+            },
+            Some(_) => Ok(resolve_plain(&self.parse_plain()?)),
+        }
+    }
+
+    fn parse_seq(&mut self) -> FlowResult<Json> {
+        self.pos += 1; // skip '['
+        let mut arr = Vec::new();
+        loop {
+            self.skip_ws();
+            match self.peek() {
+                None => return Err(FlowError::Incomplete),
+                Some(']') => { self.pos += 1; return Ok(Json::Array(arr)) },
+                _ => (),
+            }
+            let value = self.parse_value()?;
+            self.skip_ws();
+            if self.peek() == Some(':') { // single pair mapping
+                self.pos += 1;
+                let key = match value {
+                    Json::String(s) => s,
+                    value => format!("{}", value),
+                };
+                let value = self.parse_map_value(']')?;
+                arr.push(From::from(vec![Property::new(key, value)]));
+            } else {
+                arr.push(value);
+            }
+            self.skip_ws();
+            match self.peek() {
+                None => return Err(FlowError::Incomplete),
+                Some(',') => self.pos += 1,
+                Some(']') => (),
+                _ => {
+                    let msg = "expected ',' or ']'".to_string();
+                    return Err(FlowError::At(self.pos, msg))
+                },
+            }
+        }
+    }
+
+    fn parse_map(&mut self) -> FlowResult<Json> {
+        self.pos += 1; // skip '{'
+        let mut props = Vec::new();
+        loop {
+            self.skip_ws();
+            let key = match self.peek() {
+                None => return Err(FlowError::Incomplete),
+                Some('}') => {
+                    self.pos += 1;
+                    return Ok(From::from(props))
+                },
+                Some('"') => self.parse_double()?,
+                Some('\'') => self.parse_single()?,
+                _ => self.parse_plain()?,
+            };
+            self.skip_ws();
+            let value = if self.peek() == Some(':') {
+                self.pos += 1;
+                self.parse_map_value('}')?
+            } else {
+                Json::Null
+            };
+            props.push(Property::new(key, value));
+            self.skip_ws();
+            match self.peek() {
+                None => return Err(FlowError::Incomplete),
+                Some(',') => self.pos += 1,
+                Some('}') => (),
+                _ => {
+                    let msg = "expected ',' or '}'".to_string();
+                    return Err(FlowError::At(self.pos, msg))
+                },
+            }
+        }
+    }
+
+    fn parse_map_value(&mut self, close: char) -> FlowResult<Json> {
+        self.skip_ws();
+        match self.peek() {
+            None => Err(FlowError::Incomplete),
+            Some(ch) if ch == ',' || ch == close => Ok(Json::Null),
+            _ => self.parse_value(),
+        }
+    }
+
+    fn parse_plain(&mut self) -> FlowResult<String> {
+        let start = self.pos;
+        let mut out = String::new();
+        while let Some(ch) = self.peek() {
+            match ch {
+                ',' | '[' | ']' | '{' | '}' => break,
+                ':' => {
+                    let next = self.text[self.pos+1..].chars().next();
+                    match next {
+                        None | Some(' ') | Some('\t') | Some('\n') |
+                        Some(',') | Some('[') | Some(']') |
+                        Some('{') | Some('}') => break,
+                        _ => { out.push(ch); self.pos += 1 },
+                    }
+                },
+                '#' if self.prev_is_ws() => break,
+                '\n' => self.fold_newline(&mut out),
+                ch => { out.push(ch); self.pos += ch.len_utf8() },
+            }
+        }
+        let out = out.trim().to_string();
+        if out.is_empty() {
+            return Err(FlowError::At(start, "expected a value".to_string()))
+        }
+        Ok(out)
+    }
+
+    fn parse_single(&mut self) -> FlowResult<String> {
+        self.pos += 1; // skip opening quote
+        let mut out = String::new();
+        loop {
+            match self.peek() {
+                None => return Err(FlowError::Incomplete),
+                Some('\'') if self.text[self.pos+1..].starts_with('\'') => {
+                    out.push('\'');
+                    self.pos += 2;
+                },
+                Some('\'') => { self.pos += 1; return Ok(out) },
+                Some('\n') => self.fold_newline(&mut out),
+                Some(ch) => { out.push(ch); self.pos += ch.len_utf8() },
+            }
+        }
+    }
+
+    fn parse_double(&mut self) -> FlowResult<String> {
+        self.pos += 1; // skip opening quote
+        let mut out = String::new();
+        loop {
+            match self.peek() {
+                None => return Err(FlowError::Incomplete),
+                Some('"') => { self.pos += 1; return Ok(out) },
+                Some('\n') => self.fold_newline(&mut out),
+                Some('\\') => {
+                    let at = self.pos;
+                    self.pos += 1;
+                    let ch = match self.peek() {
+                        None => return Err(FlowError::Incomplete),
+                        Some(ch) => ch,
+                    };
+                    self.pos += ch.len_utf8();
+                    let ch = match ch {
+                        '0' => '\0',
+                        'a' => '\x07',
+                        'b' => '\x08',
+                        't' | '\t' => '\t',
+                        'n' => '\n',
+                        'v' => '\x0b',
+                        'f' => '\x0c',
+                        'r' => '\r',
+                        'e' => '\x1b',
+                        ' ' => ' ',
+                        '"' => '"',
+                        '/' => '/',
+                        '\\' => '\\',
+                        'N' => '\u{85}',
+                        '_' => '\u{a0}',
+                        'L' => '\u{2028}',
+                        'P' => '\u{2029}',
+                        'x' => self.parse_hex(at, 2)?,
+                        'u' => self.parse_hex(at, 4)?,
+                        'U' => self.parse_hex(at, 8)?,
+                        '\n' => { // escaped line break
+                            while let Some(' ') | Some('\t') = self.peek() {
+                                self.pos += 1
+                            }
+                            continue
+                        },
+                        ch => {
+                            let msg = format!("invalid escape character {:?}", ch);
+                            return Err(FlowError::At(at, msg))
+                        },
+                    };
+                    out.push(ch);
+                },
+                Some(ch) => { out.push(ch); self.pos += ch.len_utf8() },
+            }
+        }
+    }
+
+    fn parse_hex(&mut self, at: usize, n: usize) -> FlowResult<char> {
+        let text = &self.text[self.pos..];
+        if text.len() < n || !text.is_char_boundary(n) {
+            return Err(FlowError::At(at, "incomplete escape code".to_string()))
+        }
+        let code = u32::from_str_radix(&text[..n], 16).ok();
+        match code.and_then(char::from_u32) {
+            Some(ch) => { self.pos += n; Ok(ch) },
+            None => Err(FlowError::At(at, "invalid escape code".to_string())),
+        }
+    }
+
+    // fold line breaks within flow scalars, first line break is folded
+    // into a space and subsequent empty lines are kept as line breaks.
+    fn fold_newline(&mut self, out: &mut String) {
+        while out.ends_with(' ') || out.ends_with('\t') { out.pop(); }
+        let mut n = 0;
+        loop {
+            self.pos += 1; // skip '\n'
+            while let Some(' ') | Some('\t') | Some('\r') = self.peek() {
+                self.pos += 1
+            }
+            if self.peek() == Some('\n') { n += 1; continue }
+            break
+        }
+        if n == 0 {
+            out.push(' ')
+        } else {
+            (0..n).for_each(|_| out.push('\n'))
+        }
+    }
+}
+
+
+fn is_marker(text: &str, marker: &str) -> bool {
+    text.starts_with(marker) &&
+        (text.len() == 3 || text[3..].starts_with(' ') ||
+         text[3..].starts_with('\t'))
+}
+
+fn is_seq_item(content: &str) -> bool {
+    let content = strip_comment(content).trim_end();
+    content == "-" || content.starts_with("- ") || content.starts_with("-\t")
+}
+
+// locate the ':' indicator for block mapping key, if content is a key.
+fn key_colon(content: &str) -> Option<usize> {
+    let bs = content.as_bytes();
+    if bs.is_empty() { return None }
+
+    match bs[0] {
+        b'[' | b'{' | b'#' | b'|' | b'>' | b'&' | b'*' | b'!' | b'%' |
+        b'@' | b'`' => None,
+        b'"' | b'\'' => {
+            let mut i = skip_quoted(content)?;
+            while i < bs.len() && (bs[i] == b' ' || bs[i] == b'\t') { i += 1 }
+            let ok = i < bs.len() && bs[i] == b':' &&
+                (i + 1 == bs.len() || bs[i+1] == b' ' || bs[i+1] == b'\t');
+            if ok { Some(i) } else { None }
+        },
+        _ => {
+            for i in 0..bs.len() {
+                match bs[i] {
+                    b':' if i+1 == bs.len() || bs[i+1] == b' ' ||
+                            bs[i+1] == b'\t' => return Some(i),
+                    b'#' if i > 0 && (bs[i-1] == b' ' || bs[i-1] == b'\t') => {
+                        return None
+                    },
+                    _ => (),
+                }
+            }
+            None
+        },
+    }
+}
+
+// return the byte position after the closing quote.
+fn skip_quoted(text: &str) -> Option<usize> {
+    let bs = text.as_bytes();
+    let quote = bs[0];
+    let mut i = 1;
+    while i < bs.len() {
+        match bs[i] {
+            b'\\' if quote == b'"' => i += 1,
+            b'\'' if quote == b'\'' && i+1 < bs.len() && bs[i+1] == b'\'' => {
+                i += 1
+            },
+            b if b == quote => return Some(i + 1),
+            _ => (),
+        }
+        i += 1;
+    }
+    None
+}
+
+fn strip_comment(text: &str) -> &str {
+    let bs = text.as_bytes();
+    let mut quote: Option<u8> = None;
+    let mut i = 0;
+    while i < bs.len() {
+        let b = bs[i];
+        match quote {
+            Some(b'"') if b == b'\\' => i += 1,
+            Some(q) if b == q => {
+                if q == b'\'' && i+1 < bs.len() && bs[i+1] == b'\'' {
+                    i += 1
+                } else {
+                    quote = None
+                }
+            },
+            Some(_) => (),
+            None if b == b'#' => {
+                if i == 0 || bs[i-1] == b' ' || bs[i-1] == b'\t' {
+                    return &text[..i]
+                }
+            },
+            None if b == b'"' || b == b'\'' => {
+                if i == 0 || b" \t:[{,-".contains(&bs[i-1]) {
+                    quote = Some(b)
+                }
+            },
+            None => (),
+        }
+        i += 1;
+    }
+    text
+}
+
+// type plain scalars as per YAML 1.2 core schema.
+fn resolve_plain(text: &str) -> Json {
+    match text {
+        "" | "~" | "null" | "Null" | "NULL" => return Json::Null,
+        "true" | "True" | "TRUE" => return Json::Bool(true),
+        "false" | "False" | "FALSE" => return Json::Bool(false),
+        ".inf" | ".Inf" | ".INF" | "+.inf" | "+.Inf" | "+.INF" => {
+            return Json::Float(f64::INFINITY)
+        },
+        "-.inf" | "-.Inf" | "-.INF" => return Json::Float(f64::NEG_INFINITY),
+        ".nan" | ".NaN" | ".NAN" => return Json::Float(f64::NAN),
+        _ => (),
+    }
+
+    let is_digits = |s: &str, radix: u32| -> bool {
+        s.len() > 0 && s.chars().all(|ch| ch.is_digit(radix))
+    };
+
+    let unsigned = text.trim_start_matches(|ch| ch == '-' || ch == '+');
+    if (text.len() - unsigned.len()) <= 1 && is_digits(unsigned, 10) {
+        if let Ok(n) = text.parse::<i128>() { return Json::Integer(n) }
+    }
+    if text.starts_with("0o") && is_digits(&text[2..], 8) {
+        if let Ok(n) = i128::from_str_radix(&text[2..], 8) {
+            return Json::Integer(n)
+        }
+    }
+    if text.starts_with("0x") && is_digits(&text[2..], 16) {
+        if let Ok(n) = i128::from_str_radix(&text[2..], 16) {
+            return Json::Integer(n)
+        }
+    }
+
+    // [-+]? ( \. [0-9]+ | [0-9]+ ( \. [0-9]* )? ) ( [eE] [-+]? [0-9]+ )?
+    let (mantissa, exp) = match unsigned.find(|ch| ch == 'e' || ch == 'E') {
+        Some(i) => (&unsigned[..i], Some(&unsigned[i+1..])),
+        None => (unsigned, None),
+    };
+    let mantissa_ok = match mantissa.find('.') {
+        Some(0) => is_digits(&mantissa[1..], 10),
+        Some(i) => {
+            is_digits(&mantissa[..i], 10) &&
+            (i+1 == mantissa.len() || is_digits(&mantissa[i+1..], 10))
+        },
+        None => is_digits(mantissa, 10),
+    };
+    let exp_ok = match exp {
+        Some(exp) => is_digits(exp.trim_start_matches(|c| c == '-' || c == '+'), 10),
+        None => true,
+    };
+    if (text.len() - unsigned.len()) <= 1 && mantissa_ok && exp_ok {
+        if let Ok(f) = text.parse::<f64>() { return Json::Float(f) }
+    }
+
+    Json::String(text.to_string())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn yaml(text: &str) -> String {
+        format!("{}", Json::from_yaml(text).unwrap())
+    }
+
+    #[test]
+    fn test_yaml_block() {
+        let text = r#"
+# job definition
+name: nightly  # trailing comment
+retries: 3
+timeout: 1.5
+enabled: true
+owner: ~
+steps:
+- run: build
+  args: [--release, -v]
+- run: test
+  env:
+    RUST_LOG: debug
+    "quoted key": 'it''s'
+-
+  - nested
+  - 0x1f
+tags:
+  - a
+  -   b
+"#;
+        let mut refval = r#"{"enabled":true,"name":"nightly","owner":null,"#
+            .to_string();
+        refval += r#""retries":3,"steps":[{"args":["--release","-v"],"#;
+        refval += r#""run":"build"},{"env":{"RUST_LOG":"debug","#;
+        refval += r#""quoted key":"it's"},"run":"test"},["nested",31]],"#;
+        refval += r#""tags":["a","b"],"timeout":1.5e0}"#;
+        assert_eq!(refval, yaml(text));
+    }
+
+    #[test]
+    fn test_yaml_scalars() {
+        let text = "[null, Null, ~, TRUE, False, 10, -10, +10, 0o17, 0xff, \
+                    1., .5, -1.5e3, 1e3, .inf, -.Inf, 1_000, 0x, 2001-12-14, \
+                    http://x.com/a, a b]";
+        let mut refval = r#"[null,null,null,true,false,10,-10,10,15,255,"#
+            .to_string();
+        refval += r#"1e0,5e-1,-1.5e3,1e3,inf,-inf,"1_000","0x","2001-12-14","#;
+        refval += r#""http://x.com/a","a b"]"#;
+        assert_eq!(refval, yaml(text));
+
+        match Json::from_yaml(".nan").unwrap() {
+            Json::Float(f) => assert!(f.is_nan()),
+            val => panic!("unexpected {}", val),
+        }
+
+        let text = r#""tab\there \u00e9 \x41 \"q\" \\""#;
+        assert_eq!(r#""tab\there é A \"q\" \\""#, yaml(text));
+
+        let text = "a: plain text\n  continued here\n\n  and more\nb: 1";
+        let refval = r#"{"a":"plain text continued here\nand more","b":1}"#;
+        assert_eq!(refval, yaml(text));
+
+        let text = "a: \"double\n   quoted\"\nb: 'single\n\n quoted'";
+        let refval = r#"{"a":"double quoted","b":"single\nquoted"}"#;
+        assert_eq!(refval, yaml(text));
+    }
+
+    #[test]
+    fn test_yaml_flow() {
+        let text = r#"{"a": 1, "b": [true, null, "x"], c: {d: e}, f, g: }"#;
+        let refval = r#"{"a":1,"b":[true,null,"x"],"c":{"d":"e"},"f":null,"g":null}"#;
+        assert_eq!(refval, yaml(text));
+
+        let text = "key: [ a,   # first\n  b,\n  {c: d} ]\nnext: [x: 1]";
+        let refval = r#"{"key":["a","b",{"c":"d"}],"next":[{"x":1}]}"#;
+        assert_eq!(refval, yaml(text));
+
+        let text = r#"{"nested": {"json": [1, 2.5, "three", {"four": [4]}]}}"#;
+        let value: Json = text.parse().unwrap();
+        assert_eq!(Json::from_yaml(text).unwrap(), value);
+    }
+
+    #[test]
+    fn test_yaml_block_scalar() {
+        let text = "a: |\n  line1\n   line2\n\n  line3\n\nb: >\n  folded\n  text\n\n  para\n   more\n  end\nc: |-\n  strip\n\nd: |+\n  keep\n\ne: >2\n   indented\n";
+        let mut refval = r#"{"a":"line1\n line2\n\nline3\n","#.to_string();
+        refval += r#""b":"folded text\npara\n more\nend\n","c":"strip","#;
+        refval += r#""d":"keep\n\n","e":" indented\n"}"#;
+        assert_eq!(refval, yaml(text));
+
+        let text = "- |\n  # not a comment\n- x";
+        assert_eq!(r##"["# not a comment\n","x"]"##, yaml(text));
+    }
+
+    #[test]
+    fn test_yaml_stream() {
+        let text = "%YAML 1.2\n---\na: 1\n---\n- b\n...\n--- text\n---\n";
+        let docs: Vec<String> = parse_stream(text).unwrap().into_iter()
+            .map(|doc| format!("{}", doc))
+            .collect();
+        assert_eq!(docs, vec![r#"{"a":1}"#, r#"["b"]"#, r#""text""#, "null"]);
+
+        let docs = parse_stream("a: 1\n...\nb: 2\n").unwrap();
+        assert_eq!(docs.len(), 2);
+        assert_eq!(parse_stream("# only comments\n\n").unwrap().len(), 0);
+        assert!(Json::from_yaml("a\n---\nb").is_err());
+    }
+
+    #[test]
+    fn test_yaml_errors() {
+        let err = Json::from_yaml("a: 1\n   b: 2\n").unwrap_err();
+        let refval = "bad indentation of mapping entry at offset:8 line:2 col:4";
+        assert_eq!(Error::Parse(refval.to_string()), err);
+
+        let err = Json::from_yaml("a:\n  - 1\n  x\n").unwrap_err();
+        let refval = "bad indentation of mapping entry at offset:11 line:3 col:3";
+        assert_eq!(Error::Parse(refval.to_string()), err);
+
+        let err = Json::from_yaml("a: {b: 1 ]").unwrap_err();
+        let refval = "expected ',' or '}' at offset:9 line:1 col:10";
+        assert_eq!(Error::Parse(refval.to_string()), err);
+
+        let err = Json::from_yaml("a: [1, 2").unwrap_err();
+        let refval = "incomplete flow collection or quoted scalar at offset:3 line:1 col:4";
+        assert_eq!(Error::Parse(refval.to_string()), err);
+
+        let err = Json::from_yaml("a: &anchor 1").unwrap_err();
+        let refval = "unsupported yaml indicator '&' at offset:3 line:1 col:4";
+        assert_eq!(Error::Parse(refval.to_string()), err);
+
+        let err = Json::from_yaml("a:\n\t- 1").unwrap_err();
+        let refval = "tab in indentation at offset:3 line:2 col:1";
+        assert_eq!(Error::Parse(refval.to_string()), err);
+    }
+}